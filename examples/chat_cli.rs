@@ -1,7 +1,7 @@
 use dotenvy::dotenv;
 use openai::{
-    chat::{ChatCompletion, ChatCompletionMessage, ChatCompletionMessageRole},
-    Credentials,
+    chat::{ChatCompletion, ChatCompletionMessage},
+    ApiProvider, Credentials,
 };
 use std::io::{stdin, stdout, Write};
 
@@ -9,13 +9,9 @@ use std::io::{stdin, stdout, Write};
 async fn main() {
     // Make sure you have a file named `.env` with the `OPENAI_KEY` environment variable defined!
     dotenv().unwrap();
-    let credentials = Credentials::from_env();
+    let credentials = Credentials::from_env(ApiProvider::OpenAI);
 
-    let mut messages = vec![ChatCompletionMessage {
-        role: ChatCompletionMessageRole::System,
-        content: Some("You are a large language model built into a command line interface as an example of what the `openai` Rust library made by Valentine Briese can do.".to_string()),
-        ..Default::default()
-    }];
+    let mut messages = vec![ChatCompletionMessage::system("You are a large language model built into a command line interface as an example of what the `openai` Rust library made by Valentine Briese can do.")];
 
     loop {
         print!("User: ");
@@ -24,11 +20,7 @@ async fn main() {
         let mut user_message_content = String::new();
 
         stdin().read_line(&mut user_message_content).unwrap();
-        messages.push(ChatCompletionMessage {
-            role: ChatCompletionMessageRole::User,
-            content: Some(user_message_content),
-            ..Default::default()
-        });
+        messages.push(ChatCompletionMessage::user(user_message_content));
 
         let chat_completion = ChatCompletion::builder("gpt-3.5-turbo", messages.clone())
             .credentials(credentials.clone())
@@ -1,7 +1,7 @@
 use dotenvy::dotenv;
 use openai::{
-    chat::{ChatCompletion, ChatCompletionMessage, ChatCompletionMessageRole},
-    Credentials,
+    chat::{ChatCompletion, ChatCompletionMessage},
+    ApiProvider, Credentials,
 };
 
 #[tokio::main]
@@ -9,18 +9,10 @@ async fn main() {
     // Make sure you have a file named `.env` with the `OPENAI_KEY` environment variable defined!
     dotenv().unwrap();
     // Relies on OPENAI_KEY and optionally OPENAI_BASE_URL.
-    let credentials = Credentials::from_env();
+    let credentials = Credentials::from_env(ApiProvider::OpenAI);
     let messages = vec![
-        ChatCompletionMessage {
-            role: ChatCompletionMessageRole::System,
-            content: Some("You are a helpful assistant.".to_string()),
-            ..Default::default()
-        },
-        ChatCompletionMessage {
-            role: ChatCompletionMessageRole::User,
-            content: Some("Tell me a random crab fact".to_string()),
-            ..Default::default()
-        },
+        ChatCompletionMessage::system("You are a helpful assistant."),
+        ChatCompletionMessage::user("Tell me a random crab fact"),
     ];
     let chat_completion = ChatCompletion::builder("gpt-4o", messages.clone())
         .credentials(credentials.clone())
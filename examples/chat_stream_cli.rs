@@ -1,9 +1,6 @@
 use dotenvy::dotenv;
 use openai::chat::{ChatCompletion, ChatCompletionDelta};
-use openai::{
-    chat::{ChatCompletionMessage, ChatCompletionMessageRole},
-    Credentials,
-};
+use openai::{chat::ChatCompletionMessage, ApiProvider, ApiResponseOrError, Credentials};
 use std::io::{stdin, stdout, Write};
 use tokio::sync::mpsc::Receiver;
 
@@ -11,13 +8,11 @@ use tokio::sync::mpsc::Receiver;
 async fn main() {
     // Make sure you have a file named `.env` with the `OPENAI_KEY` environment variable defined!
     dotenv().unwrap();
-    let credentials = Credentials::from_env();
+    let credentials = Credentials::from_env(ApiProvider::OpenAI);
 
-    let mut messages = vec![ChatCompletionMessage {
-        role: ChatCompletionMessageRole::System,
-        content: Some("You're an AI that replies to each message verbosely.".to_string()),
-        ..Default::default()
-    }];
+    let mut messages = vec![ChatCompletionMessage::system(
+        "You're an AI that replies to each message verbosely.",
+    )];
 
     loop {
         print!("User: ");
@@ -26,11 +21,7 @@ async fn main() {
         let mut user_message_content = String::new();
 
         stdin().read_line(&mut user_message_content).unwrap();
-        messages.push(ChatCompletionMessage {
-            role: ChatCompletionMessageRole::User,
-            content: Some(user_message_content),
-            ..Default::default()
-        });
+        messages.push(ChatCompletionMessage::user(user_message_content));
 
         let chat_stream = ChatCompletionDelta::builder("gpt-3.5-turbo", messages.clone())
             .credentials(credentials.clone())
@@ -45,9 +36,18 @@ async fn main() {
     }
 }
 
-async fn listen_for_tokens(mut chat_stream: Receiver<ChatCompletionDelta>) -> ChatCompletion {
+async fn listen_for_tokens(
+    mut chat_stream: Receiver<ApiResponseOrError<ChatCompletionDelta>>,
+) -> ChatCompletion {
     let mut merged: Option<ChatCompletionDelta> = None;
     while let Some(delta) = chat_stream.recv().await {
+        let delta = match delta {
+            Ok(delta) => delta,
+            Err(error) => {
+                eprintln!("\nStream failed: {error}");
+                break;
+            }
+        };
         let choice = &delta.choices[0];
         if let Some(role) = &choice.delta.role {
             print!("{:#?}: ", role);
@@ -55,9 +55,9 @@ async fn listen_for_tokens(mut chat_stream: Receiver<ChatCompletionDelta>) -> Ch
         if let Some(content) = &choice.delta.content {
             print!("{}", content);
         }
-        if let Some(_) = &choice.finish_reason {
+        if choice.finish_reason.is_some() {
             // The message being streamed has been fully received.
-            print!("\n");
+            println!();
         }
         stdout().flush().unwrap();
         // Merge completion into accrued.
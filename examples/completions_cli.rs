@@ -1,12 +1,12 @@
 use dotenvy::dotenv;
-use openai::{completions::Completion, Credentials};
+use openai::{completions::Completion, ApiProvider, Credentials};
 use std::io::stdin;
 
 #[tokio::main]
 async fn main() {
     // Make sure you have a file named `.env` with the `OPENAI_KEY` environment variable defined!
     dotenv().unwrap();
-    let credentials = Credentials::from_env();
+    let credentials = Credentials::from_env(ApiProvider::OpenAI);
 
     loop {
         println!("Prompt:");
@@ -1,15 +1,21 @@
 //! Given a chat conversation, the model will return a chat completion response.
 
-use super::{anthropic_post, ApiResponseOrError, Credentials, AnthropicUsage, chat::{ChatCompletionMessage, ChatCompletionMessageRole, ChatCompletionResponseFormat, ChatCompletionFunctionDefinition, ToolCall, ChatCompletionFunctionCallDelta}};
-use crate::anthropic_request_stream;
+use super::{anthropic_post_with_meta, override_base_url, override_headers, ApiResponseOrError, OpenAiError, ResponseMeta, Credentials, AnthropicUsage, chat::{ChatCompletionContentPart, ChatCompletionMessage, ChatCompletionMessageRole, ChatCompletionRequest, ChatCompletionResponseFormat, ChatCompletionFunctionDefinition, ToolCall, ChatCompletionFunctionCallDelta}};
+use crate::{
+    anthropic_request_stream, resolve_credentials, validate_range, ResponseStream, StreamLimits,
+    StreamSender,
+};
+use crate::models;
+use crate::tokens::{estimate_message_tokens, estimate_tokens};
 use derive_builder::Builder;
-use futures_util::StreamExt;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use reqwest::Method;
 use reqwest_eventsource::{CannotCloneRequestError, Event, EventSource};
-use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+#[cfg(feature = "tokio-runtime")]
+use tokio::sync::mpsc::{channel, Receiver};
 
 /// A Anthropic Full Chat Completion
 pub type AnthropicChatCompletion = AnthropicChatCompletionGeneric<AnthropicChatCompletionContent>;
@@ -28,13 +34,57 @@ pub struct AnthropicChatCompletionGeneric<C> {
     pub stop_reason: String,
     pub stop_sequence: Option<String>,
     pub usage: Option<AnthropicUsage>,
+    /// The container the code execution tool ran in, when it was used.
+    #[serde(default)]
+    pub container: Option<AnthropicContainer>,
+    /// The ID Anthropic gave the request, to quote in support requests,
+    /// see [`ResponseMeta::request_id`]. Not set for streamed messages.
+    #[serde(skip)]
+    pub request_id: Option<String>,
 }
 
+/// A container of the code execution tool, which can be reused by later
+/// requests until it expires.
 #[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct AnthropicContainer {
+    pub id: String,
+    /// When the container expires, as an RFC 3339 timestamp.
+    pub expires_at: String,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, Eq, PartialEq)]
 pub struct AnthropicChatCompletionContent {
     #[serde(rename="type")]
     pub typ: String,
+    /// The text of a `text` block, empty for other blocks.
+    #[serde(default)]
     pub text: String,
+    /// The reasoning of a `thinking` block, with extended thinking.
+    #[serde(default)]
+    pub thinking: Option<String>,
+    /// Verifies a `thinking` block when it is sent back in a later turn.
+    #[serde(default)]
+    pub signature: Option<String>,
+    /// The encrypted reasoning of a `redacted_thinking` block.
+    #[serde(default)]
+    pub data: Option<String>,
+    /// The ID of a `tool_use` block, referenced by the tool result.
+    #[serde(default)]
+    pub id: Option<String>,
+    /// The name of the tool of a `tool_use` block.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The input of the tool of a `tool_use` block.
+    #[serde(default)]
+    pub input: Option<Value>,
+}
+
+impl AnthropicChatCompletionContent {
+    /// Deserializes the input of the tool of a `tool_use` block into `T`,
+    /// the type the tool takes, which also validates it.
+    pub fn parse_input<T: serde::de::DeserializeOwned>(&self) -> serde_json::Result<T> {
+        T::deserialize(self.input.clone().unwrap_or_default())
+    }
 }
 
 
@@ -80,9 +130,11 @@ pub struct ChatCompletionMessageDelta {
 #[builder(derive(Clone, Debug, PartialEq))]
 #[builder(pattern = "owned")]
 #[builder(name = "AnthropicChatCompletionBuilder")]
+#[builder(build_fn(private, name = "build_request", validate = "Self::validate"))]
 #[builder(setter(strip_option, into))]
 pub struct AnthropicChatCompletionRequest {
     model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     system: Option<String>,
     messages: Vec<ChatCompletionMessage>,
 
@@ -95,25 +147,27 @@ pub struct AnthropicChatCompletionRequest {
     top_p: Option<f32>,
     
     /// How many chat completion choices to generate for each input message.
+    /// Anthropic generates a single one, so building fails for more, and the
+    /// field is never sent.
     #[builder(default)]
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(skip_serializing)]
+    #[allow(dead_code)]
     n: Option<u8>,
     
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     stream: Option<bool>,
     
-    /// Up to 4 sequences where the API will stop generating further tokens.
+    /// Custom text sequences that will cause the model to stop generating.
     #[builder(default)]
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(rename = "stop_sequences", skip_serializing_if = "Vec::is_empty")]
     stop: Vec<String>,
     
-    /// This feature is in Beta. If specified, our system will make a best effort to sample deterministically, such that repeated requests with the same seed and parameters should return the same result. Determinism is not guaranteed, and you should refer to the system_fingerprint response parameter to monitor changes in the backend.
-    #[builder(default)]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    seed: Option<u64>,
-    
-    /// The maximum number of tokens allowed for the generated answer. By default, the number of tokens the model can return will be (4096 - prompt tokens).
+    /// The maximum number of tokens allowed for the generated answer, which Anthropic requires.
+    /// Defaults to the most the model can generate, see
+    /// [`models::max_output_tokens`](crate::models::max_output_tokens), and to 4096 for
+    /// unknown models. The default is worked out when the request is built, so it follows
+    /// the final model and the `output-128k-2025-02-19` beta of Claude 3.7 Sonnet.
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     max_tokens: Option<i32>,
@@ -127,28 +181,260 @@ pub struct AnthropicChatCompletionRequest {
     frequency_penalty: Option<f32>,
 
     #[builder(default)]
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "crate::serialize_sorted_option"
+    )]
     logit_bias: Option<HashMap<String, f32>>,
-    /// A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse. [Learn more](https://platform.openai.com/docs/guides/safety-best-practices/end-user-ids).
+    /// Identifies the end user of the request, to help Anthropic detect abuse.
+    /// Set it with [`AnthropicChatCompletionBuilder::user`].
     #[builder(default)]
-    #[serde(skip_serializing_if = "String::is_empty")]
-    user: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<AnthropicMetadata>,
     
     #[builder(default)]
     #[serde(skip_serializing_if = "Vec::is_empty")]
     functions: Vec<ChatCompletionFunctionDefinition>,
+
+    /// The tools the model may call.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<AnthropicTool>,
     
+    /// How the model should use the tools.
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    function_call: Option<Value>,
+    tool_choice: Option<AnthropicToolChoice>,
     
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     response_format: Option<ChatCompletionResponseFormat>,
+    /// Enables extended thinking, with the budget of tokens the model may reason with.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<AnthropicThinking>,
+    /// The ID of a container to reuse, from [`AnthropicContainer::id`].
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    container: Option<String>,
+    /// Whether the request may use priority capacity. The tier it was served with
+    /// is in [`AnthropicUsage::service_tier`].
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    service_tier: Option<AnthropicServiceTier>,
     /// The credentials to use for this request.
     #[serde(skip_serializing)]
     #[builder(default)]
     credentials: Option<Credentials>,
+    /// Overrides the base URL of the credentials for this request only.
+    /// The API key and provider are left untouched.
+    #[serde(skip_serializing)]
+    #[builder(default)]
+    base_url: Option<String>,
+    /// Extra headers sent with this request only, on top of those of the credentials.
+    #[serde(skip_serializing)]
+    #[builder(default)]
+    headers: HeaderMap,
+    /// Overrides the `anthropic-version` header of the credentials for this request only.
+    #[serde(skip_serializing)]
+    #[builder(default)]
+    anthropic_version: Option<String>,
+    /// Overrides the `anthropic-beta` header values of the credentials for this request only.
+    #[serde(skip_serializing)]
+    #[builder(default)]
+    anthropic_beta: Vec<String>,
+    /// Overrides the stream idle timeout of the credentials for this request only,
+    /// see [`Credentials::with_stream_idle_timeout`].
+    #[serde(skip_serializing)]
+    #[builder(default)]
+    stream_idle_timeout: Option<std::time::Duration>,
+    /// Set to `false` to leave out the `x-api-key` header for this request only,
+    /// see [`Credentials::without_auth_header`].
+    #[serde(skip_serializing)]
+    #[builder(default)]
+    auth_header: Option<bool>,
+}
+
+/// The metadata of a request.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct AnthropicMetadata {
+    /// An opaque identifier of the end user, such as a hash of their ID,
+    /// which mustn't contain any identifying information.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+}
+
+/// The capacity a request may be served with, see
+/// [Service tiers](https://docs.anthropic.com/en/api/service-tiers).
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Hash)]
+#[serde(from = "String", into = "String")]
+pub enum AnthropicServiceTier {
+    /// Priority capacity when the organization has some, standard capacity otherwise.
+    /// The default.
+    Auto,
+    /// Standard capacity only.
+    StandardOnly,
+    /// A tier this crate doesn't know about, sent as is.
+    Unknown(String),
+}
+
+impl AnthropicServiceTier {
+    /// The raw value, as sent to the API.
+    pub fn as_str(&self) -> &str {
+        match self {
+            AnthropicServiceTier::Auto => "auto",
+            AnthropicServiceTier::StandardOnly => "standard_only",
+            AnthropicServiceTier::Unknown(tier) => tier,
+        }
+    }
+}
+
+impl From<String> for AnthropicServiceTier {
+    fn from(tier: String) -> Self {
+        match tier.as_str() {
+            "auto" => AnthropicServiceTier::Auto,
+            "standard_only" => AnthropicServiceTier::StandardOnly,
+            _ => AnthropicServiceTier::Unknown(tier),
+        }
+    }
+}
+
+impl From<AnthropicServiceTier> for String {
+    fn from(tier: AnthropicServiceTier) -> Self {
+        match tier {
+            AnthropicServiceTier::Unknown(tier) => tier,
+            tier => tier.as_str().to_string(),
+        }
+    }
+}
+
+/// The capacity a request was served with, in its [`AnthropicUsage`].
+#[derive(Deserialize, Clone, Copy, Debug, Eq, PartialEq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum AnthropicServedTier {
+    Standard,
+    Priority,
+    Batch,
+    /// A tier this crate doesn't know about.
+    #[serde(other)]
+    Unknown,
+}
+
+/// Whether the model reasons before answering, in `thinking` content blocks.
+///
+/// [Extended thinking](https://docs.anthropic.com/en/docs/build-with-claude/extended-thinking)
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AnthropicThinking {
+    /// The budget must be at least 1024 tokens and less than `max_tokens`.
+    Enabled { budget_tokens: u32 },
+    Disabled,
+}
+
+impl AnthropicThinking {
+    pub fn enabled(budget_tokens: u32) -> Self {
+        AnthropicThinking::Enabled { budget_tokens }
+    }
+}
+
+/// A tool the model may call, with the JSON schema of its input.
+///
+/// [API Reference](https://docs.anthropic.com/en/docs/build-with-claude/tool-use)
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct AnthropicTool {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub input_schema: Value,
+}
+
+impl AnthropicTool {
+    pub fn new(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        input_schema: Value,
+    ) -> Self {
+        AnthropicTool {
+            name: name.into(),
+            description: Some(description.into()),
+            input_schema,
+        }
+    }
+
+    /// A tool whose input is the JSON schema of `T`. Parse the input of its
+    /// calls with [`AnthropicChatCompletionContent::parse_input`].
+    #[cfg(feature = "schemars")]
+    pub fn tool_from<T: schemars::JsonSchema>(
+        name: impl Into<String>,
+        description: impl Into<String>,
+    ) -> Self {
+        Self::new(name, description, crate::chat::schema_for::<T>(false))
+    }
+}
+
+/// How the model should use the tools given to it.
+///
+/// [API Reference](https://docs.anthropic.com/en/docs/build-with-claude/tool-use#controlling-claudes-output)
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AnthropicToolChoice {
+    /// The model decides whether to call a tool.
+    Auto {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        disable_parallel_tool_use: Option<bool>,
+    },
+    /// The model must call one of the tools.
+    Any {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        disable_parallel_tool_use: Option<bool>,
+    },
+    /// The model must call the named tool.
+    Tool {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        disable_parallel_tool_use: Option<bool>,
+    },
+    /// The model must not call any tool.
+    None,
+}
+
+impl AnthropicToolChoice {
+    pub fn auto() -> Self {
+        AnthropicToolChoice::Auto {
+            disable_parallel_tool_use: None,
+        }
+    }
+
+    pub fn any() -> Self {
+        AnthropicToolChoice::Any {
+            disable_parallel_tool_use: None,
+        }
+    }
+
+    pub fn tool(name: impl Into<String>) -> Self {
+        AnthropicToolChoice::Tool {
+            name: name.into(),
+            disable_parallel_tool_use: None,
+        }
+    }
+
+    /// Makes the model call at most one tool (exactly one with `Any` and `Tool`).
+    /// Has no effect on `None`, which the API doesn't accept it with.
+    pub fn disable_parallel_tool_use(self) -> Self {
+        match self {
+            AnthropicToolChoice::Auto { .. } => AnthropicToolChoice::Auto {
+                disable_parallel_tool_use: Some(true),
+            },
+            AnthropicToolChoice::Any { .. } => AnthropicToolChoice::Any {
+                disable_parallel_tool_use: Some(true),
+            },
+            AnthropicToolChoice::Tool { name, .. } => AnthropicToolChoice::Tool {
+                name,
+                disable_parallel_tool_use: Some(true),
+            },
+            AnthropicToolChoice::None => AnthropicToolChoice::None,
+        }
+    }
 }
 
 impl<C> AnthropicChatCompletionGeneric<C> {
@@ -167,28 +453,838 @@ impl<C> AnthropicChatCompletionGeneric<C> {
             .model(model)
             .system(String::from(system))
             .messages(messages)
-            .max_tokens(4096)
     }
 }
 
 
+impl AnthropicChatCompletionRequest {
+    /// The JSON body this request is sent with, such as to log a rejected request.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// The credentials this request will be sent with, including any per-request overrides.
+    fn request_credentials(&self) -> Option<Credentials> {
+        let credentials_opt = override_base_url(self.credentials.clone(), self.base_url.clone());
+        let credentials_opt = override_headers(credentials_opt, &self.headers);
+        if self.anthropic_version.is_none()
+            && self.anthropic_beta.is_empty()
+            && self.stream_idle_timeout.is_none()
+            && self.auth_header.is_none()
+        {
+            return credentials_opt;
+        }
+        let mut credentials = resolve_credentials(credentials_opt);
+        if let Some(version) = &self.anthropic_version {
+            credentials.anthropic_version = Some(version.clone());
+        }
+        if !self.anthropic_beta.is_empty() {
+            credentials.anthropic_beta = self.anthropic_beta.clone();
+        }
+        if let Some(timeout) = self.stream_idle_timeout {
+            credentials.stream_idle_timeout = Some(timeout);
+        }
+        if let Some(auth_header) = self.auth_header {
+            credentials.auth_header = auth_header;
+        }
+        Some(credentials)
+    }
+
+    /// Adds the estimated size of this request to a context length error.
+    fn explain_error(&self, error: OpenAiError) -> OpenAiError {
+        let estimated_tokens = estimate_message_tokens(&self.messages)
+            + self.system.as_deref().map_or(0, estimate_tokens);
+        error.with_context_length(&self.model, estimated_tokens)
+    }
+}
+
+impl AnthropicChatCompletionRequest {
+    /// Converts an OpenAI chat completion request into the equivalent Anthropic request.
+    ///
+    /// System messages are hoisted into the Anthropic `system` prompt, `stop` is sent as
+    /// `stop_sequences`, `user` as the `user_id` of the metadata, and `temperature`, `top_p`,
+    /// `max_tokens` and `stream` are kept.
+    /// Parameters Anthropic doesn't support but that don't change what the model is asked
+    /// (penalties, `logit_bias`, `seed`) are dropped, while the ones it can't honor
+    /// at all are reported as an error. The OpenAI credentials aren't carried over.
+    pub fn from_openai(
+        request: ChatCompletionRequest,
+    ) -> Result<Self, AnthropicConversionError> {
+        if request.n.is_some_and(|n| n > 1) {
+            return Err(AnthropicConversionError::MultipleChoices);
+        }
+        if !request.functions.is_empty()
+            || request.function_call.is_some()
+            || !request.tools.is_empty()
+            || request.tool_choice.is_some()
+        {
+            return Err(AnthropicConversionError::Functions);
+        }
+        if request
+            .response_format
+            .as_ref()
+            .is_some_and(|format| *format != ChatCompletionResponseFormat::text())
+        {
+            return Err(AnthropicConversionError::ResponseFormat);
+        }
+        if request.logprobs == Some(true) || request.top_logprobs.is_some() {
+            return Err(AnthropicConversionError::Logprobs);
+        }
+        if request.temperature.is_some_and(|temperature| temperature > 1.0) {
+            return Err(AnthropicConversionError::Temperature);
+        }
+
+        let mut system = Vec::new();
+        let mut messages = Vec::new();
+        for message in request.messages {
+            match message.role {
+                ChatCompletionMessageRole::System => system.extend(message.content),
+                ChatCompletionMessageRole::User | ChatCompletionMessageRole::Assistant => {
+                    messages.push(message)
+                }
+                role => return Err(AnthropicConversionError::MessageRole(role)),
+            }
+        }
+
+        let mut builder = AnthropicChatCompletionBuilder::create_empty()
+            .model(request.model)
+            .messages(messages)
+            .stop(request.stop);
+        if let Some(max_tokens) = request.max_tokens {
+            builder = builder.max_tokens(i32::try_from(max_tokens).unwrap_or(i32::MAX));
+        }
+        if !system.is_empty() {
+            builder = builder.system(system.join("\n\n"));
+        }
+        if let Some(temperature) = request.temperature {
+            builder = builder.temperature(temperature);
+        }
+        if let Some(top_p) = request.top_p {
+            builder = builder.top_p(top_p);
+        }
+        if let Some(stream) = request.stream {
+            builder = builder.stream(stream);
+        }
+        if !request.user.is_empty() {
+            builder = builder.user(request.user);
+        }
+        Ok(builder.build().expect("the required fields are set"))
+    }
+}
+
+/// Why an OpenAI request couldn't be converted into an Anthropic one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnthropicConversionError {
+    /// Anthropic generates a single completion per request, `n` can't be over 1.
+    MultipleChoices,
+    /// OpenAI function definitions don't translate to Anthropic tools.
+    Functions,
+    /// Anthropic has no JSON mode.
+    ResponseFormat,
+    /// Anthropic doesn't return log probabilities.
+    Logprobs,
+    /// Anthropic's temperature ranges from 0 to 1, and OpenAI's up to 2.
+    Temperature,
+    /// Anthropic conversations only hold user and assistant messages.
+    MessageRole(ChatCompletionMessageRole),
+}
+
+impl std::fmt::Display for AnthropicConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnthropicConversionError::MultipleChoices => {
+                f.write_str("Anthropic doesn't support generating multiple choices")
+            }
+            AnthropicConversionError::Functions => {
+                f.write_str("OpenAI functions can't be converted to Anthropic tools")
+            }
+            AnthropicConversionError::ResponseFormat => {
+                f.write_str("Anthropic doesn't support response formats")
+            }
+            AnthropicConversionError::Logprobs => {
+                f.write_str("Anthropic doesn't return log probabilities")
+            }
+            AnthropicConversionError::Temperature => {
+                f.write_str("Anthropic's temperature must be between 0 and 1")
+            }
+            AnthropicConversionError::MessageRole(role) => {
+                write!(f, "Anthropic doesn't support {role:?} messages")
+            }
+        }
+    }
+}
+
+impl std::error::Error for AnthropicConversionError {}
+
 impl AnthropicChatCompletion {
+    /// The text of all text content blocks, concatenated.
+    pub fn text(&self) -> String {
+        self.content
+            .iter()
+            .filter(|content| content.typ == "text")
+            .map(|content| content.text.as_str())
+            .collect()
+    }
+
+    /// The reasoning of all thinking content blocks, concatenated.
+    /// Redacted thinking isn't readable and is left out.
+    pub fn thinking(&self) -> String {
+        self.content
+            .iter()
+            .filter_map(|content| content.thinking.as_deref())
+            .collect()
+    }
+
+    /// The assistant message to append to the conversation to continue it, with
+    /// the text and `tool_use` blocks of this completion. Tool results are then
+    /// sent with [`ChatCompletionMessage::tool_results`].
+    pub fn as_message(&self) -> ChatCompletionMessage {
+        let content_parts = self
+            .content
+            .iter()
+            .filter_map(|content| match content.typ.as_str() {
+                "text" => Some(ChatCompletionContentPart::Text {
+                    text: content.text.clone(),
+                }),
+                "tool_use" => Some(ChatCompletionContentPart::ToolUse {
+                    id: content.id.clone().unwrap_or_default(),
+                    name: content.name.clone().unwrap_or_default(),
+                    input: content.input.clone().unwrap_or_else(|| serde_json::json!({})),
+                }),
+                _ => None,
+            })
+            .collect();
+        ChatCompletionMessage {
+            content: Some(self.text()),
+            content_parts,
+            ..ChatCompletionMessage::assistant("")
+        }
+    }
+
     /// Makes a POST request to create a new chat completion
     /// 
     /// # Arguments
     /// * `request` - The chat completion request parameters
     pub async fn create(request: AnthropicChatCompletionRequest) -> ApiResponseOrError<Self> {
-        let credentials_opt = request.credentials.clone();
-        anthropic_post("messages", &request, credentials_opt).await
+        let (completion, _) = Self::create_with_response_meta(request).await?;
+        Ok(completion)
     }
+
+    /// Same as `create`, but blocks the current thread until the response is received.
+    /// The retry policy of the credentials isn't applied.
+    ///
+    /// # Panics
+    /// Panics when called from within an async context.
+    #[cfg(feature = "blocking")]
+    pub fn create_blocking(request: AnthropicChatCompletionRequest) -> ApiResponseOrError<Self> {
+        let credentials_opt = request.request_credentials();
+        crate::blocking::anthropic_post("messages", &request, credentials_opt)
+            .map_err(|error| request.explain_error(error))
+    }
+
+    /// Same as `create`, but also returns the status and headers of the HTTP response.
+    pub async fn create_with_response_meta(
+        request: AnthropicChatCompletionRequest,
+    ) -> ApiResponseOrError<(Self, ResponseMeta)> {
+        let credentials_opt = request.request_credentials();
+        let (mut completion, meta): (Self, _) =
+            anthropic_post_with_meta("messages", &request, credentials_opt)
+                .await
+                .map_err(|error| request.explain_error(error))?;
+        completion.request_id = meta.request_id().map(str::to_string);
+        Ok((completion, meta))
+    }
+}
+
+/// An event of a streamed Anthropic message.
+///
+/// [API Reference](https://docs.anthropic.com/en/api/messages-streaming)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AnthropicStreamEvent {
+    /// The message the content blocks belong to, before any content.
+    MessageStart { message: AnthropicStreamMessage },
+    ContentBlockStart {
+        index: u32,
+        content_block: AnthropicContentBlock,
+    },
+    /// Text added to a text block.
+    TextDelta { index: u32, text: String },
+    /// A piece of the JSON input of a tool use block.
+    ToolUseDelta { index: u32, partial_json: String },
+    /// Reasoning added to a thinking block.
+    ThinkingDelta { index: u32, thinking: String },
+    /// The signature of a thinking block, sent once its thinking is complete.
+    SignatureDelta { index: u32, signature: String },
+    ContentBlockStop { index: u32 },
+    /// Changes to the message, with the cumulative output token count.
+    MessageDelta {
+        stop_reason: Option<String>,
+        stop_sequence: Option<String>,
+        usage: Option<AnthropicUsage>,
+    },
+    /// The last event of a successful stream.
+    MessageStop,
+    Ping,
+    /// An error sent by the API mid-stream, such as `overloaded_error`.
+    Error(OpenAiError),
 }
 
+/// The message of a [`AnthropicStreamEvent::MessageStart`] event.
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct AnthropicStreamMessage {
+    pub id: String,
+    pub role: String,
+    pub model: String,
+    pub usage: Option<AnthropicUsage>,
+}
+
+/// A content block as it starts streaming, before any delta.
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnthropicContentBlock {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    /// Reasoning, with extended thinking. The thinking and signature stream
+    /// in the following deltas.
+    Thinking {
+        #[serde(default)]
+        thinking: String,
+        #[serde(default)]
+        signature: String,
+    },
+    /// Reasoning flagged by the safety systems, encrypted.
+    RedactedThinking {
+        data: String,
+    },
+    /// A block type not modeled by the crate.
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RawStreamEvent {
+    MessageStart {
+        message: AnthropicStreamMessage,
+    },
+    ContentBlockStart {
+        index: u32,
+        content_block: AnthropicContentBlock,
+    },
+    ContentBlockDelta {
+        index: u32,
+        delta: RawContentDelta,
+    },
+    ContentBlockStop {
+        index: u32,
+    },
+    MessageDelta {
+        delta: RawMessageDelta,
+        usage: Option<AnthropicUsage>,
+    },
+    MessageStop,
+    Ping,
+    Error {
+        error: OpenAiError,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RawContentDelta {
+    TextDelta {
+        text: String,
+    },
+    InputJsonDelta {
+        partial_json: String,
+    },
+    ThinkingDelta {
+        thinking: String,
+    },
+    SignatureDelta {
+        signature: String,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Deserialize)]
+struct RawMessageDelta {
+    stop_reason: Option<String>,
+    stop_sequence: Option<String>,
+}
+
+impl AnthropicStreamEvent {
+    /// Parses the data of a server-sent event.
+    ///
+    /// Returns `None` for event and delta types not modeled by the crate,
+    /// which the API may add without notice.
+    pub fn from_data(data: &str) -> serde_json::Result<Option<Self>> {
+        let event = match serde_json::from_str(data)? {
+            RawStreamEvent::MessageStart { message } => AnthropicStreamEvent::MessageStart { message },
+            RawStreamEvent::ContentBlockStart {
+                index,
+                content_block,
+            } => AnthropicStreamEvent::ContentBlockStart {
+                index,
+                content_block,
+            },
+            RawStreamEvent::ContentBlockDelta { index, delta } => match delta {
+                RawContentDelta::TextDelta { text } => AnthropicStreamEvent::TextDelta { index, text },
+                RawContentDelta::InputJsonDelta { partial_json } => {
+                    AnthropicStreamEvent::ToolUseDelta {
+                        index,
+                        partial_json,
+                    }
+                }
+                RawContentDelta::ThinkingDelta { thinking } => {
+                    AnthropicStreamEvent::ThinkingDelta { index, thinking }
+                }
+                RawContentDelta::SignatureDelta { signature } => {
+                    AnthropicStreamEvent::SignatureDelta { index, signature }
+                }
+                RawContentDelta::Unknown => return Ok(None),
+            },
+            RawStreamEvent::ContentBlockStop { index } => {
+                AnthropicStreamEvent::ContentBlockStop { index }
+            }
+            RawStreamEvent::MessageDelta { delta, usage } => AnthropicStreamEvent::MessageDelta {
+                stop_reason: delta.stop_reason,
+                stop_sequence: delta.stop_sequence,
+                usage,
+            },
+            RawStreamEvent::MessageStop => AnthropicStreamEvent::MessageStop,
+            RawStreamEvent::Ping => AnthropicStreamEvent::Ping,
+            RawStreamEvent::Error { error } => AnthropicStreamEvent::Error(error),
+            RawStreamEvent::Unknown => return Ok(None),
+        };
+        Ok(Some(event))
+    }
+
+    /// Sends the streaming request, returning the stream of its events.
+    async fn open(
+        mut request: AnthropicChatCompletionRequest,
+    ) -> Result<(EventSource, StreamLimits), CannotCloneRequestError> {
+        request.stream = Some(true);
+        let credentials = resolve_credentials(request.request_credentials());
+        let limits = credentials.stream_limits();
+        let stream = anthropic_request_stream(
+            Method::POST,
+            "messages",
+            |r| r.json(&request),
+            Some(credentials),
+        )
+        .await?;
+        Ok((stream, limits))
+    }
+
+    /// Streams the message, event by event.
+    ///
+    /// The stream ends after [`AnthropicStreamEvent::MessageStop`] or
+    /// [`AnthropicStreamEvent::Error`]. A failed connection or an unparsable
+    /// event is received as an `Err`, also ending the stream.
+    #[cfg(feature = "tokio-runtime")]
+    pub async fn create(
+        request: AnthropicChatCompletionRequest,
+    ) -> Result<Receiver<ApiResponseOrError<Self>>, CannotCloneRequestError> {
+        let (stream, limits) = Self::open(request).await?;
+        let (tx, rx) = channel(32);
+        tokio::spawn(forward_anthropic_stream(stream, tx.into(), limits));
+        Ok(rx)
+    }
+}
+
+async fn forward_anthropic_stream(
+    mut stream: EventSource,
+    mut tx: StreamSender<ApiResponseOrError<AnthropicStreamEvent>>,
+    mut limits: StreamLimits,
+) {
+    loop {
+        let event = tx.next_event(&mut stream, &mut limits).await;
+        let event = match event {
+            None => break,
+            Some(Ok(Event::Open)) => continue,
+            Some(Ok(Event::Message(message))) => {
+                match AnthropicStreamEvent::from_data(&message.data) {
+                    Ok(Some(event)) => Ok(event),
+                    Ok(None) => continue,
+                    Err(error) => Err(error.into()),
+                }
+            }
+            Some(Err(error)) => Err(error),
+        };
+        let last = matches!(
+            event,
+            Err(_) | Ok(AnthropicStreamEvent::MessageStop | AnthropicStreamEvent::Error(_))
+        );
+        if tx.send(event).await.is_err() || last {
+            break;
+        }
+    }
+    stream.close();
+}
+
+/// Collects the events of a streamed message into the complete message.
+///
+/// ```no_run
+/// # use openai::anthrophic_chat::{AnthropicMessageAccumulator, AnthropicStreamEvent};
+/// # async fn example(
+/// #     mut rx: tokio::sync::mpsc::Receiver<openai::ApiResponseOrError<AnthropicStreamEvent>>,
+/// # ) -> openai::ApiResponseOrError<()> {
+/// let mut accumulator = AnthropicMessageAccumulator::default();
+/// while let Some(event) = rx.recv().await {
+///     let event = event?;
+///     if let AnthropicStreamEvent::TextDelta { text, .. } = &event {
+///         print!("{text}");
+///     }
+///     accumulator.push(event)?;
+/// }
+/// let message = accumulator.finish()?;
+/// println!("\n{:?}", message.usage);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct AnthropicMessageAccumulator {
+    message: Option<AnthropicStreamMessage>,
+    content: Vec<(u32, AnthropicChatCompletionContent)>,
+    /// The partial JSON input of the tool use blocks, by block index.
+    tool_inputs: HashMap<u32, String>,
+    stop_reason: Option<String>,
+    stop_sequence: Option<String>,
+    usage: Option<AnthropicUsage>,
+}
+
+impl AnthropicMessageAccumulator {
+    /// Adds an event to the message. An [`AnthropicStreamEvent::Error`] is
+    /// returned as an error.
+    pub fn push(&mut self, event: AnthropicStreamEvent) -> ApiResponseOrError<()> {
+        match event {
+            AnthropicStreamEvent::MessageStart { message } => {
+                self.usage = message.usage;
+                self.message = Some(message);
+            }
+            AnthropicStreamEvent::ContentBlockStart {
+                index,
+                content_block,
+            } => {
+                let content = match content_block {
+                    AnthropicContentBlock::Text { text } => AnthropicChatCompletionContent {
+                        typ: "text".to_string(),
+                        text,
+                        ..Default::default()
+                    },
+                    AnthropicContentBlock::ToolUse { id, name, input } => {
+                        AnthropicChatCompletionContent {
+                            typ: "tool_use".to_string(),
+                            id: Some(id),
+                            name: Some(name),
+                            input: Some(input),
+                            ..Default::default()
+                        }
+                    }
+                    AnthropicContentBlock::Thinking {
+                        thinking,
+                        signature,
+                    } => AnthropicChatCompletionContent {
+                        typ: "thinking".to_string(),
+                        thinking: Some(thinking),
+                        signature: Some(signature),
+                        ..Default::default()
+                    },
+                    AnthropicContentBlock::RedactedThinking { data } => {
+                        AnthropicChatCompletionContent {
+                            typ: "redacted_thinking".to_string(),
+                            data: Some(data),
+                            ..Default::default()
+                        }
+                    }
+                    AnthropicContentBlock::Other => return Ok(()),
+                };
+                self.content.push((index, content));
+            }
+            AnthropicStreamEvent::TextDelta { index, text } => {
+                if let Some(content) = self.block(index) {
+                    content.text.push_str(&text);
+                }
+            }
+            AnthropicStreamEvent::ToolUseDelta {
+                index,
+                partial_json,
+            } => {
+                self.tool_inputs
+                    .entry(index)
+                    .or_default()
+                    .push_str(&partial_json);
+            }
+            AnthropicStreamEvent::ThinkingDelta { index, thinking } => {
+                if let Some(content) = self.block(index) {
+                    content
+                        .thinking
+                        .get_or_insert_with(String::new)
+                        .push_str(&thinking);
+                }
+            }
+            AnthropicStreamEvent::SignatureDelta { index, signature } => {
+                if let Some(content) = self.block(index) {
+                    content
+                        .signature
+                        .get_or_insert_with(String::new)
+                        .push_str(&signature);
+                }
+            }
+            AnthropicStreamEvent::ContentBlockStop { index } => self.finish_tool_input(index)?,
+            AnthropicStreamEvent::MessageDelta {
+                stop_reason,
+                stop_sequence,
+                usage,
+            } => {
+                self.stop_reason = stop_reason.or(self.stop_reason.take());
+                self.stop_sequence = stop_sequence.or(self.stop_sequence.take());
+                if let Some(delta) = usage {
+                    let usage = self.usage.get_or_insert_with(Default::default);
+                    // The output tokens are cumulative, the other counts are
+                    // only sent when they changed since the message started.
+                    usage.output_tokens = delta.output_tokens;
+                    for (count, delta) in [
+                        (&mut usage.input_tokens, delta.input_tokens),
+                        (
+                            &mut usage.cache_creation_input_tokens,
+                            delta.cache_creation_input_tokens,
+                        ),
+                        (
+                            &mut usage.cache_read_input_tokens,
+                            delta.cache_read_input_tokens,
+                        ),
+                    ] {
+                        if delta > 0 {
+                            *count = delta;
+                        }
+                    }
+                }
+            }
+            AnthropicStreamEvent::MessageStop | AnthropicStreamEvent::Ping => {}
+            AnthropicStreamEvent::Error(error) => return Err(error),
+        }
+        Ok(())
+    }
+
+    /// The complete message, or an error when the stream ended before the
+    /// message started.
+    pub fn finish(mut self) -> ApiResponseOrError<AnthropicChatCompletion> {
+        let indices: Vec<u32> = self.tool_inputs.keys().copied().collect();
+        for index in indices {
+            self.finish_tool_input(index)?;
+        }
+        let message = self.message.ok_or_else(|| {
+            OpenAiError::new(
+                "the stream ended before the message started".to_string(),
+                "stream".to_string(),
+            )
+        })?;
+        Ok(AnthropicChatCompletion {
+            id: message.id,
+            typ: "message".to_string(),
+            role: message.role,
+            model: message.model,
+            content: self.content.into_iter().map(|(_, content)| content).collect(),
+            stop_reason: self.stop_reason.unwrap_or_default(),
+            stop_sequence: self.stop_sequence,
+            usage: self.usage,
+            container: None,
+            request_id: None,
+        })
+    }
+
+    fn block(&mut self, index: u32) -> Option<&mut AnthropicChatCompletionContent> {
+        self.content
+            .iter_mut()
+            .find(|(block_index, _)| *block_index == index)
+            .map(|(_, content)| content)
+    }
+
+    /// Parses the JSON input of a tool use block once all of it was received.
+    fn finish_tool_input(&mut self, index: u32) -> ApiResponseOrError<()> {
+        let Some(partial_json) = self.tool_inputs.remove(&index) else {
+            return Ok(());
+        };
+        if let Some(content) = self.block(index) {
+            if !partial_json.is_empty() {
+                content.input = Some(serde_json::from_str(&partial_json)?);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AnthropicChatCompletion {
+    /// Receives the events of a stream, see [`AnthropicStreamEvent::create`],
+    /// until it ends and returns the complete message.
+    #[cfg(feature = "tokio-runtime")]
+    pub async fn from_events(
+        mut rx: Receiver<ApiResponseOrError<AnthropicStreamEvent>>,
+    ) -> ApiResponseOrError<Self> {
+        let mut accumulator = AnthropicMessageAccumulator::default();
+        while let Some(event) = rx.recv().await {
+            accumulator.push(event?)?;
+        }
+        accumulator.finish()
+    }
+}
+
+/// The max tokens of models whose maximum output isn't known.
+const DEFAULT_MAX_TOKENS: i32 = 4096;
+
+/// The beta raising the output limit of Claude 3.7 Sonnet to 128k tokens.
+const OUTPUT_128K_BETA: &str = "output-128k-2025-02-19";
+
 impl AnthropicChatCompletionBuilder {
+    /// Builds the request, defaulting its max tokens to the most the model can generate.
+    pub fn build(
+        mut self,
+    ) -> Result<AnthropicChatCompletionRequest, AnthropicChatCompletionBuilderError> {
+        if self.max_tokens.is_none() {
+            self.max_tokens = Some(Some(self.default_max_tokens()));
+        }
+        self.build_request()
+    }
+
+    /// The most tokens the model can generate, with the betas of the request
+    /// and its credentials, sent when no max tokens are given.
+    fn default_max_tokens(&self) -> i32 {
+        let model = self.model.as_deref().unwrap_or_default();
+        let mut betas = self.anthropic_beta.iter().flatten().chain(
+            self.credentials
+                .iter()
+                .flatten()
+                .flat_map(|credentials| credentials.anthropic_beta()),
+        );
+        if model.starts_with("claude-3-7-sonnet") && betas.any(|beta| beta == OUTPUT_128K_BETA) {
+            return 128_000;
+        }
+        models::max_output_tokens(model)
+            .and_then(|max_tokens| i32::try_from(max_tokens).ok())
+            .unwrap_or(DEFAULT_MAX_TOKENS)
+    }
+}
+
+impl AnthropicChatCompletionBuilder {
+    /// Anthropic's temperature ranges from 0 to 1, unlike OpenAI's.
+    fn validate(&self) -> Result<(), String> {
+        validate_range("temperature", self.temperature.flatten(), 1.0)?;
+        validate_range("top_p", self.top_p.flatten(), 1.0)?;
+        if let Some(n) = self.n.flatten().filter(|&n| n > 1) {
+            return Err(format!("Anthropic generates a single choice, got n = {n}"));
+        }
+        let documents = self
+            .messages
+            .iter()
+            .flatten()
+            .flat_map(|message| &message.content_parts)
+            .filter_map(|part| match part {
+                ChatCompletionContentPart::Document { source } => Some(source),
+                _ => None,
+            });
+        for document in documents {
+            if document.media_type != "application/pdf" {
+                return Err(format!(
+                    "unsupported document media type {:?}, expected \"application/pdf\"",
+                    document.media_type
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds the request and streams the message, event by event.
+    /// See [`AnthropicStreamEvent::create`].
+    #[cfg(feature = "tokio-runtime")]
+    pub async fn create_stream(
+        self,
+    ) -> ApiResponseOrError<Receiver<ApiResponseOrError<AnthropicStreamEvent>>> {
+        Ok(AnthropicStreamEvent::create(self.build()?).await?)
+    }
+
+    /// Same as `create_stream`, as a [`Stream`](futures_util::Stream) of events.
+    /// Also available without the `tokio-runtime` feature.
+    pub async fn create_response_stream(
+        self,
+    ) -> ApiResponseOrError<ResponseStream<AnthropicStreamEvent>> {
+        #[cfg(feature = "tokio-runtime")]
+        {
+            Ok(self.create_stream().await?.into())
+        }
+        #[cfg(not(feature = "tokio-runtime"))]
+        {
+            let (stream, limits) = AnthropicStreamEvent::open(self.build()?).await?;
+            Ok(ResponseStream::forwarded(|tx| forward_anthropic_stream(stream, tx, limits)))
+        }
+    }
+
     /// Builds and executes the chat completion request
     pub async fn create(self) -> ApiResponseOrError<AnthropicChatCompletion> {
-        let resp = AnthropicChatCompletion::create(self.build().unwrap()).await;
+        let resp = AnthropicChatCompletion::create(self.build()?).await;
         resp
     }
+
+    /// Builds and executes the chat completion request, blocking the current
+    /// thread until the response is received.
+    /// See [`AnthropicChatCompletion::create_blocking`].
+    #[cfg(feature = "blocking")]
+    pub fn create_blocking(self) -> ApiResponseOrError<AnthropicChatCompletion> {
+        AnthropicChatCompletion::create_blocking(self.build()?)
+    }
+
+    /// Builds and executes the chat completion request, also returning the
+    /// status and headers of the HTTP response.
+    pub async fn create_with_response_meta(
+        self,
+    ) -> ApiResponseOrError<(AnthropicChatCompletion, ResponseMeta)> {
+        AnthropicChatCompletion::create_with_response_meta(self.build()?).await
+    }
+
+    /// Sends an extra header with this request, such as a trace ID.
+    pub fn add_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers
+            .get_or_insert_with(HeaderMap::new)
+            .insert(name, value);
+        self
+    }
+
+    /// Appends a message to the conversation.
+    ///
+    /// Messages are added after any set with `messages`; note that calling
+    /// `messages` afterwards replaces the whole conversation.
+    pub fn add_message(mut self, message: ChatCompletionMessage) -> Self {
+        self.messages.get_or_insert_with(Vec::new).push(message);
+        self
+    }
+
+    /// Identifies the end user of the request, sent as the `user_id` of the
+    /// metadata. See [`AnthropicMetadata::user_id`].
+    pub fn user(self, user_id: impl Into<String>) -> Self {
+        self.metadata(AnthropicMetadata {
+            user_id: Some(user_id.into()),
+        })
+    }
+
+    /// Appends a user message to the conversation.
+    pub fn add_user(self, content: impl Into<String>) -> Self {
+        self.add_message(ChatCompletionMessage::user(content))
+    }
+
+    /// Appends an assistant message to the conversation.
+    pub fn add_assistant(self, content: impl Into<String>) -> Self {
+        self.add_message(ChatCompletionMessage::assistant(content))
+    }
 }
 
 fn default_tool_calls_deserialization() -> Vec<ToolCall> {
@@ -215,6 +1311,8 @@ mod tests {
                 function_call: None,
                 tool_call_id: None,
                 tool_calls: Vec::new(),
+                annotations: Vec::new(),
+                content_parts: Vec::new(),
             }]
         )
         .credentials(credentials)
@@ -234,4 +1332,809 @@ mod tests {
             "Hi there! How can I help you today?"
         );
     }
+
+    #[test]
+    fn base_url_override() {
+        let credentials = Credentials::new("sk-ant-test", "https://api.anthropic.com/v1/");
+        let request = AnthropicChatCompletion::builder("claude-3-5-sonnet-20241022", "", [])
+            .credentials(credentials)
+            .base_url("https://gateway.example.com/anthropic/v1")
+            .build()
+            .unwrap();
+        let request_credentials = request.request_credentials().unwrap();
+        assert_eq!(
+            request_credentials.base_url(),
+            "https://gateway.example.com/anthropic/v1/"
+        );
+        assert_eq!(request_credentials.api_key(), "sk-ant-test");
+        assert_eq!(
+            request_credentials.provider(),
+            &crate::ApiProvider::Anthropic
+        );
+    }
+
+    #[tokio::test]
+    async fn anthropic_response_meta() {
+        let credentials = crate::tests::mock_server(crate::tests::json_response(
+            "request-id: req_123\r\n",
+            r#"{
+                "id": "msg_1",
+                "type": "message",
+                "role": "assistant",
+                "model": "claude-3-5-sonnet-20241022",
+                "content": [{"type": "text", "text": "Hi!"}],
+                "stop_reason": "end_turn",
+                "stop_sequence": null
+            }"#,
+        ))
+        .await;
+
+        let (chat_completion, meta) =
+            AnthropicChatCompletion::builder("claude-3-5-sonnet-20241022", "", [])
+                .add_user("Hello!")
+                .credentials(credentials)
+                .create_with_response_meta()
+                .await
+                .unwrap();
+
+        assert_eq!(chat_completion.content[0].text, "Hi!");
+        assert_eq!(meta.status, reqwest::StatusCode::OK);
+        assert_eq!(meta.headers["request-id"], "req_123");
+        assert_eq!(meta.request_id(), Some("req_123"));
+        assert_eq!(chat_completion.request_id.as_deref(), Some("req_123"));
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn create_blocking() {
+        // The mock server needs a runtime of its own, apart from the blocking call.
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mock_credentials = runtime.block_on(crate::tests::mock_server(
+            crate::tests::json_response(
+                "",
+                r#"{
+                    "id": "msg_1",
+                    "type": "message",
+                    "role": "assistant",
+                    "model": "claude-3-5-sonnet-20241022",
+                    "content": [{"type": "text", "text": "Hi!"}],
+                    "stop_reason": "end_turn",
+                    "stop_sequence": null
+                }"#,
+            ),
+        ));
+        let credentials = Credentials::new("sk-ant-test", "https://api.anthropic.com/v1/")
+            .with_base_url(mock_credentials.base_url());
+
+        let chat_completion = AnthropicChatCompletion::builder("claude-3-5-sonnet-20241022", "", [])
+            .add_user("Hello!")
+            .credentials(credentials)
+            .create_blocking()
+            .unwrap();
+        assert_eq!(chat_completion.text(), "Hi!");
+    }
+
+    #[test]
+    fn anthropic_headers_override() {
+        let credentials = Credentials::new("sk-ant-test", "https://api.anthropic.com/v1/")
+            .with_anthropic_version("2023-06-01")
+            .with_anthropic_beta("prompt-caching-2024-07-31");
+        let request = AnthropicChatCompletion::builder("claude-3-5-sonnet-20241022", "", [])
+            .credentials(credentials)
+            .anthropic_version("2024-10-22")
+            .anthropic_beta(vec!["pdfs-2024-09-25".to_string()])
+            .build()
+            .unwrap();
+
+        let request_credentials = request.request_credentials().unwrap();
+        let http_request = crate::anthropic_request_builder(
+            reqwest::Method::POST,
+            "messages",
+            |r| r.json(&request),
+            &request_credentials,
+        )
+        .build()
+        .unwrap();
+        assert_eq!(http_request.headers()["anthropic-version"], "2024-10-22");
+        assert_eq!(http_request.headers()["anthropic-beta"], "pdfs-2024-09-25");
+        // Only the overrides change, the credentials given to the builder don't.
+        assert_eq!(
+            request.credentials.unwrap().anthropic_beta(),
+            ["prompt-caching-2024-07-31".to_string()]
+        );
+    }
+
+    #[test]
+    fn stream_events() {
+        let parse = |data: serde_json::Value| {
+            AnthropicStreamEvent::from_data(&data.to_string())
+                .unwrap()
+                .unwrap()
+        };
+        assert_eq!(
+            parse(serde_json::json!({
+                "type": "message_start",
+                "message": {
+                    "id": "msg_1", "type": "message", "role": "assistant", "content": [],
+                    "model": "claude-3-5-sonnet-20241022", "stop_reason": null,
+                    "stop_sequence": null, "usage": {"input_tokens": 25, "output_tokens": 1}
+                }
+            })),
+            AnthropicStreamEvent::MessageStart {
+                message: AnthropicStreamMessage {
+                    id: "msg_1".to_string(),
+                    role: "assistant".to_string(),
+                    model: "claude-3-5-sonnet-20241022".to_string(),
+                    usage: Some(AnthropicUsage {
+                        input_tokens: 25,
+                        output_tokens: 1,
+                        ..Default::default()
+                    }),
+                },
+            }
+        );
+        assert_eq!(
+            parse(serde_json::json!({
+                "type": "content_block_start",
+                "index": 1,
+                "content_block": {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {}}
+            })),
+            AnthropicStreamEvent::ContentBlockStart {
+                index: 1,
+                content_block: AnthropicContentBlock::ToolUse {
+                    id: "toolu_1".to_string(),
+                    name: "get_weather".to_string(),
+                    input: serde_json::json!({}),
+                },
+            }
+        );
+        assert_eq!(
+            parse(serde_json::json!({
+                "type": "content_block_delta",
+                "index": 0,
+                "delta": {"type": "text_delta", "text": "Hello"}
+            })),
+            AnthropicStreamEvent::TextDelta {
+                index: 0,
+                text: "Hello".to_string(),
+            }
+        );
+        assert_eq!(
+            parse(serde_json::json!({
+                "type": "content_block_delta",
+                "index": 1,
+                "delta": {"type": "input_json_delta", "partial_json": "{\"location\": "}
+            })),
+            AnthropicStreamEvent::ToolUseDelta {
+                index: 1,
+                partial_json: "{\"location\": ".to_string(),
+            }
+        );
+        assert_eq!(
+            parse(serde_json::json!({"type": "content_block_stop", "index": 1})),
+            AnthropicStreamEvent::ContentBlockStop { index: 1 }
+        );
+        assert_eq!(
+            parse(serde_json::json!({
+                "type": "message_delta",
+                "delta": {"stop_reason": "tool_use", "stop_sequence": null},
+                "usage": {"output_tokens": 15}
+            })),
+            AnthropicStreamEvent::MessageDelta {
+                stop_reason: Some("tool_use".to_string()),
+                stop_sequence: None,
+                usage: Some(AnthropicUsage {
+                    output_tokens: 15,
+                    ..Default::default()
+                }),
+            }
+        );
+        assert_eq!(
+            parse(serde_json::json!({"type": "message_stop"})),
+            AnthropicStreamEvent::MessageStop
+        );
+        assert_eq!(
+            parse(serde_json::json!({"type": "ping"})),
+            AnthropicStreamEvent::Ping
+        );
+        let AnthropicStreamEvent::Error(error) = parse(serde_json::json!({
+            "type": "error",
+            "error": {"type": "overloaded_error", "message": "Overloaded"}
+        })) else {
+            panic!("expected an error event");
+        };
+        assert_eq!(error.error_type, "overloaded_error");
+        assert_eq!(error.message, "Overloaded");
+
+        assert_eq!(
+            parse(serde_json::json!({
+                "type": "content_block_delta",
+                "index": 0,
+                "delta": {"type": "thinking_delta", "thinking": "Hmm"}
+            })),
+            AnthropicStreamEvent::ThinkingDelta {
+                index: 0,
+                thinking: "Hmm".to_string()
+            }
+        );
+
+        // Types the crate doesn't model are skipped.
+        for data in [
+            r#"{"type": "content_block_delta", "index": 0, "delta": {"type": "future_delta"}}"#,
+            r#"{"type": "some_future_event"}"#,
+        ] {
+            assert_eq!(AnthropicStreamEvent::from_data(data).unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn extended_thinking() {
+        let request = AnthropicChatCompletion::builder("claude-3-7-sonnet-20250219", "", [])
+            .thinking(AnthropicThinking::enabled(2048))
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&request).unwrap()["thinking"],
+            serde_json::json!({"type": "enabled", "budget_tokens": 2048})
+        );
+
+        let completion: AnthropicChatCompletion = serde_json::from_value(serde_json::json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-3-7-sonnet-20250219",
+            "content": [
+                {"type": "thinking", "thinking": "Crabs have ten legs.", "signature": "EqQBCgIYAhIM"},
+                {"type": "redacted_thinking", "data": "EmwKAhgBEgy3va3pzix"},
+                {"type": "text", "text": "Ten."}
+            ],
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": {"input_tokens": 10, "output_tokens": 30}
+        }))
+        .unwrap();
+        assert_eq!(completion.text(), "Ten.");
+        assert_eq!(completion.thinking(), "Crabs have ten legs.");
+        assert_eq!(completion.content[0].signature.as_deref(), Some("EqQBCgIYAhIM"));
+        assert_eq!(completion.content[1].typ, "redacted_thinking");
+        assert_eq!(completion.content[1].data.as_deref(), Some("EmwKAhgBEgy3va3pzix"));
+
+        let event = AnthropicStreamEvent::from_data(
+            r#"{"type": "content_block_start", "index": 0, "content_block": {"type": "thinking", "thinking": ""}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            event,
+            Some(AnthropicStreamEvent::ContentBlockStart {
+                index: 0,
+                content_block: AnthropicContentBlock::Thinking {
+                    thinking: String::new(),
+                    signature: String::new(),
+                },
+            })
+        );
+        let event = AnthropicStreamEvent::from_data(
+            r#"{"type": "content_block_delta", "index": 0, "delta": {"type": "signature_delta", "signature": "EqQBCgIYAhIM"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            event,
+            Some(AnthropicStreamEvent::SignatureDelta {
+                index: 0,
+                signature: "EqQBCgIYAhIM".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn max_tokens_defaults_by_model() {
+        let max_tokens = |builder: AnthropicChatCompletionBuilder| {
+            serde_json::to_value(builder.build().unwrap()).unwrap()["max_tokens"].clone()
+        };
+        let sonnet = AnthropicChatCompletion::builder("claude-3-5-sonnet-20241022", "", []);
+        assert_eq!(max_tokens(sonnet.clone()), 8192);
+        assert_eq!(max_tokens(sonnet.max_tokens(100)), 100);
+        let opus = AnthropicChatCompletion::builder("claude-3-opus-20240229", "", []);
+        assert_eq!(max_tokens(opus), 4096);
+        let unknown = AnthropicChatCompletion::builder("claude-next", "", []);
+        assert_eq!(max_tokens(unknown), DEFAULT_MAX_TOKENS);
+
+        // The default follows the model it is built with, and the 128k output beta.
+        let sonnet = AnthropicChatCompletion::builder("claude-3-5-sonnet-20241022", "", [])
+            .model("claude-3-7-sonnet-20250219");
+        assert_eq!(max_tokens(sonnet.clone()), 64_000);
+        let credentials =
+            Credentials::anthropic("sk-ant-test").with_anthropic_beta(OUTPUT_128K_BETA);
+        assert_eq!(max_tokens(sonnet.clone().credentials(credentials)), 128_000);
+        assert_eq!(
+            max_tokens(sonnet.anthropic_beta(vec![OUTPUT_128K_BETA.to_string()]).max_tokens(1000)),
+            1000
+        );
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn missing_required_field() {
+        let error = AnthropicChatCompletionBuilder::default()
+            .model("claude-3-5-sonnet-20241022")
+            .system("You are a helpful assistant.")
+            .create_stream()
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_type, "builder");
+        assert_eq!(error.param.as_deref(), Some("messages"));
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn create_stream() {
+        let response = "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\n\r\n\
+            event: content_block_start\ndata: {\"type\": \"content_block_start\", \"index\": 0, \
+            \"content_block\": {\"type\": \"text\", \"text\": \"\"}}\n\n\
+            event: content_block_delta\ndata: {\"type\": \"content_block_delta\", \"index\": 0, \
+            \"delta\": {\"type\": \"text_delta\", \"text\": \"Hi!\"}}\n\n\
+            event: message_stop\ndata: {\"type\": \"message_stop\"}\n\n";
+        let (mock_credentials, _closed) = crate::tests::mock_stream_server(response).await;
+        let credentials = Credentials::new("sk-ant-test", "https://api.anthropic.com/v1/")
+            .with_base_url(mock_credentials.base_url());
+
+        let mut rx = AnthropicChatCompletion::builder("claude-3-5-sonnet-20241022", "", [])
+            .add_user("Hello!")
+            .credentials(credentials)
+            .create_stream()
+            .await
+            .unwrap();
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(event.unwrap());
+        }
+        assert_eq!(
+            events,
+            [
+                AnthropicStreamEvent::ContentBlockStart {
+                    index: 0,
+                    content_block: AnthropicContentBlock::Text {
+                        text: String::new()
+                    },
+                },
+                AnthropicStreamEvent::TextDelta {
+                    index: 0,
+                    text: "Hi!".to_string(),
+                },
+                AnthropicStreamEvent::MessageStop,
+            ]
+        );
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn accumulate_stream() {
+        let events = [
+            r#"{"type": "message_start", "message": {"id": "msg_1", "type": "message", "role": "assistant", "model": "claude-3-5-sonnet-20241022", "content": [], "stop_reason": null, "stop_sequence": null, "usage": {"input_tokens": 25, "output_tokens": 1}}}"#,
+            r#"{"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}}"#,
+            r#"{"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "Let me "}}"#,
+            r#"{"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "check."}}"#,
+            r#"{"type": "content_block_stop", "index": 0}"#,
+            r#"{"type": "content_block_start", "index": 1, "content_block": {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {}}}"#,
+            r#"{"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "{\"city\": "}}"#,
+            r#"{"type": "content_block_delta", "index": 1, "delta": {"type": "input_json_delta", "partial_json": "\"Paris\"}"}}"#,
+            r#"{"type": "content_block_stop", "index": 1}"#,
+            r#"{"type": "message_delta", "delta": {"stop_reason": "tool_use", "stop_sequence": null}, "usage": {"output_tokens": 42}}"#,
+            r#"{"type": "message_stop"}"#,
+        ];
+        let (tx, rx) = channel(events.len());
+        for data in events {
+            let event = AnthropicStreamEvent::from_data(data).unwrap().unwrap();
+            tx.send(Ok(event)).await.unwrap();
+        }
+        drop(tx);
+
+        let message = AnthropicChatCompletion::from_events(rx).await.unwrap();
+        assert_eq!(message.id, "msg_1");
+        assert_eq!(message.text(), "Let me check.");
+        assert_eq!(message.content[1].typ, "tool_use");
+        assert_eq!(message.content[1].name.as_deref(), Some("get_weather"));
+        assert_eq!(
+            message.content[1].input,
+            Some(serde_json::json!({"city": "Paris"}))
+        );
+        assert_eq!(message.stop_reason, "tool_use");
+        let usage = message.usage.unwrap();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (25, 42));
+
+        let mut accumulator = AnthropicMessageAccumulator::default();
+        let error = accumulator
+            .push(AnthropicStreamEvent::Error(OpenAiError::new(
+                "Overloaded".to_string(),
+                "overloaded_error".to_string(),
+            )))
+            .unwrap_err();
+        assert_eq!(error.error_type, "overloaded_error");
+        assert_eq!(accumulator.finish().unwrap_err().error_type, "stream");
+    }
+
+    #[test]
+    fn container_metadata() {
+        let completion: AnthropicChatCompletion = serde_json::from_str(
+            r#"{
+                "id": "msg_1",
+                "type": "message",
+                "role": "assistant",
+                "model": "claude-3-5-sonnet-20241022",
+                "content": [{"type": "text", "text": "Done."}],
+                "stop_reason": "end_turn",
+                "stop_sequence": null,
+                "container": {"id": "container_1", "expires_at": "2025-06-01T12:00:00Z"}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            completion.container,
+            Some(AnthropicContainer {
+                id: "container_1".to_string(),
+                expires_at: "2025-06-01T12:00:00Z".to_string(),
+            })
+        );
+
+        let request = AnthropicChatCompletion::builder("claude-3-5-sonnet-20241022", "", [])
+            .container(completion.container.unwrap().id)
+            .build()
+            .unwrap();
+        assert_eq!(serde_json::to_value(&request).unwrap()["container"], "container_1");
+    }
+
+    #[test]
+    fn tool_choice_serialization() {
+        let cases = [
+            (AnthropicToolChoice::auto(), serde_json::json!({"type": "auto"})),
+            (AnthropicToolChoice::any(), serde_json::json!({"type": "any"})),
+            (
+                AnthropicToolChoice::tool("get_weather"),
+                serde_json::json!({"type": "tool", "name": "get_weather"}),
+            ),
+            (AnthropicToolChoice::None, serde_json::json!({"type": "none"})),
+            (
+                AnthropicToolChoice::auto().disable_parallel_tool_use(),
+                serde_json::json!({"type": "auto", "disable_parallel_tool_use": true}),
+            ),
+            (
+                AnthropicToolChoice::any().disable_parallel_tool_use(),
+                serde_json::json!({"type": "any", "disable_parallel_tool_use": true}),
+            ),
+            (
+                AnthropicToolChoice::tool("get_weather").disable_parallel_tool_use(),
+                serde_json::json!({
+                    "type": "tool",
+                    "name": "get_weather",
+                    "disable_parallel_tool_use": true
+                }),
+            ),
+            (
+                AnthropicToolChoice::None.disable_parallel_tool_use(),
+                serde_json::json!({"type": "none"}),
+            ),
+        ];
+        for (tool_choice, expected) in cases {
+            assert_eq!(serde_json::to_value(&tool_choice).unwrap(), expected);
+        }
+
+        let request = AnthropicChatCompletion::builder("claude-3-5-sonnet-20241022", "", [])
+            .tool_choice(AnthropicToolChoice::any())
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&request).unwrap()["tool_choice"],
+            serde_json::json!({"type": "any"})
+        );
+    }
+
+    #[test]
+    fn from_openai() {
+        let openai_request = crate::chat::ChatCompletion::builder(
+            "claude-3-5-sonnet-20241022",
+            [ChatCompletionMessage {
+                role: ChatCompletionMessageRole::System,
+                content: Some("Be concise.".to_string()),
+                ..Default::default()
+            }],
+        )
+        .add_user("Hello!")
+        .temperature(0.5)
+        .top_p(0.25)
+        .max_tokens(100u64)
+        .stop(vec!["END".to_string()])
+        .presence_penalty(1.0)
+        .seed(42u64)
+        .user("5f3a9c")
+        .build()
+        .unwrap();
+
+        let request = AnthropicChatCompletionRequest::from_openai(openai_request).unwrap();
+
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "model": "claude-3-5-sonnet-20241022",
+                "system": "Be concise.",
+                "messages": [{"role": "user", "content": "Hello!"}],
+                "temperature": 0.5,
+                "top_p": 0.25,
+                "stop_sequences": ["END"],
+                "max_tokens": 100,
+                "metadata": {"user_id": "5f3a9c"}
+            })
+        );
+    }
+
+    #[test]
+    fn from_openai_unsupported() {
+        let openai_request = crate::chat::ChatCompletion::builder("gpt-4o", [])
+            .add_user("Hello!")
+            .n(2)
+            .build()
+            .unwrap();
+        assert_eq!(
+            AnthropicChatCompletionRequest::from_openai(openai_request).unwrap_err(),
+            AnthropicConversionError::MultipleChoices
+        );
+
+        let openai_request = crate::chat::ChatCompletion::builder(
+            "gpt-4o",
+            [ChatCompletionMessage {
+                role: ChatCompletionMessageRole::Tool,
+                content: Some("42".to_string()),
+                tool_call_id: Some("call_1".to_string()),
+                ..Default::default()
+            }],
+        )
+        .build()
+        .unwrap();
+        assert_eq!(
+            AnthropicChatCompletionRequest::from_openai(openai_request).unwrap_err(),
+            AnthropicConversionError::MessageRole(ChatCompletionMessageRole::Tool)
+        );
+
+        let openai_request = crate::chat::ChatCompletion::builder("gpt-4o", [])
+            .add_user("Hello!")
+            .temperature(1.5)
+            .build()
+            .unwrap();
+        assert_eq!(
+            AnthropicChatCompletionRequest::from_openai(openai_request).unwrap_err(),
+            AnthropicConversionError::Temperature
+        );
+    }
+
+    #[test]
+    fn sampling_ranges() {
+        let build = |temperature: f32, top_p: f32| {
+            AnthropicChatCompletion::builder("claude-3-5-sonnet-20241022", "", [])
+                .add_user("Hello!")
+                .temperature(temperature)
+                .top_p(top_p)
+                .build()
+        };
+        assert!(build(0.0, 0.0).is_ok());
+        assert!(build(1.0, 1.0).is_ok());
+        let error = crate::OpenAiError::from(build(1.1, 1.0).unwrap_err());
+        assert_eq!(error.error_type, "builder");
+        assert!(error.message.contains("temperature must be between 0 and 1, got 1.1"));
+        assert!(build(-0.1, 1.0).is_err());
+        assert!(build(1.0, 1.01).is_err());
+    }
+
+    #[test]
+    fn single_choice() {
+        let build = |n: u8| {
+            AnthropicChatCompletion::builder("claude-3-5-sonnet-20241022", "", [])
+                .add_user("Hello!")
+                .n(n)
+                .build()
+        };
+        let request = build(1).unwrap();
+        assert!(serde_json::to_value(&request).unwrap().get("n").is_none());
+        let error = crate::OpenAiError::from(build(3).unwrap_err());
+        assert!(error.message.contains("single choice, got n = 3"));
+    }
+
+    #[test]
+    fn tool_results() {
+        let completion: AnthropicChatCompletion = serde_json::from_value(serde_json::json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-3-5-sonnet-20241022",
+            "content": [
+                {"type": "text", "text": "Let me check."},
+                {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {"city": "Paris"}},
+                {"type": "tool_use", "id": "toolu_2", "name": "get_weather", "input": {"city": "Atlantis"}}
+            ],
+            "stop_reason": "tool_use",
+            "stop_sequence": null,
+            "usage": {"input_tokens": 25, "output_tokens": 42}
+        }))
+        .unwrap();
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Weather {
+            city: String,
+        }
+        assert_eq!(
+            completion.content[1].parse_input::<Weather>().unwrap(),
+            Weather {
+                city: "Paris".to_string()
+            }
+        );
+        assert!(completion.content[0].parse_input::<Weather>().is_err());
+
+        let request = AnthropicChatCompletion::builder("claude-3-5-sonnet-20241022", "", [])
+            .add_user("What's the weather in Paris and Atlantis?")
+            .add_message(completion.as_message())
+            .add_message(ChatCompletionMessage::tool_results([
+                ChatCompletionContentPart::tool_result("toolu_1", "15°C, cloudy"),
+                ChatCompletionContentPart::tool_error("toolu_2", "unknown city"),
+            ]))
+            .build()
+            .unwrap();
+        let messages = &serde_json::to_value(&request).unwrap()["messages"];
+        assert_eq!(
+            messages[1],
+            serde_json::json!({
+                "role": "assistant",
+                "content": [
+                    {"type": "text", "text": "Let me check."},
+                    {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {"city": "Paris"}},
+                    {"type": "tool_use", "id": "toolu_2", "name": "get_weather", "input": {"city": "Atlantis"}}
+                ]
+            })
+        );
+        assert_eq!(
+            messages[2],
+            serde_json::json!({
+                "role": "user",
+                "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": "15°C, cloudy"},
+                    {"type": "tool_result", "tool_use_id": "toolu_2", "content": "unknown city", "is_error": true}
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn pdf_document() {
+        let message =
+            ChatCompletionMessage::user("Summarize this contract.").with_pdf(b"%PDF-1.7");
+        let request =
+            AnthropicChatCompletion::builder("claude-3-5-sonnet-20241022", "", [message.clone()])
+                .build()
+                .unwrap();
+        assert_eq!(
+            serde_json::to_value(&request).unwrap()["messages"][0]["content"],
+            serde_json::json!([
+                {
+                    "type": "document",
+                    "source": {"type": "base64", "media_type": "application/pdf", "data": "JVBERi0xLjc="}
+                },
+                {"type": "text", "text": "Summarize this contract."}
+            ])
+        );
+
+        let mut message = message;
+        if let ChatCompletionContentPart::Document { source } = &mut message.content_parts[0] {
+            source.media_type = "image/png".to_string();
+        }
+        let error = AnthropicChatCompletion::builder("claude-3-5-sonnet-20241022", "", [message])
+            .build()
+            .unwrap_err();
+        assert!(error.to_string().contains("unsupported document media type \"image/png\""));
+    }
+
+    #[test]
+    fn service_tier() {
+        let request = AnthropicChatCompletion::builder("claude-3-5-sonnet-20241022", "", [])
+            .add_user("Hello!")
+            .service_tier(AnthropicServiceTier::StandardOnly)
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&request).unwrap()["service_tier"],
+            "standard_only"
+        );
+        let request = AnthropicChatCompletion::builder("claude-3-5-sonnet-20241022", "", [])
+            .service_tier(AnthropicServiceTier::from("flex".to_string()))
+            .build()
+            .unwrap();
+        assert_eq!(serde_json::to_value(&request).unwrap()["service_tier"], "flex");
+
+        let usage: AnthropicUsage = serde_json::from_value(serde_json::json!({
+            "input_tokens": 10,
+            "output_tokens": 5,
+            "service_tier": "priority"
+        }))
+        .unwrap();
+        assert_eq!(usage.service_tier, Some(AnthropicServedTier::Priority));
+        let usage: AnthropicUsage =
+            serde_json::from_value(serde_json::json!({"service_tier": "premium"})).unwrap();
+        assert_eq!(usage.service_tier, Some(AnthropicServedTier::Unknown));
+    }
+
+    #[test]
+    fn builder_add_messages() {
+        let request = AnthropicChatCompletion::builder("claude-3-5-sonnet-20241022", "", [])
+            .add_user("What's 2 + 2?")
+            .add_assistant("4")
+            .add_user("And times 3?")
+            .build()
+            .unwrap();
+
+        let contents: Vec<_> = request
+            .messages
+            .iter()
+            .map(|m| (m.role, m.content.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            contents,
+            [
+                (ChatCompletionMessageRole::User, "What's 2 + 2?"),
+                (ChatCompletionMessageRole::Assistant, "4"),
+                (ChatCompletionMessageRole::User, "And times 3?"),
+            ]
+        );
+    }
+
+    #[test]
+    fn golden_request() {
+        let request =
+            AnthropicChatCompletion::builder("claude-3-5-sonnet-20241022", "Be brief.", [])
+                .add_user("What's the weather in Paris?")
+                .add_message(ChatCompletionMessage {
+                    content_parts: vec![ChatCompletionContentPart::ToolUse {
+                        id: "toolu_1".to_string(),
+                        name: "get_weather".to_string(),
+                        input: serde_json::json!({"city": "Paris"}),
+                    }],
+                    ..ChatCompletionMessage::assistant("")
+                })
+                .add_message(ChatCompletionMessage::tool_results([
+                    ChatCompletionContentPart::tool_result("toolu_1", "15°C, cloudy"),
+                ]))
+                .temperature(0.5)
+                .stop(vec!["END".to_string()])
+                .tools(vec![AnthropicTool::new(
+                    "get_weather",
+                    "The current weather in a city",
+                    serde_json::json!({
+                        "type": "object",
+                        "properties": {"city": {"type": "string"}},
+                        "required": ["city"]
+                    }),
+                )])
+                .tool_choice(AnthropicToolChoice::auto())
+                .service_tier(AnthropicServiceTier::StandardOnly)
+                .user("user-1")
+                .build()
+                .unwrap();
+        crate::tests::assert_golden("anthropic_request", &request);
+    }
+
+    #[cfg(feature = "schemars")]
+    #[test]
+    fn tool_from_type() {
+        #[derive(schemars::JsonSchema)]
+        #[allow(dead_code)]
+        struct Weather {
+            /// The city to get the weather of.
+            city: String,
+            unit: Option<String>,
+        }
+
+        let tool = AnthropicTool::tool_from::<Weather>("get_weather", "The current weather");
+        assert_eq!(tool.name, "get_weather");
+        assert_eq!(tool.input_schema["type"], "object");
+        assert_eq!(
+            tool.input_schema["properties"]["city"],
+            serde_json::json!({"type": "string", "description": "The city to get the weather of."})
+        );
+        assert_eq!(tool.input_schema["required"], serde_json::json!(["city"]));
+        assert!(tool.input_schema.get("$schema").is_none());
+    }
 }
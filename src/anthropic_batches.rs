@@ -0,0 +1,352 @@
+//! Process many Anthropic messages requests asynchronously, at half the cost.
+//!
+//! A batch runs its requests within 24 hours. Once it has ended, its results
+//! are streamed with [`MessageBatch::results`], one per request, matched to
+//! the request by its `custom_id`.
+//!
+//! Related guide: [Message Batches](https://docs.anthropic.com/en/docs/build-with-claude/batch-processing)
+
+#[cfg(feature = "tokio-runtime")]
+use std::time::Duration;
+
+use futures_util::{Stream, StreamExt};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+use crate::anthrophic_chat::{AnthropicChatCompletion, AnthropicChatCompletionRequest};
+use crate::{
+    anthropic_post, anthropic_request, anthropic_request_json, status_error, ApiResponseOrError,
+    Credentials, MaybeSend, OpenAiError,
+};
+
+/// A request of a batch, see [`MessageBatch::create`].
+#[derive(Serialize, Debug, Clone)]
+pub struct MessageBatchRequest {
+    /// Identifies the result of this request, unique within the batch.
+    pub custom_id: String,
+    /// The request, as sent to the messages endpoint. Its credentials aren't used.
+    pub params: AnthropicChatCompletionRequest,
+}
+
+impl MessageBatchRequest {
+    pub fn new(custom_id: impl Into<String>, params: AnthropicChatCompletionRequest) -> Self {
+        MessageBatchRequest {
+            custom_id: custom_id.into(),
+            params,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct MessageBatch {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub processing_status: MessageBatchStatus,
+    pub request_counts: MessageBatchRequestCounts,
+    /// RFC 3339 datetime of when the batch was created.
+    pub created_at: String,
+    /// RFC 3339 datetime after which the requests still processing are expired.
+    pub expires_at: String,
+    pub ended_at: Option<String>,
+    pub cancel_initiated_at: Option<String>,
+    pub archived_at: Option<String>,
+    /// Where the results can be downloaded, once the batch has ended.
+    pub results_url: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageBatchStatus {
+    InProgress,
+    Canceling,
+    /// Every request has succeeded, errored, been canceled or expired.
+    Ended,
+}
+
+/// The number of requests of a batch in each state.
+#[derive(Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[serde(default)]
+pub struct MessageBatchRequestCounts {
+    pub processing: u64,
+    pub succeeded: u64,
+    pub errored: u64,
+    pub canceled: u64,
+    pub expired: u64,
+}
+
+/// A page of batches, most recent first.
+#[derive(Deserialize, Clone, Debug)]
+pub struct MessageBatches {
+    pub data: Vec<MessageBatch>,
+    #[serde(default)]
+    pub has_more: bool,
+    pub first_id: Option<String>,
+    pub last_id: Option<String>,
+}
+
+/// The result of one request of a batch.
+#[derive(Debug, Clone)]
+pub struct MessageBatchResult {
+    pub custom_id: String,
+    /// The message, or why there is none: the error of the request, or an
+    /// error of type `canceled` or `expired`.
+    pub result: ApiResponseOrError<AnthropicChatCompletion>,
+}
+
+#[derive(Deserialize)]
+struct RawResult {
+    custom_id: String,
+    result: RawOutcome,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RawOutcome {
+    Succeeded { message: AnthropicChatCompletion },
+    Errored { error: RawError },
+    Canceled,
+    Expired,
+}
+
+/// An error response, `{"type": "error", "error": {...}}`.
+#[derive(Deserialize)]
+struct RawError {
+    error: OpenAiError,
+}
+
+impl From<RawResult> for MessageBatchResult {
+    fn from(raw: RawResult) -> Self {
+        let result = match raw.result {
+            RawOutcome::Succeeded { message } => Ok(message),
+            RawOutcome::Errored { error } => Err(error.error),
+            RawOutcome::Canceled => Err(OpenAiError::new(
+                "the batch was canceled before this request was processed".to_string(),
+                "canceled".to_string(),
+            )),
+            RawOutcome::Expired => Err(OpenAiError::new(
+                "the batch expired before this request was processed".to_string(),
+                "expired".to_string(),
+            )),
+        };
+        MessageBatchResult {
+            custom_id: raw.custom_id,
+            result,
+        }
+    }
+}
+
+impl MessageBatch {
+    /// Starts a batch of up to 100,000 requests.
+    pub async fn create(
+        requests: Vec<MessageBatchRequest>,
+        credentials: Credentials,
+    ) -> ApiResponseOrError<MessageBatch> {
+        anthropic_post(
+            "messages/batches",
+            &serde_json::json!({ "requests": requests }),
+            Some(credentials),
+        )
+        .await
+    }
+
+    /// Get a batch by id.
+    pub async fn retrieve(id: &str, credentials: Credentials) -> ApiResponseOrError<MessageBatch> {
+        anthropic_request_json(
+            Method::GET,
+            &format!("messages/batches/{id}"),
+            |request| request,
+            Some(credentials),
+        )
+        .await
+    }
+
+    /// Get the most recent batches.
+    pub async fn list(credentials: Credentials) -> ApiResponseOrError<MessageBatches> {
+        anthropic_request_json(
+            Method::GET,
+            "messages/batches",
+            |request| request,
+            Some(credentials),
+        )
+        .await
+    }
+
+    /// Cancels a batch. It is `canceling` until the requests being processed
+    /// are done, then `ended`.
+    pub async fn cancel(id: &str, credentials: Credentials) -> ApiResponseOrError<MessageBatch> {
+        anthropic_request_json(
+            Method::POST,
+            &format!("messages/batches/{id}/cancel"),
+            |request| request,
+            Some(credentials),
+        )
+        .await
+    }
+
+    /// Polls the batch every `poll_interval` until it has ended.
+    #[cfg(feature = "tokio-runtime")]
+    pub async fn wait(
+        id: &str,
+        poll_interval: Duration,
+        credentials: Credentials,
+    ) -> ApiResponseOrError<MessageBatch> {
+        loop {
+            let batch = MessageBatch::retrieve(id, credentials.clone()).await?;
+            if batch.processing_status == MessageBatchStatus::Ended {
+                return Ok(batch);
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+
+    /// Streams the results of an ended batch as they are downloaded, in no
+    /// particular order. A line that can't be parsed is returned as an error,
+    /// without ending the stream.
+    pub async fn results(
+        id: &str,
+        credentials: Credentials,
+    ) -> ApiResponseOrError<
+        impl Stream<Item = ApiResponseOrError<MessageBatchResult>> + MaybeSend + Unpin,
+    > {
+        let response = anthropic_request(
+            Method::GET,
+            &format!("messages/batches/{id}/results"),
+            |request| request,
+            Some(credentials),
+        )
+        .await?;
+        if !response.status().is_success() {
+            return Err(status_error(response).await);
+        }
+        let lines = futures_util::stream::unfold(
+            (response.bytes_stream(), Vec::new()),
+            |(mut body, mut buffer)| async move {
+                loop {
+                    if let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+                        let line: Vec<u8> = buffer.drain(..=end).collect();
+                        return Some((Ok(line), (body, buffer)));
+                    }
+                    match body.next().await {
+                        Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                        Some(Err(error)) => return Some((Err(error.into()), (body, Vec::new()))),
+                        None if buffer.is_empty() => return None,
+                        None => return Some((Ok(std::mem::take(&mut buffer)), (body, buffer))),
+                    }
+                }
+            },
+        );
+        let results = lines.filter_map(|line| async move {
+            match line {
+                Ok(line) if line.trim_ascii().is_empty() => None,
+                Ok(line) => Some(
+                    serde_json::from_slice::<RawResult>(&line)
+                        .map(MessageBatchResult::from)
+                        .map_err(OpenAiError::from),
+                ),
+                Err(error) => Some(Err(error)),
+            }
+        });
+        Ok(Box::pin(results))
+    }
+}
+
+#[cfg(all(test, feature = "tokio-runtime"))]
+mod tests {
+    use super::*;
+    use crate::anthrophic_chat::AnthropicChatCompletionContent;
+    use crate::chat::ChatCompletionMessage;
+    use crate::tests::{json_response, mock_server_sequence};
+
+    fn anthropic(credentials: Credentials) -> Credentials {
+        Credentials::anthropic("sk-ant-test").with_base_url(credentials.base_url())
+    }
+
+    fn batch_json(status: &str) -> String {
+        json_response(
+            "",
+            &format!(
+                r#"{{
+                    "id": "msgbatch_123",
+                    "type": "message_batch",
+                    "processing_status": "{status}",
+                    "request_counts": {{"processing": 0, "succeeded": 1, "errored": 1, "canceled": 0, "expired": 1}},
+                    "created_at": "2024-09-24T18:37:24.100435Z",
+                    "expires_at": "2024-09-25T18:37:24.100435Z",
+                    "ended_at": null,
+                    "cancel_initiated_at": null,
+                    "archived_at": null,
+                    "results_url": null
+                }}"#
+            ),
+        )
+    }
+
+    #[tokio::test]
+    async fn create_wait_and_results() {
+        let results = [
+            r#"{"custom_id": "first", "result": {"type": "succeeded", "message": {"id": "msg_1", "type": "message", "role": "assistant", "model": "claude-3-5-sonnet-20241022", "content": [{"type": "text", "text": "Hi!"}], "stop_reason": "end_turn", "stop_sequence": null, "usage": {"input_tokens": 10, "output_tokens": 3}}}}"#,
+            r#"{"custom_id": "second", "result": {"type": "errored", "error": {"type": "error", "error": {"type": "invalid_request_error", "message": "max_tokens: Field required"}}}}"#,
+            r#"{"custom_id": "third", "result": {"type": "expired"}}"#,
+        ]
+        .join("\n");
+        let credentials = anthropic(
+            mock_server_sequence(vec![
+                batch_json("in_progress"),
+                batch_json("in_progress"),
+                batch_json("ended"),
+                json_response("", &results),
+            ])
+            .await,
+        );
+
+        let params = AnthropicChatCompletion::builder(
+            "claude-3-5-sonnet-20241022",
+            "",
+            [ChatCompletionMessage {
+                content: Some("Hello".to_string()),
+                ..Default::default()
+            }],
+        )
+        .build()
+        .unwrap();
+        let batch = MessageBatch::create(
+            vec![MessageBatchRequest::new("first", params)],
+            credentials.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(batch.processing_status, MessageBatchStatus::InProgress);
+
+        let batch = MessageBatch::wait(&batch.id, Duration::from_millis(10), credentials.clone())
+            .await
+            .unwrap();
+        assert_eq!(batch.processing_status, MessageBatchStatus::Ended);
+        assert_eq!(batch.request_counts.succeeded, 1);
+
+        let results: Vec<_> = MessageBatch::results(&batch.id, credentials)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        let results: Vec<_> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].custom_id, "first");
+        let message = results[0].result.as_ref().unwrap();
+        assert_eq!(
+            message.content,
+            [AnthropicChatCompletionContent {
+                typ: "text".to_string(),
+                text: "Hi!".to_string(),
+                ..Default::default()
+            }]
+        );
+        let error = results[1].result.as_ref().unwrap_err();
+        assert_eq!(error.error_type, "invalid_request_error");
+        assert_eq!(error.message, "max_tokens: Field required");
+        assert_eq!(
+            results[2].result.as_ref().unwrap_err().error_type,
+            "expired"
+        );
+    }
+}
@@ -0,0 +1,323 @@
+//! Turn audio into text, and text into speech.
+//!
+//! Related guides: [Speech to text](https://platform.openai.com/docs/guides/speech-to-text),
+//! [Text to speech](https://platform.openai.com/docs/guides/text-to-speech)
+
+use super::{openai_post_bytes, ApiResponseOrError, Credentials};
+use bytes::Bytes;
+use derive_builder::Builder;
+use reqwest::multipart::Part;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// The audio to transcribe, in one of the formats supported by the API
+/// (flac, mp3, mp4, mpeg, mpga, m4a, ogg, wav or webm).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudioFile {
+    /// A local file, streamed from disk.
+    Path(PathBuf),
+    /// Audio already in memory. The file name tells the API its format.
+    Bytes { file_name: String, bytes: Bytes },
+}
+
+impl AudioFile {
+    pub fn bytes(file_name: impl Into<String>, bytes: impl Into<Bytes>) -> Self {
+        AudioFile::Bytes {
+            file_name: file_name.into(),
+            bytes: bytes.into(),
+        }
+    }
+
+    async fn to_part(&self) -> ApiResponseOrError<Part> {
+        match self {
+            AudioFile::Path(path) => {
+                let file_name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                #[cfg(feature = "tokio-runtime")]
+                let part = Part::stream(tokio::fs::File::open(path).await?);
+                #[cfg(not(feature = "tokio-runtime"))]
+                let part = Part::bytes(std::fs::read(path)?);
+                Ok(part.file_name(file_name))
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            AudioFile::Bytes { file_name, bytes } => {
+                Ok(Part::stream_with_length(bytes.clone(), bytes.len() as u64)
+                    .file_name(file_name.clone()))
+            }
+            // The browser's `fetch` only takes multipart bodies held in memory.
+            #[cfg(target_arch = "wasm32")]
+            AudioFile::Bytes { file_name, bytes } => {
+                Ok(Part::bytes(bytes.to_vec()).file_name(file_name.clone()))
+            }
+        }
+    }
+}
+
+impl From<PathBuf> for AudioFile {
+    fn from(path: PathBuf) -> Self {
+        AudioFile::Path(path)
+    }
+}
+
+impl From<&std::path::Path> for AudioFile {
+    fn from(path: &std::path::Path) -> Self {
+        AudioFile::Path(path.to_path_buf())
+    }
+}
+
+/// The format of the transcript.
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TranscriptionFormat {
+    Json,
+    Text,
+    Srt,
+    VerboseJson,
+    Vtt,
+}
+
+impl TranscriptionFormat {
+    fn is_json(self) -> bool {
+        matches!(
+            self,
+            TranscriptionFormat::Json | TranscriptionFormat::VerboseJson
+        )
+    }
+}
+
+#[derive(Serialize, Builder, Debug, Clone)]
+#[builder(pattern = "owned")]
+#[builder(name = "TranscriptionBuilder")]
+#[builder(setter(strip_option, into))]
+pub struct TranscriptionRequest {
+    /// ID of the model to use, such as `whisper-1`.
+    pub model: String,
+    /// The audio to transcribe.
+    #[serde(skip_serializing)]
+    pub file: AudioFile,
+    /// The language of the audio as an ISO-639-1 code, such as `en`,
+    /// which improves accuracy and latency.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub language: Option<String>,
+    /// Text guiding the style of the transcript or continuing a previous segment.
+    /// It should be in the language of the audio.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub prompt: Option<String>,
+    /// The format of the transcript, `json` by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub response_format: Option<TranscriptionFormat>,
+    /// The credentials to use for this request.
+    #[serde(skip_serializing)]
+    #[builder(default)]
+    pub credentials: Option<Credentials>,
+}
+
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct Transcription {
+    /// The transcript, as subtitles with the `srt` and `vtt` formats.
+    pub text: String,
+}
+
+impl Transcription {
+    /// Creates a builder for a transcription of `file`, a local path or
+    /// [`AudioFile::bytes`].
+    pub fn builder(model: &str, file: impl Into<AudioFile>) -> TranscriptionBuilder {
+        TranscriptionBuilder::create_empty().model(model).file(file)
+    }
+
+    /// Transcribes audio into the language of the audio.
+    pub async fn create(request: TranscriptionRequest) -> ApiResponseOrError<Self> {
+        let format = request.response_format.unwrap_or(TranscriptionFormat::Json);
+        let file = request.file.to_part().await?;
+        let body = openai_post_bytes(
+            "audio/transcriptions",
+            &request,
+            vec![("file", file)],
+            request.credentials.clone(),
+        )
+        .await?;
+        if format.is_json() {
+            Ok(serde_json::from_slice(&body)?)
+        } else {
+            Ok(Transcription {
+                text: String::from_utf8_lossy(&body).into_owned(),
+            })
+        }
+    }
+}
+
+impl TranscriptionBuilder {
+    pub async fn create(self) -> ApiResponseOrError<Transcription> {
+        Transcription::create(self.build()?).await
+    }
+}
+
+/// The audio format of generated speech.
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SpeechFormat {
+    Mp3,
+    Opus,
+    Aac,
+    Flac,
+    Wav,
+    Pcm,
+}
+
+#[derive(Serialize, Builder, Debug, Clone)]
+#[builder(pattern = "owned")]
+#[builder(name = "SpeechBuilder")]
+#[builder(setter(strip_option, into))]
+pub struct SpeechRequest {
+    /// ID of the model to use, such as `tts-1` or `tts-1-hd`.
+    pub model: String,
+    /// The text to generate audio for, up to 4096 characters.
+    pub input: String,
+    /// The voice to use, such as `alloy`, `echo`, `fable`, `onyx`, `nova` or `shimmer`.
+    pub voice: String,
+    /// The format of the audio, `mp3` by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub response_format: Option<SpeechFormat>,
+    /// The speed of the speech, from 0.25 to 4.0, 1.0 by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub speed: Option<f32>,
+    /// The credentials to use for this request.
+    #[serde(skip_serializing)]
+    #[builder(default)]
+    pub credentials: Option<Credentials>,
+}
+
+/// Generated speech, see [`Speech::create`].
+pub struct Speech;
+
+impl Speech {
+    pub fn builder(model: &str, input: impl Into<String>, voice: &str) -> SpeechBuilder {
+        SpeechBuilder::create_empty()
+            .model(model)
+            .input(input)
+            .voice(voice)
+    }
+
+    /// Generates audio reading `input` aloud, returning the audio file.
+    pub async fn create(request: SpeechRequest) -> ApiResponseOrError<Bytes> {
+        openai_post_bytes(
+            "audio/speech",
+            &request,
+            Vec::new(),
+            request.credentials.clone(),
+        )
+        .await
+    }
+}
+
+impl SpeechBuilder {
+    pub async fn create(self) -> ApiResponseOrError<Bytes> {
+        Speech::create(self.build()?).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{http_response, json_response, mock_server};
+
+    #[tokio::test]
+    async fn transcribe_bytes() {
+        let credentials =
+            mock_server(json_response("", r#"{"text": "Crabs walk sideways."}"#)).await;
+        let transcription =
+            Transcription::builder("whisper-1", AudioFile::bytes("crab.mp3", vec![0u8; 16]))
+                .language("en")
+                .prompt("Crabs")
+                .credentials(credentials)
+                .create()
+                .await
+                .unwrap();
+        assert_eq!(transcription.text, "Crabs walk sideways.");
+    }
+
+    #[tokio::test]
+    async fn transcribe_path_as_text() {
+        let path = std::env::temp_dir().join("openai_transcribe_path_as_text.wav");
+        std::fs::write(&path, [0u8; 16]).unwrap();
+        let credentials = mock_server(http_response(
+            "200 OK",
+            "",
+            "1\n00:00:00,000 --> 00:00:01,000\nCrabs\n",
+        ))
+        .await;
+        let transcription = Transcription::builder("whisper-1", path.as_path())
+            .response_format(TranscriptionFormat::Srt)
+            .credentials(credentials)
+            .create()
+            .await
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(
+            transcription.text,
+            "1\n00:00:00,000 --> 00:00:01,000\nCrabs\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn speech() {
+        let audio = b"ID3\x04\x00fake mp3";
+        let mut response =
+            b"HTTP/1.1 200 OK\r\ncontent-type: audio/mpeg\r\ncontent-length: 13\r\n\r\n".to_vec();
+        response.extend_from_slice(audio);
+        let credentials = mock_server(response).await;
+
+        let request = Speech::builder("tts-1", "Crabs walk sideways.", "alloy")
+            .response_format(SpeechFormat::Mp3)
+            .speed(1.5)
+            .credentials(credentials)
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "model": "tts-1",
+                "input": "Crabs walk sideways.",
+                "voice": "alloy",
+                "response_format": "mp3",
+                "speed": 1.5
+            })
+        );
+        assert_eq!(Speech::create(request).await.unwrap(), audio.as_slice());
+    }
+
+    #[tokio::test]
+    async fn transcription_error() {
+        let credentials = mock_server(http_response(
+            "400 Bad Request",
+            "",
+            r#"{"error": {"message": "Invalid file format.", "type": "invalid_request_error", "param": null, "code": null}}"#,
+        ))
+        .await;
+        let error = Transcription::builder("whisper-1", AudioFile::bytes("crab.txt", "not audio"))
+            .response_format(TranscriptionFormat::Text)
+            .credentials(credentials)
+            .create()
+            .await
+            .unwrap_err();
+        assert_eq!(error.message, "Invalid file format.");
+    }
+
+    #[tokio::test]
+    async fn missing_required_field() {
+        let error = TranscriptionBuilder::default()
+            .model("whisper-1")
+            .create()
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_type, "builder");
+        assert_eq!(error.param.as_deref(), Some("file"));
+    }
+}
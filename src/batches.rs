@@ -0,0 +1,241 @@
+//! Run large numbers of requests asynchronously, at a lower cost.
+//!
+//! A batch runs the requests of an uploaded `.jsonl` file with the `batch` purpose,
+//! within the completion window. Once it has completed, its results can be
+//! downloaded with [`File::download`](crate::files::File::download) from its
+//! `output_file_id`.
+//!
+//! Related guide: [Batch API](https://platform.openai.com/docs/guides/batch)
+
+use std::collections::HashMap;
+#[cfg(feature = "tokio-runtime")]
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{openai_get, openai_post, ApiResponseOrError, Credentials};
+
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct Batch {
+    pub id: String,
+    pub object: String,
+    /// The endpoint of the requests, such as `/v1/chat/completions`.
+    pub endpoint: String,
+    /// The errors that failed the validation of the input file.
+    pub errors: Option<BatchErrors>,
+    pub input_file_id: String,
+    pub completion_window: String,
+    pub status: BatchStatus,
+    /// The file holding the responses of the requests that succeeded.
+    pub output_file_id: Option<String>,
+    /// The file holding the errors of the requests that failed.
+    pub error_file_id: Option<String>,
+    /// Unix timestamp, in seconds, of when the batch was created.
+    pub created_at: u64,
+    pub request_counts: Option<BatchRequestCounts>,
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Validating,
+    Failed,
+    InProgress,
+    Finalizing,
+    Completed,
+    Expired,
+    Cancelling,
+    Cancelled,
+}
+
+impl BatchStatus {
+    /// Whether the batch won't change anymore.
+    pub fn is_terminal(self) -> bool {
+        matches!(
+            self,
+            BatchStatus::Failed
+                | BatchStatus::Completed
+                | BatchStatus::Expired
+                | BatchStatus::Cancelled
+        )
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct BatchErrors {
+    pub data: Vec<BatchError>,
+}
+
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct BatchError {
+    pub code: Option<String>,
+    pub message: Option<String>,
+    pub param: Option<String>,
+    /// The line of the input file the error is about.
+    pub line: Option<u64>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct BatchRequestCounts {
+    pub total: u64,
+    pub completed: u64,
+    pub failed: u64,
+}
+
+/// A page of batches, most recent first.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Batches {
+    pub data: Vec<Batch>,
+    /// Whether there are older batches after this page.
+    #[serde(default)]
+    pub has_more: bool,
+    pub last_id: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BatchRequest<'a> {
+    input_file_id: &'a str,
+    endpoint: &'a str,
+    completion_window: &'a str,
+}
+
+impl Batch {
+    /// Starts a batch running the requests of the input file on `endpoint`,
+    /// such as `/v1/chat/completions`, within `completion_window`, currently
+    /// only `24h`.
+    pub async fn create(
+        input_file_id: &str,
+        endpoint: &str,
+        completion_window: &str,
+        credentials: Credentials,
+    ) -> ApiResponseOrError<Batch> {
+        let request = BatchRequest {
+            input_file_id,
+            endpoint,
+            completion_window,
+        };
+        openai_post("batches", &request, Some(credentials)).await
+    }
+
+    /// Get a batch by id.
+    pub async fn retrieve(id: &str, credentials: Credentials) -> ApiResponseOrError<Batch> {
+        openai_get(&format!("batches/{id}"), Some(credentials)).await
+    }
+
+    /// Get the most recent batches.
+    pub async fn list(credentials: Credentials) -> ApiResponseOrError<Batches> {
+        openai_get("batches", Some(credentials)).await
+    }
+
+    /// Cancels a batch. It is `cancelling` for up to 10 minutes, then `cancelled`
+    /// with the responses of the requests that completed in the meantime.
+    pub async fn cancel(id: &str, credentials: Credentials) -> ApiResponseOrError<Batch> {
+        openai_post(
+            &format!("batches/{id}/cancel"),
+            &serde_json::json!({}),
+            Some(credentials),
+        )
+        .await
+    }
+
+    /// Polls the batch every `poll_interval` until it reaches a terminal status,
+    /// see [`BatchStatus::is_terminal`].
+    #[cfg(feature = "tokio-runtime")]
+    pub async fn wait(
+        id: &str,
+        poll_interval: Duration,
+        credentials: Credentials,
+    ) -> ApiResponseOrError<Batch> {
+        loop {
+            let batch = Batch::retrieve(id, credentials.clone()).await?;
+            if batch.status.is_terminal() {
+                return Ok(batch);
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{json_response, mock_server_sequence};
+
+    fn batch_json(status: &str, output_file_id: &str) -> String {
+        json_response(
+            "",
+            &format!(
+                r#"{{
+                    "id": "batch_abc123",
+                    "object": "batch",
+                    "endpoint": "/v1/chat/completions",
+                    "errors": null,
+                    "input_file_id": "file-abc123",
+                    "completion_window": "24h",
+                    "status": "{status}",
+                    "output_file_id": {output_file_id},
+                    "error_file_id": null,
+                    "created_at": 1711471533,
+                    "request_counts": {{"total": 100, "completed": 95, "failed": 5}},
+                    "metadata": null
+                }}"#
+            ),
+        )
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn create_and_wait() {
+        let credentials = mock_server_sequence(vec![
+            batch_json("validating", "null"),
+            batch_json("in_progress", "null"),
+            batch_json("finalizing", "null"),
+            batch_json("completed", r#""file-output""#),
+        ])
+        .await;
+
+        let batch = Batch::create(
+            "file-abc123",
+            "/v1/chat/completions",
+            "24h",
+            credentials.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(batch.status, BatchStatus::Validating);
+
+        let batch = Batch::wait(&batch.id, Duration::from_millis(10), credentials)
+            .await
+            .unwrap();
+        assert_eq!(batch.status, BatchStatus::Completed);
+        assert_eq!(batch.output_file_id.as_deref(), Some("file-output"));
+        assert_eq!(
+            batch.request_counts,
+            Some(BatchRequestCounts {
+                total: 100,
+                completed: 95,
+                failed: 5,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn list_and_cancel() {
+        let credentials = mock_server_sequence(vec![
+            json_response(
+                "",
+                r#"{"object": "list", "data": [], "first_id": null, "last_id": null, "has_more": false}"#,
+            ),
+            batch_json("cancelling", "null"),
+        ])
+        .await;
+        let batches = Batch::list(credentials.clone()).await.unwrap();
+        assert!(batches.data.is_empty());
+        assert!(!batches.has_more);
+
+        let batch = Batch::cancel("batch_abc123", credentials).await.unwrap();
+        assert_eq!(batch.status, BatchStatus::Cancelling);
+        assert!(!batch.status.is_terminal());
+    }
+}
@@ -0,0 +1,467 @@
+//! Claude through [AWS Bedrock](https://docs.aws.amazon.com/bedrock/latest/userguide/model-parameters-anthropic-claude-messages.html).
+//!
+//! Create credentials with [`Credentials::bedrock`], or
+//! `Credentials::from_env(ApiProvider::Bedrock { region })`, then use
+//! [`AnthropicChatCompletion`](crate::anthrophic_chat::AnthropicChatCompletion)
+//! as with the Anthropic API, with a Bedrock model ID such as
+//! `anthropic.claude-3-5-sonnet-20241022-v2:0`. Requests are signed with
+//! [Signature Version 4](https://docs.aws.amazon.com/IAM/latest/UserGuide/reference_sigv4.html).
+//!
+//! Only messages can be created: streaming, batches and the blocking client
+//! aren't supported through Bedrock.
+//!
+//! Requires the `bedrock` feature.
+
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use reqwest::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use reqwest::{Client, RequestBuilder};
+use ring::{digest, hmac};
+use serde_json::Value;
+
+use crate::Credentials;
+
+/// The `anthropic_version` Bedrock expects in the body of Claude requests.
+pub const BEDROCK_ANTHROPIC_VERSION: &str = "bedrock-2023-05-31";
+
+/// The name of the Bedrock runtime in signed scopes.
+const SERVICE: &str = "bedrock";
+
+/// The base URL of the Bedrock runtime in `region`.
+pub fn bedrock_base_url(region: &str) -> String {
+    format!("https://bedrock-runtime.{region}.amazonaws.com/")
+}
+
+/// The access key used to sign requests.
+#[derive(Clone, Eq, PartialEq)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// The token of temporary credentials, such as those of an assumed role.
+    pub session_token: Option<String>,
+}
+
+impl std::fmt::Debug for AwsCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AwsCredentials")
+            .field("access_key_id", &self.access_key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AwsCredentials {
+    pub fn new(access_key_id: impl Into<String>, secret_access_key: impl Into<String>) -> Self {
+        AwsCredentials {
+            access_key_id: access_key_id.into(),
+            secret_access_key: secret_access_key.into(),
+            session_token: None,
+        }
+    }
+
+    pub fn with_session_token(mut self, session_token: impl Into<String>) -> Self {
+        self.session_token = Some(session_token.into());
+        self
+    }
+
+    /// Reads `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and
+    /// `AWS_SESSION_TOKEN`, falling back to the profile named by `AWS_PROFILE`,
+    /// `default` otherwise, like the AWS CLI does.
+    pub fn from_env() -> io::Result<Self> {
+        let var = |name| std::env::var(name).ok().filter(|value| !value.is_empty());
+        match (var("AWS_ACCESS_KEY_ID"), var("AWS_SECRET_ACCESS_KEY")) {
+            (Some(access_key_id), Some(secret_access_key)) => Ok(AwsCredentials {
+                access_key_id,
+                secret_access_key,
+                session_token: var("AWS_SESSION_TOKEN"),
+            }),
+            _ => Self::from_profile(&var("AWS_PROFILE").unwrap_or_else(|| "default".to_string())),
+        }
+    }
+
+    /// Reads a profile of the shared credentials file, `~/.aws/credentials`
+    /// unless `AWS_SHARED_CREDENTIALS_FILE` is set.
+    pub fn from_profile(profile: &str) -> io::Result<Self> {
+        let path = match std::env::var_os("AWS_SHARED_CREDENTIALS_FILE") {
+            Some(path) => PathBuf::from(path),
+            None => {
+                let home = std::env::var_os("HOME")
+                    .or_else(|| std::env::var_os("USERPROFILE"))
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+                PathBuf::from(home).join(".aws").join("credentials")
+            }
+        };
+        Self::parse_profile(&std::fs::read_to_string(path)?, profile)
+    }
+
+    /// Reads a profile of a credentials file in the INI format of the AWS CLI.
+    fn parse_profile(data: &str, profile: &str) -> io::Result<Self> {
+        let mut in_profile = false;
+        let mut access_key_id = None;
+        let mut secret_access_key = None;
+        let mut session_token = None;
+        for line in data.lines().map(str::trim) {
+            if let Some(section) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                in_profile = section.trim() == profile;
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=').filter(|_| in_profile) else {
+                continue;
+            };
+            let value = Some(value.trim().to_string());
+            match key.trim() {
+                "aws_access_key_id" => access_key_id = value,
+                "aws_secret_access_key" => secret_access_key = value,
+                "aws_session_token" => session_token = value,
+                _ => {}
+            }
+        }
+        match (access_key_id, secret_access_key) {
+            (Some(access_key_id), Some(secret_access_key)) => Ok(AwsCredentials {
+                access_key_id,
+                secret_access_key,
+                session_token,
+            }),
+            _ => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no AWS access key in profile {profile:?}"),
+            )),
+        }
+    }
+}
+
+/// Turns a request to the Anthropic API into a signed request to Bedrock.
+///
+/// The model moves from the body of a `messages` request to the URL, and the
+/// `anthropic_version` and `anthropic_beta` headers to the body. Requests
+/// that can't be built are returned as is, to fail when sent.
+pub(crate) fn bedrock_request(
+    client: Client,
+    request: RequestBuilder,
+    region: &str,
+    credentials: &Credentials,
+) -> RequestBuilder {
+    let Some(Ok(mut request)) = request.try_clone().map(RequestBuilder::build) else {
+        return request;
+    };
+    let Some(aws_credentials) = &credentials.aws_credentials else {
+        return RequestBuilder::from_parts(client, request);
+    };
+    let body = request.body().and_then(|body| body.as_bytes());
+    let is_messages = request.url().as_str() == crate::route_url(&credentials.base_url, "messages");
+    if let Some((model, body)) = body
+        .filter(|_| is_messages)
+        .and_then(|body| bedrock_body(body, credentials.anthropic_beta()))
+    {
+        let url = format!(
+            "{}model/{}/invoke",
+            credentials.base_url,
+            uri_encode(&model)
+        );
+        match url.parse() {
+            Ok(url) => *request.url_mut() = url,
+            Err(_) => return RequestBuilder::from_parts(client, request),
+        }
+        *request.body_mut() = Some(body.into());
+    }
+
+    let url = request.url().clone();
+    let amz_date = amz_date(SystemTime::now());
+    let mut headers = vec![("host", host(&url)), ("x-amz-date", amz_date.clone())];
+    if let Some(session_token) = &aws_credentials.session_token {
+        headers.push(("x-amz-security-token", session_token.clone()));
+    }
+    let body = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .unwrap_or_default();
+    let authorization = authorization(
+        aws_credentials,
+        region,
+        SERVICE,
+        request.method().as_str(),
+        url.path(),
+        &headers,
+        body,
+        &amz_date,
+    );
+    headers.push((AUTHORIZATION.as_str(), authorization));
+
+    let request_headers = request.headers_mut();
+    for (name, value) in headers {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            request_headers.insert(name, value);
+        }
+    }
+    request_headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    RequestBuilder::from_parts(client, request).headers(credentials.headers().clone())
+}
+
+/// The `Host` header of a request to `url`.
+fn host(url: &reqwest::Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    }
+}
+
+/// The model of an Anthropic request body, and the body in the shape Bedrock expects.
+fn bedrock_body(body: &[u8], anthropic_beta: &[String]) -> Option<(String, Vec<u8>)> {
+    let Value::Object(mut body) = serde_json::from_slice(body).ok()? else {
+        return None;
+    };
+    let Some(Value::String(model)) = body.remove("model") else {
+        return None;
+    };
+    body.remove("stream");
+    body.insert(
+        "anthropic_version".to_string(),
+        BEDROCK_ANTHROPIC_VERSION.into(),
+    );
+    if !anthropic_beta.is_empty() {
+        body.insert("anthropic_beta".to_string(), anthropic_beta.into());
+    }
+    Some((model, serde_json::to_vec(&body).ok()?))
+}
+
+/// The `Authorization` header of a request signed with Signature Version 4.
+///
+/// `headers` are the signed headers, with lowercase names, sorted by name.
+#[allow(clippy::too_many_arguments)]
+fn authorization(
+    aws_credentials: &AwsCredentials,
+    region: &str,
+    service: &str,
+    method: &str,
+    path: &str,
+    headers: &[(&str, String)],
+    body: &[u8],
+    amz_date: &str,
+) -> String {
+    let date = &amz_date[..8];
+    let canonical_uri = path
+        .split('/')
+        .map(uri_encode)
+        .collect::<Vec<_>>()
+        .join("/");
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "{method}\n{canonical_uri}\n\n{canonical_headers}\n{signed_headers}\n{}",
+        hex(digest::digest(&digest::SHA256, body).as_ref())
+    );
+    let scope = format!("{date}/{region}/{service}/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex(digest::digest(&digest::SHA256, canonical_request.as_bytes()).as_ref())
+    );
+
+    let key = format!("AWS4{}", aws_credentials.secret_access_key);
+    let key = [date, region, service, "aws4_request"]
+        .iter()
+        .fold(key.into_bytes(), |key, part| {
+            hmac_sha256(&key, part.as_bytes())
+        });
+    let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+        aws_credentials.access_key_id
+    )
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), data)
+        .as_ref()
+        .to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Percent-encodes all bytes but unreserved characters, as AWS expects.
+fn uri_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// Formats a time as `YYYYMMDD'T'HHMMSS'Z'`, in UTC.
+fn amz_date(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, seconds) = (seconds / 86_400, seconds % 86_400);
+    // Converts days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::anthrophic_chat::AnthropicChatCompletion;
+    use crate::chat::{ChatCompletionMessage, ChatCompletionMessageRole};
+    use crate::tests::{json_response, mock_server};
+    use crate::ApiProvider;
+
+    fn example_credentials() -> AwsCredentials {
+        AwsCredentials::new("AKIDEXAMPLE", "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY")
+    }
+
+    #[test]
+    fn signs_requests() {
+        // The `get-vanilla` case of the AWS Signature Version 4 test suite.
+        let headers = [
+            ("host", "example.amazonaws.com".to_string()),
+            ("x-amz-date", "20150830T123600Z".to_string()),
+        ];
+        assert_eq!(
+            authorization(
+                &example_credentials(),
+                "us-east-1",
+                "service",
+                "GET",
+                "/",
+                &headers,
+                b"",
+                "20150830T123600Z",
+            ),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+            SignedHeaders=host;x-amz-date, \
+            Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+
+        assert_eq!(
+            amz_date(UNIX_EPOCH + Duration::from_secs(1_440_938_160)),
+            "20150830T123600Z"
+        );
+        assert_eq!(
+            amz_date(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "20000229T000000Z"
+        );
+        assert_eq!(
+            uri_encode("anthropic.claude-3-5-sonnet-20241022-v2:0"),
+            "anthropic.claude-3-5-sonnet-20241022-v2%3A0"
+        );
+    }
+
+    #[test]
+    fn parses_profiles() {
+        let data = "[default]\naws_access_key_id = AKIDDEFAULT\naws_secret_access_key = secret\n\n\
+            [work]\naws_access_key_id=AKIDWORK\naws_secret_access_key=work-secret\n\
+            aws_session_token=token\n";
+        let credentials = AwsCredentials::parse_profile(data, "work").unwrap();
+        assert_eq!(
+            credentials,
+            AwsCredentials::new("AKIDWORK", "work-secret").with_session_token("token")
+        );
+        assert_eq!(
+            AwsCredentials::parse_profile(data, "default")
+                .unwrap()
+                .access_key_id,
+            "AKIDDEFAULT"
+        );
+        assert!(AwsCredentials::parse_profile(data, "missing").is_err());
+        assert!(!format!("{credentials:?}").contains("work-secret"));
+    }
+
+    #[tokio::test]
+    async fn invoke_model() {
+        let response = r#"{
+            "id": "msg_bdrk_01",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-3-5-sonnet-20241022",
+            "content": [{"type": "text", "text": "Hello from Bedrock!"}],
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": {"input_tokens": 10, "output_tokens": 5}
+        }"#;
+        let server = mock_server(json_response("", response)).await;
+        let sent = Arc::new(Mutex::new(None));
+        let hook_sent = sent.clone();
+        let credentials = Credentials::bedrock("us-east-1", example_credentials())
+            .with_anthropic_beta("token-efficient-tools-2025-02-19")
+            .with_base_url(server.base_url())
+            .with_request_hook(move |request| {
+                let body = serde_json::from_slice::<Value>(request.body).unwrap();
+                *hook_sent.lock().unwrap() = Some((request.url.to_string(), body));
+            });
+        assert_eq!(
+            credentials.provider(),
+            &ApiProvider::Bedrock {
+                region: "us-east-1".to_string()
+            }
+        );
+
+        let messages = vec![ChatCompletionMessage {
+            role: ChatCompletionMessageRole::User,
+            content: Some("Hello!".to_string()),
+            ..Default::default()
+        }];
+        let completion = AnthropicChatCompletion::builder(
+            "anthropic.claude-3-5-sonnet-20241022-v2:0",
+            "",
+            messages,
+        )
+        .max_tokens(100)
+        .credentials(credentials)
+        .create()
+        .await
+        .unwrap();
+        assert_eq!(completion.content[0].text, "Hello from Bedrock!");
+
+        let (url, body) = sent.lock().unwrap().take().unwrap();
+        assert_eq!(
+            url,
+            format!(
+                "{}model/anthropic.claude-3-5-sonnet-20241022-v2%3A0/invoke",
+                server.base_url()
+            )
+        );
+        assert_eq!(body["anthropic_version"], BEDROCK_ANTHROPIC_VERSION);
+        assert_eq!(
+            body["anthropic_beta"],
+            serde_json::json!(["token-efficient-tools-2025-02-19"])
+        );
+        assert_eq!(body["max_tokens"], 100);
+        assert!(body.get("model").is_none());
+    }
+}
@@ -0,0 +1,85 @@
+//! Synchronous request helpers built on `reqwest::blocking`, for programs
+//! without an async runtime.
+//!
+//! They share the headers of the async helpers but don't apply the retry policy.
+
+use super::{
+    anthropic_headers, openai_headers, resolve_credentials, response_too_large, route_url,
+    ApiResponse, ApiResponseOrError, Credentials, OpenAiError,
+};
+use reqwest::blocking::{Client, RequestBuilder};
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::Read;
+
+pub(crate) fn openai_post<J, T>(
+    route: &str,
+    json: &J,
+    credentials_opt: Option<Credentials>,
+) -> ApiResponseOrError<T>
+where
+    J: Serialize + ?Sized,
+    T: DeserializeOwned,
+{
+    let credentials = resolve_credentials(credentials_opt);
+    let request = Client::new()
+        .post(route_url(&credentials.base_url, route))
+        .json(json);
+    send_json(openai_headers(request, &credentials), &credentials)
+}
+
+pub(crate) fn anthropic_post<J, T>(
+    route: &str,
+    json: &J,
+    credentials_opt: Option<Credentials>,
+) -> ApiResponseOrError<T>
+where
+    J: Serialize + ?Sized,
+    T: DeserializeOwned,
+{
+    let credentials = resolve_credentials(credentials_opt);
+    let request = Client::new()
+        .post(route_url(&credentials.base_url, route))
+        .json(json);
+    send_json(anthropic_headers(request, &credentials), &credentials)
+}
+
+fn send_json<T: DeserializeOwned>(
+    mut request: RequestBuilder,
+    credentials: &Credentials,
+) -> ApiResponseOrError<T> {
+    if let Some(hook) = &credentials.request_hook {
+        if let Some(request) = request.try_clone().and_then(|request| request.build().ok()) {
+            hook.call(
+                request.method(),
+                request.url(),
+                request.body().and_then(|body| body.as_bytes()),
+            );
+        }
+    }
+    if let Some(timeout) = credentials.timeout {
+        request = request.timeout(timeout);
+    }
+    let response = request.send()?;
+    let status = response.status();
+    let request_id = crate::request_id(response.headers()).map(str::to_string);
+    let text = match credentials.max_response_bytes {
+        Some(max_bytes) => {
+            let mut text = String::new();
+            response
+                .take(max_bytes as u64 + 1)
+                .read_to_string(&mut text)?;
+            if text.len() > max_bytes {
+                return Err(response_too_large(max_bytes).with_status(status));
+            }
+            text
+        }
+        None => response.text()?,
+    };
+    match serde_json::from_str(&text)? {
+        ApiResponse::Ok(t) => Ok(t),
+        ApiResponse::Err { error } => Err(OpenAiError {
+            request_id,
+            ..error.with_status(status)
+        }),
+    }
+}
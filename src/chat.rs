@@ -1,15 +1,30 @@
 //! Given a chat conversation, the model will return a chat completion response.
 
-use super::{openai_post, ApiResponseOrError, Credentials, Usage};
-use crate::openai_request_stream;
+use super::{
+    openai_post_with_meta, override_base_url, override_headers, ApiResponseOrError,
+    Credentials, OpenAiError,
+    ResponseMeta, ResponseStream, Usage,
+};
+#[cfg(feature = "tokio-runtime")]
+use super::{StreamBroadcast, StreamCancelHandle};
+use crate::tokens::estimate_message_tokens;
+use crate::{
+    openai_request_stream, parse_stream_data, resolve_credentials, validate_range, StreamLimits,
+    StreamSender,
+};
+use base64::Engine;
 use derive_builder::Builder;
-use futures_util::StreamExt;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use reqwest::Method;
 use reqwest_eventsource::{CannotCloneRequestError, Event, EventSource};
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 use std::collections::HashMap;
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+#[cfg(feature = "tokio-runtime")]
+use tokio::sync::broadcast;
+#[cfg(feature = "tokio-runtime")]
+use tokio::sync::mpsc::{channel, Receiver};
 
 /// A full chat completion.
 pub type ChatCompletion = ChatCompletionGeneric<ChatCompletionChoice>;
@@ -23,32 +38,175 @@ pub struct ChatCompletionGeneric<C> {
     pub object: String,
     pub created: u64,
     pub model: String,
+    /// One choice per completion requested with `n`, in order of `index`.
     pub choices: Vec<C>,
     pub usage: Option<Usage>,
+    /// The backend configuration the model ran with. Responses to requests with the same
+    /// `seed` and parameters are only expected to match while it stays the same.
+    #[serde(default)]
+    pub system_fingerprint: Option<String>,
+    /// The ID OpenAI gave the request, to quote in support requests,
+    /// see [`ResponseMeta::request_id`]. Not set for streamed completions.
+    #[serde(skip)]
+    pub request_id: Option<String>,
 }
 
 #[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
 pub struct ChatCompletionChoice {
     pub index: u64,
-    pub finish_reason: String,
+    pub finish_reason: FinishReason,
     pub message: ChatCompletionMessage,
+    /// The log probabilities of the tokens of the message, when requested with `logprobs`.
+    #[serde(default)]
+    pub logprobs: Option<ChatCompletionLogprobs>,
 }
 
 #[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
 pub struct ChatCompletionChoiceDelta {
     pub index: u64,
-    pub finish_reason: Option<String>,
+    /// Set on the last delta of the choice.
+    pub finish_reason: Option<FinishReason>,
     pub delta: ChatCompletionMessageDelta,
+    /// The log probabilities of the tokens of this delta, when requested with `logprobs`.
+    #[serde(default)]
+    pub logprobs: Option<ChatCompletionLogprobs>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq, Default)]
+/// A piece of a streamed answer, see [`text_stream`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TextChunk {
+    /// Text to append to the answer.
+    Text(String),
+    /// The answer is complete, the last chunk of the stream.
+    Finished(FinishReason),
+}
+
+/// Why the model stopped generating a choice.
+///
+/// Compares equal to its raw value, e.g. `finish_reason == "length"`.
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq, Hash)]
+#[serde(from = "String", into = "String")]
+pub enum FinishReason {
+    /// The model finished its answer or hit a stop sequence.
+    Stop,
+    /// The maximum number of tokens or the context length was reached.
+    Length,
+    /// The model called tools.
+    ToolCalls,
+    /// Content was omitted by a content filter.
+    ContentFilter,
+    /// The model called a function, with the deprecated `functions`.
+    FunctionCall,
+    /// A reason this crate doesn't know about, as sent by the API.
+    Unknown(String),
+}
+
+impl FinishReason {
+    /// The raw value, as sent by the API.
+    pub fn as_str(&self) -> &str {
+        match self {
+            FinishReason::Stop => "stop",
+            FinishReason::Length => "length",
+            FinishReason::ToolCalls => "tool_calls",
+            FinishReason::ContentFilter => "content_filter",
+            FinishReason::FunctionCall => "function_call",
+            FinishReason::Unknown(reason) => reason,
+        }
+    }
+}
+
+impl From<String> for FinishReason {
+    fn from(reason: String) -> Self {
+        match reason.as_str() {
+            "stop" => FinishReason::Stop,
+            "length" => FinishReason::Length,
+            "tool_calls" => FinishReason::ToolCalls,
+            "content_filter" => FinishReason::ContentFilter,
+            "function_call" => FinishReason::FunctionCall,
+            _ => FinishReason::Unknown(reason),
+        }
+    }
+}
+
+impl From<FinishReason> for String {
+    fn from(reason: FinishReason) -> Self {
+        match reason {
+            FinishReason::Unknown(reason) => reason,
+            reason => reason.as_str().to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for FinishReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl PartialEq<str> for FinishReason {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for FinishReason {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+/// Log probabilities of the tokens of a choice.
+#[derive(Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ChatCompletionLogprobs {
+    /// One entry per token of the content, in order.
+    #[serde(default)]
+    pub content: Option<Vec<TokenLogprob>>,
+    /// One entry per token of the refusal, when the model refused.
+    #[serde(default)]
+    pub refusal: Option<Vec<TokenLogprob>>,
+}
+
+/// A token of the output, with the most likely tokens at its position.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct TokenLogprob {
+    pub token: String,
+    /// The log probability of the token, `-9999.0` when it is very unlikely.
+    pub logprob: f64,
+    /// The UTF-8 bytes of the token, since a character may span several tokens.
+    pub bytes: Option<Vec<u8>>,
+    /// The most likely tokens at this position, as many as `top_logprobs`.
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+/// One of the most likely tokens at a position of the output.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+    pub bytes: Option<Vec<u8>>,
+}
+
+// Log probabilities are never NaN.
+impl Eq for TokenLogprob {}
+impl Eq for TopLogprob {}
+
+impl TokenLogprob {
+    /// The probability of the token, from 0 to 1.
+    pub fn probability(&self) -> f64 {
+        self.logprob.exp()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq, Default)]
 pub struct ChatCompletionMessage {
     /// The role of the author of this message.
     pub role: ChatCompletionMessageRole,
     /// The contents of the message
     ///
     /// This is always required for all messages, except for when ChatGPT calls
-    /// a function.
+    /// a function. Content received as a list of parts holds the text parts only.
+    #[serde(default, deserialize_with = "deserialize_content")]
     pub content: Option<String>,
     /// The name of the user in a multi-user chat
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -70,6 +228,45 @@ pub struct ChatCompletionMessage {
         default = "default_tool_calls_deserialization"
     )]
     pub tool_calls: Vec<ToolCall>,
+    /// Annotations on the message content returned by the model,
+    /// such as the sources cited by a search-enabled model.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<ChatCompletionAnnotation>,
+    /// Content sent as a list of parts, such as text and images for vision models.
+    /// When not empty, it's sent instead of `content`, which should then hold
+    /// the text parts only.
+    ///
+    /// Content received as a list is only available through `content`.
+    #[serde(skip)]
+    pub content_parts: Vec<ChatCompletionContentPart>,
+}
+
+impl Serialize for ChatCompletionMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut message = serializer.serialize_struct("ChatCompletionMessage", 7)?;
+        message.serialize_field("role", &self.role)?;
+        if self.content_parts.is_empty() {
+            message.serialize_field("content", &self.content)?;
+        } else {
+            message.serialize_field("content", &self.content_parts)?;
+        }
+        if let Some(name) = &self.name {
+            message.serialize_field("name", name)?;
+        }
+        if let Some(function_call) = &self.function_call {
+            message.serialize_field("function_call", function_call)?;
+        }
+        if let Some(tool_call_id) = &self.tool_call_id {
+            message.serialize_field("tool_call_id", tool_call_id)?;
+        }
+        if !self.tool_calls.is_empty() {
+            message.serialize_field("tool_calls", &self.tool_calls)?;
+        }
+        if !self.annotations.is_empty() {
+            message.serialize_field("annotations", &self.annotations)?;
+        }
+        message.end()
+    }
 }
 
 /// Same as ChatCompletionMessage, but received during a response stream.
@@ -91,14 +288,252 @@ pub struct ChatCompletionMessageDelta {
     /// Required if the role is `Tool`.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_call_id: Option<String>,
-    /// Tool calls that the assistant is requesting to invoke.
-    /// Can only be populated if the role is `Assistant`,
-    /// otherwise it should be empty.
-    #[serde(
-        skip_serializing_if = "<[_]>::is_empty",
-        default = "default_tool_calls_deserialization"
-    )]
-    pub tool_calls: Vec<ToolCall>,
+    /// Fragments of the tool calls that the assistant is requesting to invoke.
+    /// Merged deltas hold one entry per tool call, see [`ToolCallAccumulator`].
+    #[serde(default)]
+    pub tool_calls: Vec<ToolCallDelta>,
+}
+
+/// An annotation on the content of a message.
+///
+/// [API Reference](https://platform.openai.com/docs/api-reference/chat/object#chat/object-choices)
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatCompletionAnnotation {
+    /// A web page cited by the model.
+    UrlCitation { url_citation: UrlCitation },
+    /// A file cited by the model.
+    FileCitation {
+        file_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        filename: Option<String>,
+        /// The position in the message content the citation applies to.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        index: Option<u32>,
+    },
+    /// An annotation type this crate doesn't know about yet.
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct UrlCitation {
+    /// The index of the first character of the citation in the message content.
+    pub start_index: u32,
+    /// The index after the last character of the citation in the message content.
+    pub end_index: u32,
+    /// The URL of the web resource.
+    pub url: String,
+    /// The title of the web resource.
+    pub title: String,
+}
+
+/// A part of message content sent as a list, as multimodal models may do.
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatCompletionContentPart {
+    Text { text: String },
+    /// The model refused to answer.
+    Refusal { refusal: String },
+    /// An image, for vision models.
+    ImageUrl { image_url: ImageUrl },
+    /// A document, for Anthropic models, see [`ChatCompletionMessage::with_pdf`].
+    Document { source: DocumentSource },
+    /// A tool call of an Anthropic model, sent back in the assistant turn that made it,
+    /// see [`AnthropicChatCompletion::as_message`](crate::anthrophic_chat::AnthropicChatCompletion::as_message).
+    ToolUse { id: String, name: String, input: Value },
+    /// The result of an Anthropic tool call, see [`ChatCompletionMessage::tool_results`].
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        is_error: Option<bool>,
+    },
+    /// A part type without text, such as audio.
+    #[serde(other)]
+    Unknown,
+}
+
+impl ChatCompletionContentPart {
+    /// The result of the Anthropic tool call with the id `tool_use_id`.
+    pub fn tool_result(tool_use_id: impl Into<String>, content: impl Into<String>) -> Self {
+        ChatCompletionContentPart::ToolResult {
+            tool_use_id: tool_use_id.into(),
+            content: content.into(),
+            is_error: None,
+        }
+    }
+
+    /// The failure of the Anthropic tool call with the id `tool_use_id`,
+    /// described by `content` for the model.
+    pub fn tool_error(tool_use_id: impl Into<String>, content: impl Into<String>) -> Self {
+        ChatCompletionContentPart::ToolResult {
+            tool_use_id: tool_use_id.into(),
+            content: content.into(),
+            is_error: Some(true),
+        }
+    }
+}
+
+/// An image given to a vision model.
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct ImageUrl {
+    /// The URL of the image, or the image itself as a base64 `data:` URL.
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<ImageDetail>,
+}
+
+/// A document given to an Anthropic model, encoded in base64.
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct DocumentSource {
+    /// How `data` is encoded, `base64`.
+    #[serde(rename = "type")]
+    pub source_type: String,
+    /// The type of the document, only `application/pdf` is supported.
+    pub media_type: String,
+    pub data: String,
+}
+
+impl DocumentSource {
+    pub fn pdf(pdf: &[u8]) -> Self {
+        DocumentSource {
+            source_type: "base64".to_string(),
+            media_type: "application/pdf".to_string(),
+            data: base64::engine::general_purpose::STANDARD.encode(pdf),
+        }
+    }
+}
+
+/// How closely the model looks at an image, trading accuracy for tokens.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageDetail {
+    Auto,
+    Low,
+    High,
+}
+
+/// Message content is either a plain string or a list of parts.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ChatCompletionContent {
+    Text(String),
+    Parts(Vec<ChatCompletionContentPart>),
+}
+
+fn deserialize_content<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let content = Option::<ChatCompletionContent>::deserialize(deserializer)?;
+    Ok(content.map(|content| match content {
+        ChatCompletionContent::Text(text) => text,
+        ChatCompletionContent::Parts(parts) => parts
+            .iter()
+            .filter_map(|part| match part {
+                ChatCompletionContentPart::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect(),
+    }))
+}
+
+impl ChatCompletionMessage {
+    fn with_role(role: ChatCompletionMessageRole, content: impl Into<String>) -> Self {
+        ChatCompletionMessage {
+            role,
+            content: Some(content.into()),
+            ..Default::default()
+        }
+    }
+
+    /// A system message, instructing the model how to behave.
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::with_role(ChatCompletionMessageRole::System, content)
+    }
+
+    /// A user message.
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::with_role(ChatCompletionMessageRole::User, content)
+    }
+
+    /// An assistant message, such as an earlier reply of the model.
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::with_role(ChatCompletionMessageRole::Assistant, content)
+    }
+
+    /// The result of the tool call with the id `tool_call_id`.
+    pub fn tool(tool_call_id: impl Into<String>, content: impl Into<String>) -> Self {
+        ChatCompletionMessage {
+            tool_call_id: Some(tool_call_id.into()),
+            ..Self::with_role(ChatCompletionMessageRole::Tool, content)
+        }
+    }
+
+    /// A user message asking about an image, for vision models such as `gpt-4o`.
+    pub fn user_with_image(text: impl Into<String>, image_url: impl Into<String>) -> Self {
+        let text = text.into();
+        ChatCompletionMessage {
+            role: ChatCompletionMessageRole::User,
+            content: Some(text.clone()),
+            content_parts: vec![
+                ChatCompletionContentPart::Text { text },
+                ChatCompletionContentPart::ImageUrl {
+                    image_url: ImageUrl {
+                        url: image_url.into(),
+                        detail: None,
+                    },
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    /// The user message answering the tool calls of an Anthropic model, with one
+    /// [`ChatCompletionContentPart::tool_result`] or `tool_error` per call.
+    pub fn tool_results(results: impl IntoIterator<Item = ChatCompletionContentPart>) -> Self {
+        ChatCompletionMessage {
+            role: ChatCompletionMessageRole::User,
+            content_parts: results.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Attaches a PDF document to the message, for Anthropic models to read.
+    /// The document is sent before the text of the message, as Anthropic recommends.
+    pub fn with_pdf(mut self, pdf: &[u8]) -> Self {
+        let document = ChatCompletionContentPart::Document {
+            source: DocumentSource::pdf(pdf),
+        };
+        if self.content_parts.is_empty() {
+            self.content_parts.extend(
+                self.content
+                    .clone()
+                    .map(|text| ChatCompletionContentPart::Text { text }),
+            );
+        }
+        self.content_parts.insert(0, document);
+        self
+    }
+
+    /// The text of the message, empty when there is none.
+    ///
+    /// Refusals and non-text parts, such as audio, are left out.
+    pub fn text(&self) -> &str {
+        self.content.as_deref().unwrap_or_default()
+    }
+
+    /// The web pages cited in this message.
+    pub fn citations(&self) -> Vec<&UrlCitation> {
+        self.annotations
+            .iter()
+            .filter_map(|annotation| match annotation {
+                ChatCompletionAnnotation::UrlCitation { url_citation } => Some(url_citation),
+                _ => None,
+            })
+            .collect()
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
@@ -123,6 +558,125 @@ pub struct ToolCallFunction {
     pub arguments: String,
 }
 
+/// A fragment of a tool call, received during a response stream.
+///
+/// The first fragment of a tool call carries its ID and function name, the
+/// following ones pieces of its arguments. Fragments of parallel tool calls
+/// are told apart by their `index`.
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub struct ToolCallDelta {
+    /// The position of the tool call in the message.
+    pub index: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub r#type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<ChatCompletionFunctionCallDelta>,
+}
+
+impl ToolCallDelta {
+    /// Merges a following fragment of the same tool call into `self`.
+    fn merge(&mut self, other: &ToolCallDelta) {
+        if self.id.is_none() {
+            self.id = other.id.clone();
+        }
+        if self.r#type.is_none() {
+            self.r#type = other.r#type.clone();
+        }
+        let Some(other_function) = &other.function else {
+            return;
+        };
+        let function = self.function.get_or_insert_with(Default::default);
+        if function.name.is_none() {
+            function.name = other_function.name.clone();
+        }
+        if let Some(other_arguments) = &other_function.arguments {
+            function
+                .arguments
+                .get_or_insert_with(String::new)
+                .push_str(other_arguments);
+        }
+    }
+}
+
+impl ToolCall {
+    /// Deserializes the arguments the model called the function with into `T`,
+    /// the type the function takes, which also validates them.
+    pub fn parse_arguments<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_str(&self.function.arguments)
+    }
+}
+
+impl From<ToolCallDelta> for ToolCall {
+    fn from(delta: ToolCallDelta) -> Self {
+        let function = delta.function.unwrap_or_default();
+        ToolCall {
+            id: delta.id.unwrap_or_default(),
+            r#type: delta.r#type.unwrap_or_else(|| "function".to_string()),
+            function: ToolCallFunction {
+                name: function.name.unwrap_or_default(),
+                arguments: function.arguments.unwrap_or_default(),
+            },
+        }
+    }
+}
+
+/// Reassembles the tool calls of a streamed message from their fragments.
+///
+/// Push the tool call fragments of every delta of a choice, then call
+/// [`ToolCallAccumulator::finish`] once the stream has ended:
+///
+/// ```no_run
+/// # use openai::chat::{ChatCompletionDelta, ToolCallAccumulator};
+/// # async fn example(mut stream: tokio::sync::mpsc::Receiver<openai::ApiResponseOrError<ChatCompletionDelta>>) -> openai::ApiResponseOrError<()> {
+/// let mut tool_calls = ToolCallAccumulator::default();
+/// while let Some(delta) = stream.recv().await {
+///     for choice in delta?.choices {
+///         tool_calls.extend(&choice.delta.tool_calls);
+///     }
+/// }
+/// for tool_call in tool_calls.finish() {
+///     println!("{}({})", tool_call.function.name, tool_call.function.arguments);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ToolCallAccumulator {
+    tool_calls: Vec<ToolCallDelta>,
+}
+
+impl ToolCallAccumulator {
+    /// Adds a fragment to the tool call at its index.
+    pub fn push(&mut self, delta: &ToolCallDelta) {
+        match self
+            .tool_calls
+            .binary_search_by_key(&delta.index, |tool_call| tool_call.index)
+        {
+            Ok(position) => self.tool_calls[position].merge(delta),
+            Err(position) => self.tool_calls.insert(position, delta.clone()),
+        }
+    }
+
+    pub fn extend<'a>(&mut self, deltas: impl IntoIterator<Item = &'a ToolCallDelta>) {
+        for delta in deltas {
+            self.push(delta);
+        }
+    }
+
+    /// The tool calls received so far, ordered by index, whose arguments may
+    /// still be incomplete.
+    pub fn tool_calls(&self) -> &[ToolCallDelta] {
+        &self.tool_calls
+    }
+
+    /// The complete tool calls, ordered by index.
+    pub fn finish(self) -> Vec<ToolCall> {
+        self.tool_calls.into_iter().map(ToolCall::from).collect()
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
 pub struct ChatCompletionFunctionDefinition {
     /// The name of the function
@@ -137,6 +691,78 @@ pub struct ChatCompletionFunctionDefinition {
     pub parameters: Option<Value>,
 }
 
+/// A tool the model may call.
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct ChatCompletionTool {
+    /// The type of the tool. Currently, only `function` is supported.
+    pub r#type: String,
+    pub function: ChatCompletionFunctionDefinition,
+}
+
+impl ChatCompletionTool {
+    pub fn function(function: ChatCompletionFunctionDefinition) -> Self {
+        ChatCompletionTool {
+            r#type: "function".to_string(),
+            function,
+        }
+    }
+
+    /// A function whose parameters are the JSON schema of `T`. Parse the
+    /// arguments of its calls with [`ToolCall::parse_arguments`].
+    #[cfg(feature = "schemars")]
+    pub fn tool_from<T: schemars::JsonSchema>(
+        name: impl Into<String>,
+        description: impl Into<String>,
+    ) -> Self {
+        ChatCompletionTool::function(ChatCompletionFunctionDefinition {
+            name: name.into(),
+            description: Some(description.into()),
+            parameters: Some(schema_for::<T>(false)),
+        })
+    }
+}
+
+impl From<ChatCompletionFunctionDefinition> for ChatCompletionTool {
+    fn from(function: ChatCompletionFunctionDefinition) -> Self {
+        ChatCompletionTool::function(function)
+    }
+}
+
+/// Which tool, if any, the model calls.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ToolChoice {
+    /// The model doesn't call any tool, and answers with a message.
+    None,
+    /// The model picks between answering and calling tools,
+    /// the default when tools are present.
+    Auto,
+    /// The model calls one or more tools.
+    Required,
+    /// The model calls the function with this name.
+    Function(String),
+}
+
+impl ToolChoice {
+    pub fn function(name: impl Into<String>) -> Self {
+        ToolChoice::Function(name.into())
+    }
+}
+
+impl Serialize for ToolChoice {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ToolChoice::None => serializer.serialize_str("none"),
+            ToolChoice::Auto => serializer.serialize_str("auto"),
+            ToolChoice::Required => serializer.serialize_str("required"),
+            ToolChoice::Function(name) => serde_json::json!({
+                "type": "function",
+                "function": {"name": name}
+            })
+            .serialize(serializer),
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct ChatCompletionFunctionCall {
     /// The name of the function ChatGPT called
@@ -147,7 +773,7 @@ pub struct ChatCompletionFunctionCall {
 }
 
 /// Same as ChatCompletionFunctionCall, but received during a response stream.
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
 pub struct ChatCompletionFunctionCallDelta {
     /// The name of the function ChatGPT called
     pub name: Option<String>,
@@ -156,10 +782,11 @@ pub struct ChatCompletionFunctionCallDelta {
     pub arguments: Option<String>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Eq, PartialEq, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum ChatCompletionMessageRole {
     System,
+    #[default]
     User,
     Assistant,
     Function,
@@ -171,74 +798,83 @@ pub enum ChatCompletionMessageRole {
 #[builder(pattern = "owned")]
 #[builder(name = "ChatCompletionBuilder")]
 #[builder(setter(strip_option, into))]
+#[builder(build_fn(validate = "Self::validate"))]
 pub struct ChatCompletionRequest {
     /// ID of the model to use. Currently, only `gpt-3.5-turbo`, `gpt-3.5-turbo-0301` and `gpt-4`
     /// are supported.
-    model: String,
+    pub(crate) model: String,
     /// The messages to generate chat completions for, in the [chat format](https://platform.openai.com/docs/guides/chat/introduction).
-    messages: Vec<ChatCompletionMessage>,
+    pub(crate) messages: Vec<ChatCompletionMessage>,
     /// What sampling temperature to use, between 0 and 2. Higher values like 0.8 will make the output more random, while lower values like 0.2 will make it more focused and deterministic.
     ///
     /// We generally recommend altering this or `top_p` but not both.
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    temperature: Option<f32>,
+    pub(crate) temperature: Option<f32>,
     /// An alternative to sampling with temperature, called nucleus sampling, where the model considers the results of the tokens with top_p probability mass. So 0.1 means only the tokens comprising the top 10% probability mass are considered.
     ///
     /// We generally recommend altering this or `temperature` but not both.
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    top_p: Option<f32>,
+    pub(crate) top_p: Option<f32>,
     /// How many chat completion choices to generate for each input message.
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    n: Option<u8>,
+    pub(crate) n: Option<u8>,
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    stream: Option<bool>,
+    pub(crate) stream: Option<bool>,
     /// Up to 4 sequences where the API will stop generating further tokens.
     #[builder(default)]
-    #[serde(skip_serializing_if = "Vec::is_empty")]
-    stop: Vec<String>,
-    /// This feature is in Beta. If specified, our system will make a best effort to sample deterministically, such that repeated requests with the same seed and parameters should return the same result. Determinism is not guaranteed, and you should refer to the system_fingerprint response parameter to monitor changes in the backend.
+    #[serde(skip_serializing_if = "StopSequences::is_empty")]
+    pub(crate) stop: StopSequences,
+    /// This feature is in Beta. If specified, our system will make a best effort to sample deterministically, such that repeated requests with the same seed and parameters should return the same result.
+    /// Determinism is not guaranteed: compare the [`system_fingerprint`](ChatCompletionGeneric::system_fingerprint) of the responses,
+    /// which changes with the backend configuration. Only OpenAI supports it.
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    seed: Option<u64>,
+    pub(crate) seed: Option<u64>,
     /// The maximum number of tokens allowed for the generated answer. By default, the number of tokens the model can return will be (4096 - prompt tokens).
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    max_tokens: Option<u64>,
+    pub(crate) max_tokens: Option<u64>,
     /// Number between -2.0 and 2.0. Positive values penalize new tokens based on whether they appear in the text so far, increasing the model's likelihood to talk about new topics.
     ///
     /// [See more information about frequency and presence penalties.](https://platform.openai.com/docs/api-reference/parameter-details)
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    presence_penalty: Option<f32>,
+    pub(crate) presence_penalty: Option<f32>,
     /// Number between -2.0 and 2.0. Positive values penalize new tokens based on their existing frequency in the text so far, decreasing the model's likelihood to repeat the same line verbatim.
     ///
     /// [See more information about frequency and presence penalties.](https://platform.openai.com/docs/api-reference/parameter-details)
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    frequency_penalty: Option<f32>,
+    pub(crate) frequency_penalty: Option<f32>,
     /// Modify the likelihood of specified tokens appearing in the completion.
     ///
     /// Accepts a json object that maps tokens (specified by their token ID in the tokenizer) to an associated bias value from -100 to 100. Mathematically, the bias is added to the logits generated by the model prior to sampling. The exact effect will vary per model, but values between -1 and 1 should decrease or increase likelihood of selection; values like -100 or 100 should result in a ban or exclusive selection of the relevant token.
     #[builder(default)]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    logit_bias: Option<HashMap<String, f32>>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "crate::serialize_sorted_option"
+    )]
+    pub(crate) logit_bias: Option<HashMap<String, f32>>,
     /// A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse. [Learn more](https://platform.openai.com/docs/guides/safety-best-practices/end-user-ids).
     #[builder(default)]
     #[serde(skip_serializing_if = "String::is_empty")]
-    user: String,
+    pub(crate) user: String,
     /// Describe functions that ChatGPT can call
     /// The latest models of ChatGPT support function calling, which allows you to define functions that can be called from the prompt.
     /// For example, you can define a function called "get_weather" that returns the weather in a given city
     ///
     /// [Function calling API Reference](https://platform.openai.com/docs/api-reference/chat/create#chat/create-functions)
     /// [See more information about function calling in ChatGPT.](https://platform.openai.com/docs/guides/gpt/function-calling)
+    ///
+    /// Deprecated by OpenAI in favor of `tools`.
     #[builder(default)]
+    #[builder_setter_attr(deprecated(note = "use `tools` instead"))]
     #[serde(skip_serializing_if = "Vec::is_empty")]
-    functions: Vec<ChatCompletionFunctionDefinition>,
+    pub(crate) functions: Vec<ChatCompletionFunctionDefinition>,
     /// A string or object of the function to call
     ///
     /// Controls how the model responds to function calls
@@ -248,77 +884,441 @@ pub struct ChatCompletionRequest {
     /// - Specifying a particular function via {"name":\ "my_function"} forces the model to call that function.
     ///
     /// "none" is the default when no functions are present. "auto" is the default if functions are present.
+    ///
+    /// Deprecated by OpenAI in favor of `tool_choice`.
+    ///
+    /// Like any [`Value`] in a request, its objects serialize with their keys
+    /// sorted, so the body doesn't change from one run to the next.
+    #[builder(default)]
+    #[builder_setter_attr(deprecated(note = "use `tool_choice` instead"))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) function_call: Option<Value>,
+    /// The tools the model may call. Currently, only functions are supported.
+    ///
+    /// [API Reference](https://platform.openai.com/docs/api-reference/chat/create#chat-create-tools)
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) tools: Vec<ChatCompletionTool>,
+    /// Controls which tool, if any, the model calls.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) tool_choice: Option<ToolChoice>,
+    /// Whether the model may call several tools in one turn, which newer models do by default.
+    /// Set to `false` to get at most one tool call per turn.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) parallel_tool_calls: Option<bool>,
+    /// Whether to return the log probabilities of the output tokens, in the `logprobs` of each choice.
+    #[builder(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) logprobs: Option<bool>,
+    /// The number of most likely tokens, from 0 to 20, to return at each position along with
+    /// their log probabilities. Requires `logprobs`.
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    function_call: Option<Value>,
+    pub(crate) top_logprobs: Option<u8>,
     /// An object specifying the format that the model must output. Compatible with GPT-4 Turbo and all GPT-3.5 Turbo models newer than gpt-3.5-turbo-1106.
     /// Setting to { "type": "json_object" } enables JSON mode, which guarantees the message the model generates is valid JSON.
     /// Important: when using JSON mode, you must also instruct the model to produce JSON yourself via a system or user message. Without this, the model may generate an unending stream of whitespace until the generation reaches the token limit, resulting in a long-running and seemingly "stuck" request. Also note that the message content may be partially cut off if finish_reason="length", which indicates the generation exceeded max_tokens or the conversation exceeded the max context length.
     #[builder(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
-    response_format: Option<ChatCompletionResponseFormat>,
+    pub(crate) response_format: Option<ChatCompletionResponseFormat>,
     /// The credentials to use for this request.
     #[serde(skip_serializing)]
     #[builder(default)]
-    credentials: Option<Credentials>,
+    pub(crate) credentials: Option<Credentials>,
+    /// Overrides the base URL of the credentials for this request only.
+    /// The API key and provider are left untouched.
+    #[serde(skip_serializing)]
+    #[builder(default)]
+    pub(crate) base_url: Option<String>,
+    /// Extra headers sent with this request only, on top of those of the credentials.
+    #[serde(skip_serializing)]
+    #[builder(default)]
+    pub(crate) headers: HeaderMap,
+    /// Overrides the stream idle timeout of the credentials for this request only,
+    /// see [`Credentials::with_stream_idle_timeout`].
+    #[serde(skip_serializing)]
+    #[builder(default)]
+    pub(crate) stream_idle_timeout: Option<Duration>,
+    /// Set to `false` to leave out the API key header for this request only,
+    /// see [`Credentials::without_auth_header`].
+    #[serde(skip_serializing)]
+    #[builder(default)]
+    pub(crate) auth_header: Option<bool>,
+}
+
+/// The most stop sequences OpenAI accepts.
+pub const MAX_STOP_SEQUENCES: usize = 4;
+
+/// Sequences where the API will stop generating further tokens.
+///
+/// A single sequence given as a string, as in `.stop("END")`, is sent as a bare
+/// string; sequences given as a list are sent as an array, even when there's
+/// only one, since proxies differ in which form they accept.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct StopSequences {
+    sequences: Vec<String>,
+    bare_string: bool,
+}
+
+impl StopSequences {
+    pub fn is_empty(&self) -> bool {
+        self.sequences.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sequences.len()
+    }
+
+    pub fn as_slice(&self) -> &[String] {
+        &self.sequences
+    }
+}
+
+impl Serialize for StopSequences {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.sequences.as_slice() {
+            [sequence] if self.bare_string => serializer.serialize_str(sequence),
+            sequences => sequences.serialize(serializer),
+        }
+    }
+}
+
+impl From<String> for StopSequences {
+    fn from(sequence: String) -> Self {
+        StopSequences {
+            sequences: vec![sequence],
+            bare_string: true,
+        }
+    }
+}
+
+impl From<&str> for StopSequences {
+    fn from(sequence: &str) -> Self {
+        sequence.to_string().into()
+    }
+}
+
+impl From<Vec<String>> for StopSequences {
+    fn from(sequences: Vec<String>) -> Self {
+        StopSequences {
+            sequences,
+            bare_string: false,
+        }
+    }
+}
+
+impl From<Vec<&str>> for StopSequences {
+    fn from(sequences: Vec<&str>) -> Self {
+        sequences
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>()
+            .into()
+    }
+}
+
+impl<const N: usize> From<[&str; N]> for StopSequences {
+    fn from(sequences: [&str; N]) -> Self {
+        Vec::from(sequences).into()
+    }
+}
+
+impl From<StopSequences> for Vec<String> {
+    fn from(stop: StopSequences) -> Self {
+        stop.sequences
+    }
 }
 
 #[derive(Serialize, Debug, Clone, Eq, PartialEq)]
 pub struct ChatCompletionResponseFormat {
-    /// Must be one of text or json_object (defaults to text)
+    /// Must be one of text, json_object or json_schema (defaults to text)
     #[serde(rename = "type")]
     typ: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    json_schema: Option<JsonSchemaFormat>,
+}
+
+/// A JSON schema the output of the model must match, for structured outputs.
+///
+/// [Structured Outputs guide](https://platform.openai.com/docs/guides/structured-outputs)
+#[derive(Serialize, Debug, Clone, Eq, PartialEq)]
+pub struct JsonSchemaFormat {
+    /// The name of the response format, made of letters, digits, underscores and dashes.
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub schema: Value,
+    /// Whether the output must match the schema exactly. Strict schemas only
+    /// support a subset of JSON Schema, see the guide.
+    pub strict: bool,
 }
 
 impl ChatCompletionResponseFormat {
     pub fn json_object() -> Self {
         ChatCompletionResponseFormat {
             typ: "json_object".to_string(),
+            json_schema: None,
         }
     }
 
     pub fn text() -> Self {
         ChatCompletionResponseFormat {
             typ: "text".to_string(),
+            json_schema: None,
         }
     }
-}
 
-impl<C> ChatCompletionGeneric<C> {
-    pub fn builder(
-        model: &str,
-        messages: impl Into<Vec<ChatCompletionMessage>>,
-    ) -> ChatCompletionBuilder {
-        ChatCompletionBuilder::create_empty()
-            .model(model)
-            .messages(messages)
+    /// Structured outputs: the model answers with JSON matching `schema`.
+    /// Parse the answer with [`ChatCompletion::parsed`].
+    pub fn json_schema(name: impl Into<String>, schema: Value, strict: bool) -> Self {
+        ChatCompletionResponseFormat {
+            typ: "json_schema".to_string(),
+            json_schema: Some(JsonSchemaFormat {
+                name: name.into(),
+                description: None,
+                schema,
+                strict,
+            }),
+        }
+    }
+
+    /// Structured outputs with the JSON schema of `T`, to parse the answer into
+    /// with [`ChatCompletion::parsed`]. A strict schema forbids properties
+    /// `T` doesn't have and requires all of them, as OpenAI expects, so optional
+    /// fields are sent as `null`.
+    #[cfg(feature = "schemars")]
+    pub fn json_schema_for<T: schemars::JsonSchema>(name: impl Into<String>, strict: bool) -> Self {
+        Self::json_schema(name, schema_for::<T>(strict), strict)
+    }
+}
+
+/// The JSON schema of `T`, without the `$schema` keyword, see
+/// [`ChatCompletionResponseFormat::json_schema_for`] for `strict`.
+#[cfg(feature = "schemars")]
+pub(crate) fn schema_for<T: schemars::JsonSchema>(strict: bool) -> Value {
+    let mut schema = schemars::schema_for!(T).to_value();
+    if let Value::Object(object) = &mut schema {
+        object.remove("$schema");
+    }
+    if strict {
+        make_strict(&mut schema);
+    }
+    schema
+}
+
+/// Forbids additional properties and requires all properties, in `schema`
+/// and its subschemas.
+#[cfg(feature = "schemars")]
+fn make_strict(schema: &mut Value) {
+    match schema {
+        Value::Object(object) => {
+            if let Some(Value::Object(properties)) = object.get("properties") {
+                let required = properties.keys().cloned().map(Value::String).collect();
+                object.insert("required".to_string(), Value::Array(required));
+                object.insert("additionalProperties".to_string(), Value::Bool(false));
+            }
+            object.values_mut().for_each(make_strict);
+        }
+        Value::Array(values) => values.iter_mut().for_each(make_strict),
+        _ => {}
+    }
+}
+
+impl<C> ChatCompletionGeneric<C> {
+    pub fn builder(
+        model: &str,
+        messages: impl Into<Vec<ChatCompletionMessage>>,
+    ) -> ChatCompletionBuilder {
+        ChatCompletionBuilder::create_empty()
+            .model(model)
+            .messages(messages)
+    }
+}
+
+impl ChatCompletionRequest {
+    /// The JSON body this request is sent with, such as to log a rejected request.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// The credentials this request will be sent with, including any per-request overrides.
+    fn request_credentials(&self) -> Option<Credentials> {
+        let credentials_opt = override_base_url(self.credentials.clone(), self.base_url.clone());
+        let credentials_opt = override_headers(credentials_opt, &self.headers);
+        if self.stream_idle_timeout.is_none() && self.auth_header.is_none() {
+            return credentials_opt;
+        }
+        let mut credentials = resolve_credentials(credentials_opt);
+        if let Some(timeout) = self.stream_idle_timeout {
+            credentials.stream_idle_timeout = Some(timeout);
+        }
+        if let Some(auth_header) = self.auth_header {
+            credentials.auth_header = auth_header;
+        }
+        Some(credentials)
+    }
+
+    /// Adds the estimated size of this request to a context length error.
+    fn explain_error(&self, error: OpenAiError) -> OpenAiError {
+        error.with_context_length(&self.model, estimate_message_tokens(&self.messages))
     }
 }
 
 impl ChatCompletion {
+    /// The message of the first choice, the only one unless `n` was set.
+    pub fn first_message(&self) -> Option<&ChatCompletionMessage> {
+        self.choices.first().map(|choice| &choice.message)
+    }
+
+    /// The messages of all choices, in order.
+    pub fn messages(&self) -> impl Iterator<Item = &ChatCompletionMessage> {
+        self.choices.iter().map(|choice| &choice.message)
+    }
+
+    /// The message of the first choice, ready to append to the conversation to
+    /// continue it, tool calls included. Its annotations are left out, as they
+    /// are only returned by the API.
+    pub fn as_message(&self) -> Option<ChatCompletionMessage> {
+        self.first_message().map(|message| ChatCompletionMessage {
+            annotations: Vec::new(),
+            ..message.clone()
+        })
+    }
+
     pub async fn create(request: ChatCompletionRequest) -> ApiResponseOrError<Self> {
-        let credentials_opt = request.credentials.clone();
-        openai_post("chat/completions", &request, credentials_opt).await
+        let (completion, _) = Self::create_with_response_meta(request).await?;
+        Ok(completion)
+    }
+
+    /// Parses the content of the first choice as JSON, for responses requested
+    /// with a JSON response format.
+    ///
+    /// Fails with [`StructuredOutputError::Truncated`] when the model hit the
+    /// token limit, since the JSON is then incomplete.
+    pub fn parsed<T: DeserializeOwned>(&self) -> Result<T, StructuredOutputError> {
+        let choice = self
+            .choices
+            .first()
+            .ok_or(StructuredOutputError::NoContent)?;
+        if choice.finish_reason == FinishReason::Length {
+            return Err(StructuredOutputError::Truncated);
+        }
+        let content = choice
+            .message
+            .content
+            .as_deref()
+            .ok_or(StructuredOutputError::NoContent)?;
+        serde_json::from_str(content).map_err(StructuredOutputError::Json)
+    }
+
+    /// Same as `create`, but blocks the current thread until the response is received.
+    ///
+    /// Meant for code not running on an async runtime, such as CLIs and scripts.
+    /// The retry policy of the credentials isn't applied.
+    ///
+    /// # Panics
+    /// Panics when called from within an async context.
+    #[cfg(feature = "blocking")]
+    pub fn create_blocking(request: ChatCompletionRequest) -> ApiResponseOrError<Self> {
+        let credentials_opt = request.request_credentials();
+        crate::blocking::openai_post("chat/completions", &request, credentials_opt)
+            .map_err(|error| request.explain_error(error))
+    }
+
+    /// Same as `create`, but also returns the status and headers of the HTTP response.
+    pub async fn create_with_response_meta(
+        request: ChatCompletionRequest,
+    ) -> ApiResponseOrError<(Self, ResponseMeta)> {
+        let credentials_opt = request.request_credentials();
+        let (mut completion, meta): (Self, _) =
+            openai_post_with_meta("chat/completions", &request, credentials_opt)
+                .await
+                .map_err(|error| request.explain_error(error))?;
+        completion.request_id = meta.request_id().map(str::to_string);
+        Ok((completion, meta))
     }
 }
 
 impl ChatCompletionDelta {
-    pub async fn create(
-        request: ChatCompletionRequest,
-    ) -> Result<Receiver<Self>, CannotCloneRequestError> {
-        let credentials_opt = request.credentials.clone();
+    /// Sends the streaming request, returning the stream of its events.
+    async fn open(
+        mut request: ChatCompletionRequest,
+    ) -> Result<(EventSource, StreamLimits), CannotCloneRequestError> {
+        request.stream = Some(true);
+        let credentials = resolve_credentials(request.request_credentials());
+        let limits = credentials.stream_limits();
         let stream = openai_request_stream(
             Method::POST,
             "chat/completions",
             |r| r.json(&request),
-            credentials_opt,
+            Some(credentials),
         )
         .await?;
-        let (tx, rx) = channel::<Self>(32);
-        tokio::spawn(forward_deserialized_chat_response_stream(stream, tx));
+        Ok((stream, limits))
+    }
+
+    /// Streams the chat completion, delta by delta.
+    ///
+    /// An error sent by the API while streaming, or a failed connection, is
+    /// received as the last item.
+    #[cfg(feature = "tokio-runtime")]
+    pub async fn create(
+        request: ChatCompletionRequest,
+    ) -> Result<Receiver<ApiResponseOrError<Self>>, CannotCloneRequestError> {
+        let (rx, _) = Self::create_cancellable(request).await?;
         Ok(rx)
     }
 
+    /// Same as `create`, but also returns a handle to abort the stream.
+    #[cfg(feature = "tokio-runtime")]
+    pub async fn create_cancellable(
+        request: ChatCompletionRequest,
+    ) -> Result<(Receiver<ApiResponseOrError<Self>>, StreamCancelHandle), CannotCloneRequestError>
+    {
+        let (stream, limits) = Self::open(request).await?;
+        let (tx, rx) = channel(32);
+        let task = tokio::spawn(forward_deserialized_chat_response_stream(
+            stream,
+            tx.into(),
+            limits,
+        ));
+        Ok((rx, StreamCancelHandle::new(&task)))
+    }
+
+    /// Streams the chat completion to any number of subscribers.
+    ///
+    /// Returns a handle to subscribe more receivers at any point of the stream,
+    /// together with a first receiver that sees every delta.
+    /// See [`StreamBroadcast`] for how slow and late subscribers are handled.
+    #[cfg(feature = "tokio-runtime")]
+    pub async fn create_broadcast(
+        request: ChatCompletionRequest,
+        capacity: usize,
+    ) -> Result<
+        (
+            StreamBroadcast<ApiResponseOrError<Self>>,
+            broadcast::Receiver<ApiResponseOrError<Self>>,
+        ),
+        CannotCloneRequestError,
+    > {
+        let rx = Self::create(request).await?;
+        Ok(StreamBroadcast::spawn(rx, capacity))
+    }
+
+    /// The text and finish reason of the first choice of this delta, skipping
+    /// deltas with only a role or tool calls.
+    pub fn text_chunks(self) -> impl Iterator<Item = TextChunk> {
+        let choice = self.choices.into_iter().find(|choice| choice.index == 0);
+        let (text, finish_reason) = match choice {
+            Some(choice) => (choice.delta.content, choice.finish_reason),
+            None => (None, None),
+        };
+        let text = text.filter(|text| !text.is_empty()).map(TextChunk::Text);
+        text.into_iter().chain(finish_reason.map(TextChunk::Finished))
+    }
+
     /// Merges the input delta completion into `self`.
     pub fn merge(
         &mut self,
@@ -327,6 +1327,9 @@ impl ChatCompletionDelta {
         if other.id.ne(&self.id) {
             return Err(ChatCompletionDeltaMergeError::DifferentCompletionIds);
         }
+        if self.system_fingerprint.is_none() {
+            self.system_fingerprint = other.system_fingerprint.clone();
+        }
         for other_choice in other.choices.iter() {
             for choice in self.choices.iter_mut() {
                 if choice.index != other_choice.index {
@@ -362,21 +1365,15 @@ impl ChatCompletionChoiceDelta {
         // Merge contents.
         match self.delta.content.as_mut() {
             Some(content) => {
-                match &other.delta.content {
-                    Some(other_content) => {
-                        // Push other content into this one.
-                        content.push_str(other_content)
-                    }
-                    None => {}
+                if let Some(other_content) = &other.delta.content {
+                    // Push other content into this one.
+                    content.push_str(other_content)
                 }
             }
             None => {
-                match &other.delta.content {
-                    Some(other_content) => {
-                        // Set this content to other content.
-                        self.delta.content = Some(other_content.clone());
-                    }
-                    None => {}
+                if let Some(other_content) = &other.delta.content {
+                    // Set this content to other content.
+                    self.delta.content = Some(other_content.clone());
                 }
             }
         };
@@ -386,32 +1383,42 @@ impl ChatCompletionChoiceDelta {
         // arguments are merged by concatenating them
         match self.delta.function_call.as_mut() {
             Some(function_call) => {
-                match &other.delta.function_call {
-                    Some(other_function_call) => {
-                        // push the arguments string of the other function call into this one
-                        match (&mut function_call.arguments, &other_function_call.arguments) {
-                            (Some(function_call), Some(other_function_call)) => {
-                                function_call.push_str(&other_function_call);
-                            }
-                            (None, Some(other_function_call)) => {
-                                function_call.arguments = Some(other_function_call.clone());
-                            }
-                            _ => {}
+                if let Some(other_function_call) = &other.delta.function_call {
+                    // push the arguments string of the other function call into this one
+                    match (&mut function_call.arguments, &other_function_call.arguments) {
+                        (Some(function_call), Some(other_function_call)) => {
+                            function_call.push_str(other_function_call);
+                        }
+                        (None, Some(other_function_call)) => {
+                            function_call.arguments = Some(other_function_call.clone());
                         }
+                        _ => {}
                     }
-                    None => {}
                 }
             }
             None => {
-                match &other.delta.function_call {
-                    Some(other_function_call) => {
-                        // Set this content to other content.
-                        self.delta.function_call = Some(other_function_call.clone());
-                    }
-                    None => {}
+                if let Some(other_function_call) = &other.delta.function_call {
+                    // Set this content to other content.
+                    self.delta.function_call = Some(other_function_call.clone());
                 }
             }
         };
+        // Merge the fragments of each tool call.
+        let mut tool_calls = ToolCallAccumulator::default();
+        tool_calls.extend(self.delta.tool_calls.iter().chain(&other.delta.tool_calls));
+        self.delta.tool_calls = tool_calls.tool_calls;
+        // Append the log probabilities of the tokens.
+        if let Some(other_logprobs) = &other.logprobs {
+            let logprobs = self.logprobs.get_or_insert_with(Default::default);
+            for (tokens, other_tokens) in [
+                (&mut logprobs.content, &other_logprobs.content),
+                (&mut logprobs.refusal, &other_logprobs.refusal),
+            ] {
+                if let Some(other_tokens) = other_tokens {
+                    tokens.get_or_insert_with(Vec::new).extend_from_slice(other_tokens);
+                }
+            }
+        }
         Ok(())
     }
 }
@@ -424,23 +1431,35 @@ impl From<ChatCompletionDelta> for ChatCompletion {
             created: delta.created,
             model: delta.model,
             usage: delta.usage,
+            system_fingerprint: delta.system_fingerprint,
+            request_id: delta.request_id,
             choices: delta
                 .choices
                 .iter()
                 .map(|choice| ChatCompletionChoice {
                     index: choice.index,
-                    finish_reason: clone_default_unwrapped_option_string(&choice.finish_reason),
+                    finish_reason: choice
+                        .finish_reason
+                        .clone()
+                        .unwrap_or_else(|| FinishReason::Unknown(String::new())),
                     message: ChatCompletionMessage {
                         role: choice
                             .delta
                             .role
-                            .unwrap_or_else(|| ChatCompletionMessageRole::System),
+                            .unwrap_or(ChatCompletionMessageRole::System),
                         content: choice.delta.content.clone(),
                         name: choice.delta.name.clone(),
                         function_call: choice.delta.function_call.clone().map(|f| f.into()),
                         tool_call_id: None,
-                        tool_calls: Vec::new(),
+                        tool_calls: {
+                            let mut tool_calls = ToolCallAccumulator::default();
+                            tool_calls.extend(&choice.delta.tool_calls);
+                            tool_calls.finish()
+                        },
+                        annotations: Vec::new(),
+                        content_parts: Vec::new(),
                     },
+                    logprobs: choice.logprobs.clone(),
                 })
                 .collect(),
         }
@@ -456,6 +1475,44 @@ impl From<ChatCompletionFunctionCallDelta> for ChatCompletionFunctionCall {
     }
 }
 
+/// Why the content of a completion couldn't be parsed with [`ChatCompletion::parsed`].
+#[derive(Debug)]
+pub enum StructuredOutputError {
+    /// The request failed.
+    Api(OpenAiError),
+    /// The completion has no choice or no message content.
+    NoContent,
+    /// The model stopped at the token limit, leaving the JSON incomplete.
+    Truncated,
+    /// The content isn't valid JSON for the requested type.
+    Json(serde_json::Error),
+}
+
+impl std::fmt::Display for StructuredOutputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StructuredOutputError::Api(error) => write!(f, "The request failed: {error}"),
+            StructuredOutputError::NoContent => f.write_str("The completion has no content"),
+            StructuredOutputError::Truncated => f.write_str(
+                "The completion was truncated by the token limit; try a higher max_tokens",
+            ),
+            StructuredOutputError::Json(error) => {
+                write!(f, "The completion isn't valid JSON: {error}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StructuredOutputError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StructuredOutputError::Api(error) => Some(error),
+            StructuredOutputError::Json(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ChatCompletionDeltaMergeError {
     DifferentCompletionIds,
@@ -481,40 +1538,219 @@ impl std::fmt::Display for ChatCompletionDeltaMergeError {
 
 impl std::error::Error for ChatCompletionDeltaMergeError {}
 
+/// Forwards the deltas of a stream until it ends, the receiver is dropped,
+/// or an error occurs, which is sent as the last item.
 async fn forward_deserialized_chat_response_stream(
     mut stream: EventSource,
-    tx: Sender<ChatCompletionDelta>,
-) -> anyhow::Result<()> {
-    while let Some(event) = stream.next().await {
-        let event = event?;
-        match event {
-            Event::Message(event) => {
-                let completion = serde_json::from_str::<ChatCompletionDelta>(&event.data)?;
-                tx.send(completion).await?;
-            }
-            _ => {}
+    mut tx: StreamSender<ApiResponseOrError<ChatCompletionDelta>>,
+    mut limits: StreamLimits,
+) {
+    loop {
+        let event = tx.next_event(&mut stream, &mut limits).await;
+        let delta = match event {
+            None => break,
+            Some(Ok(Event::Open)) => continue,
+            Some(Ok(Event::Message(message))) if message.data == "[DONE]" => break,
+            Some(Ok(Event::Message(message))) => parse_stream_data(&message.data),
+            Some(Err(error)) => Err(error),
+        };
+        let failed = delta.is_err();
+        if tx.send(delta).await.is_err() || failed {
+            break;
         }
     }
-    Ok(())
+    stream.close();
+}
+
+/// Turns a stream of deltas into the text of the answer, chunk by chunk:
+///
+/// ```no_run
+/// # use openai::chat::{text_stream, ChatCompletion, TextChunk};
+/// # async fn example() -> openai::ApiResponseOrError<()> {
+/// let deltas = ChatCompletion::builder("gpt-4o", [])
+///     .add_user("Tell me a story.")
+///     .create_stream()
+///     .await?;
+/// let mut chunks = text_stream(deltas);
+/// while let Some(chunk) = chunks.recv().await {
+///     match chunk? {
+///         TextChunk::Text(text) => print!("{text}"),
+///         TextChunk::Finished(reason) => println!("\n({reason:?})"),
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Only the first choice is followed. Errors are forwarded as they are.
+#[cfg(feature = "tokio-runtime")]
+pub fn text_stream(
+    mut deltas: Receiver<ApiResponseOrError<ChatCompletionDelta>>,
+) -> Receiver<ApiResponseOrError<TextChunk>> {
+    let (tx, rx) = channel(32);
+    tokio::spawn(async move {
+        while let Some(delta) = deltas.recv().await {
+            let chunks: Vec<_> = match delta {
+                Ok(delta) => delta.text_chunks().map(Ok).collect(),
+                Err(error) => vec![Err(error)],
+            };
+            for chunk in chunks {
+                // Dropping the deltas stops the stream when the receiver is gone.
+                if tx.send(chunk).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    rx
 }
 
 impl ChatCompletionBuilder {
+    fn validate(&self) -> Result<(), String> {
+        validate_range("temperature", self.temperature.flatten(), 2.0)?;
+        validate_range("top_p", self.top_p.flatten(), 1.0)?;
+        match &self.stop {
+            Some(stop) if stop.len() > MAX_STOP_SEQUENCES => Err(format!(
+                "at most {MAX_STOP_SEQUENCES} stop sequences are allowed, got {}",
+                stop.len()
+            )),
+            _ => Ok(()),
+        }
+    }
+
     pub async fn create(self) -> ApiResponseOrError<ChatCompletion> {
-        ChatCompletion::create(self.build().unwrap()).await
+        ChatCompletion::create(self.build()?).await
     }
 
+    /// Builds and sends the request, blocking the current thread until the
+    /// response is received. See [`ChatCompletion::create_blocking`].
+    #[cfg(feature = "blocking")]
+    pub fn create_blocking(self) -> ApiResponseOrError<ChatCompletion> {
+        ChatCompletion::create_blocking(self.build()?)
+    }
+
+    pub async fn create_with_response_meta(
+        self,
+    ) -> ApiResponseOrError<(ChatCompletion, ResponseMeta)> {
+        ChatCompletion::create_with_response_meta(self.build()?).await
+    }
+
+    #[cfg(feature = "tokio-runtime")]
     pub async fn create_stream(
         mut self,
-    ) -> Result<Receiver<ChatCompletionDelta>, CannotCloneRequestError> {
+    ) -> ApiResponseOrError<Receiver<ApiResponseOrError<ChatCompletionDelta>>> {
         self.stream = Some(Some(true));
-        ChatCompletionDelta::create(self.build().unwrap()).await
+        Ok(ChatCompletionDelta::create(self.build()?).await?)
     }
-}
 
-fn clone_default_unwrapped_option_string(string: &Option<String>) -> String {
-    match string {
-        Some(value) => value.clone(),
-        None => "".to_string(),
+    /// Same as `create_stream`, as a [`Stream`](futures_util::Stream) of deltas.
+    /// Also available without the `tokio-runtime` feature.
+    pub async fn create_response_stream(
+        self,
+    ) -> ApiResponseOrError<ResponseStream<ChatCompletionDelta>> {
+        #[cfg(feature = "tokio-runtime")]
+        {
+            Ok(self.create_stream().await?.into())
+        }
+        #[cfg(not(feature = "tokio-runtime"))]
+        {
+            let (stream, limits) = ChatCompletionDelta::open(self.build()?).await?;
+            Ok(ResponseStream::forwarded(|tx| {
+                forward_deserialized_chat_response_stream(stream, tx, limits)
+            }))
+        }
+    }
+
+    /// Same as `create_stream`, yielding only the text of the answer, see [`text_stream`].
+    #[cfg(feature = "tokio-runtime")]
+    pub async fn create_text_stream(
+        self,
+    ) -> ApiResponseOrError<Receiver<ApiResponseOrError<TextChunk>>> {
+        Ok(text_stream(self.create_stream().await?))
+    }
+
+    /// Sends the request and parses the response as JSON, see [`ChatCompletion::parsed`].
+    ///
+    /// When the response isn't valid JSON for `T`, the parse error is sent back to
+    /// the model, asking for a corrected answer, up to `max_attempts` requests in
+    /// total. Returns the last error once the attempts are exhausted. Truncated
+    /// responses and failed requests aren't retried.
+    pub async fn create_parsed<T: DeserializeOwned>(
+        self,
+        max_attempts: usize,
+    ) -> Result<T, StructuredOutputError> {
+        let mut builder = self;
+        let mut attempt = 1;
+        loop {
+            let request = builder
+                .clone()
+                .build()
+                .map_err(|error| StructuredOutputError::Api(error.into()))?;
+            let completion = ChatCompletion::create(request)
+                .await
+                .map_err(StructuredOutputError::Api)?;
+            let error = match completion.parsed() {
+                Err(StructuredOutputError::Json(error)) if attempt < max_attempts => error,
+                result => return result,
+            };
+            let answer = completion.choices.into_iter().next().unwrap().message;
+            builder = builder.add_message(answer).add_user(format!(
+                "Your answer isn't valid JSON for the expected schema: {error}. \
+                Reply with the corrected JSON only."
+            ));
+            attempt += 1;
+        }
+    }
+
+    /// Same as `create_stream`, but also returns a handle to abort the stream.
+    #[cfg(feature = "tokio-runtime")]
+    pub async fn create_stream_cancellable(
+        mut self,
+    ) -> ApiResponseOrError<(
+        Receiver<ApiResponseOrError<ChatCompletionDelta>>,
+        StreamCancelHandle,
+    )> {
+        self.stream = Some(Some(true));
+        Ok(ChatCompletionDelta::create_cancellable(self.build()?).await?)
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    pub async fn create_stream_broadcast(
+        mut self,
+        capacity: usize,
+    ) -> ApiResponseOrError<(
+        StreamBroadcast<ApiResponseOrError<ChatCompletionDelta>>,
+        broadcast::Receiver<ApiResponseOrError<ChatCompletionDelta>>,
+    )> {
+        self.stream = Some(Some(true));
+        Ok(ChatCompletionDelta::create_broadcast(self.build()?, capacity).await?)
+    }
+
+    /// Sends an extra header with this request, such as a trace ID.
+    pub fn add_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers
+            .get_or_insert_with(HeaderMap::new)
+            .insert(name, value);
+        self
+    }
+
+    /// Appends a message to the conversation.
+    ///
+    /// Messages are added after any set with `messages`; note that calling
+    /// `messages` afterwards replaces the whole conversation.
+    pub fn add_message(mut self, message: ChatCompletionMessage) -> Self {
+        self.messages.get_or_insert_with(Vec::new).push(message);
+        self
+    }
+
+    /// Appends a user message to the conversation.
+    pub fn add_user(self, content: impl Into<String>) -> Self {
+        self.add_message(ChatCompletionMessage::user(content))
+    }
+
+    /// Appends an assistant message to the conversation.
+    pub fn add_assistant(self, content: impl Into<String>) -> Self {
+        self.add_message(ChatCompletionMessage::assistant(content))
     }
 }
 
@@ -522,12 +1758,6 @@ fn default_tool_calls_deserialization() -> Vec<ToolCall> {
     Vec::new()
 }
 
-impl Default for ChatCompletionMessageRole {
-    fn default() -> Self {
-        Self::User
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -547,6 +1777,8 @@ mod tests {
                 function_call: None,
                 tool_call_id: None,
                 tool_calls: Vec::new(),
+                annotations: Vec::new(),
+                content_parts: Vec::new(),
             }],
         )
         .temperature(0.0)
@@ -588,6 +1820,8 @@ mod tests {
                 function_call: None,
                 tool_call_id: None,
                 tool_calls: Vec::new(),
+                annotations: Vec::new(),
+                content_parts: Vec::new(),
             }],
         )
         // Determinism currently comes from temperature 0, not seed.
@@ -611,6 +1845,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "tokio-runtime")]
     #[tokio::test]
     async fn chat_stream() {
         dotenv().ok();
@@ -625,6 +1860,8 @@ mod tests {
                 function_call: None,
                 tool_call_id: None,
                 tool_calls: Vec::new(),
+                annotations: Vec::new(),
+                content_parts: Vec::new(),
             }],
         )
         .temperature(0.0)
@@ -648,7 +1885,9 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "tokio-runtime")]
     #[tokio::test]
+    #[allow(deprecated)]
     async fn chat_function() {
         dotenv().ok();
         let credentials = Credentials::from_env(crate::ApiProvider::OpenAI);
@@ -663,6 +1902,8 @@ mod tests {
                     function_call: None,
                     tool_call_id: None,
                     tool_calls: Vec::new(),
+                    annotations: Vec::new(),
+                    content_parts: Vec::new(),
                 }
             ]
         ).functions([ChatCompletionFunctionDefinition {
@@ -732,6 +1973,8 @@ mod tests {
                 function_call: None,
                 tool_call_id: None,
                 tool_calls: Vec::new(),
+                annotations: Vec::new(),
+                content_parts: Vec::new(),
             }],
         )
         .temperature(0.0)
@@ -779,11 +2022,1134 @@ mod tests {
         assert_ne!(builder_c, builder_d);
     }
 
+    #[test]
+    fn base_url_override() {
+        let credentials = Credentials::new("sk-test", "https://api.openai.com/v1");
+        let request = ChatCompletion::builder("gpt-4", [])
+            .credentials(credentials)
+            .base_url("https://staging.example.com/v1")
+            .build()
+            .unwrap();
+        let request_credentials = request.request_credentials().unwrap();
+        assert_eq!(request_credentials.base_url(), "https://staging.example.com/v1/");
+        assert_eq!(request_credentials.api_key(), "sk-test");
+        assert_eq!(request_credentials.provider(), &crate::ApiProvider::OpenAI);
+        // The override doesn't leak into the credentials given to the builder.
+        assert_eq!(
+            request.credentials.unwrap().base_url(),
+            "https://api.openai.com/v1/"
+        );
+    }
+
+    #[test]
+    fn request_headers() {
+        let credentials = Credentials::new("sk-test", "https://api.openai.com/v1").with_header(
+            HeaderName::from_static("x-tenant-id"),
+            HeaderValue::from_static("tenant-1"),
+        );
+        let request = ChatCompletion::builder("gpt-4", [])
+            .credentials(credentials)
+            .add_header(
+                HeaderName::from_static("x-trace-id"),
+                HeaderValue::from_static("trace-1"),
+            )
+            .build()
+            .unwrap();
+        let headers = request.request_credentials().unwrap().headers().clone();
+        assert_eq!(headers["x-tenant-id"], "tenant-1");
+        assert_eq!(headers["x-trace-id"], "trace-1");
+    }
+
+    #[test]
+    fn request_without_auth_header() {
+        let request = ChatCompletion::builder("gpt-4", [])
+            .credentials(Credentials::new("sk-test", "https://api.openai.com/v1"))
+            .auth_header(false)
+            .build()
+            .unwrap();
+        assert!(!request.request_credentials().unwrap().auth_header());
+    }
+
+    #[tokio::test]
+    async fn context_length_error() {
+        let credentials = crate::tests::mock_server(crate::tests::http_response(
+            "400 Bad Request",
+            "",
+            r#"{"error": {
+                "message": "This model's maximum context length is 8192 tokens.",
+                "type": "invalid_request_error",
+                "param": "messages",
+                "code": "context_length_exceeded"
+            }}"#,
+        ))
+        .await;
+        let error = ChatCompletion::builder("gpt-4", [])
+            .credentials(credentials)
+            .add_user("a".repeat(36_000))
+            .create()
+            .await
+            .unwrap_err();
+
+        assert!(error.is_context_length_exceeded());
+        assert_eq!(
+            error.context_length,
+            Some(crate::ContextLengthDetails {
+                context_window: Some(8_192),
+                estimated_tokens: 9_007,
+            })
+        );
+        assert!(error.message.ends_with("(sent ~9007 tokens, limit 8192)"));
+    }
+
+    #[tokio::test]
+    async fn chat_response_meta() {
+        let credentials = crate::tests::mock_server(crate::tests::json_response(
+            "x-gateway-id: gw-123\r\nx-custom: hello\r\n",
+            r#"{
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 1700000000,
+                "model": "gpt-4o",
+                "choices": [{
+                    "index": 0,
+                    "finish_reason": "stop",
+                    "message": {"role": "assistant", "content": "Hi!"}
+                }]
+            }"#,
+        ))
+        .await;
+
+        let (chat_completion, meta) = ChatCompletion::builder("gpt-4o", [])
+            .add_user("Hello!")
+            .credentials(credentials)
+            .create_with_response_meta()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            chat_completion.choices[0].message.content.as_deref(),
+            Some("Hi!")
+        );
+        assert_eq!(meta.status, reqwest::StatusCode::OK);
+        assert_eq!(meta.headers["x-gateway-id"], "gw-123");
+        assert_eq!(meta.headers["x-custom"], "hello");
+    }
+
+    #[tokio::test]
+    async fn request_ids() {
+        let credentials = crate::tests::mock_server_sequence(vec![
+            crate::tests::http_response(
+                "200 OK",
+                "x-request-id: req_ok\r\n",
+                r#"{"id": "chatcmpl-1", "object": "chat.completion", "created": 1700000000,
+                    "model": "gpt-4o", "choices": []}"#,
+            ),
+            crate::tests::http_response(
+                "400 Bad Request",
+                "x-request-id: req_failed\r\n",
+                r#"{"error": {"message": "Bad request", "type": "invalid_request_error"}}"#,
+            ),
+        ])
+        .await;
+
+        let chat_completion = ChatCompletion::builder("gpt-4o", [])
+            .add_user("Hello!")
+            .credentials(credentials.clone())
+            .create()
+            .await
+            .unwrap();
+        assert_eq!(chat_completion.request_id.as_deref(), Some("req_ok"));
+
+        let error = ChatCompletion::builder("gpt-4o", [])
+            .add_user("Hello!")
+            .credentials(credentials)
+            .create()
+            .await
+            .unwrap_err();
+        assert_eq!(error.request_id.as_deref(), Some("req_failed"));
+        assert_eq!(error.status, Some(reqwest::StatusCode::BAD_REQUEST));
+    }
+
+    #[test]
+    fn message_annotations() {
+        let message: ChatCompletionMessage = serde_json::from_value(serde_json::json!({
+            "role": "assistant",
+            "content": "Rust 1.0 was released in May 2015.",
+            "annotations": [
+                {
+                    "type": "url_citation",
+                    "url_citation": {
+                        "start_index": 0,
+                        "end_index": 34,
+                        "url": "https://blog.rust-lang.org/2015/05/15/Rust-1.0.html",
+                        "title": "Announcing Rust 1.0"
+                    }
+                },
+                {"type": "file_citation", "file_id": "file-123", "index": 3},
+                {"type": "something_new"}
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            message.citations(),
+            [&UrlCitation {
+                start_index: 0,
+                end_index: 34,
+                url: "https://blog.rust-lang.org/2015/05/15/Rust-1.0.html".to_string(),
+                title: "Announcing Rust 1.0".to_string(),
+            }]
+        );
+        assert_eq!(
+            message.annotations[1],
+            ChatCompletionAnnotation::FileCitation {
+                file_id: "file-123".to_string(),
+                filename: None,
+                index: Some(3),
+            }
+        );
+        assert_eq!(message.annotations[2], ChatCompletionAnnotation::Unknown);
+
+        // Messages without annotations still parse, and don't send them back.
+        let message: ChatCompletionMessage =
+            serde_json::from_str(r#"{"role": "assistant", "content": "Hi!"}"#).unwrap();
+        assert!(message.citations().is_empty());
+        assert!(!serde_json::to_string(&message)
+            .unwrap()
+            .contains("annotations"));
+    }
+
+    const STREAM_START: &str = "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\n\r\n\
+        data: {\"id\": \"chatcmpl-1\", \"object\": \"chat.completion.chunk\", \"created\": 1, \
+        \"model\": \"gpt-4o\", \"choices\": [{\"index\": 0, \"finish_reason\": null, \
+        \"delta\": {\"role\": \"assistant\", \"content\": \"Hi\"}}]}\n\n";
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn cancel_stream() {
+        let (credentials, closed) = crate::tests::mock_stream_server(STREAM_START).await;
+        let (mut rx, cancel) = ChatCompletion::builder("gpt-4o", [])
+            .credentials(credentials)
+            .create_stream_cancellable()
+            .await
+            .unwrap();
+        let delta = rx.recv().await.unwrap().unwrap();
+        assert_eq!(delta.choices[0].delta.content.as_deref(), Some("Hi"));
+
+        cancel.cancel();
+        let timeout = Duration::from_secs(5);
+        assert!(tokio::time::timeout(timeout, rx.recv())
+            .await
+            .unwrap()
+            .is_none());
+        tokio::time::timeout(timeout, closed).await.unwrap().unwrap();
+        assert!(cancel.is_finished());
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn golden_request() {
+        let get_weather = ChatCompletionFunctionDefinition {
+            name: "get_weather".to_string(),
+            description: Some("The current weather in a city".to_string()),
+            parameters: Some(serde_json::json!({
+                "type": "object",
+                "properties": {"city": {"type": "string"}},
+                "required": ["city"]
+            })),
+        };
+        let request = ChatCompletion::builder(
+            "gpt-4o",
+            [
+                ChatCompletionMessage::system("Be brief."),
+                ChatCompletionMessage::user("What's the weather in Paris?"),
+                ChatCompletionMessage {
+                    tool_calls: vec![ToolCall {
+                        id: "call_1".to_string(),
+                        r#type: "function".to_string(),
+                        function: ToolCallFunction {
+                            name: "get_weather".to_string(),
+                            arguments: r#"{"city":"Paris"}"#.to_string(),
+                        },
+                    }],
+                    content: None,
+                    ..ChatCompletionMessage::assistant("")
+                },
+                ChatCompletionMessage::tool("call_1", "15°C, cloudy"),
+            ],
+        )
+        .temperature(0.5)
+        .max_tokens(256u64)
+        .stop(["END"])
+        .function_call(serde_json::json!({"name": "get_weather", "arguments": "{}"}))
+        .tools(vec![ChatCompletionTool::function(get_weather)])
+        .tool_choice(ToolChoice::Auto)
+        .logit_bias(HashMap::from([
+            ("50256".to_string(), -100.0),
+            ("1169".to_string(), 5.0),
+            ("464".to_string(), 10.0),
+        ]))
+        .response_format(ChatCompletionResponseFormat::text())
+        .user("user-1")
+        .build()
+        .unwrap();
+        crate::tests::assert_golden("chat_request", &request);
+    }
+
+    #[test]
+    fn tool_call_arguments() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Weather {
+            city: String,
+            unit: Option<String>,
+        }
+
+        let tool_call = |arguments: &str| ToolCall {
+            id: "call_1".to_string(),
+            r#type: "function".to_string(),
+            function: ToolCallFunction {
+                name: "get_weather".to_string(),
+                arguments: arguments.to_string(),
+            },
+        };
+        assert_eq!(
+            tool_call(r#"{"city": "Paris"}"#).parse_arguments::<Weather>().unwrap(),
+            Weather {
+                city: "Paris".to_string(),
+                unit: None
+            }
+        );
+        assert!(tool_call(r#"{"town": "Paris"}"#)
+            .parse_arguments::<Weather>()
+            .is_err());
+    }
+
+    #[cfg(feature = "schemars")]
+    #[test]
+    fn tool_from_type() {
+        #[derive(schemars::JsonSchema)]
+        #[allow(dead_code)]
+        struct Weather {
+            city: String,
+            unit: Option<String>,
+        }
+
+        let tool = ChatCompletionTool::tool_from::<Weather>("get_weather", "The current weather");
+        assert_eq!(tool.r#type, "function");
+        assert_eq!(tool.function.name, "get_weather");
+        assert_eq!(tool.function.description.as_deref(), Some("The current weather"));
+        let parameters = tool.function.parameters.unwrap();
+        assert_eq!(parameters["type"], "object");
+        assert_eq!(parameters["properties"]["city"]["type"], "string");
+        assert_eq!(parameters["required"], serde_json::json!(["city"]));
+    }
+
+    #[test]
+    fn wire_types_round_trip() {
+        use crate::tests::assert_round_trip;
+
+        assert_round_trip(&vec![
+            ChatCompletionMessage::system("Be brief."),
+            ChatCompletionMessage::tool("call_1", "Sunny"),
+            ChatCompletionMessage {
+                tool_calls: vec![ToolCall {
+                    id: "call_1".to_string(),
+                    r#type: "function".to_string(),
+                    function: ToolCallFunction {
+                        name: "get_weather".to_string(),
+                        arguments: "{}".to_string(),
+                    },
+                }],
+                ..ChatCompletionMessage::assistant("Let me check.")
+            },
+        ]);
+        assert_round_trip(&vec![
+            ChatCompletionContentPart::Text {
+                text: "Hi".to_string(),
+            },
+            ChatCompletionContentPart::ImageUrl {
+                image_url: ImageUrl {
+                    url: "https://example.com/crab.png".to_string(),
+                    detail: Some(ImageDetail::Low),
+                },
+            },
+            ChatCompletionContentPart::Document {
+                source: DocumentSource::pdf(b"%PDF-1.7"),
+            },
+            ChatCompletionContentPart::ToolUse {
+                id: "toolu_1".to_string(),
+                name: "get_weather".to_string(),
+                input: serde_json::json!({"city": "Paris"}),
+            },
+            ChatCompletionContentPart::tool_error("toolu_1", "unknown city"),
+        ]);
+        assert_round_trip(&ChatCompletionTool::function(
+            ChatCompletionFunctionDefinition {
+                name: "get_weather".to_string(),
+                description: None,
+                parameters: Some(serde_json::json!({"type": "object"})),
+            },
+        ));
+        assert_round_trip(&vec![
+            FinishReason::Stop,
+            FinishReason::ToolCalls,
+            FinishReason::Unknown("paused".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn role_constructors() {
+        let messages = [
+            ChatCompletionMessage::system("Be brief."),
+            ChatCompletionMessage::user("What's the weather?"),
+            ChatCompletionMessage::assistant("Let me check."),
+            ChatCompletionMessage::tool("call_1", "Sunny"),
+        ];
+        assert_eq!(
+            serde_json::to_value(&messages).unwrap(),
+            serde_json::json!([
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": "What's the weather?"},
+                {"role": "assistant", "content": "Let me check."},
+                {"role": "tool", "content": "Sunny", "tool_call_id": "call_1"}
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn several_choices() {
+        let choice = |index: u32, content: &str| {
+            format!(
+                r#"{{"index": {index}, "finish_reason": "stop", "message": {{"role": "assistant", "content": "{content}"}}}}"#
+            )
+        };
+        let response = format!(
+            r#"{{"id": "chatcmpl-1", "object": "chat.completion", "created": 1, "model": "gpt-4o",
+            "choices": [{}, {}, {}], "usage": null, "system_fingerprint": "fp_44709d6fcb"}}"#,
+            choice(0, "Crab"),
+            choice(1, "Lobster"),
+            choice(2, "Shrimp")
+        );
+        let credentials = crate::tests::mock_server(crate::tests::json_response("", &response)).await;
+        let completion = ChatCompletion::builder("gpt-4o", [])
+            .add_user("Name a crustacean.")
+            .n(3)
+            .credentials(credentials)
+            .create()
+            .await
+            .unwrap();
+        assert_eq!(completion.choices.len(), 3);
+        assert_eq!(completion.first_message().unwrap().text(), "Crab");
+        let texts: Vec<_> = completion.messages().map(ChatCompletionMessage::text).collect();
+        assert_eq!(texts, ["Crab", "Lobster", "Shrimp"]);
+        assert_eq!(completion.system_fingerprint.as_deref(), Some("fp_44709d6fcb"));
+    }
+
+    #[test]
+    fn continue_conversation() {
+        let completion: ChatCompletion = serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1,
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "finish_reason": "tool_calls",
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}
+                    }],
+                    "annotations": [{
+                        "type": "url_citation",
+                        "url_citation": {
+                            "start_index": 0,
+                            "end_index": 5,
+                            "url": "https://example.com/weather",
+                            "title": "Weather"
+                        }
+                    }]
+                }
+            }]
+        }))
+        .unwrap();
+        let mut messages = vec![ChatCompletionMessage::user("What's the weather in Paris?")];
+        messages.push(completion.as_message().unwrap());
+        messages.push(ChatCompletionMessage::tool("call_1", "15°C, cloudy"));
+        assert_eq!(
+            serde_json::to_value(&messages[1]).unwrap(),
+            serde_json::json!({
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [{
+                    "id": "call_1",
+                    "type": "function",
+                    "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}
+                }]
+            })
+        );
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn stalled_stream() {
+        let (credentials, _closed) = crate::tests::mock_stream_server(STREAM_START).await;
+        let mut deltas = ChatCompletion::builder("gpt-4o", [])
+            .add_user("Hello!")
+            .credentials(credentials.with_stream_idle_timeout(Duration::from_secs(60)))
+            .stream_idle_timeout(Duration::from_millis(100))
+            .create_stream()
+            .await
+            .unwrap();
+        assert!(deltas.recv().await.unwrap().is_ok());
+        let error = deltas.recv().await.unwrap().unwrap_err();
+        assert_eq!(error.error_type, "stream_idle_timeout");
+        assert_eq!(error.code.as_deref(), Some("timeout"));
+        assert!(deltas.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn response_stream() {
+        use futures_util::StreamExt;
+
+        let chunk = "data: {\"id\": \"chatcmpl-1\", \"object\": \"chat.completion.chunk\", \
+            \"created\": 1, \"model\": \"gpt-4o\", \"choices\": [{\"index\": 0, \
+            \"finish_reason\": null, \"delta\": {\"content\": \" there\"}}]}\n\n";
+        let response = format!("{STREAM_START}{chunk}{chunk}data: [DONE]\n\n");
+        let (credentials, _closed) = crate::tests::mock_stream_server(response).await;
+        let stream = ChatCompletion::builder("gpt-4o", [])
+            .credentials(credentials)
+            .create_response_stream()
+            .await
+            .unwrap();
+        let contents: Vec<String> = stream
+            .take(2)
+            .map(|delta| delta.unwrap().choices[0].delta.content.clone().unwrap())
+            .collect()
+            .await;
+        assert_eq!(contents, ["Hi", " there"]);
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn text_chunks() {
+        let chunk = |choice: &str| {
+            format!(
+                "data: {{\"id\": \"chatcmpl-1\", \"object\": \"chat.completion.chunk\", \
+                \"created\": 1, \"model\": \"gpt-4o\", \"choices\": [{choice}]}}\n\n"
+            )
+        };
+        let response = format!(
+            "{STREAM_START}{}{}{}{}data: [DONE]\n\n",
+            chunk(r#"{"index": 0, "finish_reason": null, "delta": {"content": ""}}"#),
+            chunk(r#"{"index": 0, "finish_reason": null, "delta": {"tool_calls": [{"index": 0, "function": {"arguments": "{}"}}]}}"#),
+            chunk(r#"{"index": 0, "finish_reason": null, "delta": {"content": " there"}}"#),
+            chunk(r#"{"index": 0, "finish_reason": "stop", "delta": {}}"#),
+        );
+        let (credentials, _closed) = crate::tests::mock_stream_server(response).await;
+        let mut rx = ChatCompletion::builder("gpt-4o", [])
+            .credentials(credentials)
+            .create_text_stream()
+            .await
+            .unwrap();
+        let mut chunks = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            chunks.push(chunk.unwrap());
+        }
+        assert_eq!(
+            chunks,
+            [
+                TextChunk::Text("Hi".to_string()),
+                TextChunk::Text(" there".to_string()),
+                TextChunk::Finished(FinishReason::Stop),
+            ]
+        );
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn stream_error_event() {
+        let response = format!(
+            "{STREAM_START}data: {{\"error\": {{\"message\": \"The server is overloaded\", \
+            \"type\": \"server_error\", \"param\": null, \"code\": null}}}}\n\n"
+        );
+        let (credentials, _closed) = crate::tests::mock_stream_server(response).await;
+        let mut rx = ChatCompletion::builder("gpt-4o", [])
+            .credentials(credentials)
+            .create_stream()
+            .await
+            .unwrap();
+        assert!(rx.recv().await.unwrap().is_ok());
+        let error = rx.recv().await.unwrap().unwrap_err();
+        assert_eq!(error.error_type, "server_error");
+        assert_eq!(error.message, "The server is overloaded");
+        // The error ends the stream.
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn stream_error_status() {
+        let credentials = crate::tests::mock_server(crate::tests::http_response(
+            "401 Unauthorized",
+            "",
+            r#"{"error": {"message": "Incorrect API key provided", "type": "invalid_request_error", "param": null, "code": "invalid_api_key"}}"#,
+        ))
+        .await;
+        let mut rx = ChatCompletion::builder("gpt-4o", [])
+            .credentials(credentials)
+            .create_stream()
+            .await
+            .unwrap();
+        let error = rx.recv().await.unwrap().unwrap_err();
+        assert_eq!(error.code.as_deref(), Some("invalid_api_key"));
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn drop_stream_receiver() {
+        let (credentials, closed) = crate::tests::mock_stream_server(STREAM_START).await;
+        let mut rx = ChatCompletion::builder("gpt-4o", [])
+            .credentials(credentials)
+            .create_stream()
+            .await
+            .unwrap();
+        rx.recv().await.unwrap().unwrap();
+
+        // The server sends nothing more, yet the connection is closed right away.
+        drop(rx);
+        tokio::time::timeout(Duration::from_secs(5), closed)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    fn create_blocking() {
+        // The mock server needs a runtime of its own, apart from the blocking call.
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let credentials = runtime.block_on(crate::tests::mock_server(
+            crate::tests::json_response(
+                "",
+                r#"{
+                    "id": "chatcmpl-1",
+                    "object": "chat.completion",
+                    "created": 1700000000,
+                    "model": "gpt-4o",
+                    "choices": [{
+                        "index": 0,
+                        "finish_reason": "stop",
+                        "message": {"role": "assistant", "content": "Hi!"}
+                    }]
+                }"#,
+            ),
+        ));
+
+        let completion = ChatCompletion::builder("gpt-4o", [])
+            .credentials(credentials)
+            .add_user("Hello!")
+            .create_blocking()
+            .unwrap();
+        assert_eq!(completion.choices[0].message.text(), "Hi!");
+    }
+
+    #[test]
+    fn parsed_structured_output() {
+        fn completion(finish_reason: &str, content: &str) -> ChatCompletion {
+            serde_json::from_value(serde_json::json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 1700000000,
+                "model": "gpt-4o",
+                "choices": [{
+                    "index": 0,
+                    "finish_reason": finish_reason,
+                    "message": {"role": "assistant", "content": content}
+                }]
+            }))
+            .unwrap()
+        }
+
+        let parsed: HashMap<String, u32> = completion("stop", r#"{"crabs": 10}"#)
+            .parsed()
+            .unwrap();
+        assert_eq!(parsed["crabs"], 10);
+
+        let truncated = completion("length", r#"{"crabs": 10, "lobst"#);
+        let error = truncated.parsed::<HashMap<String, u32>>().unwrap_err();
+        assert!(matches!(error, StructuredOutputError::Truncated));
+        assert!(error.to_string().contains("max_tokens"));
+
+        let invalid = completion("stop", "not json");
+        assert!(matches!(
+            invalid.parsed::<HashMap<String, u32>>(),
+            Err(StructuredOutputError::Json(_))
+        ));
+    }
+
+    fn json_completion(content: &str) -> String {
+        let body = serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "finish_reason": "stop",
+                "message": {"role": "assistant", "content": content}
+            }]
+        });
+        crate::tests::json_response("", &body.to_string())
+    }
+
+    #[tokio::test]
+    async fn create_parsed_retries_invalid_json() {
+        let credentials = crate::tests::mock_server_sequence(vec![
+            json_completion(r#"{"crabs": "ten"}"#),
+            json_completion(r#"{"crabs": 10}"#),
+        ])
+        .await;
+        let parsed: HashMap<String, u32> = ChatCompletion::builder("gpt-4o", [])
+            .credentials(credentials)
+            .add_user("How many crabs? Answer as JSON.")
+            .response_format(ChatCompletionResponseFormat::json_object())
+            .create_parsed(3)
+            .await
+            .unwrap();
+        assert_eq!(parsed["crabs"], 10);
+    }
+
+    #[tokio::test]
+    async fn create_parsed_exhausted() {
+        let credentials =
+            crate::tests::mock_server_sequence(vec![json_completion("not json")]).await;
+        let result = ChatCompletion::builder("gpt-4o", [])
+            .credentials(credentials)
+            .create_parsed::<HashMap<String, u32>>(2)
+            .await;
+        assert!(matches!(result, Err(StructuredOutputError::Json(_))));
+    }
+
+    #[test]
+    fn tools_serialization() {
+        let get_weather = ChatCompletionFunctionDefinition {
+            name: "get_weather".to_string(),
+            description: None,
+            parameters: Some(serde_json::json!({"type": "object", "properties": {}})),
+        };
+        let request = ChatCompletion::builder("gpt-4o", [])
+            .add_user("What is the weather in Boston?")
+            .tools([ChatCompletionTool::function(get_weather)])
+            .tool_choice(ToolChoice::function("get_weather"))
+            .build()
+            .unwrap();
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(
+            json["tools"],
+            serde_json::json!([{
+                "type": "function",
+                "function": {
+                    "name": "get_weather",
+                    "parameters": {"type": "object", "properties": {}}
+                }
+            }])
+        );
+        assert_eq!(
+            json["tool_choice"],
+            serde_json::json!({"type": "function", "function": {"name": "get_weather"}})
+        );
+
+        for (tool_choice, expected) in [
+            (ToolChoice::None, "none"),
+            (ToolChoice::Auto, "auto"),
+            (ToolChoice::Required, "required"),
+        ] {
+            assert_eq!(serde_json::to_value(tool_choice).unwrap(), expected);
+        }
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn builder_errors() {
+        let error = ChatCompletionBuilder::default().create().await.unwrap_err();
+        assert_eq!(error.error_type, "builder");
+        assert_eq!(error.param.as_deref(), Some("model"));
+
+        let error = ChatCompletion::builder("gpt-4o", [])
+            .stop(vec!["a".to_string(); MAX_STOP_SEQUENCES + 1])
+            .create_stream()
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_type, "builder");
+        assert_eq!(error.param, None);
+    }
+
+    #[test]
+    fn json_schema_response_format() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {"fact": {"type": "string"}},
+            "required": ["fact"],
+            "additionalProperties": false
+        });
+        let request = ChatCompletion::builder("gpt-4o", [])
+            .add_user("Tell me a random crab fact")
+            .response_format(ChatCompletionResponseFormat::json_schema(
+                "crab_fact",
+                schema.clone(),
+                true,
+            ))
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&request).unwrap()["response_format"],
+            serde_json::json!({
+                "type": "json_schema",
+                "json_schema": {"name": "crab_fact", "schema": schema, "strict": true}
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(ChatCompletionResponseFormat::json_object()).unwrap(),
+            serde_json::json!({"type": "json_object"})
+        );
+    }
+
+    #[cfg(feature = "schemars")]
+    #[test]
+    fn json_schema_for_type() {
+        #[derive(schemars::JsonSchema)]
+        #[allow(dead_code)]
+        struct CrabFact {
+            fact: String,
+            source: Option<String>,
+        }
+
+        let format = ChatCompletionResponseFormat::json_schema_for::<CrabFact>("crab_fact", true);
+        let schema = &format.json_schema.as_ref().unwrap().schema;
+        assert_eq!(schema["type"], "object");
+        assert_eq!(schema["properties"]["fact"]["type"], "string");
+        assert_eq!(schema["required"], serde_json::json!(["fact", "source"]));
+        assert_eq!(schema["additionalProperties"], false);
+        assert!(schema.get("$schema").is_none());
+
+        let format = ChatCompletionResponseFormat::json_schema_for::<CrabFact>("crab_fact", false);
+        let schema = &format.json_schema.unwrap().schema;
+        assert_eq!(schema["required"], serde_json::json!(["fact"]));
+        assert!(schema.get("additionalProperties").is_none());
+    }
+
+    #[test]
+    fn logprobs() {
+        let request = ChatCompletion::builder("gpt-4o", [])
+            .logprobs(true)
+            .top_logprobs(2)
+            .build()
+            .unwrap();
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["logprobs"], true);
+        assert_eq!(json["top_logprobs"], 2);
+
+        let token = |token: &str, logprob: f64| {
+            format!(
+                r#"{{"token": "{token}", "logprob": {logprob}, "bytes": [72, 105], "top_logprobs": [
+                    {{"token": "{token}", "logprob": {logprob}, "bytes": [72, 105]}},
+                    {{"token": "Hello", "logprob": -2.5, "bytes": null}}
+                ]}}"#
+            )
+        };
+        let chunk = |content: &str, token: String| {
+            serde_json::from_str::<ChatCompletionDelta>(&format!(
+                r#"{{"id": "chatcmpl-1", "object": "chat.completion.chunk", "created": 1, "model": "gpt-4o",
+                "choices": [{{"index": 0, "finish_reason": null, "delta": {{"content": "{content}"}},
+                "logprobs": {{"content": [{token}], "refusal": null}}}}]}}"#
+            ))
+            .unwrap()
+        };
+        let mut delta = chunk("Hi", token("Hi", -0.01));
+        delta.merge(chunk("!", token("!", -0.5))).unwrap();
+        let completion = ChatCompletion::from(delta);
+        let logprobs = completion.choices[0].logprobs.as_ref().unwrap();
+        let tokens = logprobs.content.as_ref().unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].token, "Hi");
+        assert_eq!(tokens[0].bytes.as_deref(), Some(b"Hi".as_slice()));
+        assert!((tokens[0].probability() - 0.99).abs() < 0.001);
+        assert_eq!(tokens[1].token, "!");
+        assert_eq!(tokens[1].top_logprobs[1].token, "Hello");
+        assert_eq!(tokens[1].top_logprobs[1].logprob, -2.5);
+        assert_eq!(logprobs.refusal, None);
+    }
+
+    #[test]
+    fn to_json() {
+        let request = ChatCompletion::builder("gpt-4o", [])
+            .add_user("Hello!")
+            .stop(vec!["END".to_string()])
+            .credentials(Credentials::new("sk-test", "https://api.openai.com/v1/"))
+            .build()
+            .unwrap();
+        let json = request.to_json().unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&json).unwrap(),
+            serde_json::json!({
+                "model": "gpt-4o",
+                "messages": [{"role": "user", "content": "Hello!"}],
+                "stop": ["END"]
+            })
+        );
+        assert!(!json.contains("sk-test"));
+    }
+
+    #[test]
+    fn tool_call_deltas() {
+        let chunk = |tool_calls: &str| {
+            serde_json::from_str::<ChatCompletionDelta>(&format!(
+                r#"{{"id": "chatcmpl-1", "object": "chat.completion.chunk", "created": 1, "model": "gpt-4o",
+                "choices": [{{"index": 0, "finish_reason": null, "delta": {{"tool_calls": [{tool_calls}]}}}}]}}"#
+            ))
+            .unwrap()
+        };
+        let chunks = [
+            r#"{"index": 0, "id": "call_paris", "type": "function", "function": {"name": "get_weather", "arguments": ""}}"#,
+            r#"{"index": 1, "id": "call_tokyo", "type": "function", "function": {"name": "get_weather", "arguments": "{\"ci"}}"#,
+            r#"{"index": 0, "function": {"arguments": "{\"city\": "}}"#,
+            r#"{"index": 1, "function": {"arguments": "ty\": \"Tokyo\"}"}}, {"index": 0, "function": {"arguments": "\"Paris\"}"}}"#,
+        ]
+        .map(chunk);
+
+        let mut accumulator = ToolCallAccumulator::default();
+        for chunk in &chunks {
+            accumulator.extend(&chunk.choices[0].delta.tool_calls);
+        }
+        let tool_call = |id: &str, city: &str| ToolCall {
+            id: id.to_string(),
+            r#type: "function".to_string(),
+            function: ToolCallFunction {
+                name: "get_weather".to_string(),
+                arguments: format!(r#"{{"city": "{city}"}}"#),
+            },
+        };
+        let expected = [tool_call("call_paris", "Paris"), tool_call("call_tokyo", "Tokyo")];
+        assert_eq!(accumulator.finish(), expected);
+
+        let [mut merged, rest @ ..] = chunks;
+        for chunk in rest {
+            merged.merge(chunk).unwrap();
+        }
+        let completion = ChatCompletion::from(merged);
+        assert_eq!(completion.choices[0].message.tool_calls, expected);
+    }
+
+    #[test]
+    fn tool_calls_out_of_order() {
+        let delta = |index: u32, id: &str| ToolCallDelta {
+            index,
+            id: Some(id.to_string()),
+            r#type: None,
+            function: None,
+        };
+        let mut accumulator = ToolCallAccumulator::default();
+        accumulator.extend(&[delta(2, "call_c"), delta(0, "call_a"), delta(1, "call_b")]);
+        let ids: Vec<_> = accumulator
+            .tool_calls()
+            .iter()
+            .map(|tool_call| tool_call.id.as_deref().unwrap())
+            .collect();
+        assert_eq!(ids, ["call_a", "call_b", "call_c"]);
+    }
+
+    #[test]
+    fn finish_reason() {
+        let reason = |raw: &str| serde_json::from_value::<FinishReason>(raw.into()).unwrap();
+        assert_eq!(reason("stop"), FinishReason::Stop);
+        assert_eq!(reason("length"), FinishReason::Length);
+        assert_eq!(reason("tool_calls"), FinishReason::ToolCalls);
+        assert_eq!(reason("content_filter"), FinishReason::ContentFilter);
+        assert_eq!(reason("function_call"), FinishReason::FunctionCall);
+        let unknown = reason("paused");
+        assert_eq!(unknown, FinishReason::Unknown("paused".to_string()));
+        assert_eq!(unknown.as_str(), "paused");
+        assert_eq!(serde_json::to_value(&unknown).unwrap(), "paused");
+        assert_eq!(serde_json::to_value(FinishReason::Length).unwrap(), "length");
+        assert!(FinishReason::Length == "length");
+    }
+
+    #[test]
+    fn sampling_ranges() {
+        let build = |temperature: f32, top_p: f32| {
+            ChatCompletion::builder("gpt-4o", [])
+                .temperature(temperature)
+                .top_p(top_p)
+                .build()
+        };
+        assert!(build(0.0, 0.0).is_ok());
+        assert!(build(2.0, 1.0).is_ok());
+        let error = OpenAiError::from(build(2.5, 1.0).unwrap_err());
+        assert_eq!(error.error_type, "builder");
+        assert!(error.message.contains("temperature must be between 0 and 2, got 2.5"));
+        assert!(build(-0.5, 1.0).is_err());
+        assert!(build(f32::NAN, 1.0).is_err());
+        assert!(build(1.0, 1.5).is_err());
+    }
+
+    #[test]
+    fn parallel_tool_calls() {
+        let request = ChatCompletion::builder("gpt-4o", []).build().unwrap();
+        assert!(serde_json::to_value(&request)
+            .unwrap()
+            .get("parallel_tool_calls")
+            .is_none());
+
+        let request = ChatCompletion::builder("gpt-4o", [])
+            .parallel_tool_calls(false)
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&request).unwrap()["parallel_tool_calls"],
+            false
+        );
+    }
+
+    #[test]
+    fn stop_serialization() {
+        let stop_json = |builder: ChatCompletionBuilder| {
+            serde_json::to_value(builder.build().unwrap()).unwrap()["stop"].clone()
+        };
+        assert_eq!(
+            stop_json(ChatCompletion::builder("gpt-4o", []).stop("END")),
+            serde_json::json!("END")
+        );
+        assert_eq!(
+            stop_json(ChatCompletion::builder("gpt-4o", []).stop(["END"])),
+            serde_json::json!(["END"])
+        );
+        assert_eq!(
+            stop_json(ChatCompletion::builder("gpt-4o", []).stop(vec!["a", "b"])),
+            serde_json::json!(["a", "b"])
+        );
+        assert_eq!(
+            stop_json(ChatCompletion::builder("gpt-4o", [])),
+            serde_json::Value::Null
+        );
+
+        let error = ChatCompletion::builder("gpt-4o", [])
+            .stop(["a", "b", "c", "d", "e"])
+            .build()
+            .unwrap_err();
+        assert!(error.to_string().contains("at most 4 stop sequences"));
+    }
+
+    #[test]
+    fn message_text_parts() {
+        let message: ChatCompletionMessage = serde_json::from_value(serde_json::json!({
+            "role": "assistant",
+            "content": [
+                {"type": "text", "text": "Here is "},
+                {"type": "audio", "audio": {"id": "audio_123"}},
+                {"type": "refusal", "refusal": "I can't say that."},
+                {"type": "text", "text": "your answer."}
+            ]
+        }))
+        .unwrap();
+        assert_eq!(message.text(), "Here is your answer.");
+
+        let message: ChatCompletionMessage =
+            serde_json::from_str(r#"{"role": "assistant", "content": null}"#).unwrap();
+        assert_eq!(message.text(), "");
+        let message: ChatCompletionMessage =
+            serde_json::from_str(r#"{"role": "assistant"}"#).unwrap();
+        assert_eq!(message.content, None);
+    }
+
+    #[test]
+    fn image_message_serialization() {
+        let mut message =
+            ChatCompletionMessage::user_with_image("What is this?", "https://example.com/crab.png");
+        assert_eq!(message.text(), "What is this?");
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            serde_json::json!({
+                "role": "user",
+                "content": [
+                    {"type": "text", "text": "What is this?"},
+                    {"type": "image_url", "image_url": {"url": "https://example.com/crab.png"}}
+                ]
+            })
+        );
+
+        if let ChatCompletionContentPart::ImageUrl { image_url } = &mut message.content_parts[1] {
+            image_url.detail = Some(ImageDetail::Low);
+        }
+        assert_eq!(
+            serde_json::to_value(&message).unwrap()["content"][1]["image_url"]["detail"],
+            "low"
+        );
+
+        // Messages without parts are sent as before.
+        let message = ChatCompletionMessage {
+            role: ChatCompletionMessageRole::User,
+            content: Some("Hello!".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            serde_json::json!({"role": "user", "content": "Hello!"})
+        );
+    }
+
+    #[test]
+    fn builder_add_messages() {
+        let request = ChatCompletion::builder(
+            "gpt-4",
+            [ChatCompletionMessage {
+                role: ChatCompletionMessageRole::System,
+                content: Some("You are a helpful assistant.".to_string()),
+                ..Default::default()
+            }],
+        )
+        .add_user("Hello!")
+        .add_assistant("Hi! How can I help?")
+        .add_message(ChatCompletionMessage {
+            role: ChatCompletionMessageRole::User,
+            content: Some("Tell me a joke.".to_string()),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+
+        let roles: Vec<_> = request.messages.iter().map(|m| m.role).collect();
+        assert_eq!(
+            roles,
+            [
+                ChatCompletionMessageRole::System,
+                ChatCompletionMessageRole::User,
+                ChatCompletionMessageRole::Assistant,
+                ChatCompletionMessageRole::User,
+            ]
+        );
+        assert_eq!(request.messages[1].content.as_deref(), Some("Hello!"));
+
+        // Adding to a builder without initial messages starts a new conversation.
+        let request = ChatCompletionBuilder::default()
+            .model("gpt-4")
+            .add_user("Hello!")
+            .build()
+            .unwrap();
+        assert_eq!(request.messages.len(), 1);
+    }
+
+    #[cfg(feature = "tokio-runtime")]
     async fn stream_to_completion(
-        mut chat_stream: Receiver<ChatCompletionDelta>,
+        mut chat_stream: Receiver<ApiResponseOrError<ChatCompletionDelta>>,
     ) -> ChatCompletion {
         let mut merged: Option<ChatCompletionDelta> = None;
         while let Some(delta) = chat_stream.recv().await {
+            let delta = delta.unwrap();
             match merged.as_mut() {
                 Some(c) => {
                     c.merge(delta).unwrap();
@@ -816,6 +3182,8 @@ mod tests {
                     function_call: None,
                     tool_call_id: None,
                     tool_calls: Vec::new(),
+                    annotations: Vec::new(),
+                    content_parts: Vec::new(),
                 },
                 ChatCompletionMessage {
                     role: ChatCompletionMessageRole::Assistant,
@@ -831,6 +3199,8 @@ mod tests {
                             arguments: "not_required_to_be_valid_here".to_string(),
                         },
                     }],
+                    annotations: Vec::new(),
+                    content_parts: Vec::new(),
                 },
                 ChatCompletionMessage {
                     role: ChatCompletionMessageRole::Tool,
@@ -839,6 +3209,8 @@ mod tests {
                     function_call: None,
                     tool_call_id: Some("the_tool_call".to_owned()),
                     tool_calls: Vec::new(),
+                    annotations: Vec::new(),
+                    content_parts: Vec::new(),
                 },
             ],
         )
@@ -1,11 +1,12 @@
 //! Given a prompt, the model will return one or more predicted completions,
 //! and can also return the probabilities of alternative tokens at each position.
-use super::{openai_post, ApiResponseOrError, Credentials, Usage};
+use super::chat::MAX_STOP_SEQUENCES;
+use super::{openai_post, validate_range, ApiResponseOrError, Credentials, Usage};
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct Completion {
     pub id: String,
     pub created: u32,
@@ -14,17 +15,48 @@ pub struct Completion {
     pub usage: Usage,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct CompletionChoice {
     pub text: String,
     pub index: u16,
-    pub logprobs: Option<u16>,
+    /// The log probabilities of the tokens, when requested with `logprobs`.
+    pub logprobs: Option<CompletionLogprobs>,
     pub finish_reason: String,
 }
 
+/// The log probabilities of the tokens of a completion, and of the prompt
+/// when it is echoed.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct CompletionLogprobs {
+    pub tokens: Vec<String>,
+    /// The log probability of each token, `None` for the first token of an
+    /// echoed prompt, which nothing precedes.
+    pub token_logprobs: Vec<Option<f64>>,
+    /// The most likely tokens at each position, with their log probabilities.
+    pub top_logprobs: Vec<Option<HashMap<String, f64>>>,
+    /// The position of each token in the text, the prompt included when echoed.
+    pub text_offset: Vec<u32>,
+}
+
+impl CompletionLogprobs {
+    /// The perplexity of the tokens, the exponential of their mean negative
+    /// log probability. Tokens without a log probability are left out.
+    /// `None` when there are none.
+    pub fn perplexity(&self) -> Option<f64> {
+        let logprobs: Vec<f64> = self.token_logprobs.iter().flatten().copied().collect();
+        if logprobs.is_empty() {
+            return None;
+        }
+        let mean = logprobs.iter().sum::<f64>() / logprobs.len() as f64;
+        Some((-mean).exp())
+    }
+}
+
 #[derive(Serialize, Builder, Debug, Clone)]
 #[builder(pattern = "owned")]
 #[builder(name = "CompletionBuilder")]
+#[builder(build_fn(validate = "Self::validate"))]
 #[builder(setter(strip_option, into))]
 pub struct CompletionRequest {
     /// ID of the model to use.
@@ -135,7 +167,10 @@ pub struct CompletionRequest {
     /// values like -100 or 100 should result in a ban or exclusive selection of the relevant token.
     ///
     /// As an example, you can pass `{"50256": -100}` to prevent the <|endoftext|> token from being generated.
-    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    #[serde(
+        skip_serializing_if = "HashMap::is_empty",
+        serialize_with = "crate::serialize_sorted"
+    )]
     #[builder(default)]
     pub logit_bias: HashMap<String, i16>,
     /// A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse.
@@ -149,6 +184,13 @@ pub struct CompletionRequest {
     pub credentials: Option<Credentials>,
 }
 
+impl CompletionRequest {
+    /// The JSON body this request is sent with, such as to log a rejected request.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
+
 impl Completion {
     /// Creates a completion for the provided prompt and parameters
     async fn create(request: CompletionRequest) -> ApiResponseOrError<Self> {
@@ -162,15 +204,30 @@ impl Completion {
 }
 
 impl CompletionBuilder {
+    fn validate(&self) -> Result<(), String> {
+        validate_range("temperature", self.temperature.flatten(), 2.0)?;
+        validate_range("top_p", self.top_p.flatten(), 1.0)?;
+        if let Some(logprobs) = self.logprobs.flatten().filter(|&logprobs| logprobs > 5) {
+            return Err(format!("logprobs must be at most 5, got {logprobs}"));
+        }
+        match &self.stop {
+            Some(stop) if stop.len() > MAX_STOP_SEQUENCES => Err(format!(
+                "at most {MAX_STOP_SEQUENCES} stop sequences are allowed, got {}",
+                stop.len()
+            )),
+            _ => Ok(()),
+        }
+    }
+
     pub async fn create(self) -> ApiResponseOrError<Completion> {
-        Completion::create(self.build().unwrap()).await
+        Completion::create(self.build()?).await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::tests::DEFAULT_LEGACY_MODEL;
+    use crate::tests::{json_response, mock_server, DEFAULT_LEGACY_MODEL};
     use dotenvy::dotenv;
 
     #[tokio::test]
@@ -192,4 +249,88 @@ mod tests {
             "\n\nThis is a test."
         );
     }
+
+    #[tokio::test]
+    async fn logprobs() {
+        let response = r#"{
+            "id": "cmpl-1",
+            "created": 1,
+            "model": "gpt-3.5-turbo-instruct",
+            "choices": [{
+                "text": "Hello world",
+                "index": 0,
+                "finish_reason": "length",
+                "logprobs": {
+                    "tokens": ["Hello", " world"],
+                    "token_logprobs": [null, -0.5],
+                    "top_logprobs": [null, {" world": -0.5, " there": -1.5}],
+                    "text_offset": [0, 5]
+                }
+            }],
+            "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2}
+        }"#;
+        let credentials = mock_server(json_response("", response)).await;
+        let request = Completion::builder(DEFAULT_LEGACY_MODEL)
+            .prompt("Hello")
+            .max_tokens(1)
+            .logprobs(1)
+            .echo(true)
+            .credentials(credentials)
+            .build()
+            .unwrap();
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(
+            (&json["logprobs"], &json["echo"]),
+            (&1.into(), &true.into())
+        );
+
+        let completion = Completion::create(request).await.unwrap();
+        let logprobs = completion.choices[0].logprobs.as_ref().unwrap();
+        assert_eq!(logprobs.tokens, ["Hello", " world"]);
+        assert_eq!(logprobs.top_logprobs[1].as_ref().unwrap()[" there"], -1.5);
+        assert_eq!(logprobs.perplexity(), Some(0.5f64.exp()));
+
+        let error = Completion::builder(DEFAULT_LEGACY_MODEL)
+            .logprobs(6)
+            .build()
+            .unwrap_err();
+        assert!(error.to_string().contains("logprobs"));
+    }
+
+    #[test]
+    fn too_many_stop_sequences() {
+        let build = |count: usize| {
+            Completion::builder(DEFAULT_LEGACY_MODEL)
+                .stop(vec!["END".to_string(); count])
+                .build()
+        };
+        assert!(build(MAX_STOP_SEQUENCES).is_ok());
+        let error = build(MAX_STOP_SEQUENCES + 1).unwrap_err();
+        assert!(error.to_string().contains("at most 4 stop sequences"));
+    }
+
+    #[tokio::test]
+    async fn missing_required_field() {
+        let error = CompletionBuilder::default().create().await.unwrap_err();
+        assert_eq!(error.error_type, "builder");
+        assert_eq!(error.param.as_deref(), Some("model"));
+    }
+
+    #[test]
+    fn golden_request() {
+        let request = Completion::builder(DEFAULT_LEGACY_MODEL)
+            .prompt("Say this is a test")
+            .max_tokens(16)
+            .temperature(0.0)
+            .stop(vec!["\n".to_string()])
+            .logit_bias(HashMap::from([
+                ("50256".to_string(), -100),
+                ("1169".to_string(), 5),
+                ("464".to_string(), 10),
+            ]))
+            .user("user-1")
+            .build()
+            .unwrap();
+        crate::tests::assert_golden("completion_request", &request);
+    }
 }
@@ -0,0 +1,284 @@
+//! Reading credentials from settings files, see [`Credentials::from_file`].
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    ApiProvider, Credentials, ANTHROPIC_BASE_URL, DEFAULT_BASE_URL, GEMINI_BASE_URL, GROQ_BASE_URL,
+    OPENROUTER_BASE_URL, TOGETHER_BASE_URL,
+};
+
+/// The section of a provider in a settings file.
+#[derive(Deserialize, Debug, Default, Eq, PartialEq)]
+struct ProviderConfig {
+    api_key: Option<String>,
+    base_url: Option<String>,
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// The name of the section of `provider` in settings files.
+fn section_name(provider: &ApiProvider) -> Option<&'static str> {
+    match provider {
+        ApiProvider::OpenAI => Some("openai"),
+        ApiProvider::Anthropic => Some("anthropic"),
+        ApiProvider::OpenAICompatible => Some("openai_compatible"),
+        ApiProvider::OpenRouter => Some("openrouter"),
+        ApiProvider::Gemini => Some("gemini"),
+        ApiProvider::Together => Some("together"),
+        ApiProvider::Groq => Some("groq"),
+        #[cfg(feature = "bedrock")]
+        ApiProvider::Bedrock { .. } => None,
+    }
+}
+
+/// The base URL of `provider` when none is configured.
+fn default_base_url(provider: &ApiProvider) -> Option<&'static str> {
+    match provider {
+        ApiProvider::OpenAI => Some(DEFAULT_BASE_URL.as_str()),
+        ApiProvider::Anthropic => Some(ANTHROPIC_BASE_URL),
+        ApiProvider::OpenRouter => Some(OPENROUTER_BASE_URL),
+        ApiProvider::Gemini => Some(GEMINI_BASE_URL),
+        ApiProvider::Together => Some(TOGETHER_BASE_URL),
+        ApiProvider::Groq => Some(GROQ_BASE_URL),
+        _ => None,
+    }
+}
+
+fn unsupported(provider: &ApiProvider) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{provider:?} credentials can't be read from a file"),
+    )
+}
+
+pub(crate) fn credentials_from_file(
+    path: &Path,
+    provider: ApiProvider,
+    env: impl Fn(&str) -> Option<String>,
+) -> io::Result<Credentials> {
+    let Some(section) = section_name(&provider) else {
+        return Err(unsupported(&provider));
+    };
+    let data = std::fs::read_to_string(path)?;
+    let is_toml = path
+        .extension()
+        .is_some_and(|extension| extension == "toml");
+    let config = if is_toml {
+        parse_toml(&data, section)?
+    } else {
+        parse_json(&data, section)?
+    };
+    resolve(provider, config, env)
+}
+
+#[cfg(feature = "dotenv")]
+pub(crate) fn credentials_from_dotenv(
+    path: &Path,
+    provider: ApiProvider,
+) -> io::Result<Credentials> {
+    if section_name(&provider).is_none() {
+        return Err(unsupported(&provider));
+    }
+    let into_io = |error: dotenvy::Error| match error {
+        dotenvy::Error::Io(error) => error,
+        error => invalid_data(error.to_string()),
+    };
+    let vars = dotenvy::from_path_iter(path)
+        .map_err(into_io)?
+        .collect::<Result<HashMap<_, _>, _>>()
+        .map_err(into_io)?;
+    resolve(provider, ProviderConfig::default(), |name| {
+        vars.get(name).cloned()
+    })
+}
+
+/// Applies the environment variables over the settings of a provider.
+fn resolve(
+    provider: ApiProvider,
+    config: ProviderConfig,
+    env: impl Fn(&str) -> Option<String>,
+) -> io::Result<Credentials> {
+    let (api_key_var, base_url_var) = Credentials::env_vars(&provider);
+    let api_key = match env(api_key_var).or(config.api_key) {
+        Some(api_key) => api_key,
+        // Local servers often don't need a key.
+        None if provider == ApiProvider::OpenAICompatible => String::new(),
+        None => return Err(invalid_data(format!("no api_key for {provider:?}"))),
+    };
+    let base_url = env(base_url_var)
+        .or(config.base_url)
+        .or_else(|| default_base_url(&provider).map(str::to_string))
+        .ok_or_else(|| invalid_data(format!("no base_url for {provider:?}")))?;
+    Ok(Credentials::new_with_provider(api_key, base_url, provider))
+}
+
+fn parse_json(data: &str, section: &str) -> io::Result<ProviderConfig> {
+    let mut config: HashMap<String, Value> = serde_json::from_str(data)?;
+    match config.remove(section) {
+        Some(section) => Ok(serde_json::from_value(section)?),
+        None => Ok(ProviderConfig::default()),
+    }
+}
+
+/// Reads a section of a TOML file. Only string values are supported, which
+/// is all credentials need; the values of other keys aren't parsed.
+#[cfg(feature = "toml-subset")]
+fn parse_toml(data: &str, section: &str) -> io::Result<ProviderConfig> {
+    let mut config = ProviderConfig::default();
+    let mut in_section = false;
+    for (number, line) in data.lines().enumerate() {
+        let invalid = || invalid_data(format!("invalid TOML on line {}: {line:?}", number + 1));
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[') {
+            let name = name.split_once(']').ok_or_else(invalid)?.0;
+            in_section = name.trim().trim_matches('"') == section;
+            continue;
+        }
+        if !in_section {
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+        let field = match key.trim().trim_matches('"') {
+            "api_key" => &mut config.api_key,
+            "base_url" => &mut config.base_url,
+            _ => continue,
+        };
+        *field = Some(parse_toml_string(value.trim()).ok_or_else(invalid)?);
+    }
+    Ok(config)
+}
+
+#[cfg(not(feature = "toml-subset"))]
+fn parse_toml(_data: &str, _section: &str) -> io::Result<ProviderConfig> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reading TOML requires the toml-subset feature",
+    ))
+}
+
+/// Parses a basic (`"..."`) or literal (`'...'`) TOML string, followed by an
+/// optional comment.
+#[cfg(feature = "toml-subset")]
+fn parse_toml_string(value: &str) -> Option<String> {
+    let mut chars = value.chars();
+    let quote = chars.next().filter(|c| matches!(c, '"' | '\''))?;
+    let mut string = String::new();
+    loop {
+        match chars.next()? {
+            c if c == quote => break,
+            '\\' if quote == '"' => string.push(match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                c @ ('"' | '\\') => c,
+                _ => return None,
+            }),
+            c => string.push(c),
+        }
+    }
+    let rest = chars.as_str().trim();
+    (rest.is_empty() || rest.starts_with('#')).then_some(string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_overrides_file() {
+        let path =
+            std::env::temp_dir().join(format!("openai-credentials-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{"theme": "dark", "anthropic": {"api_key": "sk-ant-file"}, "openai_compatible": {"base_url": "http://localhost:11434/v1/"}}"#,
+        )
+        .unwrap();
+        let no_env = |_: &str| None;
+
+        let credentials = credentials_from_file(&path, ApiProvider::Anthropic, no_env).unwrap();
+        assert_eq!(credentials.api_key(), "sk-ant-file");
+        assert_eq!(credentials.base_url(), ANTHROPIC_BASE_URL);
+
+        let env = |name: &str| (name == "ANTHROPIC_KEY").then(|| "sk-ant-env".to_string());
+        let credentials = credentials_from_file(&path, ApiProvider::Anthropic, env).unwrap();
+        assert_eq!(credentials.api_key(), "sk-ant-env");
+
+        let credentials =
+            credentials_from_file(&path, ApiProvider::OpenAICompatible, no_env).unwrap();
+        assert_eq!(credentials.api_key(), "");
+        assert_eq!(credentials.base_url(), "http://localhost:11434/v1/");
+
+        let error = credentials_from_file(&path, ApiProvider::OpenAI, no_env).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "dotenv")]
+    #[test]
+    fn dotenv() {
+        let path =
+            std::env::temp_dir().join(format!("openai-credentials-{}.env", std::process::id()));
+        std::fs::write(
+            &path,
+            "# tenant A\nANTHROPIC_KEY=sk-ant-tenant\nOPENAI_KEY=\"sk-tenant\"\nOPENAI_BASE_URL=https://proxy.example.com/v1/\n",
+        )
+        .unwrap();
+
+        let credentials = credentials_from_dotenv(&path, ApiProvider::Anthropic).unwrap();
+        assert_eq!(credentials.api_key(), "sk-ant-tenant");
+        assert_eq!(credentials.base_url(), ANTHROPIC_BASE_URL);
+
+        let credentials = credentials_from_dotenv(&path, ApiProvider::OpenAI).unwrap();
+        assert_eq!(credentials.api_key(), "sk-tenant");
+        assert_eq!(credentials.base_url(), "https://proxy.example.com/v1/");
+
+        let error = credentials_from_dotenv(&path, ApiProvider::Gemini).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_file(&path).unwrap();
+
+        let error = credentials_from_dotenv(&path, ApiProvider::OpenAI).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[cfg(feature = "toml-subset")]
+    #[test]
+    fn toml() {
+        let data = r#"
+            # Provider keys
+            [openai]
+            api_key = "sk-\"quoted\""  # inline comment
+            timeout = 30
+            base_url = 'https://example.com/v1/'
+            enabled = true
+
+            [anthropic]
+            api_key = "sk-ant"
+        "#;
+        assert_eq!(
+            parse_toml(data, "openai").unwrap(),
+            ProviderConfig {
+                api_key: Some("sk-\"quoted\"".to_string()),
+                base_url: Some("https://example.com/v1/".to_string()),
+            }
+        );
+        assert_eq!(
+            parse_toml(data, "anthropic").unwrap().api_key.as_deref(),
+            Some("sk-ant")
+        );
+        assert_eq!(
+            parse_toml(data, "gemini").unwrap(),
+            ProviderConfig::default()
+        );
+        assert!(parse_toml("[openai]\napi_key = sk-unquoted", "openai").is_err());
+    }
+}
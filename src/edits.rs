@@ -3,7 +3,7 @@ use super::{openai_post, ApiResponseOrError, Credentials, OpenAiError, Usage};
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct Edit {
     pub created: u32,
     #[serde(skip_deserializing)]
@@ -13,7 +13,7 @@ pub struct Edit {
     choices_bad: Vec<EditChoice>,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, Debug)]
 struct EditChoice {
     text: String,
 }
@@ -85,7 +85,7 @@ impl Edit {
 
 impl EditBuilder {
     pub async fn create(self) -> ApiResponseOrError<Edit> {
-        Edit::create(self.build().unwrap()).await
+        Edit::create(self.build()?).await
     }
 }
 
@@ -113,4 +113,11 @@ mod tests {
             "What day of the week is it?\n"
         );
     }
+
+    #[tokio::test]
+    async fn missing_required_field() {
+        let error = EditBuilder::default().create().await.unwrap_err();
+        assert_eq!(error.error_type, "builder");
+        assert_eq!(error.param.as_deref(), Some("model"));
+    }
 }
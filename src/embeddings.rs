@@ -2,36 +2,99 @@
 //!
 //! Related guide: [Embeddings](https://beta.openai.com/docs/guides/embeddings)
 
-use super::{openai_post, ApiResponseOrError, Credentials};
-use serde::{Deserialize, Serialize};
-
-#[derive(Serialize, Clone)]
-struct CreateEmbeddingsRequestBody<'a> {
-    model: &'a str,
-    input: Vec<&'a str>,
-    #[serde(skip_serializing_if = "str::is_empty")]
-    user: &'a str,
+use super::{openai_post, ApiResponseOrError, Credentials, OpenAiError};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use derive_builder::Builder;
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+
+#[derive(Serialize, Builder, Debug, Clone)]
+#[builder(pattern = "owned")]
+#[builder(name = "EmbeddingsBuilder")]
+#[builder(setter(strip_option, into))]
+pub struct EmbeddingsRequest {
+    /// ID of the model to use.
+    pub model: String,
+    /// Input texts to get embeddings for. Each input must not exceed 8192 tokens in length.
+    pub input: Vec<String>,
+    /// The number of dimensions the embeddings should have, truncating them.
+    /// Only supported by `text-embedding-3` and later models.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub dimensions: Option<u32>,
+    /// The format the embeddings are transported in. They are decoded into floats either way.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub encoding_format: Option<EncodingFormat>,
+    /// A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse.
+    /// [Learn more](https://beta.openai.com/docs/guides/safety-best-practices/end-user-ids).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub user: Option<String>,
+    /// The credentials to use for this request.
+    #[serde(skip_serializing)]
+    #[builder(default)]
+    pub credentials: Option<Credentials>,
 }
 
-#[derive(Deserialize, Clone)]
+/// How embeddings are encoded in the response.
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodingFormat {
+    /// A JSON array of floats, the default.
+    Float,
+    /// Base64 encoded little-endian 32-bit floats, about half the size.
+    Base64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
 pub struct Embeddings {
     pub data: Vec<Embedding>,
     pub model: String,
     pub usage: EmbeddingsUsage,
 }
 
-#[derive(Deserialize, Clone, Copy)]
+#[derive(Deserialize, Clone, Copy, Debug)]
 pub struct EmbeddingsUsage {
     pub prompt_tokens: u32,
     pub total_tokens: u32,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct Embedding {
-    #[serde(rename = "embedding")]
+    /// The position of the input this embedding is for.
+    #[serde(default)]
+    pub index: usize,
+    #[serde(rename = "embedding", deserialize_with = "deserialize_embedding")]
     pub vec: Vec<f64>,
 }
 
+/// Embeddings are either an array of floats or, with [`EncodingFormat::Base64`],
+/// base64 encoded little-endian 32-bit floats.
+fn deserialize_embedding<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<f64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum EncodedEmbedding {
+        Float(Vec<f64>),
+        Base64(String),
+    }
+
+    match EncodedEmbedding::deserialize(deserializer)? {
+        EncodedEmbedding::Float(vec) => Ok(vec),
+        EncodedEmbedding::Base64(encoded) => {
+            let bytes = BASE64.decode(encoded).map_err(D::Error::custom)?;
+            if bytes.len() % 4 != 0 {
+                return Err(D::Error::custom(
+                    "base64 embedding isn't a whole number of floats",
+                ));
+            }
+            Ok(bytes
+                .chunks_exact(4)
+                .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()).into())
+                .collect())
+        }
+    }
+}
+
 impl Embeddings {
     /// Creates an embedding vector representing the input text.
     ///
@@ -53,12 +116,38 @@ impl Embeddings {
         user: &str,
         credentials: Credentials,
     ) -> ApiResponseOrError<Self> {
-        openai_post(
-            "embeddings",
-            &CreateEmbeddingsRequestBody { model, input, user },
-            Some(credentials),
-        )
-        .await
+        let mut builder = Self::builder(model, input).credentials(credentials);
+        if !user.is_empty() {
+            builder = builder.user(user);
+        }
+        builder.create().await
+    }
+
+    /// Creates a builder for an embeddings request, to set optional parameters
+    /// such as `dimensions` and `encoding_format`.
+    pub fn builder(
+        model: &str,
+        input: impl IntoIterator<Item = impl Into<String>>,
+    ) -> EmbeddingsBuilder {
+        EmbeddingsBuilder::create_empty()
+            .model(model)
+            .input(input.into_iter().map(Into::into).collect::<Vec<String>>())
+    }
+
+    /// Sorts the embeddings by [`Embedding::index`], so that each one is at the
+    /// position of its input. Responses aren't guaranteed to be in input order;
+    /// [`EmbeddingsBuilder::create`] already sorts them.
+    pub fn sort_by_index(&mut self) {
+        self.data.sort_by_key(|embedding| embedding.index);
+    }
+
+    /// The embedding vectors, in the order of the inputs.
+    pub fn into_vectors(mut self) -> Vec<Vec<f64>> {
+        self.sort_by_index();
+        self.data
+            .into_iter()
+            .map(|embedding| embedding.vec)
+            .collect()
     }
 
     pub fn distances(&self) -> Vec<f64> {
@@ -77,6 +166,64 @@ impl Embeddings {
     }
 }
 
+impl EmbeddingsBuilder {
+    pub async fn create(self) -> ApiResponseOrError<Embeddings> {
+        create(&self.build()?).await
+    }
+
+    /// Embeds the inputs with one request per chunk of `chunk_size` inputs,
+    /// so that a failed chunk doesn't lose the embeddings of the others.
+    ///
+    /// The result at each position is the embedding of the input at that
+    /// position, or the error of its chunk. Fails as a whole only when the
+    /// request can't be built.
+    ///
+    /// # Panics
+    /// Panics if `chunk_size` is 0.
+    pub async fn create_batched(
+        self,
+        chunk_size: usize,
+    ) -> ApiResponseOrError<Vec<ApiResponseOrError<Embedding>>> {
+        let request = self.build()?;
+        let mut results = Vec::with_capacity(request.input.len());
+        for (chunk_index, chunk) in request.input.chunks(chunk_size).enumerate() {
+            let offset = chunk_index * chunk_size;
+            let chunk_request = EmbeddingsRequest {
+                input: chunk.to_vec(),
+                ..request.clone()
+            };
+            match create(&chunk_request).await {
+                Ok(embeddings) => {
+                    let mut chunk_results = vec![None; chunk.len()];
+                    for mut embedding in embeddings.data {
+                        if let Some(slot) = chunk_results.get_mut(embedding.index) {
+                            embedding.index += offset;
+                            *slot = Some(embedding);
+                        }
+                    }
+                    results.extend(chunk_results.into_iter().map(|embedding| {
+                        embedding.ok_or_else(|| {
+                            OpenAiError::new(
+                                "no embedding was returned for this input".to_string(),
+                                "missing_embedding".to_string(),
+                            )
+                        })
+                    }));
+                }
+                Err(error) => results.extend(std::iter::repeat_n(Err(error), chunk.len())),
+            }
+        }
+        Ok(results)
+    }
+}
+
+async fn create(request: &EmbeddingsRequest) -> ApiResponseOrError<Embeddings> {
+    let mut embeddings: Embeddings =
+        openai_post("embeddings", request, request.credentials.clone()).await?;
+    embeddings.sort_by_index();
+    Ok(embeddings)
+}
+
 impl Embedding {
     pub async fn create(
         model: &str,
@@ -144,14 +291,153 @@ mod tests {
         assert!(!embedding.vec.is_empty());
     }
 
+    #[tokio::test]
+    async fn base64_embeddings() {
+        let floats: [f32; 3] = [0.5, -1.25, 3.0];
+        let encoded = BASE64.encode(
+            floats
+                .iter()
+                .flat_map(|x| x.to_le_bytes())
+                .collect::<Vec<_>>(),
+        );
+        let credentials = crate::tests::mock_server(crate::tests::json_response(
+            "",
+            &serde_json::json!({
+                "object": "list",
+                "data": [{"object": "embedding", "index": 0, "embedding": encoded}],
+                "model": "text-embedding-3-small",
+                "usage": {"prompt_tokens": 3, "total_tokens": 3}
+            })
+            .to_string(),
+        ))
+        .await;
+
+        let request = Embeddings::builder("text-embedding-3-small", ["Crabs"])
+            .dimensions(3u32)
+            .encoding_format(EncodingFormat::Base64)
+            .credentials(credentials.clone())
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "model": "text-embedding-3-small",
+                "input": ["Crabs"],
+                "dimensions": 3,
+                "encoding_format": "base64"
+            })
+        );
+
+        let embeddings = Embeddings::builder("text-embedding-3-small", ["Crabs"])
+            .encoding_format(EncodingFormat::Base64)
+            .credentials(credentials)
+            .create()
+            .await
+            .unwrap();
+        assert_eq!(embeddings.data[0].vec, vec![0.5, -1.25, 3.0]);
+    }
+
+    #[tokio::test]
+    async fn batched_embeddings_in_input_order() {
+        let credentials = crate::tests::mock_server(crate::tests::json_response(
+            "",
+            r#"{
+                "object": "list",
+                "data": [
+                    {"object": "embedding", "index": 2, "embedding": [2.0]},
+                    {"object": "embedding", "index": 0, "embedding": [0.0]},
+                    {"object": "embedding", "index": 1, "embedding": [1.0]}
+                ],
+                "model": "text-embedding-3-small",
+                "usage": {"prompt_tokens": 3, "total_tokens": 3}
+            }"#,
+        ))
+        .await;
+
+        let inputs = vec!["zero".to_string(), "one".to_string(), "two".to_string()];
+        let embeddings = Embeddings::builder("text-embedding-3-small", inputs)
+            .credentials(credentials)
+            .create()
+            .await
+            .unwrap();
+        for (position, embedding) in embeddings.data.iter().enumerate() {
+            assert_eq!(embedding.index, position);
+            assert_eq!(embedding.vec, vec![position as f64]);
+        }
+    }
+
+    #[tokio::test]
+    async fn batched_embeddings_partial_failure() {
+        let embeddings = |values: [f64; 2]| {
+            crate::tests::json_response(
+                "",
+                &serde_json::json!({
+                    "object": "list",
+                    "data": [
+                        {"object": "embedding", "index": 1, "embedding": [values[1]]},
+                        {"object": "embedding", "index": 0, "embedding": [values[0]]}
+                    ],
+                    "model": "text-embedding-3-small",
+                    "usage": {"prompt_tokens": 2, "total_tokens": 2}
+                })
+                .to_string(),
+            )
+        };
+        let credentials = crate::tests::mock_server_sequence(vec![
+            embeddings([0.0, 1.0]),
+            crate::tests::http_response(
+                "500 Internal Server Error",
+                "",
+                r#"{"error": {"message": "The server had an error.", "type": "server_error", "param": null, "code": null}}"#,
+            ),
+            embeddings([4.0, 5.0]),
+        ])
+        .await;
+
+        let inputs = ["zero", "one", "two", "three", "four", "five"];
+        let results = Embeddings::builder("text-embedding-3-small", inputs)
+            .credentials(credentials)
+            .create_batched(2)
+            .await
+            .unwrap();
+
+        assert_eq!(results.len(), 6);
+        for index in [0, 1, 4, 5] {
+            let embedding = results[index].as_ref().unwrap();
+            assert_eq!(embedding.index, index);
+            assert_eq!(embedding.vec, vec![index as f64]);
+        }
+        for index in [2, 3] {
+            assert_eq!(
+                results[index].as_ref().unwrap_err().message,
+                "The server had an error."
+            );
+        }
+    }
+
+    #[test]
+    fn float_embeddings_by_default() {
+        let request = Embeddings::builder("text-embedding-ada-002", ["Crabs"])
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({"model": "text-embedding-ada-002", "input": ["Crabs"]})
+        );
+        let embedding: Embedding = serde_json::from_str(r#"{"embedding": [0.25, 1.0]}"#).unwrap();
+        assert_eq!(embedding.vec, vec![0.25, 1.0]);
+    }
+
     #[test]
     fn right_angle() {
         let embeddings = Embeddings {
             data: vec![
                 Embedding {
+                    index: 0,
                     vec: vec![1.0, 0.0, 0.0],
                 },
                 Embedding {
+                    index: 1,
                     vec: vec![0.0, 1.0, 0.0],
                 },
             ],
@@ -169,9 +455,11 @@ mod tests {
         let embeddings = Embeddings {
             data: vec![
                 Embedding {
+                    index: 0,
                     vec: vec![1.0, 1.0, 0.0],
                 },
                 Embedding {
+                    index: 1,
                     vec: vec![0.0, 1.0, 0.0],
                 },
             ],
@@ -184,4 +472,42 @@ mod tests {
 
         assert_eq!(embeddings.distances()[0], 0.29289321881345254);
     }
+
+    #[test]
+    fn vectors_in_input_order() {
+        let embeddings: Embeddings = serde_json::from_value(serde_json::json!({
+            "object": "list",
+            "data": [
+                {"object": "embedding", "index": 1, "embedding": [0.0, 1.0]},
+                {"object": "embedding", "index": 0, "embedding": [1.0, 0.0]}
+            ],
+            "model": "text-embedding-3-small",
+            "usage": {"prompt_tokens": 4, "total_tokens": 4}
+        }))
+        .unwrap();
+        assert_eq!(embeddings.model, "text-embedding-3-small");
+        assert_eq!(embeddings.usage.prompt_tokens, 4);
+        assert_eq!(
+            embeddings.into_vectors(),
+            vec![vec![1.0, 0.0], vec![0.0, 1.0]]
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_required_field() {
+        let error = EmbeddingsBuilder::default().create().await.unwrap_err();
+        assert_eq!(error.error_type, "builder");
+        assert_eq!(error.param.as_deref(), Some("model"));
+    }
+
+    #[test]
+    fn golden_request() {
+        let request = Embeddings::builder("text-embedding-3-small", ["crabs", "lobsters"])
+            .dimensions(256u32)
+            .encoding_format(EncodingFormat::Base64)
+            .user("user-1")
+            .build()
+            .unwrap();
+        crate::tests::assert_golden("embeddings_request", &request);
+    }
 }
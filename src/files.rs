@@ -14,12 +14,12 @@
 //!use openai::ApiResponseOrError;
 //!use dotenvy::dotenv;
 //!use std::env;
-//!use openai::Credentials;
+//!use openai::{ApiProvider, Credentials};
 //!
 //!#[tokio::main]
 //!async fn main() -> ApiResponseOrError<()> {
 //!     dotenv().ok();
-//!     let credentials = Credentials::from_env();
+//!     let credentials = Credentials::from_env(ApiProvider::OpenAI);
 //!     let uploaded_file = File::builder()
 //!         .file_name("test_data/file_upload_test1.jsonl") // local file path to upload.
 //!         .purpose("fine-tune")
@@ -36,12 +36,12 @@
 //!use openai::ApiResponseOrError;
 //!use dotenvy::dotenv;
 //!use std::env;
-//!use openai::Credentials;
+//!use openai::{ApiProvider, Credentials};
 //!
 //!#[tokio::main]
 //!async fn main() -> ApiResponseOrError<()> {
 //!     dotenv().ok();
-//!     let credentials = Credentials::from_env();
+//!     let credentials = Credentials::from_env(ApiProvider::OpenAI);
 //!     let openai_files = Files::list(credentials).await?;
 //!     let file_count = openai_files.len();
 //!     println!("Listing {} files", file_count);
@@ -52,6 +52,29 @@
 //!}
 //! ```
 //!
+//! Page through the files of a purpose, fetching the next page as needed.
+//! [Reference API](https://platform.openai.com/docs/api-reference/files/list)
+//!
+//! ```no_run
+//!use futures_util::StreamExt;
+//!use openai::files::Files;
+//!use openai::{ApiProvider, ApiResponseOrError, Credentials};
+//!
+//!#[tokio::main]
+//!async fn main() -> ApiResponseOrError<()> {
+//!     let credentials = Credentials::from_env(ApiProvider::OpenAI);
+//!     let mut files = Files::builder()
+//!         .purpose("batch")
+//!         .limit(1000u32)
+//!         .credentials(credentials)
+//!         .stream()?;
+//!     while let Some(file) = files.next().await {
+//!         println!("id: {}", file?.id);
+//!     }
+//!     Ok(())
+//!}
+//! ```
+//!
 //! Retrieve a file (json metadata only). [Reference API](https://platform.openai.com/docs/api-reference/files/retrieve)
 //!
 //! ```no_run
@@ -59,12 +82,12 @@
 //!use openai::ApiResponseOrError;
 //!use dotenvy::dotenv;
 //!use std::env;
-//!use openai::Credentials;
+//!use openai::{ApiProvider, Credentials};
 //!
 //!#[tokio::main]
 //!async fn main() -> ApiResponseOrError<()> {
 //!     dotenv().ok();
-//!     let credentials = Credentials::from_env();
+//!     let credentials = Credentials::from_env(ApiProvider::OpenAI);
 //!     let file_id = "file-XjGxS3KTG0uNmNOK362iJua3"; // Use a real file id.
 //!     let file = File::fetch(file_id, credentials).await?;
 //!     println!("id: {}, file: {}, size: {}", file.id, file.filename, file.bytes);
@@ -79,12 +102,12 @@
 //!use openai::ApiResponseOrError;
 //!use dotenvy::dotenv;
 //!use std::env;
-//!use openai::Credentials;
+//!use openai::{ApiProvider, Credentials};
 //!
 //!#[tokio::main]
 //!async fn main() -> ApiResponseOrError<()> {
 //!     dotenv().ok();
-//!     let credentials = Credentials::from_env();
+//!     let credentials = Credentials::from_env(ApiProvider::OpenAI);
 //!     let test_file = "test_file.jsonl";
 //!     let file_id = "file-XjGxS3KTG0uNmNOK362iJua3"; // Use a real file id.
 //!     File::download_content_to_file(file_id, test_file, credentials).await?;
@@ -99,12 +122,12 @@
 //!use openai::ApiResponseOrError;
 //!use dotenvy::dotenv;
 //!use std::env;
-//!use openai::Credentials;
+//!use openai::{ApiProvider, Credentials};
 //!
 //!#[tokio::main]
 //!async fn main() -> ApiResponseOrError<()> {
 //!     dotenv().ok();
-//!     let credentials = Credentials::from_env();
+//!     let credentials = Credentials::from_env(ApiProvider::OpenAI);
 //!     let file_id = "file-XjGxS3KTG0uNmNOK362iJua3"; // Use a real file id.
 //!     File::delete(file_id, credentials).await?;
 //!     Ok(())
@@ -117,19 +140,22 @@
 use std::io::Write;
 use std::path::Path;
 
-use bytes::{BufMut, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 use derive_builder::Builder;
-use futures_util::StreamExt;
-use reqwest::multipart::{Form, Part};
+use futures_util::{Stream, StreamExt};
+use reqwest::multipart::Part;
 use reqwest::Method;
 use serde::{Deserialize, Serialize};
 
-use crate::{openai_delete, openai_get, openai_post_multipart, openai_request, Credentials};
+use crate::{
+    openai_delete, openai_get, openai_post_with_files, openai_request, openai_request_bytes,
+    openai_request_json, Credentials, MaybeSend,
+};
 
 use super::ApiResponseOrError;
 
 /// Upload, download and delete a file from the openai platform.
-#[derive(Deserialize, Serialize, Clone)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct File {
     /// The unique id for this uploaded the in the openai platform.
     /// This id is generated by openai for each uploaded file.
@@ -158,6 +184,36 @@ pub struct DeletedFile {
 pub struct Files {
     data: Vec<File>,
     pub object: String,
+    /// Whether there are more files after this page.
+    #[serde(default)]
+    pub has_more: bool,
+    /// The ID of the last file of this page, to request the next page `after` it.
+    #[serde(default)]
+    pub last_id: Option<String>,
+}
+
+/// The filters and page of a file listing, see [`Files::builder`].
+#[derive(Serialize, Builder, Debug, Clone)]
+#[builder(pattern = "owned")]
+#[builder(name = "FileListBuilder")]
+#[builder(setter(strip_option, into))]
+pub struct FileListRequest {
+    /// Only list the files with this purpose, such as `batch` or `fine-tune`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub purpose: Option<String>,
+    /// The number of files per page, from 1 to 10,000.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub limit: Option<u32>,
+    /// List the files after the one with this ID, the `last_id` of the previous page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub after: Option<String>,
+    /// The credentials to use for this request.
+    #[serde(skip_serializing)]
+    #[builder(default)]
+    pub credentials: Option<Credentials>,
 }
 
 #[derive(Serialize, Builder, Debug, Clone)]
@@ -184,14 +240,20 @@ impl File {
             .unwrap()
             .to_string()
             .clone();
-        let async_file = tokio::fs::File::open(upload_file_path).await?;
-        let file_part = Part::stream(async_file)
+        #[cfg(feature = "tokio-runtime")]
+        let file_part = Part::stream(tokio::fs::File::open(upload_file_path).await?);
+        #[cfg(not(feature = "tokio-runtime"))]
+        let file_part = Part::bytes(std::fs::read(upload_file_path)?);
+        let file_part = file_part
             .file_name(simple_name)
             .mime_str("application/jsonl")?;
-        let form = Form::new()
-            .part("file", file_part)
-            .text("purpose", request.purpose);
-        openai_post_multipart("files", form, request.credentials).await
+        openai_post_with_files(
+            "files",
+            &serde_json::json!({ "purpose": request.purpose }),
+            vec![("file", file_part)],
+            request.credentials,
+        )
+        .await
     }
 
     /// New FileUploadBuilder
@@ -250,6 +312,20 @@ impl File {
         Ok(file_bytes.to_vec())
     }
 
+    /// Download the content of a file by id, such as the output file of a batch.
+    ///
+    /// Unlike [`File::fetch_content_bytes`], an error response is returned as an error
+    /// rather than as the content.
+    pub async fn download(id: &str, credentials: Credentials) -> ApiResponseOrError<Bytes> {
+        openai_request_bytes(
+            Method::GET,
+            format!("files/{}/content", id).as_str(),
+            |request| request,
+            Some(credentials),
+        )
+        .await
+    }
+
     /// Download a file to a new local file by id.
     pub async fn download_content_to_file(
         id: &str,
@@ -276,7 +352,7 @@ impl File {
 impl FileUploadBuilder {
     /// Upload the file to the openai platform.
     pub async fn create(self) -> ApiResponseOrError<File> {
-        File::create(self.build().unwrap()).await
+        File::create(self.build()?).await
     }
 }
 
@@ -285,9 +361,62 @@ impl Files {
     pub async fn list(credentials: Credentials) -> ApiResponseOrError<Files> {
         openai_get("files", Some(credentials)).await
     }
+
+    /// New FileListBuilder, to filter and page through the files.
+    pub fn builder() -> FileListBuilder {
+        FileListBuilder::create_empty()
+    }
+
+    async fn list_page(request: &FileListRequest) -> ApiResponseOrError<Files> {
+        openai_request_json(
+            Method::GET,
+            "files",
+            |builder| builder.query(request),
+            request.credentials.clone(),
+        )
+        .await
+    }
+
     pub fn len(&self) -> usize {
         self.data.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl FileListBuilder {
+    /// Get a single page of files.
+    pub async fn list(self) -> ApiResponseOrError<Files> {
+        Files::list_page(&self.build()?).await
+    }
+
+    /// Streams the files of every page, fetching each page once the files of
+    /// the previous one have been consumed. An error ends the stream.
+    pub fn stream(
+        self,
+    ) -> ApiResponseOrError<impl Stream<Item = ApiResponseOrError<File>> + MaybeSend + Unpin> {
+        let request = self.build()?;
+        let pages = futures_util::stream::unfold(Some(request), |request| async move {
+            let mut request = request?;
+            let page = match Files::list_page(&request).await {
+                Ok(page) => page,
+                Err(error) => return Some((vec![Err(error)], None)),
+            };
+            let after = page
+                .last_id
+                .clone()
+                .or_else(|| page.data.last().map(|file| file.id.clone()))
+                .filter(|_| page.has_more);
+            let next = after.map(|after| {
+                request.after = Some(after);
+                request
+            });
+            Some((page.data.into_iter().map(Ok).collect::<Vec<_>>(), next))
+        });
+        Ok(Box::pin(pages.flat_map(futures_util::stream::iter)))
+    }
 }
 
 impl<'a> IntoIterator for &'a Files {
@@ -307,7 +436,7 @@ mod tests {
 
     use dotenvy::dotenv;
 
-    use crate::DEFAULT_CREDENTIALS;
+    use crate::default_credentials;
 
     use super::*;
 
@@ -383,16 +512,14 @@ mod tests {
         // wait to avoid recent upload still processing error
         tokio::time::sleep(Duration::from_secs(7)).await;
         let openai_files = Files::list(credentials).await.unwrap();
-        assert!(openai_files.data.len() > 0);
+        assert!(!openai_files.data.is_empty());
         let mut files = openai_files.data;
-        files.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        files.sort_by_key(|file| file.created_at);
+        let default_credentials = default_credentials();
         for file in files {
-            let deleted_file = File::delete(
-                file.id.as_str(),
-                DEFAULT_CREDENTIALS.read().unwrap().clone(),
-            )
-            .await
-            .unwrap();
+            let deleted_file = File::delete(file.id.as_str(), default_credentials.clone())
+                .await
+                .unwrap();
             assert!(deleted_file.deleted);
             println!("deleted: {} {}", deleted_file.id, deleted_file.deleted)
         }
@@ -443,4 +570,89 @@ mod tests {
         let file_exists = file_upload_path.exists();
         assert!(file_exists)
     }
+
+    #[tokio::test]
+    async fn missing_required_field() {
+        let error = super::FileUploadBuilder::default()
+            .purpose("fine-tune")
+            .create()
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_type, "builder");
+        assert_eq!(error.param.as_deref(), Some("file_name"));
+    }
+
+    #[tokio::test]
+    async fn download() {
+        let content = "{\"custom_id\": \"request-1\"}\n";
+        let credentials = crate::tests::mock_server_sequence(vec![
+            crate::tests::http_response("200 OK", "", content),
+            crate::tests::http_response(
+                "404 Not Found",
+                "",
+                r#"{"error": {"message": "No such File object: file-missing", "type": "invalid_request_error", "param": "id", "code": null}}"#,
+            ),
+        ])
+        .await;
+        let bytes = File::download("file-abc123", credentials.clone())
+            .await
+            .unwrap();
+        assert_eq!(bytes, content.as_bytes());
+
+        let error = File::download("file-missing", credentials)
+            .await
+            .unwrap_err();
+        assert_eq!(error.message, "No such File object: file-missing");
+    }
+
+    #[tokio::test]
+    async fn stream_pages() {
+        let file = |id: &str| {
+            format!(
+                r#"{{"id": "{id}", "object": "file", "bytes": 10, "created_at": 1, "filename": "{id}.jsonl", "purpose": "batch"}}"#
+            )
+        };
+        let credentials = crate::tests::mock_server_sequence(vec![
+            crate::tests::json_response(
+                "",
+                &format!(
+                    r#"{{"object": "list", "data": [{}, {}], "has_more": true, "last_id": "file-2"}}"#,
+                    file("file-1"),
+                    file("file-2")
+                ),
+            ),
+            crate::tests::json_response(
+                "",
+                &format!(
+                    r#"{{"object": "list", "data": [{}], "has_more": false, "last_id": "file-3"}}"#,
+                    file("file-3")
+                ),
+            ),
+        ])
+        .await;
+        let files: Vec<_> = Files::builder()
+            .purpose("batch")
+            .limit(2u32)
+            .credentials(credentials)
+            .stream()
+            .unwrap()
+            .collect()
+            .await;
+        let ids: Vec<_> = files.into_iter().map(|file| file.unwrap().id).collect();
+        assert_eq!(ids, ["file-1", "file-2", "file-3"]);
+
+        let request = Files::builder()
+            .purpose("batch")
+            .after("file-2")
+            .build()
+            .unwrap();
+        let url = reqwest::Client::new()
+            .get("https://api.openai.com/v1/files")
+            .query(&request)
+            .build()
+            .unwrap()
+            .url()
+            .clone();
+        assert_eq!(url.query(), Some("purpose=batch&after=file-2"));
+    }
 }
@@ -0,0 +1,452 @@
+//! Generate the next message of a conversation with the Google Gemini API,
+//! through its native `generateContent` endpoints.
+//!
+//! The conversation is given as [`ChatCompletionMessage`]s: system messages
+//! become the system instruction, and assistant messages are sent with the
+//! `model` role Gemini uses.
+//!
+//! Related guide: [Text generation](https://ai.google.dev/gemini-api/docs/text-generation)
+
+use derive_builder::Builder;
+use reqwest::Method;
+use reqwest_eventsource::{CannotCloneRequestError, Event, EventSource};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "tokio-runtime")]
+use tokio::sync::mpsc::{channel, Receiver};
+
+use crate::chat::{ChatCompletionMessage, ChatCompletionMessageRole};
+use crate::{
+    gemini_post, gemini_request_stream, parse_stream_data, resolve_credentials, validate_range,
+    ApiResponseOrError, Credentials, ResponseStream, StreamLimits, StreamSender,
+};
+
+/// A turn of the conversation, or the system instruction.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct GeminiContent {
+    /// `user` or `model`, absent from the system instruction.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub parts: Vec<GeminiPart>,
+}
+
+impl GeminiContent {
+    /// The text of all parts, concatenated.
+    pub fn text(&self) -> String {
+        self.parts
+            .iter()
+            .filter_map(|part| part.text.as_deref())
+            .collect()
+    }
+}
+
+/// A part of a content. Only text parts are modeled by the crate.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct GeminiPart {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+impl GeminiPart {
+    pub fn text(text: impl Into<String>) -> Self {
+        GeminiPart {
+            text: Some(text.into()),
+        }
+    }
+}
+
+/// Options of the generation. Fields left to `None` use the defaults of the model.
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiGenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    /// The number of candidates to generate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub candidate_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    /// Sequences where the model will stop generating, up to 5.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop_sequences: Vec<String>,
+}
+
+impl GeminiGenerationConfig {
+    fn is_empty(&self) -> bool {
+        *self == GeminiGenerationConfig::default()
+    }
+}
+
+#[derive(Serialize, Builder, Debug, Clone)]
+#[builder(pattern = "owned")]
+#[builder(name = "GeminiChatBuilder")]
+#[builder(build_fn(validate = "Self::validate"))]
+#[builder(setter(strip_option, into))]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiChatRequest {
+    /// ID of the model to use, such as `gemini-2.0-flash`. Sent in the URL.
+    #[serde(skip_serializing)]
+    pub model: String,
+    /// The conversation, alternating `user` and `model` turns.
+    pub contents: Vec<GeminiContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub system_instruction: Option<GeminiContent>,
+    #[serde(skip_serializing_if = "GeminiGenerationConfig::is_empty")]
+    #[builder(default)]
+    pub generation_config: GeminiGenerationConfig,
+    /// The credentials to use for this request.
+    #[serde(skip_serializing)]
+    #[builder(default)]
+    pub credentials: Option<Credentials>,
+}
+
+impl GeminiChatRequest {
+    /// The JSON body this request is sent with, such as to log a rejected request.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    /// The route of `method`, such as `generateContent`, for the model.
+    fn route(&self, method: &str) -> String {
+        let model = self.model.strip_prefix("models/").unwrap_or(&self.model);
+        format!("models/{model}:{method}")
+    }
+}
+
+/// The generated content, or a piece of it when streamed.
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiChatCompletion {
+    /// The candidates, empty when the prompt was blocked.
+    #[serde(default)]
+    pub candidates: Vec<GeminiCandidate>,
+    /// Why the prompt was blocked, if it was.
+    #[serde(default)]
+    pub prompt_feedback: Option<GeminiPromptFeedback>,
+    /// The token counts, cumulative when streamed.
+    #[serde(default)]
+    pub usage_metadata: Option<GeminiUsage>,
+    #[serde(default)]
+    pub model_version: Option<String>,
+    #[serde(default)]
+    pub response_id: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiCandidate {
+    /// The content, absent when the candidate was blocked.
+    #[serde(default)]
+    pub content: Option<GeminiContent>,
+    /// Why the model stopped, such as `STOP` or `MAX_TOKENS`. Set on the last
+    /// piece when streamed.
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+    #[serde(default)]
+    pub index: u32,
+}
+
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiPromptFeedback {
+    /// Such as `SAFETY` or `BLOCKLIST`.
+    #[serde(default)]
+    pub block_reason: Option<String>,
+}
+
+/// Token usage of a Gemini request. Counts missing from the response are 0.
+#[derive(Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct GeminiUsage {
+    pub prompt_token_count: u64,
+    pub candidates_token_count: u64,
+    pub total_token_count: u64,
+}
+
+impl GeminiChatCompletion {
+    /// Creates a builder for a request continuing `messages`.
+    ///
+    /// System messages are joined into the system instruction. Function and
+    /// tool messages are sent as user turns, since the crate doesn't model
+    /// Gemini function calling.
+    pub fn builder(
+        model: &str,
+        messages: impl IntoIterator<Item = ChatCompletionMessage>,
+    ) -> GeminiChatBuilder {
+        let mut system = Vec::new();
+        let mut contents = Vec::new();
+        for message in messages {
+            let role = match message.role {
+                ChatCompletionMessageRole::System => {
+                    system.extend(message.content);
+                    continue;
+                }
+                ChatCompletionMessageRole::Assistant => "model",
+                ChatCompletionMessageRole::User
+                | ChatCompletionMessageRole::Function
+                | ChatCompletionMessageRole::Tool => "user",
+            };
+            contents.push(GeminiContent {
+                role: Some(role.to_string()),
+                parts: vec![GeminiPart::text(message.text())],
+            });
+        }
+        let mut builder = GeminiChatBuilder::create_empty()
+            .model(model)
+            .contents(contents);
+        if !system.is_empty() {
+            builder = builder.system_instruction(GeminiContent {
+                role: None,
+                parts: vec![GeminiPart::text(system.join("\n\n"))],
+            });
+        }
+        builder
+    }
+
+    /// The text of the first candidate.
+    pub fn text(&self) -> String {
+        self.candidates
+            .first()
+            .and_then(|candidate| candidate.content.as_ref())
+            .map(GeminiContent::text)
+            .unwrap_or_default()
+    }
+
+    pub async fn create(request: GeminiChatRequest) -> ApiResponseOrError<Self> {
+        gemini_post(
+            &request.route("generateContent"),
+            &request,
+            request.credentials.clone(),
+        )
+        .await
+    }
+
+    /// Sends the streaming request, returning the stream of its events.
+    async fn open(
+        request: GeminiChatRequest,
+    ) -> Result<(EventSource, StreamLimits), CannotCloneRequestError> {
+        let credentials = resolve_credentials(request.credentials.clone());
+        let limits = credentials.stream_limits();
+        let stream = gemini_request_stream(
+            Method::POST,
+            &request.route("streamGenerateContent?alt=sse"),
+            |r| r.json(&request),
+            Some(credentials),
+        )
+        .await?;
+        Ok((stream, limits))
+    }
+
+    /// Streams the content as it is generated, each item holding the new text
+    /// of the candidates.
+    ///
+    /// A failed connection or an unparsable piece is received as an `Err`,
+    /// ending the stream.
+    #[cfg(feature = "tokio-runtime")]
+    pub async fn create_stream(
+        request: GeminiChatRequest,
+    ) -> Result<Receiver<ApiResponseOrError<Self>>, CannotCloneRequestError> {
+        let (stream, limits) = Self::open(request).await?;
+        let (tx, rx) = channel(32);
+        tokio::spawn(forward_gemini_stream(stream, tx.into(), limits));
+        Ok(rx)
+    }
+}
+
+async fn forward_gemini_stream(
+    mut stream: EventSource,
+    mut tx: StreamSender<ApiResponseOrError<GeminiChatCompletion>>,
+    mut limits: StreamLimits,
+) {
+    loop {
+        let event = tx.next_event(&mut stream, &mut limits).await;
+        // Gemini ends the stream by closing it, without a final event.
+        let completion = match event {
+            None => break,
+            Some(Ok(Event::Open)) => continue,
+            Some(Ok(Event::Message(message))) => parse_stream_data(&message.data),
+            Some(Err(error)) => Err(error),
+        };
+        let last = completion.is_err();
+        if tx.send(completion).await.is_err() || last {
+            break;
+        }
+    }
+    stream.close();
+}
+
+impl GeminiChatBuilder {
+    fn validate(&self) -> Result<(), String> {
+        if let Some(config) = &self.generation_config {
+            validate_range("temperature", config.temperature, 2.0)?;
+            validate_range("top_p", config.top_p, 1.0)?;
+        }
+        Ok(())
+    }
+
+    pub async fn create(self) -> ApiResponseOrError<GeminiChatCompletion> {
+        GeminiChatCompletion::create(self.build()?).await
+    }
+
+    /// Builds the request and streams the content.
+    /// See [`GeminiChatCompletion::create_stream`].
+    #[cfg(feature = "tokio-runtime")]
+    pub async fn create_stream(
+        self,
+    ) -> ApiResponseOrError<Receiver<ApiResponseOrError<GeminiChatCompletion>>> {
+        Ok(GeminiChatCompletion::create_stream(self.build()?).await?)
+    }
+
+    /// Same as `create_stream`, as a [`Stream`](futures_util::Stream).
+    /// Also available without the `tokio-runtime` feature.
+    pub async fn create_response_stream(
+        self,
+    ) -> ApiResponseOrError<ResponseStream<GeminiChatCompletion>> {
+        #[cfg(feature = "tokio-runtime")]
+        {
+            Ok(self.create_stream().await?.into())
+        }
+        #[cfg(not(feature = "tokio-runtime"))]
+        {
+            let (stream, limits) = GeminiChatCompletion::open(self.build()?).await?;
+            Ok(ResponseStream::forwarded(|tx| forward_gemini_stream(stream, tx, limits)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{http_response, json_response, mock_server};
+    #[cfg(feature = "tokio-runtime")]
+    use crate::tests::mock_stream_server;
+
+    fn gemini(credentials: Credentials) -> Credentials {
+        Credentials::gemini("AIza-test").with_base_url(credentials.base_url())
+    }
+
+    #[tokio::test]
+    async fn generate_content() {
+        let credentials = gemini(
+            mock_server(json_response(
+                "",
+                r#"{
+                    "candidates": [{
+                        "content": {"role": "model", "parts": [{"text": "Crabs have "}, {"text": "ten legs."}]},
+                        "finishReason": "STOP",
+                        "index": 0
+                    }],
+                    "usageMetadata": {"promptTokenCount": 12, "candidatesTokenCount": 5, "totalTokenCount": 17},
+                    "modelVersion": "gemini-2.0-flash"
+                }"#,
+            ))
+            .await,
+        );
+        let request = GeminiChatCompletion::builder(
+            "gemini-2.0-flash",
+            [
+                ChatCompletionMessage {
+                    role: ChatCompletionMessageRole::System,
+                    content: Some("Be concise.".to_string()),
+                    ..Default::default()
+                },
+                ChatCompletionMessage {
+                    content: Some("Hello!".to_string()),
+                    ..Default::default()
+                },
+                ChatCompletionMessage {
+                    role: ChatCompletionMessageRole::Assistant,
+                    content: Some("Hi!".to_string()),
+                    ..Default::default()
+                },
+                ChatCompletionMessage {
+                    content: Some("How many legs do crabs have?".to_string()),
+                    ..Default::default()
+                },
+            ],
+        )
+        .generation_config(GeminiGenerationConfig {
+            max_output_tokens: Some(100),
+            ..Default::default()
+        })
+        .credentials(credentials)
+        .build()
+        .unwrap();
+        assert_eq!(
+            request.route("generateContent"),
+            "models/gemini-2.0-flash:generateContent"
+        );
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "contents": [
+                    {"role": "user", "parts": [{"text": "Hello!"}]},
+                    {"role": "model", "parts": [{"text": "Hi!"}]},
+                    {"role": "user", "parts": [{"text": "How many legs do crabs have?"}]}
+                ],
+                "systemInstruction": {"parts": [{"text": "Be concise."}]},
+                "generationConfig": {"maxOutputTokens": 100}
+            })
+        );
+
+        let completion = GeminiChatCompletion::create(request).await.unwrap();
+        assert_eq!(completion.text(), "Crabs have ten legs.");
+        assert_eq!(
+            completion.candidates[0].finish_reason.as_deref(),
+            Some("STOP")
+        );
+        assert_eq!(
+            completion.usage_metadata,
+            Some(GeminiUsage {
+                prompt_token_count: 12,
+                candidates_token_count: 5,
+                total_token_count: 17,
+            })
+        );
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn stream_generate_content() {
+        let response = "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\n\r\n\
+            data: {\"candidates\": [{\"content\": {\"role\": \"model\", \"parts\": [{\"text\": \"Ten\"}]}, \"index\": 0}]}\n\n\
+            data: {\"candidates\": [{\"content\": {\"role\": \"model\", \"parts\": [{\"text\": \" legs.\"}]}, \
+            \"finishReason\": \"STOP\", \"index\": 0}]}\n\n";
+        let (credentials, _closed) = mock_stream_server(response).await;
+        let mut rx = GeminiChatCompletion::builder("gemini-2.0-flash", [])
+            .credentials(gemini(credentials))
+            .create_stream()
+            .await
+            .unwrap();
+        let mut text = String::new();
+        for _ in 0..2 {
+            text.push_str(&rx.recv().await.unwrap().unwrap().text());
+        }
+        assert_eq!(text, "Ten legs.");
+    }
+
+    #[tokio::test]
+    async fn error() {
+        let credentials = gemini(
+            mock_server(http_response(
+                "400 Bad Request",
+                "content-type: application/json\r\n",
+                r#"{"error": {"code": 400, "message": "API key not valid.", "status": "INVALID_ARGUMENT"}}"#,
+            ))
+            .await,
+        );
+        let error = GeminiChatCompletion::builder("gemini-2.0-flash", [])
+            .credentials(credentials)
+            .create()
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_type, "INVALID_ARGUMENT");
+        assert_eq!(error.code.as_deref(), Some("400"));
+        assert_eq!(error.message, "API key not valid.");
+    }
+}
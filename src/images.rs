@@ -0,0 +1,171 @@
+//! Given a prompt, the model will generate new images.
+//!
+//! Related guide: [Image generation](https://platform.openai.com/docs/guides/images)
+
+use super::{openai_post, ApiResponseOrError, Credentials};
+use derive_builder::Builder;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Builder, Debug, Clone)]
+#[builder(pattern = "owned")]
+#[builder(name = "ImageGenerationBuilder")]
+#[builder(setter(strip_option, into))]
+pub struct ImageGenerationRequest {
+    /// ID of the model to use, such as `dall-e-2` or `dall-e-3`.
+    pub model: String,
+    /// A text description of the desired images.
+    pub prompt: String,
+    /// The number of images to generate. `dall-e-3` only supports 1.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub n: Option<u8>,
+    /// The size of the images, such as `1024x1024`. The supported sizes depend on the model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub size: Option<String>,
+    /// The quality of the images, only supported by `dall-e-3`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub quality: Option<ImageQuality>,
+    /// The style of the images, only supported by `dall-e-3`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub style: Option<ImageStyle>,
+    /// Whether images are returned as URLs, the default, or base64 encoded data.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub response_format: Option<ImageResponseFormat>,
+    /// A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse.
+    /// [Learn more](https://platform.openai.com/docs/guides/safety-best-practices/end-user-ids).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub user: Option<String>,
+    /// The credentials to use for this request.
+    #[serde(skip_serializing)]
+    #[builder(default)]
+    pub credentials: Option<Credentials>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageQuality {
+    Standard,
+    /// Finer details and greater consistency.
+    Hd,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageStyle {
+    /// Hyper-real and dramatic images.
+    Vivid,
+    /// More natural, less hyper-real images.
+    Natural,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageResponseFormat {
+    /// URLs to the images, valid for an hour.
+    Url,
+    /// The images themselves, base64 encoded.
+    B64Json,
+}
+
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct ImageGeneration {
+    /// Unix timestamp, in seconds, of when the images were created.
+    pub created: u64,
+    pub data: Vec<ImageData>,
+}
+
+/// A generated image, as either a URL or base64 encoded data depending on
+/// the requested [`ImageResponseFormat`].
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct ImageData {
+    pub url: Option<String>,
+    pub b64_json: Option<String>,
+    /// The prompt the image was generated from, when the model rewrote it.
+    pub revised_prompt: Option<String>,
+}
+
+impl ImageGeneration {
+    pub fn builder(model: &str, prompt: impl Into<String>) -> ImageGenerationBuilder {
+        ImageGenerationBuilder::create_empty()
+            .model(model)
+            .prompt(prompt)
+    }
+
+    pub async fn create(request: ImageGenerationRequest) -> ApiResponseOrError<Self> {
+        openai_post("images/generations", &request, request.credentials.clone()).await
+    }
+}
+
+impl ImageGenerationBuilder {
+    pub async fn create(self) -> ApiResponseOrError<ImageGeneration> {
+        ImageGeneration::create(self.build()?).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{json_response, mock_server};
+
+    #[tokio::test]
+    async fn generate_image() {
+        let credentials = mock_server(json_response(
+            "",
+            r#"{
+                "created": 1700000000,
+                "data": [{
+                    "b64_json": "iVBORw0KGgo=",
+                    "revised_prompt": "A red crab walking sideways on a sandy beach."
+                }]
+            }"#,
+        ))
+        .await;
+
+        let request = ImageGeneration::builder("dall-e-3", "A crab on a beach")
+            .size("1024x1024")
+            .quality(ImageQuality::Hd)
+            .style(ImageStyle::Natural)
+            .response_format(ImageResponseFormat::B64Json)
+            .credentials(credentials)
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "model": "dall-e-3",
+                "prompt": "A crab on a beach",
+                "size": "1024x1024",
+                "quality": "hd",
+                "style": "natural",
+                "response_format": "b64_json"
+            })
+        );
+
+        let generation = ImageGeneration::create(request).await.unwrap();
+        assert_eq!(generation.created, 1700000000);
+        assert_eq!(
+            generation.data,
+            [ImageData {
+                url: None,
+                b64_json: Some("iVBORw0KGgo=".to_string()),
+                revised_prompt: Some("A red crab walking sideways on a sandy beach.".to_string()),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_required_field() {
+        let error = ImageGenerationBuilder::default()
+            .model("dall-e-3")
+            .create()
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_type, "builder");
+        assert_eq!(error.param.as_deref(), Some("prompt"));
+    }
+}
@@ -1,26 +1,60 @@
-use reqwest::multipart::Form;
-use reqwest::{header::AUTHORIZATION,header::CONTENT_TYPE, Client, Method, RequestBuilder, Response};
-use reqwest_eventsource::{CannotCloneRequestError, EventSource, RequestBuilderExt};
+use reqwest::multipart::{Form, Part};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{header::AUTHORIZATION,header::CONTENT_TYPE, Client, Method, RequestBuilder, Response, StatusCode};
+use futures_util::StreamExt;
+use reqwest_eventsource::{CannotCloneRequestError, Event, EventSource, RequestBuilderExt};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::env;
-use std::env::VarError;
-use std::sync::{LazyLock, RwLock};
+use std::io;
+use std::path::Path;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, LazyLock, RwLock};
+use std::time::{Duration, Instant};
+#[cfg(feature = "tokio-runtime")]
+use tokio::sync::{broadcast, mpsc};
+use rate_limit::RateLimiter;
+use retry::RetryPolicy;
+use transport::{SharedTransport, Transport};
 
 pub mod chat;
 pub mod completions;
 pub mod edits;
 pub mod embeddings;
 pub mod files;
+pub mod images;
 pub mod models;
 pub mod moderations;
+pub mod responses;
 pub mod anthrophic_chat;
+pub mod anthropic_batches;
+pub mod gemini_chat;
+#[cfg(feature = "bedrock")]
+pub mod bedrock;
+pub mod audio;
+pub mod batches;
+pub mod unified_chat;
+pub mod retry;
+pub mod rate_limit;
+pub mod pricing;
+pub mod tokens;
+pub mod transport;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod testing;
+mod config;
+#[cfg(feature = "tiktoken")]
+pub mod tiktoken;
+#[cfg(feature = "blocking")]
+mod blocking;
+
+/// The `anthropic-version` header sent unless configured otherwise.
+pub const DEFAULT_ANTHROPIC_VERSION: &str = "2023-06-01";
 
 pub static DEFAULT_BASE_URL: LazyLock<String> =
     LazyLock::new(|| String::from("https://api.openai.com/v1/"));
-static DEFAULT_CREDENTIALS: LazyLock<RwLock<Credentials>> =
-    LazyLock::new(|| {
-        let provider = ApiProvider::OpenAI;
-        RwLock::new(Credentials::from_env(provider))});
+/// The credentials set with [`set_default_credentials`], read from the OpenAI
+/// environment variables on first use otherwise.
+static DEFAULT_CREDENTIALS: RwLock<Option<Credentials>> = RwLock::new(None);
 
 
 // Holds the api provider
@@ -28,14 +62,63 @@ static DEFAULT_CREDENTIALS: LazyLock<RwLock<Credentials>> =
 pub enum ApiProvider {
     OpenAI,
     Anthropic,
+    /// A server implementing the OpenAI API, such as Ollama, LM Studio or vLLM.
+    /// The API key may be empty, in which case no `Authorization` header is sent.
+    OpenAICompatible,
+    /// The OpenRouter aggregator, an OpenAI-compatible API serving models of many providers.
+    OpenRouter,
+    /// Together AI, an OpenAI-compatible API serving open models.
+    Together,
+    /// Groq, an OpenAI-compatible API with fast inference of open models.
+    Groq,
+    /// The Google Gemini API, through its native endpoints, see [`gemini_chat`].
+    Gemini,
+    /// Claude through AWS Bedrock in `region`, such as `us-east-1`, see [`bedrock`].
+    #[cfg(feature = "bedrock")]
+    Bedrock { region: String },
 }
 
+/// The base URL of the Anthropic API.
+pub const ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com/v1/";
+
+/// The base URL of the OpenRouter API.
+pub const OPENROUTER_BASE_URL: &str = "https://openrouter.ai/api/v1/";
+
+/// The base URL of the Together AI API.
+pub const TOGETHER_BASE_URL: &str = "https://api.together.xyz/v1/";
+
+/// The base URL of the OpenAI-compatible Groq API.
+pub const GROQ_BASE_URL: &str = "https://api.groq.com/openai/v1/";
+
+/// The base URL of the Gemini API.
+pub const GEMINI_BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta/";
+
 /// Holds the API key and base URL for an OpenAI-compatible API.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Credentials {
     provider: ApiProvider,
     api_key: String,
     base_url: String,
+    timeout: Option<Duration>,
+    stream_idle_timeout: Option<Duration>,
+    anthropic_version: Option<String>,
+    anthropic_beta: Vec<String>,
+    organization: Option<String>,
+    project: Option<String>,
+    retry_policy: Option<RetryPolicy>,
+    headers: HeaderMap,
+    auth_header: bool,
+    http_referer: Option<String>,
+    app_title: Option<String>,
+    request_hook: Option<RequestHookFn>,
+    concurrency_limit: Option<ConcurrencyLimit>,
+    rate_limiter: Option<RateLimiter>,
+    client: Option<HttpClient>,
+    transport: Option<SharedTransport>,
+    max_response_bytes: Option<usize>,
+    token_provider: Option<TokenProvider>,
+    #[cfg(feature = "bedrock")]
+    aws_credentials: Option<bedrock::AwsCredentials>,
 }
 
 
@@ -47,41 +130,184 @@ impl Credentials {
     ) -> Self {
         let base_url = parse_base_url(base_url.into());
         let provider = Self::infer_provider(&base_url);
+        Self::new_with_provider(api_key, base_url, provider)
+    }
+
+    /// Creates credentials for the OpenAI API, at its standard base URL.
+    pub fn openai(api_key: impl Into<String>) -> Self {
+        Self::new_with_provider(api_key, DEFAULT_BASE_URL.as_str(), ApiProvider::OpenAI)
+    }
+
+    /// Creates credentials for the Anthropic API, at its standard base URL.
+    pub fn anthropic(api_key: impl Into<String>) -> Self {
+        Self::new_with_provider(api_key, ANTHROPIC_BASE_URL, ApiProvider::Anthropic)
+    }
+
+    /// Creates credentials for the Gemini API, at its standard base URL.
+    pub fn gemini(api_key: impl Into<String>) -> Self {
+        Self::new_with_provider(api_key, GEMINI_BASE_URL, ApiProvider::Gemini)
+    }
+
+    /// Creates credentials for the Together AI API, at its standard base URL.
+    pub fn together(api_key: impl Into<String>) -> Self {
+        Self::new_with_provider(api_key, TOGETHER_BASE_URL, ApiProvider::Together)
+    }
+
+    /// Creates credentials for the Groq API, at its standard base URL.
+    pub fn groq(api_key: impl Into<String>) -> Self {
+        Self::new_with_provider(api_key, GROQ_BASE_URL, ApiProvider::Groq)
+    }
+
+    /// Creates credentials for Claude through AWS Bedrock in `region`, signing
+    /// requests with `aws_credentials`.
+    #[cfg(feature = "bedrock")]
+    pub fn bedrock(region: impl Into<String>, aws_credentials: bedrock::AwsCredentials) -> Self {
+        let region = region.into();
+        let base_url = bedrock::bedrock_base_url(&region);
+        Self {
+            aws_credentials: Some(aws_credentials),
+            ..Self::new_with_provider("", base_url, ApiProvider::Bedrock { region })
+        }
+    }
+
+    /// Creates credentials for a server implementing the OpenAI API, such as a
+    /// local Ollama instance at `http://localhost:11434/v1/`.
+    /// Pass an empty `api_key` for servers that don't need one.
+    pub fn openai_compatible(api_key: impl Into<String>, base_url: impl Into<String>) -> Self {
+        Self::new_with_provider(api_key, base_url, ApiProvider::OpenAICompatible)
+    }
 
+    fn new_with_provider(
+        api_key: impl Into<String>,
+        base_url: impl Into<String>,
+        provider: ApiProvider,
+    ) -> Self {
         Self {
             api_key: api_key.into(),
-            base_url,
-            provider
+            base_url: parse_base_url(base_url.into()),
+            provider,
+            timeout: None,
+            stream_idle_timeout: None,
+            anthropic_version: None,
+            anthropic_beta: Vec::new(),
+            organization: None,
+            project: None,
+            retry_policy: None,
+            headers: HeaderMap::new(),
+            auth_header: true,
+            http_referer: None,
+            app_title: None,
+            request_hook: None,
+            concurrency_limit: None,
+            rate_limiter: None,
+            client: None,
+            transport: None,
+            max_response_bytes: None,
+            token_provider: None,
+            #[cfg(feature = "bedrock")]
+            aws_credentials: None,
         }
     }
 
     /// Fetches credentials from the environment variables for a specific provider.
+    ///
+    /// The key of an OpenAI-compatible server (`OPENAI_COMPATIBLE_KEY`) is optional.
+    /// Bedrock reads AWS credentials from the environment or the shared credentials file.
     /// # Panics
     /// This function panics if the necessary environment variables are missing.
     pub fn from_env(provider:ApiProvider) -> Credentials {
-        let (api_key_var, base_url_var) = match provider {
-            ApiProvider::OpenAI => ("OPENAI_KEY", "OPENAI_BASE_URL"),
-            ApiProvider::Anthropic => ("ANTHROPIC_KEY", "ANTHROPIC_URL"),
-        };
+        #[cfg(feature = "bedrock")]
+        if let ApiProvider::Bedrock { region } = provider {
+            let aws_credentials = bedrock::AwsCredentials::from_env()
+                .unwrap_or_else(|error| panic!("AWS credentials not found: {error}"));
+            return Self::bedrock(region, aws_credentials);
+        }
+        let (api_key_var, base_url_var) = Self::env_vars(&provider);
         
-        let api_key = env::var(api_key_var)
-            .unwrap_or_else(|_| panic!("Environment variable {api_key_var} is not set"));
+        let api_key = match env::var(api_key_var) {
+            Ok(api_key) => api_key,
+            Err(_) if provider == ApiProvider::OpenAICompatible => String::new(),
+            Err(_) => panic!("Environment variable {api_key_var} is not set"),
+        };
         
-        let base_url_unparsed = env::var(base_url_var)
-            .unwrap_or_else(|_| panic!("Environment variable {base_url_var} is not set"));
+        let base_url_unparsed = match env::var(base_url_var) {
+            Ok(base_url) => base_url,
+            Err(_) if provider == ApiProvider::OpenRouter => OPENROUTER_BASE_URL.to_string(),
+            Err(_) if provider == ApiProvider::Gemini => GEMINI_BASE_URL.to_string(),
+            Err(_) if provider == ApiProvider::Together => TOGETHER_BASE_URL.to_string(),
+            Err(_) if provider == ApiProvider::Groq => GROQ_BASE_URL.to_string(),
+            Err(_) => panic!("Environment variable {base_url_var} is not set"),
+        };
 
-        let base_url = parse_base_url(base_url_unparsed);
+        Self::new_with_provider(api_key, base_url_unparsed, provider)
+    }
+
+    /// Reads credentials from the `provider` section of a settings file, such as
+    /// `{"openai": {"api_key": "sk-...", "base_url": "https://api.openai.com/v1/"}}`.
+    ///
+    /// Files ending in `.toml` are read as TOML, which requires the `toml-subset`
+    /// feature, others as JSON. The environment variables read by
+    /// [`Credentials::from_env`] take precedence over the file. The base URL
+    /// defaults to the standard one of the provider.
+    pub fn from_file(path: impl AsRef<Path>, provider: ApiProvider) -> io::Result<Credentials> {
+        config::credentials_from_file(path.as_ref(), provider, |name| env::var(name).ok())
+    }
 
-        Credentials { api_key, base_url, provider}
+    /// Reads credentials from the variables of a `.env` file, named like the
+    /// environment variables read by [`Credentials::from_env`].
+    ///
+    /// Unlike `dotenvy::dotenv`, the process environment is left untouched and
+    /// isn't read, so each tenant of an app can have its own file. The base URL
+    /// defaults to the standard one of the provider.
+    #[cfg(feature = "dotenv")]
+    pub fn from_dotenv(path: impl AsRef<Path>, provider: ApiProvider) -> io::Result<Credentials> {
+        config::credentials_from_dotenv(path.as_ref(), provider)
+    }
 
+    /// The environment variables holding the API key and base URL of `provider`.
+    fn env_vars(provider: &ApiProvider) -> (&'static str, &'static str) {
+        match provider {
+            ApiProvider::OpenAI => ("OPENAI_KEY", "OPENAI_BASE_URL"),
+            ApiProvider::Anthropic => ("ANTHROPIC_KEY", "ANTHROPIC_URL"),
+            ApiProvider::OpenAICompatible => {
+                ("OPENAI_COMPATIBLE_KEY", "OPENAI_COMPATIBLE_BASE_URL")
+            }
+            ApiProvider::OpenRouter => ("OPENROUTER_KEY", "OPENROUTER_BASE_URL"),
+            ApiProvider::Gemini => ("GEMINI_KEY", "GEMINI_BASE_URL"),
+            ApiProvider::Together => ("TOGETHER_API_KEY", "TOGETHER_BASE_URL"),
+            ApiProvider::Groq => ("GROQ_API_KEY", "GROQ_BASE_URL"),
+            #[cfg(feature = "bedrock")]
+            ApiProvider::Bedrock { .. } => unreachable!("Bedrock uses AWS credentials"),
+        }
     }
 
     /// Infers the provider based on the base URL.
+    /// Servers on the local machine are assumed to be OpenAI-compatible.
     fn infer_provider(base_url: &str) -> ApiProvider {
-        if base_url.contains("openai") {
+        let authority = base_url
+            .split_once("://")
+            .map_or(base_url, |(_, rest)| rest)
+            .split('/')
+            .next()
+            .unwrap_or_default();
+        let host = match authority.split_once(']') {
+            Some((ipv6, _)) => &authority[..=ipv6.len()],
+            None => authority.split(':').next().unwrap_or_default(),
+        };
+        if host == "openrouter.ai" {
+            ApiProvider::OpenRouter
+        } else if host == "api.together.xyz" {
+            ApiProvider::Together
+        } else if host == "api.groq.com" {
+            ApiProvider::Groq
+        } else if base_url.contains("openai") {
             ApiProvider::OpenAI
         } else if base_url.contains("anthropic") {
             ApiProvider::Anthropic
+        } else if host == "generativelanguage.googleapis.com" {
+            ApiProvider::Gemini
+        } else if matches!(host, "localhost" | "127.0.0.1" | "0.0.0.0" | "[::1]") {
+            ApiProvider::OpenAICompatible
         } else {
             panic!("Unrecognized base URL: {}", base_url);
         }
@@ -98,16 +324,300 @@ impl Credentials {
     pub fn provider(&self) -> &ApiProvider {
         &self.provider
     }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn stream_idle_timeout(&self) -> Option<Duration> {
+        self.stream_idle_timeout
+    }
+
+    pub fn max_response_bytes(&self) -> Option<usize> {
+        self.max_response_bytes
+    }
+
+    /// Fails requests whose response body is larger than `max_bytes` instead of
+    /// reading all of it into memory, e.g. with untrusted endpoints.
+    /// Streams fail once the data of their events exceeds `max_bytes` in total.
+    pub fn with_max_response_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_response_bytes = Some(max_bytes);
+        self
+    }
+
+    fn stream_limits(&self) -> StreamLimits {
+        StreamLimits {
+            idle_timeout: self.stream_idle_timeout,
+            max_bytes: self.max_response_bytes,
+            received_bytes: 0,
+        }
+    }
+
+    /// Bounds how long a (non-streaming) request may take, from sending it
+    /// until the response body has been read.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Fails a response stream with a `timeout` error, and closes it, when no
+    /// event has been received for `timeout`. Chat requests can override it
+    /// per request.
+    ///
+    /// Streams aren't bound by [`Credentials::with_timeout`], since a long
+    /// generation can legitimately take minutes.
+    pub fn with_stream_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.stream_idle_timeout = Some(timeout);
+        self
+    }
+
+    /// The `anthropic-version` header sent with Anthropic requests.
+    pub fn anthropic_version(&self) -> &str {
+        self.anthropic_version
+            .as_deref()
+            .unwrap_or(DEFAULT_ANTHROPIC_VERSION)
+    }
+
+    /// The `anthropic-beta` header values sent with Anthropic requests.
+    pub fn anthropic_beta(&self) -> &[String] {
+        &self.anthropic_beta
+    }
+
+    /// Sets the `anthropic-version` header, [`DEFAULT_ANTHROPIC_VERSION`] by default.
+    pub fn with_anthropic_version(mut self, version: impl Into<String>) -> Self {
+        self.anthropic_version = Some(version.into());
+        self
+    }
+
+    /// Opts into an Anthropic beta feature, e.g. `"prompt-caching-2024-07-31"`.
+    /// Can be called several times, all values are sent in the `anthropic-beta` header.
+    pub fn with_anthropic_beta(mut self, beta: impl Into<String>) -> Self {
+        self.anthropic_beta.push(beta.into());
+        self
+    }
+
+    pub fn organization(&self) -> Option<&str> {
+        self.organization.as_deref()
+    }
+
+    pub fn project(&self) -> Option<&str> {
+        self.project.as_deref()
+    }
+
+    /// Sets the `OpenAI-Organization` header, for accounts belonging to several organizations.
+    pub fn with_organization(mut self, organization: impl Into<String>) -> Self {
+        self.organization = Some(organization.into());
+        self
+    }
+
+    /// Sets the `OpenAI-Project` header, attributing usage to a specific project.
+    pub fn with_project(mut self, project: impl Into<String>) -> Self {
+        self.project = Some(project.into());
+        self
+    }
+
+    pub fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retry_policy.as_ref()
+    }
+
+    /// Retries failed (non-streaming) requests according to `policy`.
+    /// Requests aren't retried by default.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Extra headers sent with every request.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Sends an extra header with every request, such as a tenant ID required
+    /// by an API gateway. Replaces any header the crate would set with the same name.
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Whether the API key is sent in the header of the provider, such as
+    /// `Authorization: Bearer <key>` or `x-api-key`.
+    pub fn auth_header(&self) -> bool {
+        self.auth_header
+    }
+
+    /// Leaves out the API key header, for endpoints that authenticate some other
+    /// way, e.g. with a header set by [`Credentials::with_header`], a signed URL
+    /// or not at all. Bedrock requests are still signed.
+    pub fn without_auth_header(mut self) -> Self {
+        self.auth_header = false;
+        self
+    }
+
+    pub fn http_referer(&self) -> Option<&str> {
+        self.http_referer.as_deref()
+    }
+
+    pub fn app_title(&self) -> Option<&str> {
+        self.app_title.as_deref()
+    }
+
+    /// Sets the `HTTP-Referer` header, the URL of your app, which OpenRouter
+    /// uses to attribute requests to it.
+    pub fn with_http_referer(mut self, http_referer: impl Into<String>) -> Self {
+        self.http_referer = Some(http_referer.into());
+        self
+    }
+
+    /// Sets the `X-Title` header, the name of your app shown by OpenRouter.
+    pub fn with_app_title(mut self, app_title: impl Into<String>) -> Self {
+        self.app_title = Some(app_title.into());
+        self
+    }
+
+    /// Calls `hook` with the serialized body of every request just before it's
+    /// sent, e.g. to keep an audit log of exactly what was sent.
+    ///
+    /// The hook sees the body only, never the headers carrying the API key.
+    /// Streamed bodies, such as file uploads, aren't passed to it.
+    pub fn with_request_hook(
+        mut self,
+        hook: impl Fn(&OutgoingRequest) + Send + Sync + 'static,
+    ) -> Self {
+        self.request_hook = Some(RequestHookFn(Arc::new(hook)));
+        self
+    }
+
+    /// Fetches the API key with `fetch` instead of using a fixed one, for
+    /// short-lived tokens such as the OAuth tokens of a gateway.
+    ///
+    /// The token is cached, across clones of the credentials, until it expires.
+    /// A request rejected with `401 Unauthorized` fetches a new token and is
+    /// sent again, once. A stream whose token can't be fetched is sent with
+    /// the current key. Blocking requests use the current key.
+    pub fn with_token_provider<F, Fut>(mut self, fetch: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ApiResponseOrError<AccessToken>> + Send + 'static,
+    {
+        self.token_provider = Some(TokenProvider {
+            fetch: Arc::new(move || Box::pin(fetch())),
+            cache: Default::default(),
+        });
+        self
+    }
+
+    /// Replaces the API key with the token of the token provider, if any,
+    /// fetching a new one when `force` is set or the cached one has expired.
+    async fn refresh_token(&mut self, force: bool) -> ApiResponseOrError<()> {
+        if let Some(token_provider) = &self.token_provider {
+            self.api_key = token_provider.token(force).await?;
+        }
+        Ok(())
+    }
+
+    /// Limits the number of requests in flight at once to `max_requests`, across
+    /// these credentials and all their clones. Other requests wait for a slot.
+    ///
+    /// A request holds its slot until its response arrives, retries included.
+    /// Streams and blocking requests aren't limited.
+    ///
+    /// # Panics
+    /// Panics if `max_requests` is 0.
+    pub fn with_max_concurrent_requests(mut self, max_requests: usize) -> Self {
+        assert!(max_requests > 0, "max_requests must be at least 1");
+        self.concurrency_limit = Some(ConcurrencyLimit(Arc::new(
+            tokio::sync::Semaphore::new(max_requests),
+        )));
+        self
+    }
+
+    pub fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_ref()
+    }
+
+    /// Paces (non-streaming) requests to each model according to the rate
+    /// limits the responses report, and to the limits set with
+    /// [`RateLimiter::with_limits`], see [`rate_limit`].
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Sends the requests with `client` instead of a default one, e.g. for custom
+    /// TLS roots, a proxy or connection pool settings. Clones of the credentials
+    /// share the client and its connections.
+    ///
+    /// The timeout of the credentials still applies, on top of the timeouts of
+    /// the client. Blocking requests use their own client.
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = Some(HttpClient(Arc::new(client)));
+        self
+    }
+
+    /// Sends the buffered requests through `transport` instead of the HTTP client,
+    /// e.g. a [`MockTransport`](testing::MockTransport) in tests, see [`transport`].
+    /// Clones of the credentials share the transport.
+    pub fn with_transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Some(SharedTransport(Arc::new(transport)));
+        self
+    }
+
+    /// Returns a copy of these credentials pointing at a different base URL.
+    /// The API key and provider are left untouched.
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = parse_base_url(base_url.into());
+        self
+    }
 }
 
 
 #[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
 pub struct OpenAiError {
     pub message: String,
-    #[serde(rename = "type")]
+    /// The type of the error, such as `invalid_request_error`. With Gemini,
+    /// its status, such as `INVALID_ARGUMENT`.
+    #[serde(rename = "type", alias = "status")]
     pub error_type: String,
     pub param: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_error_code")]
     pub code: Option<String>,
+    /// How far over the context window a chat request was, for context length errors.
+    #[serde(skip)]
+    pub context_length: Option<ContextLengthDetails>,
+    /// The HTTP status of the response the error was read from, if any.
+    #[serde(skip)]
+    pub status: Option<StatusCode>,
+    /// The ID the provider gave the failed request, to quote in support requests,
+    /// see [`ResponseMeta::request_id`].
+    #[serde(skip)]
+    pub request_id: Option<String>,
+}
+
+/// Reads an error code, a string with OpenAI and the HTTP status with Gemini.
+fn deserialize_error_code<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Code {
+        String(String),
+        Number(i64),
+    }
+
+    Ok(Option::<Code>::deserialize(deserializer)?.map(|code| match code {
+        Code::String(code) => code,
+        Code::Number(code) => code.to_string(),
+    }))
+}
+
+/// The size of a chat request that exceeded the context window of its model.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ContextLengthDetails {
+    /// The context window of the model, `None` when it isn't known.
+    pub context_window: Option<u32>,
+    /// The estimated number of prompt tokens sent.
+    pub estimated_tokens: u32,
 }
 
 impl OpenAiError {
@@ -117,7 +627,79 @@ impl OpenAiError {
             error_type,
             param: None,
             code: None,
+            context_length: None,
+            status: None,
+            request_id: None,
+        }
+    }
+
+    fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    /// Whether the request was rejected for going over a rate limit, which a
+    /// later attempt may not be. An exhausted quota isn't a rate limit.
+    pub fn is_rate_limited(&self) -> bool {
+        if self.code.as_deref() == Some("insufficient_quota") {
+            return false;
+        }
+        self.status == Some(StatusCode::TOO_MANY_REQUESTS)
+            || self.code.as_deref() == Some("rate_limit_exceeded")
+            // Anthropic's type, then Gemini's status.
+            || matches!(
+                self.error_type.as_str(),
+                "rate_limit_error" | "RESOURCE_EXHAUSTED"
+            )
+    }
+
+    /// Whether sending the request again may succeed: for rate limits, server
+    /// errors, timeouts and failed connections. Errors in the request itself,
+    /// such as `400 Bad Request`, `401 Unauthorized` or `404 Not Found`, aren't.
+    pub fn is_retryable(&self) -> bool {
+        if self.is_rate_limited() {
+            return true;
         }
+        if matches!(self.code.as_deref(), Some("timeout" | "connection_error")) {
+            return true;
+        }
+        match self.status {
+            Some(status) => status.is_server_error(),
+            // Errors sent in the body of a stream.
+            None => matches!(
+                self.error_type.as_str(),
+                "server_error" | "api_error" | "overloaded_error" | "UNAVAILABLE" | "INTERNAL"
+            ),
+        }
+    }
+
+    /// Whether the request didn't fit in the context window of the model.
+    pub fn is_context_length_exceeded(&self) -> bool {
+        self.code.as_deref() == Some("context_length_exceeded")
+            || self.message.contains("maximum context length")
+            // Anthropic's wording.
+            || self.message.contains("prompt is too long")
+    }
+
+    /// Adds the size of the request to a context length error, so its message
+    /// tells how far over the limit it was. Other errors are left untouched.
+    fn with_context_length(mut self, model: &str, estimated_tokens: u32) -> Self {
+        if !self.is_context_length_exceeded() {
+            return self;
+        }
+        let context_window = models::context_window(model);
+        self.message = match context_window {
+            Some(context_window) => format!(
+                "{} (sent ~{estimated_tokens} tokens, limit {context_window})",
+                self.message
+            ),
+            None => format!("{} (sent ~{estimated_tokens} tokens)", self.message),
+        };
+        self.context_length = Some(ContextLengthDetails {
+            context_window,
+            estimated_tokens,
+        });
+        self
     }
 }
 
@@ -136,26 +718,118 @@ pub enum ApiResponse<T> {
     Ok(T),
 }
 
-#[derive(Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+/// Token usage of an OpenAI request. Counts missing from the response are 0.
+#[derive(Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[serde(default)]
 pub struct Usage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
 }
 
-#[derive(Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+impl Usage {
+    /// No tokens, the starting point to add up the usage of several responses.
+    pub fn zero() -> Self {
+        Usage::default()
+    }
+}
+
+impl std::ops::Add for Usage {
+    type Output = Usage;
+
+    fn add(mut self, other: Usage) -> Usage {
+        self += other;
+        self
+    }
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[serde(default)]
 pub struct AnthropicUsage {
     pub input_tokens: u64,
     pub cache_creation_input_tokens: u64,
     pub cache_read_input_tokens: u64,
     pub output_tokens: u64,
+    /// The capacity the request was served with.
+    pub service_tier: Option<anthrophic_chat::AnthropicServedTier>,
 }
 
 pub type ApiResponseOrError<T> = Result<T, OpenAiError>;
 
+/// The HTTP status and headers of the response a result was parsed from.
+///
+/// Useful to read headers the crate doesn't parse itself, such as custom
+/// headers set by an API gateway.
+#[derive(Debug, Clone)]
+pub struct ResponseMeta {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+}
+
+impl ResponseMeta {
+    fn from_response(response: &Response) -> Self {
+        ResponseMeta {
+            status: response.status(),
+            headers: response.headers().clone(),
+        }
+    }
+
+    /// The ID the provider gave the request, from the `x-request-id` header of
+    /// OpenAI or the `request-id` header of Anthropic. Support teams ask for it
+    /// to look into a request.
+    pub fn request_id(&self) -> Option<&str> {
+        request_id(&self.headers)
+    }
+
+    /// Attaches the request ID to an error read from the body of the response.
+    fn error(&self, error: OpenAiError) -> OpenAiError {
+        OpenAiError {
+            request_id: self.request_id().map(str::to_string),
+            ..error
+        }
+    }
+}
+
+/// The ID of a request, read from the headers of its response.
+fn request_id(headers: &HeaderMap) -> Option<&str> {
+    ["x-request-id", "request-id"]
+        .into_iter()
+        .find_map(|name| headers.get(name)?.to_str().ok())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn is_connect(error: &reqwest::Error) -> bool {
+    error.is_connect()
+}
+
+/// The browser doesn't tell connection errors apart from other failed fetches.
+#[cfg(target_arch = "wasm32")]
+fn is_connect(_error: &reqwest::Error) -> bool {
+    false
+}
+
 impl From<reqwest::Error> for OpenAiError {
     fn from(value: reqwest::Error) -> Self {
-        OpenAiError::new(value.to_string(), "reqwest".to_string())
+        let code = if value.is_timeout() {
+            Some("timeout".to_string())
+        } else if is_connect(&value) {
+            Some("connection_error".to_string())
+        } else {
+            None
+        };
+        OpenAiError {
+            code,
+            status: value.status(),
+            ..OpenAiError::new(value.to_string(), "reqwest".to_string())
+        }
     }
 }
 
@@ -175,6 +849,215 @@ impl From<serde_json::Error> for OpenAiError {
     }
 }
 
+impl From<CannotCloneRequestError> for OpenAiError {
+    fn from(error: CannotCloneRequestError) -> Self {
+        OpenAiError::new(error.to_string(), "cannot_clone_request".to_string())
+    }
+}
+
+/// Transport errors convert like [`reqwest::Error`], and unsuccessful responses
+/// keep their status. Their body isn't read, unlike in the streams of the crate,
+/// which surface the API error it holds.
+impl From<reqwest_eventsource::Error> for OpenAiError {
+    fn from(error: reqwest_eventsource::Error) -> Self {
+        match error {
+            reqwest_eventsource::Error::Transport(error) => error.into(),
+            reqwest_eventsource::Error::InvalidStatusCode(status, _) => OpenAiError::new(
+                format!("Unexpected status code {status}"),
+                "http_status".to_string(),
+            )
+            .with_status(status),
+            error => OpenAiError::new(error.to_string(), "stream".to_string()),
+        }
+    }
+}
+
+/// Serializes a map with its keys sorted, so that a request body doesn't change
+/// from one run to the next with the iteration order of a `HashMap`.
+fn serialize_sorted<S, V>(
+    map: &std::collections::HashMap<String, V>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    V: Serialize,
+{
+    map.iter()
+        .collect::<std::collections::BTreeMap<_, _>>()
+        .serialize(serializer)
+}
+
+/// [`serialize_sorted`] for an optional map.
+fn serialize_sorted_option<S, V>(
+    map: &Option<std::collections::HashMap<String, V>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+    V: Serialize,
+{
+    match map {
+        Some(map) => serializer.serialize_some(&SortedMap(map)),
+        None => serializer.serialize_none(),
+    }
+}
+
+struct SortedMap<'a, V>(&'a std::collections::HashMap<String, V>);
+
+impl<V: Serialize> Serialize for SortedMap<'_, V> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_sorted(self.0, serializer)
+    }
+}
+
+/// Checks that a sampling parameter, such as `temperature`, is within `0..=max`,
+/// for the validation of the builders.
+fn validate_range(name: &str, value: Option<f32>, max: f32) -> Result<(), String> {
+    match value {
+        Some(value) if !(0.0..=max).contains(&value) => {
+            Err(format!("{name} must be between 0 and {max}, got {value}"))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Builder errors become `builder` errors, with the missing field, if any, as `param`.
+macro_rules! impl_from_builder_error {
+    ($($error:path),* $(,)?) => {$(
+        impl From<$error> for OpenAiError {
+            fn from(error: $error) -> Self {
+                use $error as BuilderError;
+                let param = match &error {
+                    BuilderError::UninitializedField(field) => Some(field.to_string()),
+                    BuilderError::ValidationError(_) => None,
+                };
+                OpenAiError {
+                    param,
+                    ..OpenAiError::new(error.to_string(), "builder".to_string())
+                }
+            }
+        }
+    )*};
+}
+
+impl_from_builder_error!(
+    anthrophic_chat::AnthropicChatCompletionBuilderError,
+    audio::SpeechBuilderError,
+    audio::TranscriptionBuilderError,
+    chat::ChatCompletionBuilderError,
+    completions::CompletionBuilderError,
+    edits::EditBuilderError,
+    embeddings::EmbeddingsBuilderError,
+    files::FileListBuilderError,
+    files::FileUploadBuilderError,
+    gemini_chat::GeminiChatBuilderError,
+    images::ImageGenerationBuilderError,
+    moderations::ModerationBuilderError,
+    responses::ResponseBuilderError,
+);
+
+#[cfg(feature = "tokio-runtime")]
+tokio::task_local! {
+    static TASK_CREDENTIALS: Credentials;
+}
+
+/// Runs `future` with `credentials` as the default for every request made
+/// within it that wasn't given credentials explicitly.
+///
+/// Useful in multi-tenant servers, where each task serves a different tenant:
+///
+/// ```no_run
+/// # async fn handle_request() {}
+/// # async fn example() {
+/// use openai::{with_default_credentials, Credentials};
+///
+/// let tenant_credentials = Credentials::new("sk-tenant", "https://api.openai.com/v1/");
+/// with_default_credentials(tenant_credentials, handle_request()).await;
+/// # }
+/// ```
+///
+/// The scope doesn't extend to tasks spawned from within it.
+#[cfg(feature = "tokio-runtime")]
+pub async fn with_default_credentials<F: Future>(credentials: Credentials, future: F) -> F::Output {
+    TASK_CREDENTIALS.scope(credentials, future).await
+}
+
+/// Same as [`with_default_credentials`], for synchronous code such as the blocking API.
+#[cfg(feature = "tokio-runtime")]
+pub fn with_default_credentials_sync<R>(credentials: Credentials, f: impl FnOnce() -> R) -> R {
+    TASK_CREDENTIALS.sync_scope(credentials, f)
+}
+
+/// Sets the credentials of every request that wasn't given credentials explicitly,
+/// outside of [`with_default_credentials`], for any provider:
+///
+/// ```no_run
+/// use openai::{set_default_credentials, ApiProvider, Credentials};
+///
+/// set_default_credentials(Credentials::from_env(ApiProvider::Anthropic));
+/// ```
+///
+/// Until they are set, the OpenAI credentials of the environment are used, see
+/// [`Credentials::from_env`].
+pub fn set_default_credentials(credentials: Credentials) {
+    *DEFAULT_CREDENTIALS.write().unwrap() = Some(credentials);
+}
+
+/// The global default credentials, read from the environment the first time
+/// if none were set.
+fn default_credentials() -> Credentials {
+    if let Some(credentials) = DEFAULT_CREDENTIALS.read().unwrap().as_ref() {
+        return credentials.clone();
+    }
+    // Read outside the lock, since it panics when the variables are missing.
+    let credentials = Credentials::from_env(ApiProvider::OpenAI);
+    DEFAULT_CREDENTIALS
+        .write()
+        .unwrap()
+        .get_or_insert(credentials)
+        .clone()
+}
+
+/// Falls back to the scoped default credentials, then to the global default
+/// credentials, when none were given for a request.
+fn resolve_credentials(credentials_opt: Option<Credentials>) -> Credentials {
+    #[cfg(feature = "tokio-runtime")]
+    {
+        credentials_opt.unwrap_or_else(|| {
+            TASK_CREDENTIALS
+                .try_with(Credentials::clone)
+                .unwrap_or_else(|_| default_credentials())
+        })
+    }
+    #[cfg(not(feature = "tokio-runtime"))]
+    {
+        credentials_opt.unwrap_or_else(default_credentials)
+    }
+}
+
+/// Applies a per-request base URL override on top of the request credentials.
+fn override_base_url(
+    credentials_opt: Option<Credentials>,
+    base_url_opt: Option<String>,
+) -> Option<Credentials> {
+    match base_url_opt {
+        Some(base_url) => Some(resolve_credentials(credentials_opt).with_base_url(base_url)),
+        None => credentials_opt,
+    }
+}
+
+/// Applies per-request extra headers on top of the request credentials.
+fn override_headers(credentials_opt: Option<Credentials>, headers: &HeaderMap) -> Option<Credentials> {
+    if headers.is_empty() {
+        return credentials_opt;
+    }
+    let mut credentials = resolve_credentials(credentials_opt);
+    for (name, value) in headers {
+        credentials.headers.insert(name, value.clone());
+    }
+    Some(credentials)
+}
+
 async fn openai_request_json<F, T>(
     method: Method,
     route: &str,
@@ -185,67 +1068,869 @@ where
     F: FnOnce(RequestBuilder) -> RequestBuilder,
     T: DeserializeOwned,
 {
-    let api_response = openai_request(method, route, builder, credentials_opt)
-        .await?
-        .json()
-        .await?;
-    match api_response {
-        ApiResponse::Ok(t) => Ok(t),
-        ApiResponse::Err { error } => Err(error),
-    }
+    openai_request_json_with_meta(method, route, builder, credentials_opt)
+        .await
+        .map(|(t, _)| t)
 }
 
-async fn openai_request<F>(
+async fn openai_request_json_with_meta<F, T>(
     method: Method,
     route: &str,
     builder: F,
     credentials_opt: Option<Credentials>,
-) -> ApiResponseOrError<Response>
+) -> ApiResponseOrError<(T, ResponseMeta)>
 where
     F: FnOnce(RequestBuilder) -> RequestBuilder,
+    T: DeserializeOwned,
 {
-    let client = Client::new();
-    
-    let credentials =
-        credentials_opt.unwrap_or_else(|| DEFAULT_CREDENTIALS.read().unwrap().clone());
-    let mut request = client.request(method, format!("{}{route}", credentials.base_url));
-    request = builder(request);
-    let response = request
-        .header(AUTHORIZATION, format!("Bearer {}", credentials.api_key))
-        .send()
-        .await?;
-    Ok(response)
+    let response = openai_request(method, route, builder, credentials_opt).await?;
+    let meta = ResponseMeta::from_response(&response);
+    if !meta.status.is_success() {
+        return Err(status_error(response).await);
+    }
+    let api_response = response.json().await?;
+    match api_response {
+        ApiResponse::Ok(t) => Ok((t, meta)),
+        ApiResponse::Err { error } => Err(meta.error(error)),
+    }
 }
 
-async fn openai_request_stream<F>(
+/// Sends a request whose successful response isn't JSON, such as audio,
+/// returning the raw body.
+async fn openai_request_bytes<F>(
     method: Method,
     route: &str,
     builder: F,
     credentials_opt: Option<Credentials>,
-) -> Result<EventSource, CannotCloneRequestError>
+) -> ApiResponseOrError<bytes::Bytes>
 where
     F: FnOnce(RequestBuilder) -> RequestBuilder,
 {
-    let client = Client::new();
-    let credentials =
-        credentials_opt.unwrap_or_else(|| DEFAULT_CREDENTIALS.read().unwrap().clone());
-    let mut request = client.request(method, format!("{}{route}", credentials.base_url));
-    request = builder(request);
-    let stream = request
-        .header(AUTHORIZATION, format!("Bearer {}", credentials.api_key))
-        .eventsource()?;
-    Ok(stream)
+    let response = openai_request(method, route, builder, credentials_opt).await?;
+    if !response.status().is_success() {
+        return Err(status_error(response).await);
+    }
+    Ok(response.bytes().await?)
 }
 
-async fn openai_get<T>(route: &str, credentials_opt: Option<Credentials>) -> ApiResponseOrError<T>
+async fn openai_request<F>(
+    method: Method,
+    route: &str,
+    builder: F,
+    credentials_opt: Option<Credentials>,
+) -> ApiResponseOrError<Response>
 where
-    T: DeserializeOwned,
+    F: FnOnce(RequestBuilder) -> RequestBuilder,
 {
-    openai_request_json(Method::GET, route, |request| request, credentials_opt).await
+    let mut credentials = resolve_credentials(credentials_opt);
+    credentials.refresh_token(false).await?;
+    let request = openai_request_builder(method, route, builder, &credentials);
+    send_request(request, &credentials).await
 }
 
-async fn openai_delete<T>(
-    route: &str,
+/// A request about to be sent, as passed to [`Credentials::with_request_hook`].
+#[derive(Debug)]
+pub struct OutgoingRequest<'a> {
+    pub method: &'a Method,
+    pub url: &'a str,
+    /// The serialized body, such as the JSON of a chat completion request.
+    pub body: &'a [u8],
+}
+
+#[derive(Clone)]
+struct RequestHookFn(Arc<dyn Fn(&OutgoingRequest) + Send + Sync>);
+
+impl RequestHookFn {
+    fn call(&self, method: &Method, url: &reqwest::Url, body: Option<&[u8]>) {
+        if let Some(body) = body {
+            (self.0)(&OutgoingRequest {
+                method,
+                url: url.as_str(),
+                body,
+            });
+        }
+    }
+}
+
+impl std::fmt::Debug for RequestHookFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RequestHookFn(..)")
+    }
+}
+
+impl PartialEq for RequestHookFn {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for RequestHookFn {}
+
+/// Bounds the requests in flight, shared by clones of the credentials.
+#[derive(Debug, Clone)]
+struct ConcurrencyLimit(Arc<tokio::sync::Semaphore>);
+
+impl PartialEq for ConcurrencyLimit {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ConcurrencyLimit {}
+
+/// Runs the `requests` with at most `concurrency` of them in flight at once,
+/// returning their results in the order of `requests`. A failed request doesn't
+/// stop the others.
+///
+/// ```no_run
+/// # async fn run() {
+/// use openai::{batch_create, chat::{ChatCompletion, ChatCompletionMessage}};
+///
+/// let questions = ["What is a crab?", "What is a lobster?"];
+/// let completions = batch_create(
+///     questions.map(|question| {
+///         ChatCompletion::builder("gpt-4o", [ChatCompletionMessage::user(question)]).create()
+///     }),
+///     8,
+/// )
+/// .await;
+/// # }
+/// ```
+///
+/// To bound the requests in flight across batches, see
+/// [`Credentials::with_max_concurrent_requests`].
+///
+/// # Panics
+/// Panics if `concurrency` is 0.
+pub async fn batch_create<I, T>(requests: I, concurrency: usize) -> Vec<ApiResponseOrError<T>>
+where
+    I: IntoIterator,
+    I::Item: Future<Output = ApiResponseOrError<T>>,
+{
+    assert!(concurrency > 0, "concurrency must be at least 1");
+    futures_util::stream::iter(requests)
+        .buffered(concurrency)
+        .collect()
+        .await
+}
+
+/// A token returned by the provider of [`Credentials::with_token_provider`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AccessToken {
+    pub token: String,
+    /// How long the token is valid for, forever when `None`.
+    pub expires_in: Option<Duration>,
+}
+
+impl AccessToken {
+    pub fn new(token: impl Into<String>) -> Self {
+        AccessToken {
+            token: token.into(),
+            expires_in: None,
+        }
+    }
+
+    pub fn with_expires_in(mut self, expires_in: Duration) -> Self {
+        self.expires_in = Some(expires_in);
+        self
+    }
+}
+
+/// How long before its expiry a token is refreshed, so it doesn't expire in flight.
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+type TokenFuture = Pin<Box<dyn Future<Output = ApiResponseOrError<AccessToken>> + Send>>;
+
+/// Fetches tokens and caches them until they expire, compared by identity.
+#[derive(Clone)]
+struct TokenProvider {
+    fetch: Arc<dyn Fn() -> TokenFuture + Send + Sync>,
+    /// Locked while fetching, so concurrent requests share a single fetch.
+    cache: Arc<tokio::sync::Mutex<Option<CachedToken>>>,
+}
+
+struct CachedToken {
+    token: String,
+    expiry: Option<Instant>,
+}
+
+impl TokenProvider {
+    async fn token(&self, force: bool) -> ApiResponseOrError<String> {
+        let mut cache = self.cache.lock().await;
+        if let Some(cached) = cache.as_ref() {
+            if !force && cached.expiry.is_none_or(|expiry| Instant::now() < expiry) {
+                return Ok(cached.token.clone());
+            }
+        }
+        let token = (self.fetch)().await?;
+        let expiry = token
+            .expires_in
+            .map(|expires_in| Instant::now() + expires_in.saturating_sub(TOKEN_EXPIRY_MARGIN));
+        *cache = Some(CachedToken {
+            token: token.token.clone(),
+            expiry,
+        });
+        Ok(token.token)
+    }
+}
+
+impl std::fmt::Debug for TokenProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TokenProvider(..)")
+    }
+}
+
+impl PartialEq for TokenProvider {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.cache, &other.cache)
+    }
+}
+
+impl Eq for TokenProvider {}
+
+/// A client set with [`Credentials::with_client`], compared by identity.
+#[derive(Debug, Clone)]
+struct HttpClient(Arc<Client>);
+
+impl PartialEq for HttpClient {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for HttpClient {}
+
+impl Credentials {
+    fn http_client(&self) -> Client {
+        self.client
+            .as_ref()
+            .map_or_else(Client::new, |client| Client::clone(&client.0))
+    }
+}
+
+/// Passes the body of a request to the request hook of the credentials, if any.
+/// The clone shares the body already serialized by the builder.
+fn call_request_hook(request: &RequestBuilder, credentials: &Credentials) {
+    let Some(hook) = &credentials.request_hook else {
+        return;
+    };
+    if let Some(request) = request.try_clone().and_then(|request| request.build().ok()) {
+        hook.call(
+            request.method(),
+            request.url(),
+            request.body().and_then(|body| body.as_bytes()),
+        );
+    }
+}
+
+/// The body of a request, `None` when it is streamed, as multipart bodies are.
+#[cfg(feature = "tokio-runtime")]
+fn request_body(request: &RequestBuilder) -> Option<Vec<u8>> {
+    let request = request.try_clone()?.build().ok()?;
+    Some(request.body()?.as_bytes()?.to_vec())
+}
+
+/// The `model` of a JSON request body, used to look up its rate limits.
+#[cfg(feature = "tokio-runtime")]
+fn request_model(body: &[u8]) -> Option<String> {
+    #[derive(Deserialize)]
+    struct ModelField {
+        model: String,
+    }
+
+    serde_json::from_slice::<ModelField>(body)
+        .ok()
+        .map(|body| body.model)
+}
+
+/// Sends a buffered request with the timeout and retry policy of the credentials.
+async fn send_request(
+    mut request: RequestBuilder,
+    credentials: &Credentials,
+) -> ApiResponseOrError<Response> {
+    call_request_hook(&request, credentials);
+    if let Some(timeout) = credentials.timeout {
+        request = request.timeout(timeout);
+    }
+    #[cfg(feature = "tokio-runtime")]
+    let body = credentials
+        .rate_limiter
+        .as_ref()
+        .and_then(|_| request_body(&request));
+    #[cfg(feature = "tokio-runtime")]
+    if let Some(rate_limiter) = &credentials.rate_limiter {
+        // The whole body is counted, slightly overestimating the prompt.
+        let tokens = body
+            .as_deref()
+            .map_or(0, |body| tokens::estimate_tokens(&String::from_utf8_lossy(body)));
+        rate_limiter.throttle(tokens).await;
+    }
+    #[cfg(feature = "tokio-runtime")]
+    let rate_limited_model = credentials
+        .rate_limiter
+        .as_ref()
+        .zip(body.as_deref().and_then(request_model));
+    #[cfg(feature = "tokio-runtime")]
+    if let Some((rate_limiter, model)) = &rate_limited_model {
+        rate_limiter.acquire(model).await;
+    }
+    let _permit = match &credentials.concurrency_limit {
+        // The semaphore is never closed.
+        Some(limit) => Some(limit.0.acquire().await.unwrap()),
+        None => None,
+    };
+    let retry_request = credentials
+        .token_provider
+        .as_ref()
+        .and_then(|_| request.try_clone());
+    let mut response = send_with_retries(request, credentials).await?;
+    if let Some(request) = retry_request.filter(|_| response.status() == StatusCode::UNAUTHORIZED) {
+        // The token may have been revoked before its expiry.
+        let mut credentials = credentials.clone();
+        credentials.refresh_token(true).await?;
+        let request = with_api_key(request, &credentials)?;
+        response = send_with_retries(request, &credentials).await?;
+    }
+    #[cfg(feature = "tokio-runtime")]
+    if let Some((rate_limiter, model)) = &rate_limited_model {
+        rate_limiter.observe(model, response.headers());
+    }
+    Ok(response)
+}
+
+async fn send_with_retries(
+    request: RequestBuilder,
+    credentials: &Credentials,
+) -> ApiResponseOrError<Response> {
+    let (client, request) = request.build_split();
+    let request = request?;
+    let transport: &dyn Transport = match &credentials.transport {
+        Some(transport) => &*transport.0,
+        None => &client,
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    let limited;
+    #[cfg(not(target_arch = "wasm32"))]
+    let transport = match credentials.max_response_bytes {
+        Some(max_bytes) => {
+            limited = LimitedTransport {
+                transport,
+                max_bytes,
+            };
+            &limited as &dyn Transport
+        }
+        None => transport,
+    };
+    #[cfg(all(feature = "tokio-runtime", not(target_arch = "wasm32")))]
+    if let Some(retry_policy) = &credentials.retry_policy {
+        return retry_policy.send(request, transport).await;
+    }
+    transport.send(request).await
+}
+
+/// Reads the whole body of the responses of a transport, failing when it is
+/// larger than `max_bytes`.
+#[cfg(not(target_arch = "wasm32"))]
+struct LimitedTransport<'a> {
+    transport: &'a dyn Transport,
+    max_bytes: usize,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Transport for LimitedTransport<'_> {
+    fn send(&self, request: reqwest::Request) -> transport::TransportFuture {
+        let response = self.transport.send(request);
+        let max_bytes = self.max_bytes;
+        Box::pin(async move {
+            let mut response = response.await?;
+            let status = response.status();
+            if response
+                .content_length()
+                .is_some_and(|length| length > max_bytes as u64)
+            {
+                return Err(response_too_large(max_bytes).with_status(status));
+            }
+            let mut body = Vec::new();
+            while let Some(chunk) = response.chunk().await? {
+                if body.len() + chunk.len() > max_bytes {
+                    return Err(response_too_large(max_bytes).with_status(status));
+                }
+                body.extend_from_slice(&chunk);
+            }
+            Ok(retry::rebuild_response(
+                status,
+                response.headers().clone(),
+                body.into(),
+            ))
+        })
+    }
+}
+
+fn response_too_large(max_bytes: usize) -> OpenAiError {
+    OpenAiError::new(
+        format!("The response exceeds the limit of {max_bytes} bytes"),
+        "response_too_large".to_string(),
+    )
+}
+
+/// Replaces the API key header of a request with the key of `credentials`.
+fn with_api_key(
+    request: RequestBuilder,
+    credentials: &Credentials,
+) -> ApiResponseOrError<RequestBuilder> {
+    if !credentials.auth_header {
+        return Ok(request);
+    }
+    let (client, request) = request.build_split();
+    let mut request = request?;
+    let (name, value) = match credentials.provider {
+        ApiProvider::Anthropic => ("x-api-key", credentials.api_key.clone()),
+        ApiProvider::Gemini => ("x-goog-api-key", credentials.api_key.clone()),
+        _ => (AUTHORIZATION.as_str(), format!("Bearer {}", credentials.api_key)),
+    };
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        request.headers_mut().insert(name, value);
+    }
+    Ok(RequestBuilder::from_parts(client, request))
+}
+
+async fn openai_request_stream<F>(
+    method: Method,
+    route: &str,
+    builder: F,
+    credentials_opt: Option<Credentials>,
+) -> Result<EventSource, CannotCloneRequestError>
+where
+    F: FnOnce(RequestBuilder) -> RequestBuilder,
+{
+    let mut credentials = resolve_credentials(credentials_opt);
+    // A failed fetch leaves the current key, whose rejection ends the stream.
+    let _ = credentials.refresh_token(false).await;
+    let request = openai_request_builder(method, route, builder, &credentials);
+    call_request_hook(&request, &credentials);
+    let stream = request.eventsource()?;
+    Ok(stream)
+}
+
+/// Prepares a request to an OpenAI-compatible API, shared by the buffered and
+/// streaming helpers. The overall timeout is left out since it doesn't apply to streams.
+fn openai_request_builder<F>(
+    method: Method,
+    route: &str,
+    builder: F,
+    credentials: &Credentials,
+) -> RequestBuilder
+where
+    F: FnOnce(RequestBuilder) -> RequestBuilder,
+{
+    let client = credentials.http_client();
+    let request = client.request(method, route_url(&credentials.base_url, route));
+    openai_headers(builder(request), credentials)
+}
+
+/// Request builders the headers of a provider can be applied to, so the async
+/// and blocking clients share them.
+trait RequestHeaders: Sized {
+    fn add_header(self, name: &str, value: &str) -> Self;
+    fn add_headers(self, headers: HeaderMap) -> Self;
+}
+
+impl RequestHeaders for RequestBuilder {
+    fn add_header(self, name: &str, value: &str) -> Self {
+        self.header(name, value)
+    }
+
+    fn add_headers(self, headers: HeaderMap) -> Self {
+        self.headers(headers)
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl RequestHeaders for reqwest::blocking::RequestBuilder {
+    fn add_header(self, name: &str, value: &str) -> Self {
+        self.header(name, value)
+    }
+
+    fn add_headers(self, headers: HeaderMap) -> Self {
+        self.headers(headers)
+    }
+}
+
+/// Sets the authentication and account headers of an OpenAI request.
+fn openai_headers<R: RequestHeaders>(request: R, credentials: &Credentials) -> R {
+    let mut request = request;
+    // Local servers often don't need a key.
+    if credentials.auth_header && !credentials.api_key.trim().is_empty() {
+        request = request.add_header(
+            AUTHORIZATION.as_str(),
+            &format!("Bearer {}", credentials.api_key),
+        );
+    }
+    if let Some(organization) = &credentials.organization {
+        request = request.add_header("OpenAI-Organization", organization);
+    }
+    if let Some(project) = &credentials.project {
+        request = request.add_header("OpenAI-Project", project);
+    }
+    if let Some(http_referer) = &credentials.http_referer {
+        request = request.add_header("HTTP-Referer", http_referer);
+    }
+    if let Some(app_title) = &credentials.app_title {
+        request = request.add_header("X-Title", app_title);
+    }
+    request.add_headers(credentials.headers.clone())
+}
+
+/// How many times in a row a dropped stream is resumed before its error is returned.
+const MAX_STREAM_RESUMPTIONS: u32 = 3;
+
+/// The limits of a response stream, set on its credentials.
+#[derive(Debug, Clone, Copy)]
+struct StreamLimits {
+    #[cfg_attr(not(feature = "tokio-runtime"), allow(dead_code))]
+    idle_timeout: Option<Duration>,
+    max_bytes: Option<usize>,
+    /// The data received so far, counted against `max_bytes`.
+    received_bytes: usize,
+}
+
+/// Waits for the next event of a response stream.
+///
+/// Returns `None` when the stream has ended. Fails, closing the stream, when no
+/// event arrived within the idle timeout of `limits`, or once the data of its
+/// events exceeds the byte limit. The idle timeout needs the `tokio-runtime`
+/// feature.
+///
+/// When the connection drops on an endpoint that supports resumption, that is
+/// one that gives its events an ID, the stream reconnects with the
+/// `Last-Event-ID` of the last event received, so it resumes after it instead
+/// of starting over.
+async fn next_stream_event(
+    stream: &mut EventSource,
+    limits: &mut StreamLimits,
+) -> Option<ApiResponseOrError<Event>> {
+    let mut resumptions = 0;
+    loop {
+        #[cfg(not(feature = "tokio-runtime"))]
+        let event = stream.next().await;
+        #[cfg(feature = "tokio-runtime")]
+        let event = match limits.idle_timeout {
+            Some(idle_timeout) => match tokio::time::timeout(idle_timeout, stream.next()).await {
+                Ok(event) => event,
+                Err(_) => {
+                    stream.close();
+                    return Some(Err(OpenAiError {
+                        code: Some("timeout".to_string()),
+                        ..OpenAiError::new(
+                            format!("No stream event was received for {idle_timeout:?}"),
+                            "stream_idle_timeout".to_string(),
+                        )
+                    }));
+                }
+            },
+            None => stream.next().await,
+        };
+        match event {
+            Some(Err(reqwest_eventsource::Error::Transport(_)))
+                if !stream.last_event_id().is_empty()
+                    && resumptions < MAX_STREAM_RESUMPTIONS =>
+            {
+                resumptions += 1;
+            }
+            // The reconnection isn't a new stream for the caller.
+            Some(Ok(Event::Open)) if resumptions > 0 => {}
+            None | Some(Err(reqwest_eventsource::Error::StreamEnded)) => return None,
+            Some(Err(error)) => return Some(Err(stream_error(error).await)),
+            Some(Ok(event)) => {
+                if let (Event::Message(message), Some(max_bytes)) = (&event, limits.max_bytes) {
+                    limits.received_bytes += message.data.len();
+                    if limits.received_bytes > max_bytes {
+                        stream.close();
+                        return Some(Err(response_too_large(max_bytes)));
+                    }
+                }
+                return Some(Ok(event));
+            }
+        }
+    }
+}
+
+/// Parses the data of a stream event, which may hold an error instead of the
+/// expected item: OpenAI sends `{"error": {...}}`, Anthropic an `error` event
+/// with `{"type": "error", "error": {...}}`.
+fn parse_stream_data<T: DeserializeOwned>(data: &str) -> ApiResponseOrError<T> {
+    match serde_json::from_str(data)? {
+        ApiResponse::Ok(t) => Ok(t),
+        ApiResponse::Err { error } => Err(error),
+    }
+}
+
+/// Converts an error of the event source, reading the API error out of the
+/// response body when the stream failed to start.
+async fn stream_error(error: reqwest_eventsource::Error) -> OpenAiError {
+    match error {
+        reqwest_eventsource::Error::InvalidStatusCode(_, response) => {
+            status_error(response).await
+        }
+        error => error.into(),
+    }
+}
+
+/// Reads the API error out of an unsuccessful response, falling back to the
+/// status and body when it isn't in the API's error format.
+async fn status_error(response: Response) -> OpenAiError {
+    let status = response.status();
+    let meta = ResponseMeta::from_response(&response);
+    let body = response.text().await.unwrap_or_default();
+    let error = match serde_json::from_str::<ApiResponse<serde_json::Value>>(&body) {
+        Ok(ApiResponse::Err { error }) => error,
+        _ => OpenAiError::new(
+            format!("Unexpected status code {status}: {body}"),
+            "http_status".to_string(),
+        ),
+    };
+    meta.error(error).with_status(status)
+}
+
+/// Handle to a response stream shared between any number of subscribers.
+///
+/// Built on [`tokio::sync::broadcast`], so the usual broadcast semantics apply:
+/// a subscriber only receives the deltas sent after it subscribed, and one that
+/// falls more than the channel capacity behind skips the oldest deltas, with
+/// `recv` returning [`broadcast::error::RecvError::Lagged`] once.
+/// Receivers see [`broadcast::error::RecvError::Closed`] when the stream ends.
+#[cfg(feature = "tokio-runtime")]
+#[derive(Debug, Clone)]
+pub struct StreamBroadcast<T> {
+    sender: broadcast::WeakSender<T>,
+}
+
+#[cfg(feature = "tokio-runtime")]
+impl<T: Clone + Send + 'static> StreamBroadcast<T> {
+    /// Forwards every item of `rx` to the subscribers of the returned handle.
+    /// Also returns a first receiver, subscribed before any item is forwarded.
+    fn spawn(mut rx: mpsc::Receiver<T>, capacity: usize) -> (Self, broadcast::Receiver<T>) {
+        let (tx, first_rx) = broadcast::channel(capacity);
+        let handle = StreamBroadcast {
+            sender: tx.downgrade(),
+        };
+        tokio::spawn(async move {
+            while let Some(item) = rx.recv().await {
+                // Sending only fails while nobody is subscribed, in which case
+                // the item is dropped like any other missed item.
+                let _ = tx.send(item);
+            }
+        });
+        (handle, first_rx)
+    }
+
+    /// Subscribes to the items streamed from now on.
+    /// Returns `None` once the stream has ended.
+    pub fn subscribe(&self) -> Option<broadcast::Receiver<T>> {
+        self.sender.upgrade().map(|sender| sender.subscribe())
+    }
+}
+
+/// Handle to cancel a response stream.
+///
+/// Cancelling stops the task forwarding the stream and closes the connection,
+/// so no more tokens are generated; the receiver then sees the end of the
+/// stream. Dropping the receiver has the same effect, while dropping this
+/// handle leaves the stream running.
+#[cfg(feature = "tokio-runtime")]
+#[derive(Debug, Clone)]
+pub struct StreamCancelHandle {
+    task: tokio::task::AbortHandle,
+}
+
+#[cfg(feature = "tokio-runtime")]
+impl StreamCancelHandle {
+    fn new<T>(task: &tokio::task::JoinHandle<T>) -> Self {
+        StreamCancelHandle {
+            task: task.abort_handle(),
+        }
+    }
+
+    /// Aborts the stream. Does nothing if it has already ended.
+    pub fn cancel(&self) {
+        self.task.abort();
+    }
+
+    /// Whether the stream has ended, was cancelled or failed.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+/// `Send`, except on WebAssembly, where the futures of the browser's `fetch`
+/// can't leave their thread.
+#[cfg(not(target_arch = "wasm32"))]
+pub trait MaybeSend: Send {}
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: Send> MaybeSend for T {}
+
+/// `Send`, except on WebAssembly, where the futures of the browser's `fetch`
+/// can't leave their thread.
+#[cfg(target_arch = "wasm32")]
+pub trait MaybeSend {}
+#[cfg(target_arch = "wasm32")]
+impl<T> MaybeSend for T {}
+
+/// The sending half of a response stream, given to the task forwarding it.
+///
+/// A tokio channel with the `tokio-runtime` feature, a `futures-channel` one
+/// drained by [`ResponseStream`] otherwise.
+pub(crate) struct StreamSender<T>(
+    #[cfg(feature = "tokio-runtime")] mpsc::Sender<T>,
+    #[cfg(not(feature = "tokio-runtime"))] futures_channel::mpsc::Sender<T>,
+);
+
+impl<T> StreamSender<T> {
+    /// Waits for room in the channel and sends `item`.
+    /// Fails when the receiver was dropped.
+    pub(crate) async fn send(&mut self, item: T) -> Result<(), ()> {
+        #[cfg(feature = "tokio-runtime")]
+        {
+            self.0.send(item).await.map_err(|_| ())
+        }
+        #[cfg(not(feature = "tokio-runtime"))]
+        {
+            std::future::poll_fn(|cx| self.0.poll_ready(cx))
+                .await
+                .map_err(|_| ())?;
+            self.0.start_send(item).map_err(|_| ())
+        }
+    }
+
+    /// Waits for the next event of `stream`, see [`next_stream_event`].
+    ///
+    /// Returns `None` right away when the receiver is dropped, instead of at the
+    /// next event. Without the `tokio-runtime` feature the forwarding future is
+    /// dropped together with the receiver, so it never gets to wait.
+    pub(crate) async fn next_event(
+        &self,
+        stream: &mut EventSource,
+        limits: &mut StreamLimits,
+    ) -> Option<ApiResponseOrError<Event>> {
+        #[cfg(feature = "tokio-runtime")]
+        {
+            tokio::select! {
+                event = next_stream_event(stream, limits) => event,
+                _ = self.0.closed() => None,
+            }
+        }
+        #[cfg(not(feature = "tokio-runtime"))]
+        {
+            next_stream_event(stream, limits).await
+        }
+    }
+}
+
+#[cfg(feature = "tokio-runtime")]
+impl<T> From<mpsc::Sender<T>> for StreamSender<T> {
+    fn from(tx: mpsc::Sender<T>) -> Self {
+        StreamSender(tx)
+    }
+}
+
+/// The future forwarding a response stream, driven by the [`ResponseStream`]
+/// itself when there is no runtime to spawn it on.
+#[cfg(all(not(feature = "tokio-runtime"), not(target_arch = "wasm32")))]
+type ForwardFuture = futures_util::future::BoxFuture<'static, ()>;
+#[cfg(all(not(feature = "tokio-runtime"), target_arch = "wasm32"))]
+type ForwardFuture = futures_util::future::LocalBoxFuture<'static, ()>;
+
+/// A response stream as a [`Stream`](futures_util::Stream), to use its
+/// combinators such as `map`, `filter` or `take`.
+///
+/// Converts from the receiver returned by any `create_stream` method:
+/// `ResponseStream::from(rx)`. Without the `tokio-runtime` feature, as in the
+/// browser, it is returned by the `create_response_stream` methods and
+/// receives the events as it is polled.
+#[cfg_attr(feature = "tokio-runtime", derive(Debug))]
+pub struct ResponseStream<T> {
+    #[cfg(feature = "tokio-runtime")]
+    rx: mpsc::Receiver<ApiResponseOrError<T>>,
+    #[cfg(not(feature = "tokio-runtime"))]
+    rx: futures_channel::mpsc::Receiver<ApiResponseOrError<T>>,
+    /// `None` once the stream has been forwarded.
+    #[cfg(not(feature = "tokio-runtime"))]
+    forward: Option<ForwardFuture>,
+}
+
+#[cfg(feature = "tokio-runtime")]
+impl<T> ResponseStream<T> {
+    /// The underlying receiver.
+    pub fn into_inner(self) -> mpsc::Receiver<ApiResponseOrError<T>> {
+        self.rx
+    }
+}
+
+#[cfg(not(feature = "tokio-runtime"))]
+impl<T> ResponseStream<T> {
+    /// A stream receiving the items sent by `forward` as it is polled.
+    pub(crate) fn forwarded<F, G>(forward: F) -> Self
+    where
+        F: FnOnce(StreamSender<ApiResponseOrError<T>>) -> G,
+        G: Future<Output = ()> + MaybeSend + 'static,
+    {
+        let (tx, rx) = futures_channel::mpsc::channel(32);
+        ResponseStream {
+            rx,
+            forward: Some(Box::pin(forward(StreamSender(tx)))),
+        }
+    }
+}
+
+#[cfg(not(feature = "tokio-runtime"))]
+impl<T> std::fmt::Debug for ResponseStream<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResponseStream")
+            .field("forwarding", &self.forward.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "tokio-runtime")]
+impl<T> From<mpsc::Receiver<ApiResponseOrError<T>>> for ResponseStream<T> {
+    fn from(rx: mpsc::Receiver<ApiResponseOrError<T>>) -> Self {
+        ResponseStream { rx }
+    }
+}
+
+impl<T> futures_util::Stream for ResponseStream<T> {
+    type Item = ApiResponseOrError<T>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        #[cfg(feature = "tokio-runtime")]
+        {
+            self.rx.poll_recv(cx)
+        }
+        #[cfg(not(feature = "tokio-runtime"))]
+        {
+            // The forwarding future is woken by `rx` once there is room to send.
+            if let Some(forward) = &mut self.forward {
+                if forward.as_mut().poll(cx).is_ready() {
+                    self.forward = None;
+                }
+            }
+            self.rx.poll_next_unpin(cx)
+        }
+    }
+}
+
+async fn openai_get<T>(route: &str, credentials_opt: Option<Credentials>) -> ApiResponseOrError<T>
+where
+    T: DeserializeOwned,
+{
+    openai_request_json(Method::GET, route, |request| request, credentials_opt).await
+}
+
+async fn openai_delete<T>(
+    route: &str,
     credentials_opt: Option<Credentials>,
 ) -> ApiResponseOrError<T>
 where
@@ -254,6 +1939,93 @@ where
     openai_request_json(Method::DELETE, route, |request| request, credentials_opt).await
 }
 
+/// How an endpoint expects the body of a request to be encoded.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum BodyEncoding {
+    Json,
+    /// `multipart/form-data`, for endpoints taking files.
+    Multipart,
+}
+
+impl BodyEncoding {
+    fn of(route: &str) -> Self {
+        match route {
+            "files" | "audio/transcriptions" | "audio/translations" | "images/edits"
+            | "images/variations" => BodyEncoding::Multipart,
+            route if route.starts_with("uploads/") && route.ends_with("/parts") => {
+                BodyEncoding::Multipart
+            }
+            _ => BodyEncoding::Json,
+        }
+    }
+}
+
+/// The body of a request, encoded the way its endpoint expects.
+enum RequestBody {
+    Json(Vec<u8>),
+    Multipart(Form),
+}
+
+impl RequestBody {
+    /// Encodes `fields` for `route`, along with `files` for multipart endpoints.
+    ///
+    /// In a multipart body, each field is a text part: strings as they are,
+    /// other values as JSON, and arrays as one `name[]` part per element.
+    /// Null fields are left out.
+    fn encode<J: Serialize + ?Sized>(
+        route: &str,
+        fields: &J,
+        files: Vec<(&'static str, Part)>,
+    ) -> ApiResponseOrError<Self> {
+        match BodyEncoding::of(route) {
+            BodyEncoding::Json if files.is_empty() => {
+                Ok(RequestBody::Json(serde_json::to_vec(fields)?))
+            }
+            BodyEncoding::Json => Err(OpenAiError::new(
+                format!("The {route} endpoint doesn't accept files"),
+                "invalid_request".to_string(),
+            )),
+            BodyEncoding::Multipart => {
+                let serde_json::Value::Object(fields) = serde_json::to_value(fields)? else {
+                    return Err(OpenAiError::new(
+                        "Multipart requests must be JSON objects".to_string(),
+                        "invalid_request".to_string(),
+                    ));
+                };
+                let text = |value: serde_json::Value| match value {
+                    serde_json::Value::String(text) => text,
+                    value => value.to_string(),
+                };
+                let mut form = Form::new();
+                for (name, value) in fields {
+                    match value {
+                        serde_json::Value::Null => {}
+                        serde_json::Value::Array(values) => {
+                            for value in values {
+                                form = form.text(format!("{name}[]"), text(value));
+                            }
+                        }
+                        value => form = form.text(name, text(value)),
+                    }
+                }
+                for (name, file) in files {
+                    form = form.part(name, file);
+                }
+                Ok(RequestBody::Multipart(form))
+            }
+        }
+    }
+
+    fn apply(self, request: RequestBuilder) -> RequestBuilder {
+        match self {
+            RequestBody::Json(json) => request
+                .header(CONTENT_TYPE, "application/json")
+                .body(json),
+            RequestBody::Multipart(form) => request.multipart(form),
+        }
+    }
+}
+
 async fn openai_post<J, T>(
     route: &str,
     json: &J,
@@ -263,27 +2035,65 @@ where
     J: Serialize + ?Sized,
     T: DeserializeOwned,
 {
+    openai_post_with_files(route, json, Vec::new(), credentials_opt).await
+}
+
+/// Posts `fields` and `files` to an endpoint taking files, such as uploads.
+async fn openai_post_with_files<J, T>(
+    route: &str,
+    fields: &J,
+    files: Vec<(&'static str, Part)>,
+    credentials_opt: Option<Credentials>,
+) -> ApiResponseOrError<T>
+where
+    J: Serialize + ?Sized,
+    T: DeserializeOwned,
+{
+    let body = RequestBody::encode(route, fields, files)?;
     openai_request_json(
         Method::POST,
         route,
-        |request| request.json(json),
+        |request| body.apply(request),
         credentials_opt,
     )
     .await
 }
 
-async fn openai_post_multipart<T>(
+async fn openai_post_with_meta<J, T>(
     route: &str,
-    form: Form,
+    json: &J,
     credentials_opt: Option<Credentials>,
-) -> ApiResponseOrError<T>
+) -> ApiResponseOrError<(T, ResponseMeta)>
 where
+    J: Serialize + ?Sized,
     T: DeserializeOwned,
 {
-    openai_request_json(
+    let body = RequestBody::encode(route, json, Vec::new())?;
+    openai_request_json_with_meta(
+        Method::POST,
+        route,
+        |request| body.apply(request),
+        credentials_opt,
+    )
+    .await
+}
+
+/// Same as `openai_post_with_files`, for endpoints whose successful response
+/// isn't JSON.
+async fn openai_post_bytes<J>(
+    route: &str,
+    fields: &J,
+    files: Vec<(&'static str, Part)>,
+    credentials_opt: Option<Credentials>,
+) -> ApiResponseOrError<bytes::Bytes>
+where
+    J: Serialize + ?Sized,
+{
+    let body = RequestBody::encode(route, fields, files)?;
+    openai_request_bytes(
         Method::POST,
         route,
-        |request| request.multipart(form),
+        |request| body.apply(request),
         credentials_opt,
     )
     .await
@@ -299,19 +2109,30 @@ where
     F: FnOnce(RequestBuilder) -> RequestBuilder,
     T: DeserializeOwned,
 {
-    let response = anthropic_request(method, route, builder, credentials_opt)
-        .await?;
-        // .json()
-        // .await?;
-    let text = response.text().await?;
-    println!("Raw Response: {}", text);
-
-    // Parse the text
-    let api_response = serde_json::from_str(&text)?;
+    anthropic_request_json_with_meta(method, route, builder, credentials_opt)
+        .await
+        .map(|(t, _)| t)
+}
 
+async fn anthropic_request_json_with_meta<F, T>(
+    method: Method,
+    route: &str,
+    builder: F,
+    credentials_opt: Option<Credentials>,
+) -> ApiResponseOrError<(T, ResponseMeta)>
+where
+    F: FnOnce(RequestBuilder) -> RequestBuilder,
+    T: DeserializeOwned,
+{
+    let response = anthropic_request(method, route, builder, credentials_opt).await?;
+    let meta = ResponseMeta::from_response(&response);
+    if !meta.status.is_success() {
+        return Err(status_error(response).await);
+    }
+    let api_response = response.json().await?;
     match api_response {
-        ApiResponse::Ok(t) => Ok(t),
-        ApiResponse::Err { error } => Err(error),
+        ApiResponse::Ok(t) => Ok((t, meta)),
+        ApiResponse::Err { error } => Err(meta.error(error)),
     }
 }
 
@@ -324,19 +2145,10 @@ async fn anthropic_request<F>(
 where
     F: FnOnce(RequestBuilder) -> RequestBuilder,
 {
-    let client = Client::new();
-    let credentials =
-        credentials_opt.unwrap_or_else(|| DEFAULT_CREDENTIALS.read().unwrap().clone());
-    let mut request = client.request(method, format!("{}{route}", credentials.base_url));
-    request = builder(request);
-    let response = request
-        .header("x-api-key", format!("{}", credentials.api_key))
-        .header("anthropic-version", "2023-06-01")
-        .header(CONTENT_TYPE, format!("application/json"))
-        .send()
-        .await?;
-
-    Ok(response)
+    let mut credentials = resolve_credentials(credentials_opt);
+    credentials.refresh_token(false).await?;
+    let request = anthropic_request_builder(method, route, builder, &credentials);
+    send_request(request, &credentials).await
 }
 
 async fn anthropic_request_stream<F>(
@@ -348,19 +2160,50 @@ async fn anthropic_request_stream<F>(
 where
     F: FnOnce(RequestBuilder) -> RequestBuilder,
 {
-    let client = Client::new();
-    let credentials =
-        credentials_opt.unwrap_or_else(|| DEFAULT_CREDENTIALS.read().unwrap().clone());
-    let mut request = client.request(method, format!("{}{route}", credentials.base_url));
-    request = builder(request);
-    let stream = request
-        .header("x-api-key", format!("{}", credentials.api_key))
-        .header("anthropic-version", "2023-06-01")
-        .header(CONTENT_TYPE, format!("application/json"))
-        .eventsource()?;
+    let mut credentials = resolve_credentials(credentials_opt);
+    // A failed fetch leaves the current key, whose rejection ends the stream.
+    let _ = credentials.refresh_token(false).await;
+    let request = anthropic_request_builder(method, route, builder, &credentials);
+    call_request_hook(&request, &credentials);
+    let stream = request.eventsource()?;
     Ok(stream)
 }
 
+/// Prepares a request to the Anthropic API, shared by the buffered and
+/// streaming helpers. The overall timeout is left out since it doesn't apply to streams.
+fn anthropic_request_builder<F>(
+    method: Method,
+    route: &str,
+    builder: F,
+    credentials: &Credentials,
+) -> RequestBuilder
+where
+    F: FnOnce(RequestBuilder) -> RequestBuilder,
+{
+    let client = credentials.http_client();
+    let request = client.request(method, route_url(&credentials.base_url, route));
+    #[cfg(feature = "bedrock")]
+    if let ApiProvider::Bedrock { region } = &credentials.provider {
+        return bedrock::bedrock_request(client, builder(request), region, credentials);
+    }
+    anthropic_headers(builder(request), credentials)
+}
+
+/// Sets the authentication and versioning headers of an Anthropic request.
+fn anthropic_headers<R: RequestHeaders>(request: R, credentials: &Credentials) -> R {
+    let mut request = request;
+    if credentials.auth_header {
+        request = request.add_header("x-api-key", &credentials.api_key);
+    }
+    request = request
+        .add_header("anthropic-version", credentials.anthropic_version())
+        .add_header(CONTENT_TYPE.as_str(), "application/json");
+    if !credentials.anthropic_beta.is_empty() {
+        request = request.add_header("anthropic-beta", &credentials.anthropic_beta.join(","));
+    }
+    request.add_headers(credentials.headers.clone())
+}
+
 async fn anthropic_post<J, T>(
     route: &str,
     json: &J,
@@ -381,6 +2224,85 @@ where
     resp
 }
 
+async fn anthropic_post_with_meta<J, T>(
+    route: &str,
+    json: &J,
+    credentials_opt: Option<Credentials>,
+) -> ApiResponseOrError<(T, ResponseMeta)>
+where
+    J: Serialize + ?Sized,
+    T: DeserializeOwned,
+{
+    anthropic_request_json_with_meta(
+        Method::POST,
+        route,
+        |request| request.json(json),
+        credentials_opt,
+    )
+    .await
+}
+
+async fn gemini_post<J, T>(
+    route: &str,
+    json: &J,
+    credentials_opt: Option<Credentials>,
+) -> ApiResponseOrError<T>
+where
+    J: Serialize + ?Sized,
+    T: DeserializeOwned,
+{
+    let mut credentials = resolve_credentials(credentials_opt);
+    credentials.refresh_token(false).await?;
+    let request = gemini_request_builder(Method::POST, route, |r| r.json(json), &credentials);
+    let response = send_request(request, &credentials).await?;
+    if !response.status().is_success() {
+        return Err(status_error(response).await);
+    }
+    match response.json().await? {
+        ApiResponse::Ok(t) => Ok(t),
+        ApiResponse::Err { error } => Err(error),
+    }
+}
+
+async fn gemini_request_stream<F>(
+    method: Method,
+    route: &str,
+    builder: F,
+    credentials_opt: Option<Credentials>,
+) -> Result<EventSource, CannotCloneRequestError>
+where
+    F: FnOnce(RequestBuilder) -> RequestBuilder,
+{
+    let mut credentials = resolve_credentials(credentials_opt);
+    // A failed fetch leaves the current key, whose rejection ends the stream.
+    let _ = credentials.refresh_token(false).await;
+    let request = gemini_request_builder(method, route, builder, &credentials);
+    call_request_hook(&request, &credentials);
+    let stream = request.eventsource()?;
+    Ok(stream)
+}
+
+/// Prepares a request to the Gemini API, shared by the buffered and streaming
+/// helpers. The key is sent in a header rather than the `key` query parameter,
+/// so it doesn't end up in logged URLs.
+fn gemini_request_builder<F>(
+    method: Method,
+    route: &str,
+    builder: F,
+    credentials: &Credentials,
+) -> RequestBuilder
+where
+    F: FnOnce(RequestBuilder) -> RequestBuilder,
+{
+    let client = credentials.http_client();
+    let mut request = builder(client.request(method, route_url(&credentials.base_url, route)));
+    if credentials.auth_header {
+        request = request.add_header("x-goog-api-key", &credentials.api_key);
+    }
+    request
+        .add_header(CONTENT_TYPE.as_str(), "application/json")
+        .add_headers(credentials.headers.clone())
+}
 
 /// Sets the key for all OpenAI API functions.
 ///
@@ -398,11 +2320,12 @@ where
 /// ```
 #[deprecated(
     since = "1.0.0-alpha.16",
-    note = "use the `Credentials` struct instead"
+    note = "use `set_default_credentials` instead"
 )]
 pub fn set_key(value: String) {
-    let mut credentials = DEFAULT_CREDENTIALS.write().unwrap();
+    let mut credentials = default_credentials();
     credentials.api_key = value;
+    set_default_credentials(credentials);
 }
 
 /// Sets the base url for all OpenAI API functions.
@@ -421,15 +2344,14 @@ pub fn set_key(value: String) {
 /// ```
 #[deprecated(
     since = "1.0.0-alpha.16",
-    note = "use the `Credentials` struct instead"
+    note = "use `set_default_credentials` instead"
 )]
-pub fn set_base_url(mut value: String) {
+pub fn set_base_url(value: String) {
     if value.is_empty() {
         return;
     }
-    value = parse_base_url(value);
-    let mut credentials = DEFAULT_CREDENTIALS.write().unwrap();
-    credentials.base_url = value;
+    let credentials = default_credentials().with_base_url(value);
+    set_default_credentials(credentials);
 }
 
 fn parse_base_url(mut value: String) -> String {
@@ -439,7 +2361,834 @@ fn parse_base_url(mut value: String) -> String {
     value
 }
 
+/// The URL of `route` under `base_url`, keeping the whole path of the base URL,
+/// such as the prefix of a gateway, even when the route starts with a slash.
+/// Invalid base URLs are left for the request builder to report.
+fn route_url(base_url: &str, route: &str) -> String {
+    let route = route.trim_start_matches('/');
+    let base_url = parse_base_url(base_url.to_string());
+    // The `./` keeps routes such as `models/gemini-pro:generateContent` relative.
+    reqwest::Url::parse(&base_url)
+        .and_then(|url| url.join(&format!("./{route}")))
+        .map_or_else(|_| format!("{base_url}{route}"), String::from)
+}
+
 #[cfg(test)]
 pub mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
     pub const DEFAULT_LEGACY_MODEL: &str = "gpt-3.5-turbo-instruct";
+
+    /// Checks that `value` serializes exactly like `test_data/golden/{name}.json`,
+    /// field order included. Run the tests with `UPDATE_GOLDEN=1` to rewrite the
+    /// files after an intended change of the wire format.
+    pub fn assert_golden(name: &str, value: &impl Serialize) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("test_data/golden")
+            .join(format!("{name}.json"));
+        let json = serde_json::to_string_pretty(value).unwrap() + "\n";
+        if env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, json).unwrap();
+            return;
+        }
+        let golden = std::fs::read_to_string(&path).unwrap_or_else(|error| {
+            panic!("{}: {error}, run with UPDATE_GOLDEN=1 to create it", path.display())
+        });
+        assert_eq!(json, golden, "{name} doesn't serialize like its golden file");
+    }
+
+    /// Checks that `value` deserializes back to itself once serialized.
+    pub fn assert_round_trip<T>(value: &T)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug,
+    {
+        let json = serde_json::to_string(value).unwrap();
+        assert_eq!(&serde_json::from_str::<T>(&json).unwrap(), value, "{json}");
+    }
+
+    /// Starts a local HTTP server answering every connection with the raw
+    /// `response` bytes, keeping the connection open afterwards.
+    /// Returns OpenAI credentials pointing at it.
+    pub async fn mock_server(response: impl Into<Vec<u8>>) -> Credentials {
+        let response = response.into();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let response = response.clone();
+                tokio::spawn(async move {
+                    let mut buffer = [0u8; 8192];
+                    let _ = socket.read(&mut buffer).await;
+                    let _ = socket.write_all(&response).await;
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                });
+            }
+        });
+        Credentials::new("sk-test", "https://api.openai.com/v1/")
+            .with_base_url(format!("http://{address}/v1/"))
+    }
+
+    /// Starts a local HTTP server answering a single connection with the raw
+    /// `response` bytes, keeping the connection open afterwards.
+    /// The returned receiver resolves once the client closes the connection.
+    pub async fn mock_stream_server(
+        response: impl Into<Vec<u8>>,
+    ) -> (Credentials, tokio::sync::oneshot::Receiver<()>) {
+        let response = response.into();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (closed_tx, closed_rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buffer = [0u8; 8192];
+            let _ = socket.read(&mut buffer).await;
+            let _ = socket.write_all(&response).await;
+            while matches!(socket.read(&mut buffer).await, Ok(read) if read > 0) {}
+            let _ = closed_tx.send(());
+        });
+        let credentials = Credentials::new("sk-test", "https://api.openai.com/v1/")
+            .with_base_url(format!("http://{address}/v1/"));
+        (credentials, closed_rx)
+    }
+
+    /// Starts a local HTTP server answering each connection with the next of
+    /// `responses`, closing the connection afterwards. The last response is
+    /// repeated once the others have been served.
+    pub async fn mock_server_sequence(responses: Vec<String>) -> Credentials {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for index in 0.. {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let response = responses[index.min(responses.len() - 1)].clone();
+                let mut buffer = [0u8; 8192];
+                let _ = socket.read(&mut buffer).await;
+                let _ = socket.write_all(response.as_bytes()).await;
+                let _ = socket.shutdown().await;
+            }
+        });
+        Credentials::new("sk-test", "https://api.openai.com/v1/")
+            .with_base_url(format!("http://{address}/v1/"))
+    }
+
+    /// Formats a `200 OK` JSON response with the given extra header lines.
+    pub fn json_response(headers: &str, body: &str) -> String {
+        http_response("200 OK", headers, body)
+    }
+
+    /// Formats a JSON response with the given status line and extra header lines.
+    pub fn http_response(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n{headers}\r\n{body}",
+            body.len()
+        )
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn stream_broadcast_late_subscriber() {
+        let (tx, rx) = mpsc::channel(8);
+        let (broadcast, mut first) = StreamBroadcast::spawn(rx, 8);
+
+        tx.send(1).await.unwrap();
+        assert_eq!(first.recv().await.unwrap(), 1);
+
+        // Joins mid-stream, so only sees what is sent from now on.
+        let mut late = broadcast.subscribe().unwrap();
+        tx.send(2).await.unwrap();
+        tx.send(3).await.unwrap();
+        drop(tx);
+
+        for receiver in [&mut first, &mut late] {
+            assert_eq!(receiver.recv().await.unwrap(), 2);
+            assert_eq!(receiver.recv().await.unwrap(), 3);
+            assert_eq!(
+                receiver.recv().await,
+                Err(broadcast::error::RecvError::Closed)
+            );
+        }
+        assert!(broadcast.subscribe().is_none());
+    }
+
+    #[test]
+    fn event_source_errors() {
+        let response = Response::from(http::Response::new(""));
+        let error = OpenAiError::from(reqwest_eventsource::Error::InvalidStatusCode(
+            StatusCode::SERVICE_UNAVAILABLE,
+            response,
+        ));
+        assert_eq!(error.status, Some(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(error.is_retryable());
+
+        let error = OpenAiError::from(reqwest_eventsource::Error::StreamEnded);
+        assert_eq!(error.error_type, "stream");
+        assert!(!error.is_retryable());
+
+        let error = OpenAiError::from(CannotCloneRequestError);
+        assert_eq!(error.error_type, "cannot_clone_request");
+    }
+
+    #[test]
+    fn stream_error_data() {
+        let error = parse_stream_data::<serde_json::Value>(
+            r#"{"type": "error", "error": {"type": "overloaded_error", "message": "Overloaded"}}"#,
+        )
+        .unwrap_err();
+        assert_eq!(error.error_type, "overloaded_error");
+        assert_eq!(error.message, "Overloaded");
+
+        let value = parse_stream_data::<serde_json::Value>(r#"{"type": "ping"}"#).unwrap();
+        assert_eq!(value["type"], "ping");
+    }
+
+    #[test]
+    fn anthropic_version_and_beta_headers() {
+        let credentials = Credentials::new("sk-ant-test", "https://api.anthropic.com/v1/");
+        let request = anthropic_request_builder(Method::POST, "messages", |r| r, &credentials)
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()["anthropic-version"], DEFAULT_ANTHROPIC_VERSION);
+        assert!(request.headers().get("anthropic-beta").is_none());
+
+        let credentials = credentials
+            .with_anthropic_version("2024-01-01")
+            .with_anthropic_beta("prompt-caching-2024-07-31")
+            .with_anthropic_beta("pdfs-2024-09-25");
+        let request = anthropic_request_builder(Method::POST, "messages", |r| r, &credentials)
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()["anthropic-version"], "2024-01-01");
+        assert_eq!(
+            request.headers()["anthropic-beta"],
+            "prompt-caching-2024-07-31,pdfs-2024-09-25"
+        );
+    }
+
+    #[test]
+    fn openai_compatible_provider() {
+        for base_url in [
+            "http://localhost:11434/v1/",
+            "http://127.0.0.1:1234/v1",
+            "http://[::1]:8000/v1/",
+        ] {
+            let credentials = Credentials::new("", base_url);
+            assert_eq!(credentials.provider(), &ApiProvider::OpenAICompatible);
+        }
+
+        let credentials = Credentials::openai_compatible("", "http://gpu-box.lan:8000/v1");
+        assert_eq!(credentials.provider(), &ApiProvider::OpenAICompatible);
+        let request = openai_request_builder(Method::GET, "models", |r| r, &credentials)
+            .build()
+            .unwrap();
+        assert!(request.headers().get(AUTHORIZATION).is_none());
+        assert_eq!(request.url().as_str(), "http://gpu-box.lan:8000/v1/models");
+
+        let credentials = Credentials::openai_compatible("token", "http://gpu-box.lan:8000/v1");
+        let request = openai_request_builder(Method::GET, "models", |r| r, &credentials)
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()[AUTHORIZATION], "Bearer token");
+    }
+
+    #[test]
+    fn provider_shorthands() {
+        let credentials = Credentials::openai("sk-test");
+        assert_eq!(credentials.provider(), &ApiProvider::OpenAI);
+        assert_eq!(credentials.base_url(), "https://api.openai.com/v1/");
+        assert_eq!(credentials.api_key(), "sk-test");
+        assert_eq!(
+            credentials,
+            Credentials::new("sk-test", "https://api.openai.com/v1/")
+        );
+
+        let credentials = Credentials::anthropic("sk-ant-test");
+        assert_eq!(credentials.provider(), &ApiProvider::Anthropic);
+        assert_eq!(credentials.base_url(), "https://api.anthropic.com/v1/");
+        assert_eq!(credentials.api_key(), "sk-ant-test");
+
+        let credentials = Credentials::gemini("AIza-test");
+        assert_eq!(credentials.provider(), &ApiProvider::Gemini);
+        assert_eq!(credentials, Credentials::new("AIza-test", GEMINI_BASE_URL));
+        let request = gemini_request_builder(Method::POST, "models", |r| r, &credentials)
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()["x-goog-api-key"], "AIza-test");
+        assert!(request.headers().get(AUTHORIZATION).is_none());
+    }
+
+    #[tokio::test]
+    async fn batch_create_bounds_concurrency() {
+        let in_flight = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let max_in_flight = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let requests = (0..6u64).map(|index| {
+            let in_flight = in_flight.clone();
+            let max_in_flight = max_in_flight.clone();
+            async move {
+                let current = in_flight.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(current, std::sync::atomic::Ordering::SeqCst);
+                // Later requests finish first, to check the order is restored.
+                tokio::time::sleep(Duration::from_millis(30 - index * 5)).await;
+                in_flight.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+                match index {
+                    3 => Err(OpenAiError::new("failed".to_string(), "test".to_string())),
+                    _ => Ok(index),
+                }
+            }
+        });
+
+        let results = batch_create(requests, 2).await;
+        assert_eq!(max_in_flight.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert_eq!(results.len(), 6);
+        for (index, result) in results.into_iter().enumerate() {
+            match index {
+                3 => assert_eq!(result.unwrap_err().message, "failed"),
+                _ => assert_eq!(result.unwrap(), index as u64),
+            }
+        }
+    }
+
+    #[test]
+    fn together_and_groq_providers() {
+        let credentials = Credentials::new("tgp-test", "https://api.together.xyz/v1");
+        assert_eq!(credentials, Credentials::together("tgp-test"));
+        assert_eq!(credentials.provider(), &ApiProvider::Together);
+
+        let credentials = Credentials::new("gsk-test", GROQ_BASE_URL);
+        assert_eq!(credentials, Credentials::groq("gsk-test"));
+        assert_eq!(credentials.provider(), &ApiProvider::Groq);
+        let request = openai_request_builder(Method::GET, "models", |r| r, &credentials)
+            .build()
+            .unwrap();
+        assert_eq!(request.url().as_str(), "https://api.groq.com/openai/v1/models");
+        assert_eq!(request.headers()[AUTHORIZATION], "Bearer gsk-test");
+        assert_eq!(
+            Credentials::env_vars(&ApiProvider::Groq),
+            ("GROQ_API_KEY", "GROQ_BASE_URL")
+        );
+    }
+
+    #[test]
+    fn openrouter_provider() {
+        let credentials = Credentials::new("sk-or-test", OPENROUTER_BASE_URL);
+        assert_eq!(credentials.provider(), &ApiProvider::OpenRouter);
+        let request = openai_request_builder(Method::GET, "models", |r| r, &credentials)
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()[AUTHORIZATION], "Bearer sk-or-test");
+        assert!(request.headers().get("HTTP-Referer").is_none());
+
+        let credentials = credentials
+            .with_http_referer("https://example.com")
+            .with_app_title("Crab Facts");
+        let request = openai_request_builder(Method::GET, "models", |r| r, &credentials)
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()["HTTP-Referer"], "https://example.com");
+        assert_eq!(request.headers()["X-Title"], "Crab Facts");
+        assert_eq!(
+            request.url().as_str(),
+            "https://openrouter.ai/api/v1/models"
+        );
+    }
+
+    #[test]
+    fn openai_organization_and_project_headers() {
+        let credentials = Credentials::new("sk-test", "https://api.openai.com/v1/");
+        let request = openai_request_builder(Method::GET, "models", |r| r, &credentials)
+            .build()
+            .unwrap();
+        assert!(request.headers().get("OpenAI-Organization").is_none());
+        assert!(request.headers().get("OpenAI-Project").is_none());
+
+        let credentials = credentials
+            .with_organization("org-123")
+            .with_project("proj_456");
+        let request = openai_request_builder(Method::GET, "models", |r| r, &credentials)
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()["OpenAI-Organization"], "org-123");
+        assert_eq!(request.headers()["OpenAI-Project"], "proj_456");
+        assert_eq!(request.headers()[AUTHORIZATION], "Bearer sk-test");
+    }
+
+    #[test]
+    fn extra_headers() {
+        let credentials = Credentials::new("sk-test", "https://api.openai.com/v1/")
+            .with_header(
+                HeaderName::from_static("x-tenant-id"),
+                HeaderValue::from_static("tenant-1"),
+            )
+            .with_header(AUTHORIZATION, HeaderValue::from_static("Bearer gateway-key"));
+        let request = openai_request_builder(Method::GET, "models", |r| r, &credentials)
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()["x-tenant-id"], "tenant-1");
+        assert_eq!(request.headers()[AUTHORIZATION], "Bearer gateway-key");
+
+        let credentials = Credentials::openai_compatible("sk-test", "https://gateway.example.com/v1/")
+            .without_auth_header()
+            .with_header(
+                HeaderName::from_static("x-gateway-key"),
+                HeaderValue::from_static("gw-key"),
+            );
+        assert!(!credentials.auth_header());
+        let request = openai_request_builder(Method::GET, "models", |r| r, &credentials)
+            .build()
+            .unwrap();
+        assert!(request.headers().get(AUTHORIZATION).is_none());
+        assert_eq!(request.headers()["x-gateway-key"], "gw-key");
+        let credentials = Credentials::anthropic("sk-ant-test").without_auth_header();
+        let request = anthropic_request_builder(Method::POST, "messages", |r| r, &credentials)
+            .build()
+            .unwrap();
+        assert!(request.headers().get("x-api-key").is_none());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-tenant-id", HeaderValue::from_static("tenant-2"));
+        headers.insert("x-trace-id", HeaderValue::from_static("trace-1"));
+        let credentials = override_headers(Some(credentials), &headers).unwrap();
+        let request = anthropic_request_builder(Method::POST, "messages", |r| r, &credentials)
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()["x-tenant-id"], "tenant-2");
+        assert_eq!(request.headers()["x-trace-id"], "trace-1");
+    }
+
+    #[test]
+    fn endpoint_content_types() {
+        let credentials = Credentials::new("sk-test", "https://api.openai.com/v1/");
+        let fields = serde_json::json!({"model": "whisper-1", "temperature": 0.5, "prompt": null});
+        let content_type = |route: &str, files: Vec<(&'static str, Part)>| {
+            let body = RequestBody::encode(route, &fields, files).unwrap();
+            let request =
+                openai_request_builder(Method::POST, route, |r| body.apply(r), &credentials)
+                    .build()
+                    .unwrap();
+            request.headers()[CONTENT_TYPE].to_str().unwrap().to_string()
+        };
+
+        assert_eq!(content_type("chat/completions", Vec::new()), "application/json");
+        assert_eq!(content_type("embeddings", Vec::new()), "application/json");
+        assert_eq!(content_type("audio/speech", Vec::new()), "application/json");
+        for route in ["audio/transcriptions", "files", "uploads/upload_1/parts"] {
+            let file = Part::bytes(b"audio".as_slice()).file_name("crab.mp3");
+            assert!(content_type(route, vec![("file", file)]).starts_with("multipart/form-data"));
+        }
+
+        let file = Part::bytes(b"audio".as_slice());
+        let error = RequestBody::encode("chat/completions", &fields, vec![("file", file)])
+            .err()
+            .unwrap();
+        assert_eq!(error.error_type, "invalid_request");
+    }
+
+    #[tokio::test]
+    async fn request_hook() {
+        let sent = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let credentials = mock_server(json_response("", r#"{"ok": true}"#))
+            .await
+            .with_request_hook({
+                let sent = sent.clone();
+                move |request| {
+                    assert_eq!(request.method, Method::POST);
+                    assert!(request.url.ends_with("/test"));
+                    sent.lock().unwrap().push(request.body.to_vec());
+                }
+            });
+        let _: serde_json::Value =
+            openai_post("test", &serde_json::json!({"model": "gpt-4o"}), Some(credentials))
+                .await
+                .unwrap();
+        assert_eq!(*sent.lock().unwrap(), [br#"{"model":"gpt-4o"}"#.to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn max_concurrent_requests() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        tokio::spawn({
+            let in_flight = in_flight.clone();
+            let max_in_flight = max_in_flight.clone();
+            async move {
+                loop {
+                    let (mut socket, _) = listener.accept().await.unwrap();
+                    let in_flight = in_flight.clone();
+                    let max_in_flight = max_in_flight.clone();
+                    tokio::spawn(async move {
+                        let mut buffer = [0u8; 8192];
+                        let _ = socket.read(&mut buffer).await;
+                        let count = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_in_flight.fetch_max(count, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                        let response = json_response("", r#"{"ok": true}"#);
+                        let _ = socket.write_all(response.as_bytes()).await;
+                        let _ = socket.shutdown().await;
+                    });
+                }
+            }
+        });
+        let credentials = Credentials::new("sk-test", "https://api.openai.com/v1/")
+            .with_base_url(format!("http://{address}/v1/"))
+            .with_max_concurrent_requests(2);
+
+        let requests = (0..6).map(|_| {
+            let credentials = credentials.clone();
+            tokio::spawn(async move {
+                openai_get::<serde_json::Value>("test", Some(credentials)).await
+            })
+        });
+        for request in requests.collect::<Vec<_>>() {
+            request.await.unwrap().unwrap();
+        }
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn scoped_default_credentials() {
+        let credentials = mock_server(json_response("", r#"{"ok": true}"#)).await;
+        let result: serde_json::Value = with_default_credentials(credentials.clone(), async {
+            assert_eq!(resolve_credentials(None), credentials);
+            openai_get("test", None).await.unwrap()
+        })
+        .await;
+        assert_eq!(result, serde_json::json!({"ok": true}));
+
+        // Explicit credentials still win within the scope.
+        let explicit = Credentials::new("sk-explicit", "https://api.openai.com/v1/");
+        with_default_credentials_sync(credentials, || {
+            assert_eq!(resolve_credentials(Some(explicit.clone())), explicit);
+        });
+    }
+
+    #[tokio::test]
+    async fn request_timeout() {
+        // The server never responds.
+        let credentials = mock_server("")
+            .await
+            .with_timeout(Duration::from_millis(100));
+        let started = std::time::Instant::now();
+        let result: ApiResponseOrError<serde_json::Value> =
+            openai_get("models", Some(credentials)).await;
+        assert_eq!(result.unwrap_err().error_type, "reqwest");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn custom_client() {
+        // The server never responds, the timeout of the client ends the request.
+        let client = Client::builder()
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let credentials = mock_server("").await.with_client(client);
+        assert_eq!(credentials.clone(), credentials);
+        let started = std::time::Instant::now();
+        let result: ApiResponseOrError<serde_json::Value> =
+            openai_get("models", Some(credentials.clone())).await;
+        assert_eq!(result.unwrap_err().error_type, "reqwest");
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        assert_ne!(credentials.clone().with_client(Client::new()), credentials);
+    }
+
+    #[tokio::test]
+    async fn retryable_errors() {
+        let error = |status: &str, body: &str| {
+            let response = http_response(status, "", body);
+            async move {
+                let credentials = mock_server(response).await;
+                openai_get::<serde_json::Value>("models", Some(credentials))
+                    .await
+                    .unwrap_err()
+            }
+        };
+        let rate_limited = error(
+            "429 Too Many Requests",
+            r#"{"error": {"message": "Slow down", "type": "requests", "code": "rate_limit_exceeded"}}"#,
+        )
+        .await;
+        assert_eq!(rate_limited.status, Some(StatusCode::TOO_MANY_REQUESTS));
+        assert!(rate_limited.is_rate_limited() && rate_limited.is_retryable());
+
+        let quota = error(
+            "429 Too Many Requests",
+            r#"{"error": {"message": "No credits", "type": "insufficient_quota", "code": "insufficient_quota"}}"#,
+        )
+        .await;
+        assert!(!quota.is_rate_limited() && !quota.is_retryable());
+
+        let unavailable = error("503 Service Unavailable", "upstream down").await;
+        assert_eq!(unavailable.error_type, "http_status");
+        assert!(unavailable.is_retryable() && !unavailable.is_rate_limited());
+
+        for status in ["400 Bad Request", "401 Unauthorized", "404 Not Found"] {
+            let body = r#"{"error": {"message": "Nope", "type": "invalid_request_error"}}"#;
+            assert!(!error(status, body).await.is_retryable(), "{status}");
+        }
+
+        // Errors sent in a stream have no status.
+        let overloaded: OpenAiError = serde_json::from_str(
+            r#"{"type": "overloaded_error", "message": "Overloaded"}"#,
+        )
+        .unwrap();
+        assert!(overloaded.is_retryable());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        let refused = Credentials::openai_compatible("", format!("http://{address}/v1/"));
+        let refused = openai_get::<serde_json::Value>("models", Some(refused))
+            .await
+            .unwrap_err();
+        assert_eq!(refused.code.as_deref(), Some("connection_error"));
+        assert!(refused.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn token_provider() {
+        // Accepts the second token only, like a gateway that revoked the first one.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buffer = [0u8; 8192];
+                let read = socket.read(&mut buffer).await.unwrap();
+                let request = String::from_utf8_lossy(&buffer[..read]).to_lowercase();
+                let response = if request.contains("authorization: bearer token-2") {
+                    tests::json_response("", r#"{"object": "list", "data": []}"#)
+                } else {
+                    tests::http_response(
+                        "401 Unauthorized",
+                        "",
+                        r#"{"error": {"message": "Expired token", "type": "invalid_request_error"}}"#,
+                    )
+                };
+                let _ = socket.write_all(response.as_bytes()).await;
+                let _ = socket.shutdown().await;
+            }
+        });
+
+        let fetches = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let provider_fetches = fetches.clone();
+        let credentials = Credentials::openai_compatible("", format!("http://{address}/v1/"))
+            .with_token_provider(move || {
+                let fetches = provider_fetches.clone();
+                async move {
+                    let fetch = fetches.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+                    Ok(AccessToken::new(format!("token-{fetch}"))
+                        .with_expires_in(Duration::from_secs(3600)))
+                }
+            });
+        assert_eq!(credentials.clone(), credentials);
+
+        let models: serde_json::Value = openai_get("models", Some(credentials.clone()))
+            .await
+            .unwrap();
+        assert_eq!(models["object"], "list");
+        assert_eq!(fetches.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        // The refreshed token is cached for the clones of the credentials.
+        let _: serde_json::Value = openai_get("models", Some(credentials)).await.unwrap();
+        assert_eq!(fetches.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn usage_deserialization_and_accumulation() {
+        let chunk = |usage: &str| {
+            let chunk = format!(
+                r#"{{"id": "chatcmpl-1", "object": "chat.completion.chunk", "created": 1, "model": "gpt-4o", "choices": []{usage}}}"#
+            );
+            serde_json::from_str::<chat::ChatCompletionDelta>(&chunk)
+                .unwrap()
+                .usage
+        };
+        assert_eq!(chunk(""), None);
+        assert_eq!(chunk(r#", "usage": null"#), None);
+        assert_eq!(
+            chunk(r#", "usage": {"prompt_tokens": 3, "total_tokens": 3}"#),
+            Some(Usage {
+                prompt_tokens: 3,
+                completion_tokens: 0,
+                total_tokens: 3,
+            })
+        );
+
+        let usages = [
+            Usage {
+                prompt_tokens: 10,
+                completion_tokens: 5,
+                total_tokens: 15,
+            },
+            Usage {
+                prompt_tokens: 20,
+                completion_tokens: 1,
+                total_tokens: 21,
+            },
+        ];
+        let mut total = Usage::zero();
+        for usage in usages {
+            total += usage;
+        }
+        assert_eq!(
+            total,
+            Usage {
+                prompt_tokens: 30,
+                completion_tokens: 6,
+                total_tokens: 36,
+            }
+        );
+        assert_eq!(usages[0] + usages[1], total);
+        assert_eq!(Usage::zero() + total, total);
+    }
+
+    #[tokio::test]
+    async fn stream_resumes_with_last_event_id() {
+        const HEADERS: &str =
+            "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\ntransfer-encoding: chunked\r\n\r\n";
+        let chunk = |data: &str| format!("{:x}\r\n{data}\r\n", data.len());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (resumed_tx, resumed_rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 8192];
+            // The first connection drops in the middle of a chunk.
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let _ = socket.read(&mut buffer).await;
+            let response = format!("{HEADERS}{}ff\r\npartial", chunk("id: 1\ndata: first\n\n"));
+            socket.write_all(response.as_bytes()).await.unwrap();
+            drop(socket);
+
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let read = socket.read(&mut buffer).await.unwrap();
+            let _ = resumed_tx.send(String::from_utf8_lossy(&buffer[..read]).into_owned());
+            let response = format!("{HEADERS}{}0\r\n\r\n", chunk("id: 2\ndata: second\n\n"));
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+        let credentials = Credentials::new("sk-test", "https://api.openai.com/v1/")
+            .with_base_url(format!("http://{address}/v1/"));
+
+        let mut stream = openai_request_stream(Method::GET, "stream", |r| r, Some(credentials))
+            .await
+            .unwrap();
+        let mut data = Vec::new();
+        let mut limits = Credentials::openai("").stream_limits();
+        loop {
+            match next_stream_event(&mut stream, &mut limits).await {
+                Some(Ok(Event::Open)) => data.push("open".to_string()),
+                Some(Ok(Event::Message(message))) => data.push(message.data),
+                None => break,
+                event => panic!("unexpected event {event:?}"),
+            }
+        }
+        stream.close();
+        assert_eq!(data, ["open", "first", "second"]);
+        let resumed = resumed_rx.await.unwrap();
+        assert!(resumed.to_lowercase().contains("last-event-id: 1\r\n"));
+    }
+
+    #[tokio::test]
+    async fn max_response_bytes() {
+        let transport = testing::MockTransport::new();
+        transport
+            .push_json(serde_json::json!({"data": "a".repeat(100)}))
+            .push_json(serde_json::json!({"data": "a"}));
+        let credentials = Credentials::openai("sk-test")
+            .with_transport(transport)
+            .with_max_response_bytes(50);
+        let error = openai_get::<serde_json::Value>("models", Some(credentials.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_type, "response_too_large");
+        assert_eq!(error.status, Some(StatusCode::OK));
+        let value: serde_json::Value = openai_get("models", Some(credentials)).await.unwrap();
+        assert_eq!(value["data"], "a");
+
+        let event = format!("data: {}\n\n", "a".repeat(30));
+        let (credentials, _closed) = mock_stream_server(format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\n\r\n{event}{event}"
+        ))
+        .await;
+        let credentials = credentials.with_max_response_bytes(50);
+        let mut limits = credentials.stream_limits();
+        let mut stream = openai_request_stream(Method::GET, "stream", |r| r, Some(credentials))
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_stream_event(&mut stream, &mut limits).await,
+            Some(Ok(Event::Open))
+        ));
+        assert!(matches!(
+            next_stream_event(&mut stream, &mut limits).await,
+            Some(Ok(Event::Message(_)))
+        ));
+        let error = next_stream_event(&mut stream, &mut limits)
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.error_type, "response_too_large");
+    }
+
+    #[test]
+    fn route_urls() {
+        assert_eq!(
+            route_url("https://api.anthropic.com/v1/", "messages"),
+            "https://api.anthropic.com/v1/messages"
+        );
+        assert_eq!(
+            route_url("https://api.anthropic.com/v1", "/messages"),
+            "https://api.anthropic.com/v1/messages"
+        );
+        assert_eq!(
+            route_url("https://gateway.example.com/proxy/openai/v1/", "chat/completions"),
+            "https://gateway.example.com/proxy/openai/v1/chat/completions"
+        );
+        assert_eq!(
+            route_url("http://localhost:11434", "models"),
+            "http://localhost:11434/models"
+        );
+        assert_eq!(
+            route_url(GEMINI_BASE_URL, "models/gemini-pro:streamGenerateContent?alt=sse"),
+            "https://generativelanguage.googleapis.com/v1beta/models/gemini-pro:streamGenerateContent?alt=sse"
+        );
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn stream_idle_timeout() {
+        // The server starts an event stream, then goes quiet.
+        let credentials = mock_server(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\n\r\n",
+        )
+        .await
+        .with_stream_idle_timeout(Duration::from_millis(100));
+        let mut limits = credentials.stream_limits();
+        let mut stream = openai_request_stream(Method::GET, "stream", |r| r, Some(credentials))
+            .await
+            .unwrap();
+        let started = std::time::Instant::now();
+        // The first event is the connection opening.
+        assert!(matches!(
+            next_stream_event(&mut stream, &mut limits).await,
+            Some(Ok(Event::Open))
+        ));
+        let error = next_stream_event(&mut stream, &mut limits)
+            .await
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(error.error_type, "stream_idle_timeout");
+        assert!(error.is_retryable());
+        assert!(next_stream_event(&mut stream, &mut limits).await.is_none());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
 }
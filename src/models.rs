@@ -2,8 +2,12 @@
 //! You can refer to the [Models](https://beta.openai.com/docs/models)
 //! documentation to understand what models are available and the differences between them.
 
-use super::{openai_get, ApiResponseOrError, Credentials};
+use super::{
+    anthropic_request_json, openai_get, ApiProvider, ApiResponseOrError, Credentials, OpenAiError,
+};
+use reqwest::{Method, StatusCode};
 use serde::Deserialize;
+use std::sync::RwLock;
 
 #[derive(Deserialize, Clone)]
 pub struct Model {
@@ -13,6 +17,11 @@ pub struct Model {
     pub owned_by: String,
 }
 
+#[derive(Deserialize)]
+struct ModelList {
+    data: Vec<Model>,
+}
+
 #[derive(Deserialize, Clone)]
 pub struct ModelPermission {
     pub id: String,
@@ -41,14 +50,247 @@ impl Model {
     pub async fn fetch(id: &str, credentials: Credentials) -> ApiResponseOrError<Self> {
         openai_get(&format!("models/{id}"), Some(credentials)).await
     }
+
+    /// Lists the models available, all at once: the endpoint isn't paginated.
+    pub async fn list(credentials: Credentials) -> ApiResponseOrError<Vec<Self>> {
+        let list: ModelList = openai_get("models", Some(credentials)).await?;
+        Ok(list.data)
+    }
+
+    /// Whether the model `id` is available with `credentials`, asking the
+    /// models endpoint of their provider, which Anthropic and Gemini have too.
+    pub async fn exists(id: &str, credentials: Credentials) -> ApiResponseOrError<bool> {
+        let route = format!("models/{id}");
+        let model: ApiResponseOrError<serde_json::Value> = match credentials.provider() {
+            ApiProvider::Anthropic => {
+                anthropic_request_json(Method::GET, &route, |request| request, Some(credentials))
+                    .await
+            }
+            _ => openai_get(&route, Some(credentials)).await,
+        };
+        match model {
+            Ok(_) => Ok(true),
+            Err(error) if error.status == Some(StatusCode::NOT_FOUND) => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Like [`Model::exists`], failing with a `model_not_found` error when the
+    /// model isn't available, to catch a misspelled model before sending a request.
+    pub async fn check(id: &str, credentials: Credentials) -> ApiResponseOrError<()> {
+        if Self::exists(id, credentials).await? {
+            Ok(())
+        } else {
+            Err(OpenAiError::new(
+                format!("The model `{id}` does not exist or you do not have access to it"),
+                "model_not_found".to_string(),
+            ))
+        }
+    }
+}
+
+/// The limits and features of a model, see [`model_info`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelInfo {
+    /// The context window in tokens, prompt and output included.
+    pub context_window: u32,
+    /// The most tokens the model can generate in one response.
+    pub max_output_tokens: u32,
+    /// Whether messages may contain images.
+    pub vision: bool,
+    /// Whether the model can call tools.
+    pub tools: bool,
+    /// Whether the model supports a JSON `response_format`.
+    pub json_mode: bool,
+}
+
+impl ModelInfo {
+    /// A model without vision, tools or JSON mode.
+    pub const fn new(context_window: u32, max_output_tokens: u32) -> Self {
+        ModelInfo {
+            context_window,
+            max_output_tokens,
+            vision: false,
+            tools: false,
+            json_mode: false,
+        }
+    }
+
+    pub const fn with_vision(mut self) -> Self {
+        self.vision = true;
+        self
+    }
+
+    pub const fn with_tools(mut self) -> Self {
+        self.tools = true;
+        self
+    }
+
+    pub const fn with_json_mode(mut self) -> Self {
+        self.json_mode = true;
+        self
+    }
+}
+
+/// Known models, matched by model name prefix.
+const BUILTIN_MODELS: &[(&str, ModelInfo)] = &[
+    (
+        "gpt-4o",
+        ModelInfo::new(128_000, 16_384)
+            .with_vision()
+            .with_tools()
+            .with_json_mode(),
+    ),
+    (
+        "gpt-4-turbo",
+        ModelInfo::new(128_000, 4_096)
+            .with_vision()
+            .with_tools()
+            .with_json_mode(),
+    ),
+    ("gpt-4-32k", ModelInfo::new(32_768, 32_768).with_tools()),
+    ("gpt-4", ModelInfo::new(8_192, 8_192).with_tools()),
+    ("gpt-3.5-turbo-instruct", ModelInfo::new(4_096, 4_096)),
+    (
+        "gpt-3.5-turbo",
+        ModelInfo::new(16_385, 4_096).with_tools().with_json_mode(),
+    ),
+    (
+        "o1",
+        ModelInfo::new(200_000, 100_000)
+            .with_vision()
+            .with_tools()
+            .with_json_mode(),
+    ),
+    ("o1-mini", ModelInfo::new(128_000, 65_536)),
+    // Older Claude models, and the output all of them support at least.
+    ("claude-", ModelInfo::new(200_000, 4_096)),
+    (
+        "claude-3-",
+        ModelInfo::new(200_000, 4_096).with_vision().with_tools(),
+    ),
+    (
+        "claude-3-5-",
+        ModelInfo::new(200_000, 8_192).with_vision().with_tools(),
+    ),
+    (
+        "claude-3-5-haiku",
+        ModelInfo::new(200_000, 8_192).with_tools(),
+    ),
+    (
+        "claude-3-7-sonnet",
+        ModelInfo::new(200_000, 64_000).with_vision().with_tools(),
+    ),
+    (
+        "claude-sonnet-4",
+        ModelInfo::new(200_000, 64_000).with_vision().with_tools(),
+    ),
+    (
+        "claude-opus-4",
+        ModelInfo::new(200_000, 32_000).with_vision().with_tools(),
+    ),
+];
+
+/// Models added with [`register_model`].
+static CUSTOM_MODELS: RwLock<Vec<(String, ModelInfo)>> = RwLock::new(Vec::new());
+
+/// Adds a model to those known to [`model_info`], or overrides a built-in one,
+/// for the whole process. `prefix` is matched like the built-in names, so
+/// `"my-finetune"` also covers `"my-finetune-v2"`.
+///
+/// The Anthropic chat builder then defaults to its `max_output_tokens`.
+pub fn register_model(prefix: impl Into<String>, info: ModelInfo) {
+    let prefix = prefix.into();
+    let mut custom_models = CUSTOM_MODELS.write().unwrap();
+    match custom_models.iter_mut().find(|(known, _)| *known == prefix) {
+        Some((_, known_info)) => *known_info = info,
+        None => custom_models.push((prefix, info)),
+    }
+}
+
+/// The limits and features of a model, `None` when it isn't known.
+///
+/// The model is matched by the longest known prefix of its name, so dated
+/// snapshots such as `"gpt-4o-2024-08-06"` are covered. Models added with
+/// [`register_model`] take precedence over the built-in ones.
+///
+/// ```
+/// use openai::models::model_info;
+///
+/// let info = model_info("gpt-4o-2024-08-06").unwrap();
+/// assert_eq!(info.context_window, 128_000);
+/// assert!(info.vision && info.tools);
+/// ```
+pub fn model_info(model: &str) -> Option<ModelInfo> {
+    let custom_models = CUSTOM_MODELS.read().unwrap();
+    // The last of the longest matches wins, so custom models come after.
+    BUILTIN_MODELS
+        .iter()
+        .copied()
+        .chain(
+            custom_models
+                .iter()
+                .map(|(prefix, info)| (prefix.as_str(), *info)),
+        )
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, info)| info)
+}
+
+/// The context window of a model in tokens, `None` when it isn't known.
+/// Matched like [`model_info`].
+pub fn context_window(model: &str) -> Option<u32> {
+    model_info(model).map(|info| info.context_window)
+}
+
+/// The most tokens a model can generate in one response, `None` when it
+/// isn't known. Matched like [`model_info`].
+pub fn max_output_tokens(model: &str) -> Option<u32> {
+    model_info(model).map(|info| info.max_output_tokens)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::MockTransport;
     use crate::tests::DEFAULT_LEGACY_MODEL;
     use dotenvy::dotenv;
 
+    #[test]
+    fn context_windows() {
+        assert_eq!(context_window("gpt-4"), Some(8_192));
+        assert_eq!(context_window("gpt-4-0613"), Some(8_192));
+        assert_eq!(context_window("gpt-4o-mini"), Some(128_000));
+        assert_eq!(context_window("claude-3-5-sonnet-20241022"), Some(200_000));
+        assert_eq!(context_window("llama3"), None);
+    }
+
+    #[test]
+    fn max_output_tokens_by_model() {
+        assert_eq!(max_output_tokens("gpt-4o-mini"), Some(16_384));
+        assert_eq!(max_output_tokens("claude-3-opus-20240229"), Some(4_096));
+        assert_eq!(max_output_tokens("claude-3-5-sonnet-20241022"), Some(8_192));
+        assert_eq!(max_output_tokens("llama3"), None);
+    }
+
+    #[test]
+    fn model_capabilities() {
+        let info = model_info("claude-3-5-haiku-20241022").unwrap();
+        assert_eq!(info.max_output_tokens, 8_192);
+        assert!(info.tools && !info.vision && !info.json_mode);
+        assert!(model_info("gpt-4-0613").is_some_and(|info| !info.vision));
+
+        register_model("crab-llm", ModelInfo::new(32_000, 2_000).with_tools());
+        register_model("crab-llm", ModelInfo::new(64_000, 4_000).with_tools());
+        register_model("gpt-4o-mini-crab", ModelInfo::new(16_000, 1_000));
+        assert_eq!(
+            model_info("crab-llm-7b"),
+            Some(ModelInfo::new(64_000, 4_000).with_tools())
+        );
+        assert_eq!(context_window("gpt-4o-mini-crab-ft"), Some(16_000));
+        assert_eq!(context_window("gpt-4o-mini"), Some(128_000));
+    }
+
     #[tokio::test]
     async fn model() {
         dotenv().ok();
@@ -58,4 +300,61 @@ mod tests {
             .unwrap();
         assert_eq!(model.id, DEFAULT_LEGACY_MODEL);
     }
+
+    #[tokio::test]
+    async fn list_models() {
+        let credentials = crate::tests::mock_server(crate::tests::json_response(
+            "",
+            r#"{"object": "list", "data": [
+                {"id": "gpt-4o", "object": "model", "created": 1715367049, "owned_by": "system"},
+                {"id": "whisper-1", "object": "model", "created": 1677532384, "owned_by": "openai-internal"}
+            ]}"#,
+        ))
+        .await;
+        let models = Model::list(credentials).await.unwrap();
+        let ids: Vec<_> = models.iter().map(|model| model.id.as_str()).collect();
+        assert_eq!(ids, ["gpt-4o", "whisper-1"]);
+    }
+
+    #[tokio::test]
+    async fn model_exists() {
+        let transport = MockTransport::new();
+        transport
+            .push_json(serde_json::json!({
+                "type": "model",
+                "id": "claude-3-5-sonnet-20241022",
+                "display_name": "Claude 3.5 Sonnet (New)",
+                "created_at": "2024-10-22T00:00:00Z"
+            }))
+            .push_response(
+                StatusCode::NOT_FOUND,
+                r#"{"type": "error", "error": {"type": "not_found_error", "message": "model: claude-3.5-sonnet"}}"#,
+            )
+            .push_response(
+                StatusCode::UNAUTHORIZED,
+                r#"{"type": "error", "error": {"type": "authentication_error", "message": "invalid x-api-key"}}"#,
+            );
+        let credentials = Credentials::anthropic("sk-ant-test").with_transport(transport.clone());
+
+        assert!(
+            Model::exists("claude-3-5-sonnet-20241022", credentials.clone())
+                .await
+                .unwrap()
+        );
+        let error = Model::check("claude-3.5-sonnet", credentials.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_type, "model_not_found");
+        let error = Model::exists("claude-3-5-haiku-latest", credentials)
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_type, "authentication_error");
+
+        let requests = transport.requests();
+        assert_eq!(
+            requests[0].url,
+            "https://api.anthropic.com/v1/models/claude-3-5-sonnet-20241022"
+        );
+        assert_eq!(requests[0].headers["x-api-key"], "sk-ant-test");
+    }
 }
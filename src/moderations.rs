@@ -7,6 +7,7 @@ use serde::{Deserialize, Serialize};
 pub struct Moderation {
     pub id: String,
     pub model: String,
+    /// One result per input, in the order of the inputs.
     pub results: Vec<ModerationResult>,
 }
 
@@ -17,13 +18,26 @@ pub struct ModerationResult {
     pub category_scores: CategoryScores,
 }
 
-#[derive(Deserialize, Clone, Copy, Debug)]
+/// Whether the input was flagged in each category. Categories the model
+/// doesn't classify are `false`.
+#[derive(Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[serde(default)]
 pub struct Categories {
+    pub harassment: bool,
+    #[serde(rename = "harassment/threatening")]
+    pub harassment_threatening: bool,
     pub hate: bool,
     #[serde(rename = "hate/threatening")]
     pub hate_threatening: bool,
+    pub illicit: bool,
+    #[serde(rename = "illicit/violent")]
+    pub illicit_violent: bool,
     #[serde(rename = "self-harm")]
     pub self_harm: bool,
+    #[serde(rename = "self-harm/intent")]
+    pub self_harm_intent: bool,
+    #[serde(rename = "self-harm/instructions")]
+    pub self_harm_instructions: bool,
     pub sexual: bool,
     #[serde(rename = "sexual/minors")]
     pub sexual_minors: bool,
@@ -32,13 +46,26 @@ pub struct Categories {
     pub violence_graphic: bool,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+/// The confidence of the model in each category, from 0 to 1. Categories the
+/// model doesn't classify are 0.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
 pub struct CategoryScores {
+    pub harassment: f64,
+    #[serde(rename = "harassment/threatening")]
+    pub harassment_threatening: f64,
     pub hate: f64,
     #[serde(rename = "hate/threatening")]
     pub hate_threatening: f64,
+    pub illicit: f64,
+    #[serde(rename = "illicit/violent")]
+    pub illicit_violent: f64,
     #[serde(rename = "self-harm")]
     pub self_harm: f64,
+    #[serde(rename = "self-harm/intent")]
+    pub self_harm_intent: f64,
+    #[serde(rename = "self-harm/instructions")]
+    pub self_harm_instructions: f64,
     pub sexual: f64,
     #[serde(rename = "sexual/minors")]
     pub sexual_minors: f64,
@@ -47,13 +74,46 @@ pub struct CategoryScores {
     pub violence_graphic: f64,
 }
 
+/// The text, or texts, to classify.
+#[derive(Serialize, Clone, Debug, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum ModerationInput {
+    Text(String),
+    /// Several texts, classified in one request. Their results are in the same order.
+    Texts(Vec<String>),
+}
+
+impl From<String> for ModerationInput {
+    fn from(text: String) -> Self {
+        ModerationInput::Text(text)
+    }
+}
+
+impl From<&str> for ModerationInput {
+    fn from(text: &str) -> Self {
+        ModerationInput::Text(text.to_string())
+    }
+}
+
+impl From<Vec<String>> for ModerationInput {
+    fn from(texts: Vec<String>) -> Self {
+        ModerationInput::Texts(texts)
+    }
+}
+
+impl From<Vec<&str>> for ModerationInput {
+    fn from(texts: Vec<&str>) -> Self {
+        ModerationInput::Texts(texts.into_iter().map(String::from).collect())
+    }
+}
+
 #[derive(Serialize, Builder, Debug, Clone)]
 #[builder(pattern = "owned")]
 #[builder(name = "ModerationBuilder")]
 #[builder(setter(strip_option, into))]
 pub struct ModerationRequest {
-    /// The input text to classify.
-    pub input: String,
+    /// The input text, or texts, to classify.
+    pub input: ModerationInput,
     /// ID of the model to use.
     /// Two content moderations models are available: `text-moderation-stable` and `text-moderation-latest`.
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -70,14 +130,14 @@ impl Moderation {
         openai_post("moderations", &request, request.credentials.clone()).await
     }
 
-    pub fn builder(input: impl Into<String>) -> ModerationBuilder {
+    pub fn builder(input: impl Into<ModerationInput>) -> ModerationBuilder {
         ModerationBuilder::create_empty().input(input)
     }
 }
 
 impl ModerationBuilder {
     pub async fn create(self) -> ApiResponseOrError<Moderation> {
-        Moderation::create(self.build().unwrap()).await
+        Moderation::create(self.build()?).await
     }
 }
 
@@ -98,10 +158,61 @@ mod tests {
             .await
             .unwrap();
 
+        assert!(moderation.results.first().unwrap().categories.violence);
+        assert!(moderation.results.first().unwrap().flagged);
+    }
+
+    #[tokio::test]
+    async fn multiple_inputs() {
+        let credentials = crate::tests::mock_server(crate::tests::json_response(
+            "",
+            r#"{
+                "id": "modr-1",
+                "model": "omni-moderation-latest",
+                "results": [
+                    {
+                        "flagged": false,
+                        "categories": {"hate": false, "violence": false},
+                        "category_scores": {"hate": 0.0001, "violence": 0.0002}
+                    },
+                    {
+                        "flagged": true,
+                        "categories": {"hate": false, "violence": true, "self-harm/intent": false},
+                        "category_scores": {"hate": 0.001, "violence": 0.97, "self-harm/intent": 0.0003}
+                    }
+                ]
+            }"#,
+        ))
+        .await;
+        let request = Moderation::builder(vec!["Hello there.", "I want to kill them."])
+            .credentials(credentials)
+            .build()
+            .unwrap();
         assert_eq!(
-            moderation.results.first().unwrap().categories.violence,
-            true
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({"input": ["Hello there.", "I want to kill them."]})
         );
-        assert_eq!(moderation.results.first().unwrap().flagged, true);
+
+        let moderation = Moderation::create(request).await.unwrap();
+        assert_eq!(moderation.results.len(), 2);
+        assert!(!moderation.results[0].flagged);
+        let flagged = &moderation.results[1];
+        assert!(flagged.flagged);
+        assert_eq!(
+            flagged.categories,
+            Categories {
+                violence: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(flagged.category_scores.violence, 0.97);
+        assert_eq!(flagged.category_scores.harassment, 0.0);
+    }
+
+    #[tokio::test]
+    async fn missing_required_field() {
+        let error = ModerationBuilder::default().create().await.unwrap_err();
+        assert_eq!(error.error_type, "builder");
+        assert_eq!(error.param.as_deref(), Some("input"));
     }
 }
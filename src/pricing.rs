@@ -0,0 +1,226 @@
+//! Estimating the cost of requests from their token usage.
+//!
+//! Prices change and differ between accounts, so the built-in table is only a
+//! starting point: extend or override it with [`PricingTable::with_model`].
+
+use super::{AnthropicUsage, Usage};
+use std::collections::HashMap;
+
+/// The price of a model in USD per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPricing {
+    pub input: f64,
+    pub output: f64,
+    /// Writing to the prompt cache, priced as regular input when `None`.
+    pub cache_write: Option<f64>,
+    /// Reading from the prompt cache, priced as regular input when `None`.
+    pub cache_read: Option<f64>,
+}
+
+impl ModelPricing {
+    pub fn new(input: f64, output: f64) -> Self {
+        ModelPricing {
+            input,
+            output,
+            cache_write: None,
+            cache_read: None,
+        }
+    }
+
+    pub fn with_cache(mut self, write: f64, read: f64) -> Self {
+        self.cache_write = Some(write);
+        self.cache_read = Some(read);
+        self
+    }
+
+    /// The cost of the given token counts in USD.
+    pub fn cost(&self, tokens: TokenCounts) -> f64 {
+        let per_token =
+            |price_per_million: f64, tokens: u64| price_per_million * tokens as f64 / 1_000_000.0;
+        per_token(self.input, tokens.input)
+            + per_token(self.output, tokens.output)
+            + per_token(self.cache_write.unwrap_or(self.input), tokens.cache_write)
+            + per_token(self.cache_read.unwrap_or(self.input), tokens.cache_read)
+    }
+}
+
+/// Token counts of a request, split the way they are priced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenCounts {
+    /// Uncached input tokens.
+    pub input: u64,
+    pub output: u64,
+    pub cache_write: u64,
+    pub cache_read: u64,
+}
+
+impl From<Usage> for TokenCounts {
+    fn from(usage: Usage) -> Self {
+        TokenCounts {
+            input: usage.prompt_tokens.into(),
+            output: usage.completion_tokens.into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<AnthropicUsage> for TokenCounts {
+    fn from(usage: AnthropicUsage) -> Self {
+        TokenCounts {
+            input: usage.input_tokens,
+            output: usage.output_tokens,
+            cache_write: usage.cache_creation_input_tokens,
+            cache_read: usage.cache_read_input_tokens,
+        }
+    }
+}
+
+/// Per-model prices used to estimate the cost of requests.
+///
+/// A model is looked up by its exact name first, then by the longest known
+/// prefix, so `"gpt-4o-2024-08-06"` is priced as `"gpt-4o"`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PricingTable {
+    models: HashMap<String, ModelPricing>,
+}
+
+impl PricingTable {
+    /// An empty table, without any built-in prices.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A table with the public list prices of common OpenAI and Anthropic models.
+    pub fn builtin() -> Self {
+        let models = [
+            ("gpt-4o", ModelPricing::new(2.5, 10.0)),
+            ("gpt-4o-mini", ModelPricing::new(0.15, 0.6)),
+            ("gpt-4-turbo", ModelPricing::new(10.0, 30.0)),
+            ("gpt-4", ModelPricing::new(30.0, 60.0)),
+            ("gpt-3.5-turbo", ModelPricing::new(0.5, 1.5)),
+            ("gpt-3.5-turbo-instruct", ModelPricing::new(1.5, 2.0)),
+            ("o1", ModelPricing::new(15.0, 60.0)),
+            ("o1-mini", ModelPricing::new(3.0, 12.0)),
+            ("text-embedding-3-small", ModelPricing::new(0.02, 0.0)),
+            ("text-embedding-3-large", ModelPricing::new(0.13, 0.0)),
+            ("text-embedding-ada-002", ModelPricing::new(0.1, 0.0)),
+            (
+                "claude-3-5-sonnet",
+                ModelPricing::new(3.0, 15.0).with_cache(3.75, 0.3),
+            ),
+            (
+                "claude-3-5-haiku",
+                ModelPricing::new(0.8, 4.0).with_cache(1.0, 0.08),
+            ),
+            (
+                "claude-3-opus",
+                ModelPricing::new(15.0, 75.0).with_cache(18.75, 1.5),
+            ),
+            (
+                "claude-3-sonnet",
+                ModelPricing::new(3.0, 15.0).with_cache(3.75, 0.3),
+            ),
+            (
+                "claude-3-haiku",
+                ModelPricing::new(0.25, 1.25).with_cache(0.3, 0.03),
+            ),
+        ];
+        PricingTable {
+            models: models
+                .into_iter()
+                .map(|(model, pricing)| (model.to_string(), pricing))
+                .collect(),
+        }
+    }
+
+    /// Adds a model, or overrides the price of a known one.
+    pub fn with_model(mut self, model: impl Into<String>, pricing: ModelPricing) -> Self {
+        self.insert(model, pricing);
+        self
+    }
+
+    pub fn insert(&mut self, model: impl Into<String>, pricing: ModelPricing) {
+        self.models.insert(model.into(), pricing);
+    }
+
+    /// The price of a model, `None` when it isn't in the table.
+    pub fn get(&self, model: &str) -> Option<&ModelPricing> {
+        self.models.get(model).or_else(|| {
+            self.models
+                .iter()
+                .filter(|(known, _)| model.starts_with(known.as_str()))
+                .max_by_key(|(known, _)| known.len())
+                .map(|(_, pricing)| pricing)
+        })
+    }
+
+    /// The estimated cost of a request in USD, `None` when the model isn't in the table.
+    pub fn estimated_cost(&self, model: &str, usage: impl Into<TokenCounts>) -> Option<f64> {
+        self.get(model).map(|pricing| pricing.cost(usage.into()))
+    }
+}
+
+/// The estimated cost of a request in USD according to [`PricingTable::builtin`],
+/// `None` when the model isn't known.
+///
+/// ```
+/// use openai::{pricing::estimated_cost, Usage};
+///
+/// let usage = Usage {
+///     prompt_tokens: 1_000_000,
+///     completion_tokens: 0,
+///     total_tokens: 1_000_000,
+/// };
+/// assert_eq!(estimated_cost("gpt-4o-2024-08-06", usage), Some(2.5));
+/// ```
+pub fn estimated_cost(model: &str, usage: impl Into<TokenCounts>) -> Option<f64> {
+    PricingTable::builtin().estimated_cost(model, usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn openai_cost() {
+        let usage = Usage {
+            prompt_tokens: 2_000,
+            completion_tokens: 500,
+            total_tokens: 2_500,
+        };
+        // gpt-4o-mini must not be priced as gpt-4o.
+        let cost = estimated_cost("gpt-4o-mini", usage).unwrap();
+        assert!((cost - (0.15 * 0.002 + 0.6 * 0.0005)).abs() < 1e-12);
+        assert_eq!(estimated_cost("unknown-model", usage), None);
+    }
+
+    #[test]
+    fn anthropic_cache_cost() {
+        let usage = AnthropicUsage {
+            input_tokens: 1_000_000,
+            cache_creation_input_tokens: 1_000_000,
+            cache_read_input_tokens: 1_000_000,
+            output_tokens: 1_000_000,
+            service_tier: None,
+        };
+        let cost = estimated_cost("claude-3-5-sonnet-20241022", usage).unwrap();
+        assert!((cost - (3.0 + 3.75 + 0.3 + 15.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn custom_models() {
+        let table = PricingTable::builtin()
+            .with_model("gpt-4o", ModelPricing::new(1.0, 1.0))
+            .with_model("my-finetune", ModelPricing::new(2.0, 4.0));
+        let usage = TokenCounts {
+            input: 1_000_000,
+            output: 1_000_000,
+            cache_read: 1_000_000,
+            ..Default::default()
+        };
+        assert_eq!(table.estimated_cost("gpt-4o-2024-08-06", usage), Some(3.0));
+        // Without cache prices, cached tokens cost as much as input.
+        assert_eq!(table.estimated_cost("my-finetune-v2", usage), Some(8.0));
+        assert_eq!(PricingTable::new().estimated_cost("gpt-4o", usage), None);
+    }
+}
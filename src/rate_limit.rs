@@ -0,0 +1,315 @@
+//! Pacing requests to stay within the rate limits of each model.
+//!
+//! A [`RateLimiter`] set on the credentials with
+//! [`Credentials::with_rate_limiter`](crate::Credentials::with_rate_limiter)
+//! learns the limits of each model from the `x-ratelimit-limit-*` (OpenAI) and
+//! `anthropic-ratelimit-*-limit` (Anthropic) headers of the responses, then
+//! spaces out the following requests to that model to match its request limit.
+//! Models whose limits haven't been seen yet aren't paced.
+//!
+//! Limits known upfront, such as those of an account tier, can be set with
+//! [`RateLimiter::with_limits`]. They apply to all requests through the
+//! limiter, whatever their model, from the first request on.
+//!
+//! Pacing needs the `tokio-runtime` feature to wait between requests.
+#![cfg_attr(not(feature = "tokio-runtime"), allow(dead_code))]
+
+use reqwest::header::HeaderMap;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The limits of a model, per minute.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct ModelRateLimits {
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u32>,
+}
+
+impl ModelRateLimits {
+    /// Reads the limits from the headers of a response, `None` when it has none.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header = |names: [&str; 2]| {
+            names.iter().find_map(|name| {
+                headers
+                    .get(*name)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse().ok())
+            })
+        };
+        let limits = ModelRateLimits {
+            requests_per_minute: header([
+                "x-ratelimit-limit-requests",
+                "anthropic-ratelimit-requests-limit",
+            ]),
+            tokens_per_minute: header([
+                "x-ratelimit-limit-tokens",
+                "anthropic-ratelimit-tokens-limit",
+            ]),
+        };
+        (limits != ModelRateLimits::default()).then_some(limits)
+    }
+
+    /// The time between two requests that keeps within the request limit.
+    fn request_interval(&self) -> Option<Duration> {
+        self.requests_per_minute
+            .filter(|&requests| requests > 0)
+            .map(|requests| Duration::from_secs(60) / requests)
+    }
+}
+
+/// Requests or tokens available, refilling continuously up to a minute's worth.
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    available: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn per_minute(limit: u32) -> Self {
+        Bucket {
+            capacity: f64::from(limit),
+            available: f64::from(limit),
+            updated: Instant::now(),
+        }
+    }
+
+    /// Takes `amount` from the bucket and returns how long to wait until it was
+    /// refilled. The bucket goes into debt, so that waiting requests are served
+    /// in order.
+    fn take(&mut self, amount: u32, now: Instant) -> Duration {
+        let per_second = self.capacity / 60.0;
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.available = (self.available + elapsed * per_second).min(self.capacity);
+        self.updated = now;
+        // An amount larger than the bucket would never fit.
+        self.available -= f64::from(amount).min(self.capacity);
+        if self.available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.available / per_second)
+        }
+    }
+}
+
+/// The limits set with [`RateLimiter::with_limits`].
+#[derive(Debug)]
+struct Budget {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+}
+
+#[derive(Debug, Default)]
+struct ModelState {
+    limits: ModelRateLimits,
+    /// When the next request may be sent.
+    next_request: Option<Instant>,
+}
+
+/// Learned rate limits, shared by all clones of the limiter.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    models: Arc<Mutex<HashMap<String, ModelState>>>,
+    budget: Option<Arc<Mutex<Budget>>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also keeps all requests within `limits`, whatever their model.
+    ///
+    /// The tokens of a request are estimated from the size of its body, see
+    /// [`estimate_tokens`](crate::tokens::estimate_tokens), so the token limit
+    /// is approximate. A limit of 0 is ignored.
+    pub fn with_limits(mut self, limits: ModelRateLimits) -> Self {
+        let bucket = |limit: Option<u32>| limit.filter(|&limit| limit > 0).map(Bucket::per_minute);
+        self.budget = Some(Arc::new(Mutex::new(Budget {
+            requests: bucket(limits.requests_per_minute),
+            tokens: bucket(limits.tokens_per_minute),
+        })));
+        self
+    }
+
+    /// Waits until a request of about `tokens` tokens fits within the limits
+    /// set with [`RateLimiter::with_limits`].
+    #[cfg(feature = "tokio-runtime")]
+    pub async fn throttle(&self, tokens: u32) {
+        let Some(budget) = &self.budget else {
+            return;
+        };
+        let wait = {
+            let mut budget = budget.lock().unwrap();
+            let now = Instant::now();
+            let requests = budget
+                .requests
+                .as_mut()
+                .map_or(Duration::ZERO, |bucket| bucket.take(1, now));
+            let tokens = budget
+                .tokens
+                .as_mut()
+                .map_or(Duration::ZERO, |bucket| bucket.take(tokens, now));
+            requests.max(tokens)
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// The limits learned for `model`, `None` when none have been seen yet.
+    pub fn limits(&self, model: &str) -> Option<ModelRateLimits> {
+        let models = self.models.lock().unwrap();
+        models.get(model).map(|state| state.limits)
+    }
+
+    /// Updates the limits of `model` from the headers of one of its responses.
+    pub fn observe(&self, model: &str, headers: &HeaderMap) {
+        if let Some(limits) = ModelRateLimits::from_headers(headers) {
+            let mut models = self.models.lock().unwrap();
+            models.entry(model.to_string()).or_default().limits = limits;
+        }
+    }
+
+    /// Waits until a request to `model` fits within its request limit.
+    #[cfg(feature = "tokio-runtime")]
+    pub async fn acquire(&self, model: &str) {
+        let wait = {
+            let mut models = self.models.lock().unwrap();
+            let Some(state) = models.get_mut(model) else {
+                return;
+            };
+            let Some(interval) = state.limits.request_interval() else {
+                return;
+            };
+            let now = Instant::now();
+            let slot = state.next_request.map_or(now, |next| next.max(now));
+            state.next_request = Some(slot + interval);
+            slot - now
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+impl PartialEq for RateLimiter {
+    fn eq(&self, other: &Self) -> bool {
+        let same_budget = match (&self.budget, &other.budget) {
+            (Some(budget), Some(other_budget)) => Arc::ptr_eq(budget, other_budget),
+            (None, None) => true,
+            _ => false,
+        };
+        Arc::ptr_eq(&self.models, &other.models) && same_budget
+    }
+}
+
+impl Eq for RateLimiter {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "tokio-runtime")]
+    use crate::openai_post;
+    #[cfg(feature = "tokio-runtime")]
+    use crate::tests::{json_response, mock_server};
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn learns_limits_from_headers() {
+        let credentials = mock_server(json_response(
+            "x-ratelimit-limit-requests: 600\r\nx-ratelimit-limit-tokens: 150000\r\n",
+            r#"{"ok": true}"#,
+        ))
+        .await;
+        let limiter = RateLimiter::new();
+        let credentials = credentials.with_rate_limiter(limiter.clone());
+
+        assert_eq!(limiter.limits("gpt-4o"), None);
+        let _: serde_json::Value = openai_post(
+            "chat/completions",
+            &serde_json::json!({"model": "gpt-4o"}),
+            Some(credentials),
+        )
+        .await
+        .unwrap();
+        let limits = limiter.limits("gpt-4o").unwrap();
+        assert_eq!(
+            limits,
+            ModelRateLimits {
+                requests_per_minute: Some(600),
+                tokens_per_minute: Some(150000),
+            }
+        );
+        assert_eq!(limits.request_interval(), Some(Duration::from_millis(100)));
+        assert_eq!(limiter.limits("gpt-4o-mini"), None);
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn paces_requests() {
+        let limiter = RateLimiter::new();
+        let mut headers = HeaderMap::new();
+        headers.insert("anthropic-ratelimit-requests-limit", "600".parse().unwrap());
+        limiter.observe("claude-3-5-sonnet-20241022", &headers);
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire("claude-3-5-sonnet-20241022").await;
+        }
+        // One request per 100ms: the first is immediate, the others wait.
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        let start = Instant::now();
+        limiter.acquire("unknown-model").await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn throttles_to_fixed_limits() {
+        // 100 tokens per second, starting with a full minute's worth.
+        let limiter = RateLimiter::new().with_limits(ModelRateLimits {
+            requests_per_minute: None,
+            tokens_per_minute: Some(6000),
+        });
+        let start = Instant::now();
+        limiter.throttle(6000).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+        limiter.throttle(20).await;
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        let credentials = mock_server(json_response("", r#"{"ok": true}"#))
+            .await
+            .with_rate_limiter(limiter.clone());
+        // The token debt of the previous request is paid off first.
+        let start = Instant::now();
+        let _: serde_json::Value = openai_post(
+            "embeddings",
+            &serde_json::json!({"input": "x".repeat(40)}),
+            Some(credentials),
+        )
+        .await
+        .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        let start = Instant::now();
+        RateLimiter::new().throttle(u32::MAX).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+
+    #[test]
+    fn equality() {
+        let limiter = RateLimiter::new();
+        assert_eq!(limiter, limiter.clone());
+        let limits = ModelRateLimits {
+            requests_per_minute: Some(60),
+            tokens_per_minute: None,
+        };
+        let limited = limiter.clone().with_limits(limits);
+        assert_ne!(limiter, limited);
+        assert_eq!(limited, limited.clone());
+        assert_ne!(limited, limiter.with_limits(limits));
+    }
+}
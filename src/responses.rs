@@ -0,0 +1,540 @@
+//! Generate model responses with the Responses API, OpenAI's successor to chat
+//! completions.
+//!
+//! A response can continue an earlier one with `previous_response_id`, the
+//! conversation being kept server-side, instead of sending it all again.
+//!
+//! Related guide: [Responses](https://platform.openai.com/docs/api-reference/responses)
+
+use derive_builder::Builder;
+use reqwest::Method;
+use reqwest_eventsource::{CannotCloneRequestError, Event, EventSource};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+#[cfg(feature = "tokio-runtime")]
+use tokio::sync::mpsc::{channel, Receiver};
+
+use crate::chat::ChatCompletionMessage;
+use crate::{
+    openai_get, openai_post, openai_request_stream, parse_stream_data, resolve_credentials,
+    validate_range, ApiResponseOrError, Credentials, OpenAiError, ResponseStream, StreamLimits,
+    StreamSender,
+};
+
+/// The input of a response: a text, or a conversation.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ResponseInput {
+    Text(String),
+    Messages(Vec<ChatCompletionMessage>),
+    /// Input items not modeled by the crate, such as function call outputs,
+    /// as JSON.
+    Items(Vec<Value>),
+}
+
+impl From<String> for ResponseInput {
+    fn from(text: String) -> Self {
+        ResponseInput::Text(text)
+    }
+}
+
+impl From<&str> for ResponseInput {
+    fn from(text: &str) -> Self {
+        ResponseInput::Text(text.to_string())
+    }
+}
+
+impl From<Vec<ChatCompletionMessage>> for ResponseInput {
+    fn from(messages: Vec<ChatCompletionMessage>) -> Self {
+        ResponseInput::Messages(messages)
+    }
+}
+
+#[derive(Serialize, Builder, Debug, Clone)]
+#[builder(pattern = "owned")]
+#[builder(name = "ResponseBuilder")]
+#[builder(setter(strip_option, into))]
+#[builder(build_fn(validate = "Self::validate"))]
+pub struct ResponseRequest {
+    /// ID of the model to use, such as `gpt-4o`.
+    pub model: String,
+    pub input: ResponseInput,
+    /// A system message inserted before the input. Instructions of previous
+    /// responses aren't carried over.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub instructions: Option<String>,
+    /// Continues the conversation of a previous response, which must have been stored.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub previous_response_id: Option<String>,
+    /// Whether the response is stored, to be retrieved or continued later.
+    /// `true` by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub store: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub top_p: Option<f32>,
+    /// The maximum number of tokens to generate, reasoning tokens included.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub max_output_tokens: Option<u64>,
+    /// The tools the model may call, as JSON, such as
+    /// `{"type": "web_search_preview"}` or function definitions.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    pub tools: Vec<Value>,
+    /// A unique identifier representing your end-user, which can help OpenAI to monitor and detect abuse.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default)]
+    stream: Option<bool>,
+    /// The credentials to use for this request.
+    #[serde(skip_serializing)]
+    #[builder(default)]
+    pub credentials: Option<Credentials>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Response {
+    pub id: String,
+    pub object: String,
+    /// Unix timestamp, in seconds, of when the response was created.
+    pub created_at: u64,
+    pub status: ResponseStatus,
+    pub model: String,
+    /// The items generated, such as messages, function calls and reasoning.
+    #[serde(default)]
+    pub output: Vec<ResponseOutputItem>,
+    /// Why the response failed, when its status is `failed`.
+    #[serde(default)]
+    pub error: Option<ResponseError>,
+    /// Why the response is incomplete, such as `max_output_tokens`.
+    #[serde(default)]
+    pub incomplete_details: Option<ResponseIncompleteDetails>,
+    #[serde(default)]
+    pub previous_response_id: Option<String>,
+    #[serde(default)]
+    pub usage: Option<ResponseUsage>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseStatus {
+    Queued,
+    InProgress,
+    Completed,
+    Incomplete,
+    Failed,
+    Cancelled,
+    /// A status this crate doesn't know about.
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct ResponseError {
+    pub code: String,
+    pub message: String,
+}
+
+#[derive(Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct ResponseIncompleteDetails {
+    pub reason: String,
+}
+
+/// Token usage of a response. Counts missing from the response are 0.
+#[derive(Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[serde(default)]
+pub struct ResponseUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub total_tokens: u64,
+}
+
+/// An item generated by the model.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseOutputItem {
+    Message {
+        id: String,
+        role: String,
+        #[serde(default)]
+        content: Vec<ResponseOutputContent>,
+    },
+    /// A call of a function tool, whose output is sent back as an input item.
+    FunctionCall {
+        #[serde(default)]
+        id: Option<String>,
+        call_id: String,
+        name: String,
+        /// The arguments, as a JSON string.
+        arguments: String,
+    },
+    Reasoning {
+        id: String,
+        #[serde(default)]
+        summary: Vec<Value>,
+    },
+    /// An item type not modeled by the crate, such as web search calls.
+    #[serde(other)]
+    Other,
+}
+
+/// A part of the content of an output message.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseOutputContent {
+    OutputText {
+        text: String,
+        /// Citations of the text, such as URLs found with web search.
+        #[serde(default)]
+        annotations: Vec<Value>,
+    },
+    Refusal {
+        refusal: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+impl ResponseRequest {
+    /// The JSON body this request is sent with, such as to log a rejected request.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+}
+
+impl Response {
+    pub fn builder(model: &str, input: impl Into<ResponseInput>) -> ResponseBuilder {
+        ResponseBuilder::create_empty().model(model).input(input)
+    }
+
+    /// The text of all output messages, concatenated.
+    pub fn output_text(&self) -> String {
+        self.output
+            .iter()
+            .filter_map(|item| match item {
+                ResponseOutputItem::Message { content, .. } => Some(content),
+                _ => None,
+            })
+            .flatten()
+            .filter_map(|content| match content {
+                ResponseOutputContent::OutputText { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub async fn create(request: ResponseRequest) -> ApiResponseOrError<Self> {
+        openai_post("responses", &request, request.credentials.clone()).await
+    }
+
+    /// Get a stored response by id.
+    pub async fn retrieve(id: &str, credentials: Credentials) -> ApiResponseOrError<Self> {
+        openai_get(&format!("responses/{id}"), Some(credentials)).await
+    }
+
+    /// Sends the streaming request, returning the stream of its events.
+    async fn open(
+        mut request: ResponseRequest,
+    ) -> Result<(EventSource, StreamLimits), CannotCloneRequestError> {
+        request.stream = Some(true);
+        let credentials = resolve_credentials(request.credentials.clone());
+        let limits = credentials.stream_limits();
+        let stream = openai_request_stream(
+            Method::POST,
+            "responses",
+            |r| r.json(&request),
+            Some(credentials),
+        )
+        .await?;
+        Ok((stream, limits))
+    }
+
+    /// Streams the response, event by event.
+    ///
+    /// The stream ends after the event carrying the final response, or after
+    /// an error, received as an `Err`.
+    #[cfg(feature = "tokio-runtime")]
+    pub async fn create_stream(
+        request: ResponseRequest,
+    ) -> Result<Receiver<ApiResponseOrError<ResponseStreamEvent>>, CannotCloneRequestError> {
+        let (stream, limits) = Self::open(request).await?;
+        let (tx, rx) = channel(32);
+        tokio::spawn(forward_response_stream(stream, tx.into(), limits));
+        Ok(rx)
+    }
+}
+
+/// An event of a streamed response.
+///
+/// [API Reference](https://platform.openai.com/docs/api-reference/responses-streaming)
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type")]
+pub enum ResponseStreamEvent {
+    #[serde(rename = "response.created")]
+    Created { response: Response },
+    #[serde(rename = "response.in_progress")]
+    InProgress { response: Response },
+    #[serde(rename = "response.output_item.added")]
+    OutputItemAdded {
+        output_index: u32,
+        item: ResponseOutputItem,
+    },
+    /// Text added to a part of an output message.
+    #[serde(rename = "response.output_text.delta")]
+    OutputTextDelta {
+        item_id: String,
+        output_index: u32,
+        content_index: u32,
+        delta: String,
+    },
+    /// A piece of the arguments of a function call.
+    #[serde(rename = "response.function_call_arguments.delta")]
+    FunctionCallArgumentsDelta {
+        item_id: String,
+        output_index: u32,
+        delta: String,
+    },
+    #[serde(rename = "response.output_item.done")]
+    OutputItemDone {
+        output_index: u32,
+        item: ResponseOutputItem,
+    },
+    /// The last event of a successful stream, with the complete response.
+    #[serde(rename = "response.completed")]
+    Completed { response: Response },
+    #[serde(rename = "response.incomplete")]
+    Incomplete { response: Response },
+    #[serde(rename = "response.failed")]
+    Failed { response: Response },
+    /// An event type not modeled by the crate.
+    #[serde(other)]
+    Other,
+}
+
+impl ResponseStreamEvent {
+    /// Whether the event ends the stream.
+    fn is_last(&self) -> bool {
+        matches!(
+            self,
+            ResponseStreamEvent::Completed { .. }
+                | ResponseStreamEvent::Incomplete { .. }
+                | ResponseStreamEvent::Failed { .. }
+        )
+    }
+}
+
+/// An `error` event of a response stream.
+#[derive(Deserialize)]
+struct StreamErrorEvent {
+    message: String,
+    code: Option<String>,
+    param: Option<String>,
+}
+
+/// Parses an event, which may be an `error` event instead.
+fn parse_response_event(data: &str) -> ApiResponseOrError<ResponseStreamEvent> {
+    let value: Value = serde_json::from_str(data)?;
+    if value["type"] != "error" {
+        return parse_stream_data(data);
+    }
+    let error: StreamErrorEvent = serde_json::from_value(value)?;
+    Err(OpenAiError {
+        code: error.code,
+        param: error.param,
+        ..OpenAiError::new(error.message, "error".to_string())
+    })
+}
+
+async fn forward_response_stream(
+    mut stream: EventSource,
+    mut tx: StreamSender<ApiResponseOrError<ResponseStreamEvent>>,
+    mut limits: StreamLimits,
+) {
+    loop {
+        let event = tx.next_event(&mut stream, &mut limits).await;
+        let event = match event {
+            None => break,
+            Some(Ok(Event::Open)) => continue,
+            Some(Ok(Event::Message(message))) => parse_response_event(&message.data),
+            Some(Err(error)) => Err(error),
+        };
+        let last = event.as_ref().map_or(true, ResponseStreamEvent::is_last);
+        if tx.send(event).await.is_err() || last {
+            break;
+        }
+    }
+    stream.close();
+}
+
+impl ResponseBuilder {
+    fn validate(&self) -> Result<(), String> {
+        validate_range("temperature", self.temperature.flatten(), 2.0)?;
+        validate_range("top_p", self.top_p.flatten(), 1.0)
+    }
+
+    pub async fn create(self) -> ApiResponseOrError<Response> {
+        Response::create(self.build()?).await
+    }
+
+    /// Builds the request and streams the response.
+    /// See [`Response::create_stream`].
+    #[cfg(feature = "tokio-runtime")]
+    pub async fn create_stream(
+        self,
+    ) -> ApiResponseOrError<Receiver<ApiResponseOrError<ResponseStreamEvent>>> {
+        Ok(Response::create_stream(self.build()?).await?)
+    }
+
+    /// Same as `create_stream`, as a [`Stream`](futures_util::Stream) of events.
+    /// Also available without the `tokio-runtime` feature.
+    pub async fn create_response_stream(
+        self,
+    ) -> ApiResponseOrError<ResponseStream<ResponseStreamEvent>> {
+        #[cfg(feature = "tokio-runtime")]
+        {
+            Ok(self.create_stream().await?.into())
+        }
+        #[cfg(not(feature = "tokio-runtime"))]
+        {
+            let (stream, limits) = Response::open(self.build()?).await?;
+            Ok(ResponseStream::forwarded(|tx| forward_response_stream(stream, tx, limits)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{json_response, mock_server};
+    #[cfg(feature = "tokio-runtime")]
+    use crate::tests::mock_stream_server;
+
+    const RESPONSE: &str = r#"{
+        "id": "resp_123",
+        "object": "response",
+        "created_at": 1741476542,
+        "status": "completed",
+        "error": null,
+        "incomplete_details": null,
+        "model": "gpt-4o-2024-08-06",
+        "output": [
+            {"type": "reasoning", "id": "rs_1", "summary": []},
+            {
+                "type": "message",
+                "id": "msg_1",
+                "status": "completed",
+                "role": "assistant",
+                "content": [{"type": "output_text", "text": "Crabs walk sideways.", "annotations": []}]
+            },
+            {"type": "function_call", "id": "fc_1", "call_id": "call_1", "name": "get_weather", "arguments": "{\"city\": \"Paris\"}"},
+            {"type": "web_search_call", "id": "ws_1", "status": "completed"}
+        ],
+        "previous_response_id": "resp_122",
+        "usage": {"input_tokens": 36, "output_tokens": 87, "total_tokens": 123}
+    }"#;
+
+    #[tokio::test]
+    async fn create() {
+        let credentials = mock_server(json_response("", RESPONSE)).await;
+        let request = Response::builder("gpt-4o", "How do crabs walk?")
+            .instructions("Be concise.")
+            .previous_response_id("resp_122")
+            .credentials(credentials)
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "model": "gpt-4o",
+                "input": "How do crabs walk?",
+                "instructions": "Be concise.",
+                "previous_response_id": "resp_122"
+            })
+        );
+
+        let response = Response::create(request).await.unwrap();
+        assert_eq!(response.status, ResponseStatus::Completed);
+        assert_eq!(response.output_text(), "Crabs walk sideways.");
+        assert_eq!(response.output.len(), 4);
+        assert!(matches!(
+            &response.output[2],
+            ResponseOutputItem::FunctionCall { name, .. } if name == "get_weather"
+        ));
+        assert_eq!(response.output[3], ResponseOutputItem::Other);
+        assert_eq!(response.usage.unwrap().total_tokens, 123);
+    }
+
+    #[tokio::test]
+    async fn retrieve() {
+        let credentials = mock_server(json_response("", RESPONSE)).await;
+        let response = Response::retrieve("resp_123", credentials).await.unwrap();
+        assert_eq!(response.id, "resp_123");
+        assert_eq!(response.previous_response_id.as_deref(), Some("resp_122"));
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn create_stream() {
+        let completed = RESPONSE.replace('\n', " ");
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\n\r\n\
+            event: response.output_text.delta\ndata: {{\"type\": \"response.output_text.delta\", \
+            \"item_id\": \"msg_1\", \"output_index\": 1, \"content_index\": 0, \"delta\": \"Crabs\"}}\n\n\
+            event: response.content_part.added\ndata: {{\"type\": \"response.content_part.added\"}}\n\n\
+            event: response.completed\ndata: {{\"type\": \"response.completed\", \"response\": {completed}}}\n\n"
+        );
+        let (credentials, _closed) = mock_stream_server(response).await;
+        let mut rx = Response::builder("gpt-4o", "How do crabs walk?")
+            .credentials(credentials)
+            .create_stream()
+            .await
+            .unwrap();
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(event.unwrap());
+        }
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[0],
+            ResponseStreamEvent::OutputTextDelta {
+                item_id: "msg_1".to_string(),
+                output_index: 1,
+                content_index: 0,
+                delta: "Crabs".to_string(),
+            }
+        );
+        assert_eq!(events[1], ResponseStreamEvent::Other);
+        let ResponseStreamEvent::Completed { response } = &events[2] else {
+            panic!("expected the completed response, got {:?}", events[2]);
+        };
+        assert_eq!(response.output_text(), "Crabs walk sideways.");
+
+        let error = parse_response_event(
+            r#"{"type": "error", "code": "rate_limit_exceeded", "message": "Slow down.", "param": null}"#,
+        )
+        .unwrap_err();
+        assert_eq!(error.code.as_deref(), Some("rate_limit_exceeded"));
+        assert_eq!(error.message, "Slow down.");
+    }
+
+    #[test]
+    fn golden_request() {
+        let request = Response::builder("gpt-4o", "Tell me a crab fact.")
+            .instructions("Be brief.")
+            .previous_response_id("resp_1")
+            .max_output_tokens(256u64)
+            .tools(vec![serde_json::json!({"type": "web_search_preview"})])
+            .build()
+            .unwrap();
+        crate::tests::assert_golden("response_request", &request);
+    }
+}
@@ -0,0 +1,426 @@
+//! Retrying failed requests.
+//!
+//! Requests aren't retried unless a [`RetryPolicy`] is set on the credentials
+//! with [`Credentials::with_retry_policy`](crate::Credentials::with_retry_policy).
+//! By default rate limited (429) and server error (5xx) responses, as well as
+//! timeouts and connection errors, are retried with an exponential backoff.
+//! A custom classifier can override which failures are retried.
+//!
+//! The policy is set per credentials, so each provider can be retried
+//! differently. With full jitter, clients backing off from the same outage
+//! don't all retry at once:
+//!
+//! ```
+//! use openai::retry::RetryPolicy;
+//! use openai::Credentials;
+//! use std::time::Duration;
+//!
+//! let openai = Credentials::openai("sk-...").with_retry_policy(
+//!     RetryPolicy::new(5)
+//!         .with_backoff(Duration::from_millis(200), Duration::from_secs(10))
+//!         .with_full_jitter(),
+//! );
+//! let anthropic = Credentials::anthropic("sk-ant-...")
+//!     .with_retry_policy(RetryPolicy::new(2).with_full_jitter());
+//! ```
+//!
+//! Retrying needs the `tokio-runtime` feature to wait between attempts, and
+//! isn't available on WebAssembly.
+#![cfg_attr(
+    not(all(feature = "tokio-runtime", not(target_arch = "wasm32"))),
+    allow(dead_code, unused_imports)
+)]
+
+use super::transport::Transport;
+use super::{ApiResponseOrError, OpenAiError};
+use reqwest::{Request, Response, StatusCode};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// What to do about a request that failed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FailureAction {
+    /// Send the request again, after a backoff.
+    Retry,
+    /// Give up and return the failure.
+    Fail,
+    /// Give up and fail any request sent with the same policy
+    /// until the circuit breaker cooldown has passed.
+    CircuitBreak,
+}
+
+/// Decides what to do about a failed response given its status and body.
+pub type FailureClassifier = Arc<dyn Fn(StatusCode, &str) -> FailureAction + Send + Sync>;
+
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    classifier: Option<FailureClassifier>,
+    /// The state of the random numbers jittering the backoff, shared by all
+    /// clones of the policy. No jitter when `None`.
+    jitter: Option<Arc<Mutex<u64>>>,
+    circuit_breaker_cooldown: Duration,
+    /// Until when the circuit breaker is open, shared by all clones of the policy.
+    circuit_open_until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl RetryPolicy {
+    /// Retries failed requests up to `max_retries` times.
+    pub fn new(max_retries: u32) -> Self {
+        RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            classifier: None,
+            jitter: None,
+            circuit_breaker_cooldown: Duration::from_secs(30),
+            circuit_open_until: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Waits `initial` before the first retry, doubling the wait for every
+    /// following retry up to `max`.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Waits a random time between 0 and the backoff before each retry, instead
+    /// of the backoff itself.
+    pub fn with_full_jitter(self) -> Self {
+        let seed = RandomState::new().build_hasher().finish();
+        self.with_jitter_seed(seed)
+    }
+
+    /// Like [`RetryPolicy::with_full_jitter`], with the random waits drawn from
+    /// `seed`, so that they are the same from one run to the next.
+    pub fn with_jitter_seed(mut self, seed: u64) -> Self {
+        self.jitter = Some(Arc::new(Mutex::new(seed)));
+        self
+    }
+
+    /// Overrides the default classification of failed responses.
+    ///
+    /// ```
+    /// use openai::retry::{FailureAction, RetryPolicy};
+    /// use reqwest::StatusCode;
+    ///
+    /// // Our proxy sometimes answers 400 when it is overloaded.
+    /// let policy = RetryPolicy::new(3).with_classifier(|status, body| {
+    ///     if status == StatusCode::BAD_REQUEST && body.contains("proxy overloaded") {
+    ///         FailureAction::Retry
+    ///     } else {
+    ///         RetryPolicy::default_classification(status)
+    ///     }
+    /// });
+    /// ```
+    pub fn with_classifier(
+        mut self,
+        classifier: impl Fn(StatusCode, &str) -> FailureAction + Send + Sync + 'static,
+    ) -> Self {
+        self.classifier = Some(Arc::new(classifier));
+        self
+    }
+
+    /// How long requests fail right away after a failure was classified
+    /// as [`FailureAction::CircuitBreak`]. 30 seconds by default.
+    pub fn with_circuit_breaker_cooldown(mut self, cooldown: Duration) -> Self {
+        self.circuit_breaker_cooldown = cooldown;
+        self
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries
+    }
+
+    /// Retries rate limited and server error responses, fails on anything else.
+    pub fn default_classification(status: StatusCode) -> FailureAction {
+        if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+            FailureAction::Retry
+        } else {
+            FailureAction::Fail
+        }
+    }
+
+    /// Classifies a failed response, using the custom classifier if there is one.
+    pub fn classify(&self, status: StatusCode, body: &str) -> FailureAction {
+        match &self.classifier {
+            Some(classifier) => classifier(status, body),
+            None => Self::default_classification(status),
+        }
+    }
+
+    /// The time to wait before the given retry, starting at 0.
+    fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+
+    /// The time to actually wait before the given retry, jittered if enabled.
+    fn delay(&self, retry: u32) -> Duration {
+        let backoff = self.backoff(retry);
+        match &self.jitter {
+            Some(state) => {
+                // splitmix64
+                let mut state = state.lock().unwrap();
+                *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                let mut z = *state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                z ^= z >> 31;
+                // The top 53 bits, as a fraction in [0, 1).
+                backoff.mul_f64((z >> 11) as f64 / (1u64 << 53) as f64)
+            }
+            None => backoff,
+        }
+    }
+
+    fn circuit_open(&self) -> bool {
+        let open_until = self.circuit_open_until.lock().unwrap();
+        open_until.is_some_and(|open_until| Instant::now() < open_until)
+    }
+
+    fn open_circuit(&self) {
+        *self.circuit_open_until.lock().unwrap() =
+            Some(Instant::now() + self.circuit_breaker_cooldown);
+    }
+
+    /// Sends a request, retrying it as long as the policy allows.
+    ///
+    /// Requests with a streamed body (such as file uploads) can't be sent twice
+    /// and are never retried.
+    #[cfg(all(feature = "tokio-runtime", not(target_arch = "wasm32")))]
+    pub(crate) async fn send(
+        &self,
+        request: Request,
+        transport: &dyn Transport,
+    ) -> ApiResponseOrError<Response> {
+        if self.circuit_open() {
+            return Err(OpenAiError::new(
+                "Circuit breaker is open after a previous failure".to_string(),
+                "circuit_open".to_string(),
+            ));
+        }
+        let mut retry = 0;
+        loop {
+            let Some(attempt) = request.try_clone() else {
+                return transport.send(request).await;
+            };
+            let can_retry = retry < self.max_retries;
+            match transport.send(attempt).await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) => {
+                    let status = response.status();
+                    let headers = response.headers().clone();
+                    let body = response.bytes().await?;
+                    match self.classify(status, &String::from_utf8_lossy(&body)) {
+                        FailureAction::Retry if can_retry => {}
+                        FailureAction::CircuitBreak => {
+                            self.open_circuit();
+                            return Ok(rebuild_response(status, headers, body));
+                        }
+                        _ => return Ok(rebuild_response(status, headers, body)),
+                    }
+                }
+                Err(error)
+                    if can_retry
+                        && matches!(
+                            error.code.as_deref(),
+                            Some("timeout" | "connection_error")
+                        ) => {}
+                Err(error) => return Err(error),
+            }
+            tokio::time::sleep(self.delay(retry)).await;
+            retry += 1;
+        }
+    }
+}
+
+/// Puts a response back together after its body has been read to classify it,
+/// so it can be handled like any other response.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn rebuild_response(
+    status: StatusCode,
+    headers: reqwest::header::HeaderMap,
+    body: bytes::Bytes,
+) -> Response {
+    let mut response = http::Response::new(body);
+    *response.status_mut() = status;
+    *response.headers_mut() = headers;
+    Response::from(response)
+}
+
+impl std::fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_retries", &self.max_retries)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("classifier", &self.classifier.as_ref().map(|_| ".."))
+            .field("jitter", &self.jitter.is_some())
+            .field("circuit_breaker_cooldown", &self.circuit_breaker_cooldown)
+            .finish()
+    }
+}
+
+/// Policies are equal when they retry the same way, sharing the same classifier
+/// and jitter.
+impl PartialEq for RetryPolicy {
+    fn eq(&self, other: &Self) -> bool {
+        self.max_retries == other.max_retries
+            && self.initial_backoff == other.initial_backoff
+            && self.max_backoff == other.max_backoff
+            && self.circuit_breaker_cooldown == other.circuit_breaker_cooldown
+            && match (&self.classifier, &other.classifier) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                (None, None) => true,
+                _ => false,
+            }
+            && match (&self.jitter, &other.jitter) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                (None, None) => true,
+                _ => false,
+            }
+    }
+}
+
+impl Eq for RetryPolicy {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{http_response, json_response, mock_server_sequence};
+    use crate::{openai_get, Credentials};
+
+    fn fast_policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy::new(max_retries)
+            .with_backoff(Duration::from_millis(1), Duration::from_millis(1))
+    }
+
+    #[test]
+    fn default_classification() {
+        let policy = RetryPolicy::new(1);
+        assert_eq!(
+            policy.classify(StatusCode::TOO_MANY_REQUESTS, ""),
+            FailureAction::Retry
+        );
+        assert_eq!(
+            policy.classify(StatusCode::BAD_GATEWAY, ""),
+            FailureAction::Retry
+        );
+        assert_eq!(
+            policy.classify(StatusCode::BAD_REQUEST, ""),
+            FailureAction::Fail
+        );
+        assert_eq!(
+            policy.classify(StatusCode::UNAUTHORIZED, ""),
+            FailureAction::Fail
+        );
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let policy = RetryPolicy::new(10)
+            .with_backoff(Duration::from_millis(100), Duration::from_millis(500));
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(3), Duration::from_millis(500));
+        assert_eq!(policy.backoff(40), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn full_jitter() {
+        let policy = RetryPolicy::new(10)
+            .with_backoff(Duration::from_millis(100), Duration::from_secs(10))
+            .with_jitter_seed(42);
+        let delays: Vec<_> = (0..8).map(|retry| policy.delay(retry)).collect();
+        for (retry, delay) in delays.iter().enumerate() {
+            assert!(*delay <= policy.backoff(retry as u32));
+        }
+        assert!(delays
+            .iter()
+            .enumerate()
+            .any(|(retry, delay)| *delay != policy.backoff(retry as u32)));
+
+        let same_seed = RetryPolicy::new(10)
+            .with_backoff(Duration::from_millis(100), Duration::from_secs(10))
+            .with_jitter_seed(42);
+        let same_delays: Vec<_> = (0..8).map(|retry| same_seed.delay(retry)).collect();
+        assert_eq!(delays, same_delays);
+    }
+
+    #[tokio::test]
+    async fn classifier_forces_retry() {
+        let flaky_proxy_error = http_response(
+            "400 Bad Request",
+            "",
+            r#"{"error": {"message": "proxy overloaded", "type": "proxy_error"}}"#,
+        );
+        let credentials = mock_server_sequence(vec![
+            flaky_proxy_error.clone(),
+            json_response("", r#"{"ok": true}"#),
+        ])
+        .await;
+
+        // A 400 is fatal by default.
+        let result: ApiResponseOrError<serde_json::Value> = openai_get(
+            "test",
+            Some(credentials.clone().with_retry_policy(fast_policy(1))),
+        )
+        .await;
+        assert_eq!(result.unwrap_err().message, "proxy overloaded");
+
+        let policy = fast_policy(1).with_classifier(|status, body| {
+            if status == StatusCode::BAD_REQUEST && body.contains("proxy overloaded") {
+                FailureAction::Retry
+            } else {
+                RetryPolicy::default_classification(status)
+            }
+        });
+        let result: serde_json::Value =
+            openai_get("test", Some(credentials.with_retry_policy(policy)))
+                .await
+                .unwrap();
+        assert_eq!(result, serde_json::json!({"ok": true}));
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn circuit_breaker() {
+        let credentials = mock_server_sequence(vec![http_response(
+            "503 Service Unavailable",
+            "",
+            r#"{"error": {"message": "down", "type": "server_error"}}"#,
+        )])
+        .await
+        .with_retry_policy(fast_policy(3).with_classifier(|_, _| FailureAction::CircuitBreak));
+
+        let result: ApiResponseOrError<serde_json::Value> =
+            openai_get("test", Some(credentials.clone())).await;
+        assert_eq!(result.unwrap_err().message, "down");
+
+        let result: ApiResponseOrError<serde_json::Value> =
+            openai_get("test", Some(credentials)).await;
+        assert_eq!(result.unwrap_err().error_type, "circuit_open");
+    }
+
+    #[test]
+    fn policy_equality() {
+        let credentials = Credentials::new("sk-test", "https://api.openai.com/v1/");
+        let policy = RetryPolicy::new(2).with_classifier(|_, _| FailureAction::Fail);
+        assert_eq!(
+            credentials.clone().with_retry_policy(policy.clone()),
+            credentials.clone().with_retry_policy(policy)
+        );
+        assert_ne!(
+            credentials.clone().with_retry_policy(RetryPolicy::new(2)),
+            credentials.with_retry_policy(RetryPolicy::new(3))
+        );
+    }
+}
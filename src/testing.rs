@@ -0,0 +1,168 @@
+//! Testing code that calls the APIs without a network.
+//!
+//! ```
+//! use openai::chat::ChatCompletion;
+//! use openai::testing::MockTransport;
+//! use openai::Credentials;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let transport = MockTransport::new();
+//! transport.push_json(serde_json::json!({
+//!     "id": "chatcmpl-1",
+//!     "object": "chat.completion",
+//!     "created": 1,
+//!     "model": "gpt-4o",
+//!     "choices": [{
+//!         "index": 0,
+//!         "finish_reason": "stop",
+//!         "message": {"role": "assistant", "content": "Hi!"}
+//!     }]
+//! }));
+//! let credentials = Credentials::openai("sk-test").with_transport(transport.clone());
+//!
+//! let completion = ChatCompletion::builder("gpt-4o", [])
+//!     .add_user("Hello!")
+//!     .credentials(credentials)
+//!     .create()
+//!     .await
+//!     .unwrap();
+//! assert_eq!(completion.first_message().unwrap().text(), "Hi!");
+//! assert_eq!(transport.requests()[0].json().unwrap()["model"], "gpt-4o");
+//! # }
+//! ```
+
+use crate::transport::{Transport, TransportFuture};
+use crate::OpenAiError;
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use reqwest::{Method, Request, Response, StatusCode};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// A request received by a [`MockTransport`].
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: HeaderMap,
+    /// The body, empty for requests without one or with a streamed one.
+    pub body: Vec<u8>,
+}
+
+impl RecordedRequest {
+    pub fn json(&self) -> serde_json::Result<serde_json::Value> {
+        serde_json::from_slice(&self.body)
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    responses: VecDeque<(StatusCode, String)>,
+    requests: Vec<RecordedRequest>,
+}
+
+/// A [`Transport`] answering requests with canned responses, in the order they
+/// were pushed, and recording the requests. Clones share the same responses and
+/// requests, so a clone can be set on the credentials.
+#[derive(Debug, Clone, Default)]
+pub struct MockTransport {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a successful response with `body` serialized as JSON.
+    pub fn push_json(&self, body: impl Serialize) -> &Self {
+        let body = serde_json::to_string(&body).expect("the mock body can't be serialized");
+        self.push_response(StatusCode::OK, body)
+    }
+
+    /// Queues a JSON response with any status, such as an error.
+    pub fn push_response(&self, status: StatusCode, body: impl Into<String>) -> &Self {
+        let mut state = self.state.lock().unwrap();
+        state.responses.push_back((status, body.into()));
+        self
+    }
+
+    /// The requests received so far, oldest first.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+impl Transport for MockTransport {
+    fn send(&self, request: Request) -> TransportFuture {
+        let mut state = self.state.lock().unwrap();
+        state.requests.push(RecordedRequest {
+            method: request.method().clone(),
+            url: request.url().to_string(),
+            headers: request.headers().clone(),
+            body: request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .unwrap_or_default()
+                .to_vec(),
+        });
+        let response = state.responses.pop_front().map(|(status, body)| {
+            let mut response = http::Response::new(body);
+            *response.status_mut() = status;
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            Response::from(response)
+        });
+        Box::pin(async move {
+            response.ok_or_else(|| {
+                OpenAiError::new(
+                    "The mock transport has no response left".to_string(),
+                    "mock_error".to_string(),
+                )
+            })
+        })
+    }
+}
+
+#[cfg(all(test, feature = "tokio-runtime"))]
+mod tests {
+    use super::*;
+    use crate::retry::RetryPolicy;
+    use crate::{openai_get, Credentials};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn retries_through_the_transport() {
+        let transport = MockTransport::new();
+        transport
+            .push_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                r#"{"error": {"message": "overloaded", "type": "server_error"}}"#,
+            )
+            .push_json(serde_json::json!({"ok": true}));
+        let credentials = Credentials::openai("sk-test")
+            .with_transport(transport.clone())
+            .with_retry_policy(
+                RetryPolicy::new(1)
+                    .with_backoff(Duration::from_millis(1), Duration::from_millis(1)),
+            );
+
+        let value: serde_json::Value = openai_get("models", Some(credentials.clone()))
+            .await
+            .unwrap();
+        assert_eq!(value, serde_json::json!({"ok": true}));
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].method, Method::GET);
+        assert_eq!(requests[1].url, "https://api.openai.com/v1/models");
+        assert_eq!(requests[1].headers["authorization"], "Bearer sk-test");
+
+        let error = openai_get::<serde_json::Value>("models", Some(credentials))
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_type, "mock_error");
+    }
+}
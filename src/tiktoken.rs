@@ -0,0 +1,288 @@
+//! Counting tokens locally with the byte pair encodings of OpenAI models, in
+//! the `.tiktoken` format of [tiktoken](https://github.com/openai/tiktoken).
+//!
+//! The encodings aren't bundled with the crate. Download the one of your
+//! model, see [`encoding_name`], from
+//! `https://openaipublic.blob.core.windows.net/encodings/<name>.tiktoken`,
+//! then load it with [`Encoding::load`].
+//!
+//! Text is split into pieces like the `cl100k_base` pattern of tiktoken before
+//! being encoded, which `o200k_base` counts are close to as well. Counts are
+//! meant for pre-checks against context windows and cost estimates; see
+//! [`tokens`](crate::tokens) for a rougher estimate without an encoding.
+//!
+//! Requires the `tiktoken` feature.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use base64::Engine;
+
+use crate::chat::{ChatCompletionMessage, ChatCompletionMessageRole};
+
+/// Tokens added to every message for its role and delimiters.
+const TOKENS_PER_MESSAGE: usize = 3;
+
+/// Tokens added to a message with a name.
+const TOKENS_PER_NAME: usize = 1;
+
+/// Tokens priming the reply of the assistant.
+const TOKENS_PER_REPLY: usize = 3;
+
+/// The name of the encoding of `model`, `None` when it isn't known.
+pub fn encoding_name(model: &str) -> Option<&'static str> {
+    const O200K: [&str; 5] = ["gpt-4o", "gpt-4.1", "gpt-4.5", "gpt-5", "chatgpt-4o"];
+    const CL100K: [&str; 4] = [
+        "gpt-4",
+        "gpt-3.5",
+        "text-embedding-3",
+        "text-embedding-ada-002",
+    ];
+    // Reasoning models are named o1, o3, o4-mini...
+    let is_reasoning = |model: &str| {
+        model
+            .strip_prefix('o')
+            .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+    };
+    if O200K.iter().any(|prefix| model.starts_with(prefix)) || is_reasoning(model) {
+        Some("o200k_base")
+    } else if CL100K.iter().any(|prefix| model.starts_with(prefix)) {
+        Some("cl100k_base")
+    } else {
+        None
+    }
+}
+
+/// A byte pair encoding, mapping byte sequences to their rank.
+#[derive(Debug, Clone)]
+pub struct Encoding {
+    ranks: HashMap<Vec<u8>, u32>,
+}
+
+impl Encoding {
+    /// Loads a `.tiktoken` file.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parses the contents of a `.tiktoken` file: one token per line, in
+    /// base64, followed by its rank.
+    pub fn parse(data: &str) -> io::Result<Self> {
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid tiktoken line: {line:?}"),
+            )
+        };
+        let mut ranks = HashMap::new();
+        for line in data.lines().filter(|line| !line.is_empty()) {
+            let (token, rank) = line.split_once(' ').ok_or_else(|| invalid(line))?;
+            let token = base64::engine::general_purpose::STANDARD
+                .decode(token)
+                .map_err(|_| invalid(line))?;
+            let rank = rank.parse().map_err(|_| invalid(line))?;
+            ranks.insert(token, rank);
+        }
+        Ok(Encoding { ranks })
+    }
+
+    /// Counts the tokens of a text.
+    pub fn count_tokens(&self, text: &str) -> usize {
+        split(text)
+            .map(|piece| self.count_piece_tokens(piece.as_bytes()))
+            .sum()
+    }
+
+    /// Counts the prompt tokens of a conversation, the way OpenAI counts those
+    /// of its chat models. Tool calls and images aren't counted.
+    pub fn count_message_tokens(&self, messages: &[ChatCompletionMessage]) -> usize {
+        messages
+            .iter()
+            .map(|message| {
+                TOKENS_PER_MESSAGE
+                    + self.count_tokens(role_name(message.role))
+                    + self.count_tokens(message.text())
+                    + message
+                        .name
+                        .as_deref()
+                        .map_or(0, |name| TOKENS_PER_NAME + self.count_tokens(name))
+            })
+            .sum::<usize>()
+            + TOKENS_PER_REPLY
+    }
+
+    /// Merges the bytes of a piece pair by pair, lowest rank first, until no
+    /// pair is in the encoding, and returns the number of parts left.
+    fn count_piece_tokens(&self, piece: &[u8]) -> usize {
+        if piece.is_empty() {
+            return 0;
+        }
+        if self.ranks.contains_key(piece) {
+            return 1;
+        }
+        // The start of every part, and the end of the piece.
+        let mut bounds: Vec<usize> = (0..=piece.len()).collect();
+        loop {
+            let lowest = bounds
+                .windows(3)
+                .enumerate()
+                .filter_map(|(index, window)| {
+                    let rank = self.ranks.get(&piece[window[0]..window[2]])?;
+                    Some((*rank, index))
+                })
+                .min();
+            match lowest {
+                Some((_, index)) => {
+                    bounds.remove(index + 1);
+                }
+                None => return bounds.len() - 1,
+            }
+        }
+    }
+}
+
+fn role_name(role: ChatCompletionMessageRole) -> &'static str {
+    match role {
+        ChatCompletionMessageRole::System => "system",
+        ChatCompletionMessageRole::User => "user",
+        ChatCompletionMessageRole::Assistant => "assistant",
+        ChatCompletionMessageRole::Function => "function",
+        ChatCompletionMessageRole::Tool => "tool",
+    }
+}
+
+/// Splits a text into the pieces encoded separately, following the
+/// `cl100k_base` pattern:
+///
+/// ```text
+/// (?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+
+/// ```
+fn split(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        let len = piece_len(rest)?;
+        let (piece, tail) = rest.split_at(len);
+        rest = tail;
+        Some(piece)
+    })
+}
+
+/// The length in bytes of the piece at the start of `text`.
+fn piece_len(text: &str) -> Option<usize> {
+    let is_letter = |c: char| c.is_alphabetic();
+    let is_number = |c: char| c.is_numeric();
+    let is_newline = |c: char| c == '\r' || c == '\n';
+    // The length of the run of chars at the start of `text` matching `f`.
+    let run =
+        |text: &str, f: &dyn Fn(char) -> bool| text.find(|c: char| !f(c)).unwrap_or(text.len());
+
+    let first = text.chars().next()?;
+    if first == '\'' {
+        let lower = text[1..].chars().take(2).collect::<String>().to_lowercase();
+        for suffix in ["re", "ve", "ll", "s", "t", "m", "d"] {
+            if lower.starts_with(suffix) {
+                return Some(1 + suffix.len());
+            }
+        }
+    }
+    let letters_at = |start: usize| run(&text[start..], &is_letter);
+    if is_letter(first) {
+        return Some(letters_at(0));
+    }
+    if !is_newline(first) && !is_number(first) {
+        let letters = letters_at(first.len_utf8());
+        if letters > 0 {
+            return Some(first.len_utf8() + letters);
+        }
+    }
+    if is_number(first) {
+        return Some(
+            text.chars()
+                .take(3)
+                .take_while(|&c| is_number(c))
+                .map(char::len_utf8)
+                .sum(),
+        );
+    }
+    let is_symbol = |c: char| !c.is_whitespace() && !is_letter(c) && !is_number(c);
+    let start = if first == ' ' { 1 } else { 0 };
+    let symbols = run(&text[start..], &is_symbol);
+    if symbols > 0 {
+        let end = start + symbols;
+        return Some(end + run(&text[end..], &is_newline));
+    }
+    // The text starts with whitespace.
+    let spaces = run(text, &char::is_whitespace);
+    if let Some(newline) = text[..spaces].rfind(is_newline) {
+        return Some(newline + 1);
+    }
+    if spaces == text.len() {
+        return Some(spaces);
+    }
+    // Leave the last whitespace char to the piece that follows.
+    let last = text[..spaces].chars().next_back()?.len_utf8();
+    Some(if spaces > last { spaces - last } else { spaces })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An encoding of all single bytes, plus a few merges.
+    fn encoding() -> Encoding {
+        let engine = base64::engine::general_purpose::STANDARD;
+        let mut data = String::new();
+        let merges: [&[u8]; 6] = [b"he", b"ll", b"hell", b"hello", b" w", b" world"];
+        let tokens = (0..=255u8)
+            .map(|byte| vec![byte])
+            .chain(merges.map(<[u8]>::to_vec));
+        for (rank, token) in tokens.enumerate() {
+            data.push_str(&format!("{} {rank}\n", engine.encode(token)));
+        }
+        Encoding::parse(&data).unwrap()
+    }
+
+    #[test]
+    fn splits_like_cl100k() {
+        let pieces: Vec<_> = split("Hello world, it's 12345!!\n\n  ok   go").collect();
+        assert_eq!(
+            pieces,
+            [
+                "Hello", " world", ",", " it", "'s", " ", "123", "45", "!!\n\n", " ", " ok", "  ",
+                " go"
+            ]
+        );
+    }
+
+    #[test]
+    fn counts_tokens() {
+        let encoding = encoding();
+        assert_eq!(encoding.count_tokens(""), 0);
+        assert_eq!(encoding.count_tokens("hello"), 1);
+        assert_eq!(encoding.count_tokens("hello world"), 2);
+        // "he" and "ll" merge into "hell", the "p" is left alone.
+        assert_eq!(encoding.count_tokens("hellp"), 2);
+        assert_eq!(encoding.count_tokens("hello!"), 2);
+
+        let messages = [ChatCompletionMessage {
+            role: ChatCompletionMessageRole::User,
+            content: Some("hello world".to_string()),
+            ..Default::default()
+        }];
+        // 3 per message, 4 for "user", 2 for the content and 3 for the reply.
+        assert_eq!(encoding.count_message_tokens(&messages), 3 + 4 + 2 + 3);
+
+        assert!(Encoding::parse("not base64!").is_err());
+    }
+
+    #[test]
+    fn encodings_by_model() {
+        assert_eq!(encoding_name("gpt-4o-mini"), Some("o200k_base"));
+        assert_eq!(encoding_name("o3-mini"), Some("o200k_base"));
+        assert_eq!(encoding_name("gpt-4-turbo"), Some("cl100k_base"));
+        assert_eq!(encoding_name("gpt-3.5-turbo"), Some("cl100k_base"));
+        assert_eq!(encoding_name("claude-3-5-sonnet-20241022"), None);
+        assert_eq!(encoding_name("omni-moderation-latest"), None);
+    }
+}
@@ -0,0 +1,60 @@
+//! Estimating token counts locally, without calling the API.
+//!
+//! The estimates are heuristics meant for diagnostics and budgeting, not exact
+//! counts: the real tokenization depends on the model.
+
+use super::chat::ChatCompletionMessage;
+
+/// The average number of characters per token of English text.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Tokens added to every message for its role and delimiters.
+const TOKENS_PER_MESSAGE: u32 = 4;
+
+/// Tokens priming the reply of the assistant.
+const TOKENS_PER_REPLY: u32 = 3;
+
+/// Estimates the number of tokens of a text.
+pub fn estimate_tokens(text: &str) -> u32 {
+    let chars = text.chars().count();
+    u32::try_from(chars.div_ceil(CHARS_PER_TOKEN)).unwrap_or(u32::MAX)
+}
+
+/// Estimates the number of prompt tokens of a conversation.
+pub fn estimate_message_tokens(messages: &[ChatCompletionMessage]) -> u32 {
+    messages
+        .iter()
+        .map(|message| {
+            TOKENS_PER_MESSAGE
+                + estimate_tokens(message.text())
+                + message.name.as_deref().map_or(0, estimate_tokens)
+        })
+        .fold(TOKENS_PER_REPLY, u32::saturating_add)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::ChatCompletionMessageRole;
+
+    #[test]
+    fn estimates() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("Hello!"), 2);
+        assert_eq!(estimate_tokens(&"a".repeat(400)), 100);
+
+        let messages = [
+            ChatCompletionMessage {
+                role: ChatCompletionMessageRole::System,
+                content: Some("a".repeat(40)),
+                ..Default::default()
+            },
+            ChatCompletionMessage {
+                role: ChatCompletionMessageRole::User,
+                content: Some("a".repeat(80)),
+                ..Default::default()
+            },
+        ];
+        assert_eq!(estimate_message_tokens(&messages), 3 + 4 + 10 + 4 + 20);
+    }
+}
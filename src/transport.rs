@@ -0,0 +1,53 @@
+//! Replacing how requests are sent.
+//!
+//! Buffered requests are sent through the [`Transport`] set on the credentials
+//! with [`Credentials::with_transport`](crate::Credentials::with_transport),
+//! by default the reqwest client of the credentials. A transport receives the
+//! request once its headers, body and timeout are set, and after the rate and
+//! concurrency limits were waited for, so a fake transport such as
+//! [`MockTransport`](crate::testing::MockTransport) sees exactly what would
+//! have been sent. Retries go through the transport too.
+//!
+//! Streamed responses and the blocking client are always sent with reqwest.
+
+use super::ApiResponseOrError;
+use reqwest::{Client, Request, Response};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub type TransportFuture = Pin<Box<dyn Future<Output = ApiResponseOrError<Response>> + Send>>;
+/// The futures of the browser's `fetch` aren't `Send`.
+#[cfg(target_arch = "wasm32")]
+pub type TransportFuture = Pin<Box<dyn Future<Output = ApiResponseOrError<Response>>>>;
+
+/// Sends a request and returns its response, whatever its status.
+pub trait Transport: Send + Sync {
+    fn send(&self, request: Request) -> TransportFuture;
+}
+
+impl Transport for Client {
+    fn send(&self, request: Request) -> TransportFuture {
+        let client = self.clone();
+        Box::pin(async move { Ok(client.execute(request).await?) })
+    }
+}
+
+/// A transport set on the credentials, compared by identity.
+#[derive(Clone)]
+pub(crate) struct SharedTransport(pub(crate) Arc<dyn Transport>);
+
+impl std::fmt::Debug for SharedTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SharedTransport(..)")
+    }
+}
+
+impl PartialEq for SharedTransport {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SharedTransport {}
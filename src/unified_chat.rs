@@ -0,0 +1,451 @@
+//! Provider-agnostic chat completions.
+//!
+//! [`ChatProvider`] hides the differences between the OpenAI, Anthropic and
+//! Gemini chat APIs, so the same code can run against any of them by swapping
+//! the credentials.
+
+use super::{
+    anthrophic_chat::AnthropicChatCompletion,
+    chat::{ChatCompletion, ChatCompletionMessage, ChatCompletionMessageRole, FinishReason},
+    gemini_chat::{GeminiChatCompletion, GeminiGenerationConfig},
+    ApiProvider, ApiResponseOrError, Credentials, MaybeSend,
+};
+use std::future::Future;
+
+/// Request options shared by all providers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChatOptions {
+    /// ID of the model to use.
+    pub model: String,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    /// The maximum number of tokens to generate.
+    pub max_tokens: Option<u64>,
+    /// Sequences where the model will stop generating further tokens.
+    pub stop: Vec<String>,
+}
+
+impl ChatOptions {
+    pub fn new(model: impl Into<String>) -> Self {
+        ChatOptions {
+            model: model.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// A chat completion normalized across providers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnifiedCompletion {
+    /// The model that generated the completion.
+    pub model: String,
+    /// The generated text.
+    pub text: String,
+    pub finish_reason: UnifiedFinishReason,
+    /// The stop sequence that ended the answer, for providers reporting it.
+    /// Only Anthropic does, OpenAI and Gemini just stop.
+    pub stop_sequence: Option<String>,
+    pub usage: Option<UnifiedUsage>,
+}
+
+/// Why the model stopped generating, normalized across providers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UnifiedFinishReason {
+    /// The model finished its answer or hit a stop sequence.
+    Stop,
+    /// The maximum number of tokens was reached.
+    Length,
+    /// The model wants to call a tool.
+    ToolUse,
+    /// The content was omitted by a content filter.
+    ContentFilter,
+    /// A reason this crate doesn't know about, as sent by the provider.
+    Other(String),
+}
+
+/// Token usage normalized across providers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnifiedUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+/// A chat API, for code that shouldn't depend on a specific provider.
+pub trait ChatProvider {
+    /// Generates the next message of the conversation.
+    fn complete(
+        &self,
+        messages: Vec<ChatCompletionMessage>,
+        options: &ChatOptions,
+    ) -> impl Future<Output = ApiResponseOrError<UnifiedCompletion>> + MaybeSend;
+}
+
+/// The OpenAI chat completions API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenAIChat {
+    pub credentials: Credentials,
+}
+
+/// The Anthropic messages API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnthropicChat {
+    pub credentials: Credentials,
+}
+
+/// The Gemini `generateContent` API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeminiChat {
+    pub credentials: Credentials,
+}
+
+impl ChatProvider for OpenAIChat {
+    fn complete(
+        &self,
+        messages: Vec<ChatCompletionMessage>,
+        options: &ChatOptions,
+    ) -> impl Future<Output = ApiResponseOrError<UnifiedCompletion>> + MaybeSend {
+        let mut builder = ChatCompletion::builder(&options.model, messages)
+            .credentials(self.credentials.clone())
+            .stop(options.stop.clone());
+        if let Some(temperature) = options.temperature {
+            builder = builder.temperature(temperature);
+        }
+        if let Some(top_p) = options.top_p {
+            builder = builder.top_p(top_p);
+        }
+        if let Some(max_tokens) = options.max_tokens {
+            builder = builder.max_tokens(max_tokens);
+        }
+        async move { Ok(builder.create().await?.into()) }
+    }
+}
+
+impl ChatProvider for AnthropicChat {
+    fn complete(
+        &self,
+        messages: Vec<ChatCompletionMessage>,
+        options: &ChatOptions,
+    ) -> impl Future<Output = ApiResponseOrError<UnifiedCompletion>> + MaybeSend {
+        // Anthropic takes the system prompt separately from the conversation.
+        let (system, messages): (Vec<_>, Vec<_>) = messages
+            .into_iter()
+            .partition(|message| message.role == ChatCompletionMessageRole::System);
+        let system = system
+            .into_iter()
+            .filter_map(|message| message.content)
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        let mut builder = AnthropicChatCompletion::builder(&options.model, &system, messages)
+            .credentials(self.credentials.clone())
+            .stop(options.stop.clone());
+        if let Some(max_tokens) = options.max_tokens {
+            builder = builder.max_tokens(i32::try_from(max_tokens).unwrap_or(i32::MAX));
+        }
+        if let Some(temperature) = options.temperature {
+            builder = builder.temperature(temperature);
+        }
+        if let Some(top_p) = options.top_p {
+            builder = builder.top_p(top_p);
+        }
+        async move { Ok(builder.create().await?.into()) }
+    }
+}
+
+impl ChatProvider for GeminiChat {
+    fn complete(
+        &self,
+        messages: Vec<ChatCompletionMessage>,
+        options: &ChatOptions,
+    ) -> impl Future<Output = ApiResponseOrError<UnifiedCompletion>> + MaybeSend {
+        let builder = GeminiChatCompletion::builder(&options.model, messages)
+            .credentials(self.credentials.clone())
+            .generation_config(GeminiGenerationConfig {
+                temperature: options.temperature,
+                top_p: options.top_p,
+                max_output_tokens: options
+                    .max_tokens
+                    .map(|max_tokens| u32::try_from(max_tokens).unwrap_or(u32::MAX)),
+                stop_sequences: options.stop.clone(),
+                ..Default::default()
+            });
+        let model = options.model.clone();
+        async move {
+            let mut completion: UnifiedCompletion = builder.create().await?.into();
+            // Gemini only reports the version of the model.
+            if completion.model.is_empty() {
+                completion.model = model;
+            }
+            Ok(completion)
+        }
+    }
+}
+
+/// Dispatches to the API of the credentials' provider.
+impl ChatProvider for Credentials {
+    fn complete(
+        &self,
+        messages: Vec<ChatCompletionMessage>,
+        options: &ChatOptions,
+    ) -> impl Future<Output = ApiResponseOrError<UnifiedCompletion>> + MaybeSend {
+        let credentials = self.clone();
+        let options = options.clone();
+        async move {
+            match credentials.provider() {
+                ApiProvider::OpenAI
+                | ApiProvider::OpenAICompatible
+                | ApiProvider::OpenRouter
+                | ApiProvider::Together
+                | ApiProvider::Groq => {
+                    OpenAIChat { credentials }
+                        .complete(messages, &options)
+                        .await
+                }
+                ApiProvider::Anthropic => {
+                    AnthropicChat { credentials }
+                        .complete(messages, &options)
+                        .await
+                }
+                #[cfg(feature = "bedrock")]
+                ApiProvider::Bedrock { .. } => {
+                    AnthropicChat { credentials }
+                        .complete(messages, &options)
+                        .await
+                }
+                ApiProvider::Gemini => {
+                    GeminiChat { credentials }
+                        .complete(messages, &options)
+                        .await
+                }
+            }
+        }
+    }
+}
+
+impl From<ChatCompletion> for UnifiedCompletion {
+    fn from(completion: ChatCompletion) -> Self {
+        let choice = completion.choices.into_iter().next();
+        let finish_reason = match choice.as_ref().map(|choice| &choice.finish_reason) {
+            Some(FinishReason::Stop) => UnifiedFinishReason::Stop,
+            Some(FinishReason::Length) => UnifiedFinishReason::Length,
+            Some(FinishReason::ToolCalls | FinishReason::FunctionCall) => {
+                UnifiedFinishReason::ToolUse
+            }
+            Some(FinishReason::ContentFilter) => UnifiedFinishReason::ContentFilter,
+            Some(FinishReason::Unknown(other)) => UnifiedFinishReason::Other(other.clone()),
+            None => UnifiedFinishReason::Other(String::new()),
+        };
+        UnifiedCompletion {
+            model: completion.model,
+            text: choice
+                .map(|choice| choice.message.text().to_string())
+                .unwrap_or_default(),
+            finish_reason,
+            stop_sequence: None,
+            usage: completion.usage.map(|usage| UnifiedUsage {
+                input_tokens: usage.prompt_tokens.into(),
+                output_tokens: usage.completion_tokens.into(),
+            }),
+        }
+    }
+}
+
+impl From<AnthropicChatCompletion> for UnifiedCompletion {
+    fn from(completion: AnthropicChatCompletion) -> Self {
+        let finish_reason = match completion.stop_reason.as_str() {
+            "end_turn" | "stop_sequence" => UnifiedFinishReason::Stop,
+            "max_tokens" => UnifiedFinishReason::Length,
+            "tool_use" => UnifiedFinishReason::ToolUse,
+            other => UnifiedFinishReason::Other(other.to_string()),
+        };
+        UnifiedCompletion {
+            text: completion.text(),
+            model: completion.model,
+            finish_reason,
+            stop_sequence: completion.stop_sequence,
+            usage: completion.usage.map(|usage| UnifiedUsage {
+                input_tokens: usage.input_tokens,
+                output_tokens: usage.output_tokens,
+            }),
+        }
+    }
+}
+
+impl From<GeminiChatCompletion> for UnifiedCompletion {
+    fn from(completion: GeminiChatCompletion) -> Self {
+        let finish_reason = match completion
+            .candidates
+            .first()
+            .and_then(|candidate| candidate.finish_reason.as_deref())
+        {
+            Some("STOP") => UnifiedFinishReason::Stop,
+            Some("MAX_TOKENS") => UnifiedFinishReason::Length,
+            Some("SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII") => {
+                UnifiedFinishReason::ContentFilter
+            }
+            Some(other) => UnifiedFinishReason::Other(other.to_string()),
+            None => match completion
+                .prompt_feedback
+                .as_ref()
+                .and_then(|feedback| feedback.block_reason.as_ref())
+            {
+                Some(_) => UnifiedFinishReason::ContentFilter,
+                None => UnifiedFinishReason::Other(String::new()),
+            },
+        };
+        UnifiedCompletion {
+            text: completion.text(),
+            model: completion.model_version.unwrap_or_default(),
+            finish_reason,
+            stop_sequence: None,
+            usage: completion.usage_metadata.map(|usage| UnifiedUsage {
+                input_tokens: usage.prompt_token_count,
+                output_tokens: usage.candidates_token_count,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{json_response, mock_server};
+
+    fn conversation() -> Vec<ChatCompletionMessage> {
+        vec![
+            ChatCompletionMessage {
+                role: ChatCompletionMessageRole::System,
+                content: Some("You are a helpful assistant.".to_string()),
+                ..Default::default()
+            },
+            ChatCompletionMessage {
+                role: ChatCompletionMessageRole::User,
+                content: Some("Hello!".to_string()),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[tokio::test]
+    async fn unified_openai() {
+        let credentials = mock_server(json_response(
+            "",
+            r#"{
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 1700000000,
+                "model": "gpt-4o",
+                "choices": [{
+                    "index": 0,
+                    "finish_reason": "length",
+                    "message": {"role": "assistant", "content": "Hi there"}
+                }],
+                "usage": {"prompt_tokens": 12, "completion_tokens": 2, "total_tokens": 14}
+            }"#,
+        ))
+        .await;
+
+        let completion = credentials
+            .complete(conversation(), &ChatOptions::new("gpt-4o"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            completion,
+            UnifiedCompletion {
+                model: "gpt-4o".to_string(),
+                text: "Hi there".to_string(),
+                finish_reason: UnifiedFinishReason::Length,
+                stop_sequence: None,
+                usage: Some(UnifiedUsage {
+                    input_tokens: 12,
+                    output_tokens: 2,
+                }),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn unified_anthropic() {
+        let mock_credentials = mock_server(json_response(
+            "",
+            r#"{
+                "id": "msg_1",
+                "type": "message",
+                "role": "assistant",
+                "model": "claude-3-5-sonnet-20241022",
+                "content": [
+                    {"type": "text", "text": "Hi "},
+                    {"type": "text", "text": "there"}
+                ],
+                "stop_reason": "stop_sequence",
+                "stop_sequence": "END",
+                "usage": {
+                    "input_tokens": 10,
+                    "cache_creation_input_tokens": 0,
+                    "cache_read_input_tokens": 0,
+                    "output_tokens": 3
+                }
+            }"#,
+        ))
+        .await;
+        let credentials = Credentials::new("sk-ant-test", "https://api.anthropic.com/v1/")
+            .with_base_url(mock_credentials.base_url());
+
+        let completion = credentials
+            .complete(
+                conversation(),
+                &ChatOptions::new("claude-3-5-sonnet-20241022"),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(completion.text, "Hi there");
+        assert_eq!(completion.finish_reason, UnifiedFinishReason::Stop);
+        assert_eq!(completion.stop_sequence.as_deref(), Some("END"));
+        assert_eq!(
+            completion.usage,
+            Some(UnifiedUsage {
+                input_tokens: 10,
+                output_tokens: 3,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn unified_gemini() {
+        let mock_credentials = mock_server(json_response(
+            "",
+            r#"{
+                "candidates": [{
+                    "content": {"role": "model", "parts": [{"text": "Hi there"}]},
+                    "finishReason": "MAX_TOKENS",
+                    "index": 0
+                }],
+                "usageMetadata": {"promptTokenCount": 8, "candidatesTokenCount": 2, "totalTokenCount": 10}
+            }"#,
+        ))
+        .await;
+        let credentials =
+            Credentials::gemini("AIza-test").with_base_url(mock_credentials.base_url());
+
+        let completion = credentials
+            .complete(conversation(), &ChatOptions::new("gemini-2.0-flash"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            completion,
+            UnifiedCompletion {
+                model: "gemini-2.0-flash".to_string(),
+                text: "Hi there".to_string(),
+                finish_reason: UnifiedFinishReason::Length,
+                stop_sequence: None,
+                usage: Some(UnifiedUsage {
+                    input_tokens: 8,
+                    output_tokens: 2,
+                }),
+            }
+        );
+    }
+}